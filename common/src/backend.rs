@@ -0,0 +1,135 @@
+// Where run sends its samples: a real cpal output stream, or a buffer so the math can be checked without a device
+
+use crate::fade::Faded;
+use cpal::{
+    traits::{DeviceTrait, StreamTrait},
+    Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig,
+};
+
+pub trait AudioBackend {
+    // What keeps the output going, a real stream stops when it's dropped
+    type Stream;
+
+    // Starts calling `fill` with interleaved output buffers (`conf.channels` samples per frame) and returns straight away
+    fn start<T, F>(&mut self, conf: &StreamConfig, fill: F) -> Self::Stream
+    where
+        T: SizedSample + Send + 'static,
+        f32: FromSample<T>,
+        F: FnMut(&mut [T]) + Send + 'static;
+
+    // The output device, None when there isn't one (nothing else can be played through it then)
+    fn device(&self) -> Option<&Device>;
+
+    // Like `start` for `duration_ms`, the stream is kept until `fill` has set `faded`
+    fn play<T, F>(&mut self, conf: &StreamConfig, fill: F, duration_ms: u64, faded: &Faded)
+    where
+        T: SizedSample + Send + 'static,
+        f32: FromSample<T>,
+        F: FnMut(&mut [T]) + Send + 'static,
+    {
+        let _stream = self.start(conf, fill);
+        faded.wait(duration_ms);
+    }
+}
+
+// The default output device
+pub struct CpalBackend<'a> {
+    pub dev: &'a Device,
+}
+
+impl AudioBackend for CpalBackend<'_> {
+    type Stream = Stream;
+
+    fn start<T, F>(&mut self, conf: &StreamConfig, mut fill: F) -> Stream
+    where
+        T: SizedSample + Send + 'static,
+        f32: FromSample<T>,
+        F: FnMut(&mut [T]) + Send + 'static,
+    {
+        let stream = self
+            .dev
+            .build_output_stream(
+                conf,
+                move |data: &mut [T], _: &cpal::OutputCallbackInfo| fill(data),
+                |err| eprintln!("An error occurred during output stream: {}", err),
+                None,
+            )
+            .expect("Failed to create stream!");
+
+        stream.play().expect("Failed to play the stream!");
+        stream
+    }
+
+    fn device(&self) -> Option<&Device> {
+        Some(self.dev)
+    }
+}
+
+// Captures a fixed number of frames as f32 instead of playing them, straight away and in `buffer_frames` chunks
+// Deterministic, it doesn't care about the duration or any device
+pub struct MockBackend {
+    frames: usize,
+    buffer_frames: usize,
+    captured: Vec<f32>,
+    // Sample type run picked for the stream, None until started
+    sample_format: Option<SampleFormat>,
+}
+
+impl MockBackend {
+    pub fn new(frames: usize, buffer_frames: usize) -> Self {
+        Self {
+            frames,
+            buffer_frames: buffer_frames.max(1),
+            captured: Vec::new(),
+            sample_format: None,
+        }
+    }
+
+    // Every captured sample, interleaved like the stream
+    pub fn captured(&self) -> &[f32] {
+        &self.captured
+    }
+
+    // Which `T` the stream was started with, to check the format dispatch
+    pub fn sample_format(&self) -> Option<SampleFormat> {
+        self.sample_format
+    }
+}
+
+impl AudioBackend for MockBackend {
+    // Everything has been captured by the time `start` returns, there's nothing to hold on to
+    type Stream = ();
+
+    fn start<T, F>(&mut self, conf: &StreamConfig, mut fill: F)
+    where
+        T: SizedSample + Send + 'static,
+        f32: FromSample<T>,
+        F: FnMut(&mut [T]) + Send + 'static,
+    {
+        self.sample_format = Some(T::FORMAT);
+        let channels = conf.channels as usize;
+        let mut buffer = vec![T::EQUILIBRIUM; self.buffer_frames * channels];
+        let mut remaining = self.frames;
+        while remaining > 0 {
+            let frames = remaining.min(self.buffer_frames);
+            let data = &mut buffer[..frames * channels];
+            fill(data);
+            self.captured.extend(data.iter().map(|s| s.to_sample::<f32>()));
+            remaining -= frames;
+        }
+    }
+
+    fn device(&self) -> Option<&Device> {
+        None
+    }
+
+    // No waiting, the frames were all captured up front
+    fn play<T, F>(&mut self, conf: &StreamConfig, fill: F, _duration_ms: u64, _faded: &Faded)
+    where
+        T: SizedSample + Send + 'static,
+        f32: FromSample<T>,
+        F: FnMut(&mut [T]) + Send + 'static,
+    {
+        self.start(conf, fill);
+    }
+}
@@ -0,0 +1,102 @@
+// The fade at the end of a fixed length play, and telling main when it's done so the stream isn't dropped mid-ramp
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How long the programs with a fixed play time play
+pub const PLAY_MS: u64 = 1000;
+
+// Past the play time before giving up on the callback, a stalled device never gets to the end
+const FADE_WAIT_SLACK: Duration = Duration::from_millis(500);
+// Once the end is written it still has to get through the device buffer
+const FADE_TAIL: Duration = Duration::from_millis(50);
+
+// Gain for the fade at the end of the playback, 1 until the last `fade` samples then a straight ramp to 0
+pub fn fade_out_gain(sample: usize, total: usize, fade: usize) -> f32 {
+    let remaining = total.saturating_sub(sample);
+    if remaining >= fade {
+        1.0
+    } else {
+        remaining as f32 / fade as f32
+    }
+}
+
+// Set by the callback once it has written all `total` samples (the fade is down to silence), main waits for it
+#[derive(Clone, Default)]
+pub struct Faded(Arc<AtomicBool>);
+
+impl Faded {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // From the callback, a single atomic store
+    pub fn set(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    // Blocks until the callback got to the end, at most `play_ms` plus some slack, false when it gave up
+    pub fn wait(&self, play_ms: u64) -> bool {
+        let deadline = Instant::now() + Duration::from_millis(play_ms) + FADE_WAIT_SLACK;
+        while !self.is_set() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        thread::sleep(FADE_TAIL);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramps_over_the_last_samples() {
+        assert_eq!(fade_out_gain(0, 100, 10), 1.0);
+        assert_eq!(fade_out_gain(90, 100, 10), 1.0);
+        assert_eq!(fade_out_gain(95, 100, 10), 0.5);
+        assert_eq!(fade_out_gain(100, 100, 10), 0.0);
+        assert_eq!(fade_out_gain(150, 100, 10), 0.0);
+    }
+
+    #[test]
+    fn ramp_falls_monotonically_to_zero_over_the_fade() {
+        // 20ms at 48kHz
+        let (total, fade) = (48000, 960);
+        let gains: Vec<f32> = (0..=total).map(|n| fade_out_gain(n, total, fade)).collect();
+        assert!(gains.windows(2).all(|w| w[1] <= w[0]));
+        assert!(gains[..=total - fade].iter().all(|g| *g == 1.0));
+        assert!(gains[total - fade + 1..].iter().all(|g| *g < 1.0));
+        assert_eq!(gains[total], 0.0);
+    }
+
+    #[test]
+    fn no_fade_is_full_level() {
+        assert_eq!(fade_out_gain(99, 100, 0), 1.0);
+    }
+
+    #[test]
+    fn wait_returns_once_set() {
+        let faded = Faded::new();
+        let callback = faded.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            callback.set();
+        });
+        assert!(faded.wait(1000));
+    }
+
+    #[test]
+    fn wait_gives_up() {
+        assert!(!Faded::new().wait(0));
+    }
+}
@@ -0,0 +1,14 @@
+// Pieces every test program needs, kept here so the copies can't drift apart
+
+pub mod backend;
+pub mod fade;
+pub mod logging;
+pub mod notes;
+pub mod pan;
+pub mod preview;
+pub mod rng;
+pub mod safety;
+pub mod sample_format;
+pub mod setup;
+pub mod wav;
+pub mod waveform;
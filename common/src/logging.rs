@@ -0,0 +1,195 @@
+// How much is printed to stderr, -q for Silent and -v for Verbose on the command line
+
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    // Only results that were asked for
+    Silent,
+    // Setup, arguments and warnings
+    Normal,
+    // Also per-callback diagnostics (buffer sizes, late callbacks)
+    Verbose,
+}
+
+impl LogLevel {
+    // -q/--quiet and -v/--verbose, the last one given wins and anything else is ignored
+    pub fn from_args(args: impl Iterator<Item = String>) -> Self {
+        args.fold(LogLevel::Normal, |level, arg| match arg.as_str() {
+            "-q" | "--quiet" => LogLevel::Silent,
+            "-v" | "--verbose" => LogLevel::Verbose,
+            _ => level,
+        })
+    }
+}
+
+// Prints `msg` unless `level` is Silent
+pub fn log(level: LogLevel, msg: &str) {
+    log_to(&mut std::io::stderr(), level, msg);
+}
+
+// Prints `msg` only when `level` is Verbose
+pub fn verbose(level: LogLevel, msg: &str) {
+    verbose_to(&mut std::io::stderr(), level, msg);
+}
+
+// `log` into any writer, write errors are dropped since there is nowhere left to report them
+pub fn log_to(out: &mut dyn Write, level: LogLevel, msg: &str) {
+    if level >= LogLevel::Normal {
+        let _ = writeln!(out, "{}", msg);
+    }
+}
+
+pub fn verbose_to(out: &mut dyn Write, level: LogLevel, msg: &str) {
+    if level >= LogLevel::Verbose {
+        let _ = writeln!(out, "{}", msg);
+    }
+}
+
+// How often the reporter thread prints what the callback recorded
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+// Written by the callback, read by the reporter thread, plain atomics so the callback never
+// locks or prints
+#[derive(Default)]
+struct Counters {
+    frames: AtomicUsize,
+    late: AtomicUsize,
+    // How late the latest late callback was, in microseconds past one buffer
+    late_us: AtomicUsize,
+}
+
+// What the reporter printed last, so it only prints changes
+#[derive(Default)]
+struct Reported {
+    frames: usize,
+    late: usize,
+}
+
+impl Counters {
+    fn report(&self, stream: &str, seen: &mut Reported, out: &mut dyn Write) {
+        let frames = self.frames.load(Ordering::Relaxed);
+        if frames != seen.frames {
+            let _ = writeln!(out, "- {} callback buffer: {} frames", stream, frames);
+            seen.frames = frames;
+        }
+        let late = self.late.load(Ordering::Relaxed);
+        if late != seen.late {
+            let _ = writeln!(
+                out,
+                "- {} callback {:.1}ms late, possible xrun ({} so far)",
+                stream,
+                self.late_us.load(Ordering::Relaxed) as f32 / 1000.0,
+                late
+            );
+            seen.late = late;
+        }
+    }
+}
+
+// Verbose diagnostics for a stream callback: the buffer size whenever it changes and callbacks
+// that came so late the device most likely ran dry (an xrun, underrun on output, overrun on input)
+// The callback only updates counters, a reporter thread prints them until the stats are dropped
+pub struct CallbackStats {
+    enabled: bool,
+    sample_rate: f32,
+    last_call: Option<Instant>,
+    last_frames: usize,
+    counters: Arc<Counters>,
+}
+
+impl CallbackStats {
+    pub fn new(level: LogLevel, stream: &'static str, sample_rate: u32) -> Self {
+        let stats = Self::quiet(level, sample_rate);
+        if stats.enabled {
+            let counters = Arc::clone(&stats.counters);
+            thread::spawn(move || {
+                let mut seen = Reported::default();
+                // Only this thread is left holding the counters once the stream is dropped
+                while Arc::strong_count(&counters) > 1 {
+                    thread::sleep(REPORT_INTERVAL);
+                    counters.report(stream, &mut seen, &mut std::io::stderr());
+                }
+            });
+        }
+        stats
+    }
+
+    // Without the reporter thread, for tests that read the counters themselves
+    fn quiet(level: LogLevel, sample_rate: u32) -> Self {
+        Self {
+            enabled: level >= LogLevel::Verbose,
+            sample_rate: sample_rate as f32,
+            last_call: None,
+            last_frames: 0,
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    // Call at the start of every callback with the number of frames in its buffer
+    pub fn record(&mut self, frames: usize) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(last) = self.last_call {
+            // More than two buffers since the last callback, the previous one must have run out
+            let gap = now.duration_since(last).as_secs_f32();
+            if gap > 2.0 * self.last_frames as f32 / self.sample_rate {
+                let late = gap - self.last_frames as f32 / self.sample_rate;
+                self.counters.late_us.store((late * 1e6) as usize, Ordering::Relaxed);
+                self.counters.late.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.counters.frames.store(frames, Ordering::Relaxed);
+        self.last_call = Some(now);
+        self.last_frames = frames;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured(level: LogLevel) -> String {
+        let mut out = Vec::new();
+        log_to(&mut out, level, "setup");
+        verbose_to(&mut out, level, "detail");
+        let mut stats = CallbackStats::quiet(level, 48000);
+        stats.record(256);
+        stats.counters.report("Output", &mut Reported::default(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn silent_prints_nothing() {
+        assert_eq!(captured(LogLevel::Silent), "");
+    }
+
+    #[test]
+    fn normal_skips_verbose_lines() {
+        assert_eq!(captured(LogLevel::Normal), "setup\n");
+    }
+
+    #[test]
+    fn verbose_adds_detail_and_callback_stats() {
+        assert_eq!(
+            captured(LogLevel::Verbose),
+            "setup\ndetail\n- Output callback buffer: 256 frames\n"
+        );
+    }
+
+    #[test]
+    fn late_callback_is_counted() {
+        let mut stats = CallbackStats::quiet(LogLevel::Verbose, 48000);
+        stats.record(48);
+        // 48 frames is 1ms, sleeping well past two buffers has to count as late
+        thread::sleep(Duration::from_millis(10));
+        stats.record(48);
+        assert_eq!(stats.counters.late.load(Ordering::Relaxed), 1);
+    }
+}
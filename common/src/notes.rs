@@ -0,0 +1,81 @@
+// Note names <-> frequencies, equal temperament with A4 = 440Hz
+
+const A4: f32 = 440.0;
+// Midi number of A4, C4 (middle C) is 60
+const A4_MIDI: i32 = 69;
+const NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+// Nearest note name and how far off it the frequency is in cents (-50..50)
+// e.g. 445Hz -> ("A4", +19.6), None for 0, negative or non-finite frequencies, they have no note
+pub fn freq_to_note_name(hz: f32) -> Option<(String, f32)> {
+    if !hz.is_finite() || hz <= 0.0 {
+        return None;
+    }
+    let semitones = 12.0 * (hz / A4).log2();
+    let nearest = semitones.round();
+    let cents = (semitones - nearest) * 100.0;
+    let midi = A4_MIDI + nearest as i32;
+    // Octaves start at C, midi 0 is C-1
+    let name = NAMES[midi.rem_euclid(12) as usize];
+    let octave = midi.div_euclid(12) - 1;
+    Some((format!("{}{}", name, octave), cents))
+}
+
+// Parses a note name like "A4", "C#3" or "Bb2" into its frequency
+pub fn note_name_to_freq(name: &str) -> Result<f32, String> {
+    let name = name.trim();
+    let mut chars = name.chars();
+    let letter = chars
+        .next()
+        .ok_or_else(|| "empty note name".to_string())?
+        .to_ascii_uppercase();
+    let base = NAMES
+        .iter()
+        .position(|n| *n == letter.to_string())
+        .ok_or_else(|| format!("'{}' is not a note name", name))? as i32;
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+    let octave: i32 = octave
+        .parse()
+        .map_err(|_| format!("'{}' is missing an octave number (e.g. A4)", name))?;
+    let midi = (octave + 1) * 12 + base + accidental;
+    Ok(A4 * 2f32.powf((midi - A4_MIDI) as f32 / 12.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequency_to_nearest_note_and_cents() {
+        let (note, cents) = freq_to_note_name(440.0).unwrap();
+        assert_eq!(note, "A4");
+        assert!(cents.abs() < 0.01);
+        let (note, cents) = freq_to_note_name(445.0).unwrap();
+        assert_eq!(note, "A4");
+        assert!((cents - 19.56).abs() < 0.05, "{}", cents);
+        assert_eq!(freq_to_note_name(261.6256).unwrap().0, "C4");
+    }
+
+    #[test]
+    fn no_note_without_a_positive_frequency() {
+        assert_eq!(freq_to_note_name(0.0), None);
+        assert_eq!(freq_to_note_name(-440.0), None);
+        assert_eq!(freq_to_note_name(f32::NAN), None);
+        assert_eq!(freq_to_note_name(f32::INFINITY), None);
+    }
+
+    #[test]
+    fn note_names_to_frequencies() {
+        assert_eq!(note_name_to_freq("A4"), Ok(440.0));
+        assert!((note_name_to_freq("C4").unwrap() - 261.6256).abs() < 1e-3);
+        assert_eq!(note_name_to_freq("A#3"), note_name_to_freq("Bb3"));
+        assert!((note_name_to_freq("a5").unwrap() - 880.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,32 @@
+// Placing a signal between the left and right channel
+
+// Constant power pan, cos/sin of 0..pi/2 so left^2 + right^2 stays the same at every position
+// The center is -3dB on each channel, fully to one side is unity on that side
+pub fn pan(input: [f32; 2], position: f32) -> [f32; 2] {
+    let angle = (position.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    let (right, left) = angle.sin_cos();
+    [input[0] * left, input[1] * right]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pan_is_constant_power_and_minus_3db_in_the_center() {
+        let center = pan([1.0, 1.0], 0.0);
+        assert!((center[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert!((center[1] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert!((pan([1.0, 1.0], -1.0)[0] - 1.0).abs() < 1e-6 && pan([1.0, 1.0], -1.0)[1].abs() < 1e-6);
+        for n in -10..=10 {
+            let [left, right] = pan([1.0, 1.0], n as f32 / 10.0);
+            assert!((left * left + right * right - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn positions_past_the_sides_are_clamped() {
+        assert_eq!(pan([1.0, 1.0], 2.0), pan([1.0, 1.0], 1.0));
+        assert_eq!(pan([1.0, 1.0], -5.0), pan([1.0, 1.0], -1.0));
+    }
+}
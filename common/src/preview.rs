@@ -0,0 +1,96 @@
+// Short audition of the generated arrays through the device before they're written, to check the patch by ear
+
+use cpal::{
+    traits::{DeviceTrait, StreamTrait},
+    Device, FromSample, Sample, SizedSample, StreamConfig,
+};
+
+use crate::logging::{log, LogLevel};
+use crate::safety::ClipGuard;
+
+// Kept down like the live playback (save your ears!), the arrays themselves go up to full scale
+const PREVIEW_VOLUME: f32 = 0.3333;
+
+// Samples in `preview_ms` at `sample_rate`
+pub fn preview_len(preview_ms: f32, sample_rate: u32) -> usize {
+    (preview_ms.max(0.0) / 1000.0 * sample_rate as f32).round() as usize
+}
+
+// Plays the first `preview_ms` of the mono `samples` on every channel, then returns
+// Arrays shorter than that (a single period) are looped to fill the time
+pub fn play_preview<T: SizedSample + FromSample<f32>>(
+    dev: &Device,
+    conf: &StreamConfig,
+    samples: &[f32],
+    preview_ms: f32,
+    safety: ClipGuard,
+) {
+    let len = preview_len(preview_ms, conf.sample_rate.0);
+    if samples.is_empty() || len == 0 {
+        return;
+    }
+    let channels = conf.channels as usize;
+    let samples = samples.to_vec();
+    let mut n = 0_usize;
+    let stream = dev
+        .build_output_stream(
+            conf,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                for frame in data.chunks_mut(channels) {
+                    let value = if n < len { safety.process(samples[n % samples.len()] * PREVIEW_VOLUME) } else { 0.0 };
+                    n += 1;
+                    for sample in frame.iter_mut() {
+                        *sample = value.to_sample::<T>();
+                    }
+                }
+            },
+            |err| eprintln!("An error occurred during preview stream: {}", err),
+            None,
+        )
+        .expect("Failed to create the preview stream!");
+
+    stream.play().expect("Failed to play the preview stream!");
+    // A little extra so the last buffer gets out before the stream is dropped
+    std::thread::sleep(std::time::Duration::from_millis(preview_ms.max(0.0) as u64 + 100));
+}
+
+// Plays the samples it's given, borrows the device and config from run
+pub type PreviewFn<'a> = &'a dyn Fn(&[f32]);
+
+// Runs the preview before the arrays are written when `preview_ms` is set, `play` is None without a device
+pub fn preview_arrays(samples: &[f32], preview_ms: f32, play: Option<PreviewFn>, log_level: LogLevel) {
+    if preview_ms <= 0.0 {
+        return;
+    }
+    match play {
+        Some(play) => {
+            log(log_level, &format!("- Previewing the first {}ms", preview_ms));
+            play(samples);
+        }
+        None => log(log_level, "- No device, skipping the preview"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn len_in_samples() {
+        assert_eq!(preview_len(500.0, 48000), 24000);
+        assert_eq!(preview_len(-10.0, 48000), 0);
+    }
+
+    #[test]
+    fn plays_only_when_asked() {
+        let played = Cell::new(0);
+        let play = |samples: &[f32]| played.set(samples.len());
+        preview_arrays(&[0.0; 8], 0.0, Some(&play), LogLevel::Silent);
+        assert_eq!(played.get(), 0);
+        preview_arrays(&[0.0; 8], 250.0, Some(&play), LogLevel::Silent);
+        assert_eq!(played.get(), 8);
+        // No device is just a note, not an error
+        preview_arrays(&[0.0; 8], 250.0, None, LogLevel::Silent);
+    }
+}
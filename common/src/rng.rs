@@ -0,0 +1,94 @@
+// Tiny xorshift32 generator so we don't need a dependency for noise and dice rolls
+// Seeded, the same seed always gives the same numbers so a good run can be repeated
+
+pub struct XorShift(u32);
+
+impl XorShift {
+    pub fn new(seed: u32) -> Self {
+        // xorshift gets stuck on 0 forever
+        Self(if seed == 0 { 0x9E3779B9 } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    // Uniform in [-1, 1]
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    // 0..1, never exactly 0 so it can go through ln
+    pub fn unit(&mut self) -> f32 {
+        (self.next_u32() as f32 + 1.0) / (u32::MAX as f32 + 2.0)
+    }
+
+    // 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        self.next_u32() as usize % n
+    }
+
+    // min..max
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * (self.next_u32() as f32 / u32::MAX as f32)
+    }
+}
+
+// Seed from the clock for when none was given, print it so a good run can be repeated
+pub fn seed_from_time() -> u32 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    now.subsec_nanos() ^ now.as_secs() as u32
+}
+
+// The seed a run uses, `seed` or one from the clock. It's printed whenever something random is `used`,
+// even with -q, as it's the only way to get the same output again
+pub fn resolve_seed(seed: Option<u32>, used: bool) -> u32 {
+    let seed = seed.unwrap_or_else(seed_from_time);
+    if used {
+        eprintln!("- Seed: {}", seed);
+    }
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut a = XorShift::new(1234);
+        let mut b = XorShift::new(1234);
+        assert!((0..100).all(|_| a.next_u32() == b.next_u32()));
+    }
+
+    #[test]
+    fn zero_seed_still_moves() {
+        let mut rng = XorShift::new(0);
+        assert_ne!(rng.next_u32(), 0);
+    }
+
+    #[test]
+    fn ranges_hold() {
+        let mut rng = XorShift::new(7);
+        for _ in 0..1000 {
+            assert!((-1.0..=1.0).contains(&rng.next_f32()));
+            let unit = rng.unit();
+            assert!(unit > 0.0 && unit <= 1.0);
+            assert!(rng.below(3) < 3);
+            assert!((2.0..=40.0).contains(&rng.range(2.0, 40.0)));
+        }
+    }
+
+    #[test]
+    fn given_seed_is_kept() {
+        assert_eq!(resolve_seed(Some(1234), false), 1234);
+        assert_eq!(resolve_seed(Some(1234), true), 1234);
+    }
+}
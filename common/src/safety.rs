@@ -0,0 +1,88 @@
+// Hearing protection on the final output: a clip guard, not a limiter. Every sample is hard clipped
+// to the ceiling right before it's converted for the device, there's no gain reduction, so anything
+// over the ceiling is audibly distorted rather than turned down
+// It doesn't trust the volume math, so a bug upstream can't send full scale noise (save your ears!)
+// Only `--no-limit` on the command line turns it off
+
+// Default ceiling, -1 dBFS is ~0.89 of full scale
+pub const DEFAULT_CEILING_DBFS: f32 = -1.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClipGuard {
+    // Linear ceiling, None when bypassed
+    ceiling: Option<f32>,
+}
+
+impl ClipGuard {
+    // The ceiling can't go above full scale
+    pub fn new(ceiling_dbfs: f32, bypass: bool) -> Self {
+        Self {
+            ceiling: (!bypass).then(|| 10f32.powf(ceiling_dbfs.min(0.0) / 20.0)),
+        }
+    }
+
+    pub fn process(&self, x: f32) -> f32 {
+        match self.ceiling {
+            // NaN or infinity from a bug upstream, silence rather than whatever the conversion makes of it
+            Some(_) if !x.is_finite() => 0.0,
+            Some(ceiling) => x.clamp(-ceiling, ceiling),
+            None => x,
+        }
+    }
+}
+
+// True when `--no-limit` is on the command line
+pub fn no_limit_from_args(mut args: impl Iterator<Item = String>) -> bool {
+    args.any(|arg| arg == "--no-limit")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clips_to_the_ceiling() {
+        let guard = ClipGuard::new(-6.0, false);
+        let ceiling = 10f32.powf(-6.0 / 20.0);
+        assert_eq!(guard.process(1.0), ceiling);
+        assert_eq!(guard.process(-1.0), -ceiling);
+        assert_eq!(guard.process(0.25), 0.25);
+    }
+
+    #[test]
+    fn double_amplitude_sine_stays_under_the_default_ceiling() {
+        let guard = ClipGuard::new(DEFAULT_CEILING_DBFS, false);
+        let ceiling = 10f32.powf(DEFAULT_CEILING_DBFS / 20.0);
+        let out: Vec<f32> = (0..44100)
+            .map(|n| guard.process(2.0 * (2.0 * std::f32::consts::PI * 440.0 * n as f32 / 44100.0).sin()))
+            .collect();
+        assert!(out.iter().all(|s| s.abs() <= ceiling));
+        // Hits the ceiling on both sides, not just turned down
+        assert!(out.contains(&ceiling) && out.contains(&-ceiling));
+    }
+
+    #[test]
+    fn ceiling_stops_at_full_scale() {
+        assert_eq!(ClipGuard::new(6.0, false).process(2.0), 1.0);
+    }
+
+    #[test]
+    fn non_finite_is_silenced() {
+        let guard = ClipGuard::new(DEFAULT_CEILING_DBFS, false);
+        assert_eq!(guard.process(f32::NAN), 0.0);
+        assert_eq!(guard.process(f32::INFINITY), 0.0);
+    }
+
+    #[test]
+    fn bypass_passes_everything() {
+        let guard = ClipGuard::new(DEFAULT_CEILING_DBFS, true);
+        assert_eq!(guard.process(2.0), 2.0);
+        assert!(guard.process(f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn no_limit_flag() {
+        assert!(no_limit_from_args(["-v", "--no-limit"].iter().map(|s| s.to_string())));
+        assert!(!no_limit_from_args(["-v"].iter().map(|s| s.to_string())));
+    }
+}
@@ -0,0 +1,177 @@
+// Forcing the output sample format from the command line (`--sample-format i16`), for debugging the conversions
+// The device has to support the format at the default config's sample rate and channel count, it isn't converted
+
+use cpal::{traits::DeviceTrait, Device, SampleFormat, SupportedStreamConfig, SupportedStreamConfigRange};
+
+// Every format the dispatch has a `run::<T>` for, by the name cpal prints
+const FORMATS: [SampleFormat; 10] = [
+    SampleFormat::I8,
+    SampleFormat::I16,
+    SampleFormat::I32,
+    SampleFormat::I64,
+    SampleFormat::U8,
+    SampleFormat::U16,
+    SampleFormat::U32,
+    SampleFormat::U64,
+    SampleFormat::F32,
+    SampleFormat::F64,
+];
+
+// "i16", "f32", ... in any case
+pub fn parse_sample_format(value: &str) -> Result<SampleFormat, String> {
+    let name = value.trim().to_lowercase();
+    FORMATS
+        .into_iter()
+        .find(|format| format.to_string() == name)
+        .ok_or_else(|| {
+            let names: Vec<String> = FORMATS.iter().map(|format| format.to_string()).collect();
+            format!(
+                "unknown sample format '{}', expected one of {}",
+                value,
+                names.join(", ")
+            )
+        })
+}
+
+// `--sample-format <format>` or `--sample-format=<format>`, the last one given wins and anything else is ignored
+pub fn sample_format_from_args(mut args: impl Iterator<Item = String>) -> Result<Option<SampleFormat>, String> {
+    let mut format = None;
+    while let Some(arg) = args.next() {
+        let value = if arg == "--sample-format" {
+            args.next()
+                .ok_or("--sample-format needs a format, e.g. --sample-format i16")?
+        } else if let Some(value) = arg.strip_prefix("--sample-format=") {
+            value.to_string()
+        } else {
+            continue;
+        };
+        format = Some(parse_sample_format(&value)?);
+    }
+    Ok(format)
+}
+
+// `default` in `format` instead, from a supported range with the same channel count that covers its sample rate
+pub fn override_sample_format(
+    default: SupportedStreamConfig,
+    supported: &[SupportedStreamConfigRange],
+    format: SampleFormat,
+) -> Result<SupportedStreamConfig, String> {
+    if default.sample_format() == format {
+        return Ok(default);
+    }
+    let rate = default.sample_rate();
+    let channels = default.channels();
+    supported
+        .iter()
+        .find(|range| {
+            range.sample_format() == format
+                && range.channels() == channels
+                && range.min_sample_rate() <= rate
+                && rate <= range.max_sample_rate()
+        })
+        .map(|range| range.with_sample_rate(rate))
+        .ok_or_else(|| {
+            let mut listed: Vec<String> = supported
+                .iter()
+                .filter(|range| {
+                    range.channels() == channels && range.min_sample_rate() <= rate && rate <= range.max_sample_rate()
+                })
+                .map(|range| range.sample_format().to_string())
+                .collect();
+            listed.sort();
+            listed.dedup();
+            format!(
+                "The output device doesn't support {} at {}Hz with {} channels (supported: {})",
+                format,
+                rate.0,
+                channels,
+                if listed.is_empty() {
+                    "none listed".to_string()
+                } else {
+                    listed.join(", ")
+                }
+            )
+        })
+}
+
+// `default` as it is without a `format`, otherwise checked against the device's supported configs
+// Errors when the device can't list them, as the format can't be checked
+pub fn force_sample_format(
+    dev: &Device,
+    default: SupportedStreamConfig,
+    format: Option<SampleFormat>,
+) -> Result<SupportedStreamConfig, String> {
+    let format = match format {
+        Some(format) => format,
+        None => return Ok(default),
+    };
+    let supported: Vec<SupportedStreamConfigRange> = dev
+        .supported_output_configs()
+        .map_err(|e| {
+            format!(
+                "Could not list the supported output configs to check {} ({})",
+                format, e
+            )
+        })?
+        .collect();
+    override_sample_format(default, &supported, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{SampleRate, SupportedBufferSize};
+
+    fn range(channels: u16, format: SampleFormat) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(8000),
+            SampleRate(192000),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    fn default_f32() -> SupportedStreamConfig {
+        SupportedStreamConfig::new(2, SampleRate(44100), SupportedBufferSize::Unknown, SampleFormat::F32)
+    }
+
+    fn args(list: &[&str]) -> impl Iterator<Item = String> {
+        list.iter().map(|s| s.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    #[test]
+    fn parses_any_case() {
+        assert_eq!(parse_sample_format("I16"), Ok(SampleFormat::I16));
+        assert!(parse_sample_format("i24").is_err());
+    }
+
+    #[test]
+    fn both_flag_spellings() {
+        assert_eq!(sample_format_from_args(args(&["--sample-format", "u8"])), Ok(Some(SampleFormat::U8)));
+        assert_eq!(sample_format_from_args(args(&["--sample-format=f64"])), Ok(Some(SampleFormat::F64)));
+        assert_eq!(sample_format_from_args(args(&["-v"])), Ok(None));
+        assert!(sample_format_from_args(args(&["--sample-format"])).is_err());
+    }
+
+    #[test]
+    fn override_picks_a_matching_range() {
+        let supported = [range(2, SampleFormat::F32), range(1, SampleFormat::I16), range(2, SampleFormat::I16)];
+        let conf = override_sample_format(default_f32(), &supported, SampleFormat::I16).unwrap();
+        assert_eq!(conf.sample_format(), SampleFormat::I16);
+        assert_eq!(conf.channels(), 2);
+        assert_eq!(conf.sample_rate(), SampleRate(44100));
+    }
+
+    #[test]
+    fn unsupported_lists_each_format_once() {
+        let supported = [
+            range(2, SampleFormat::I16),
+            range(2, SampleFormat::F32),
+            range(2, SampleFormat::I16),
+            range(1, SampleFormat::U8),
+        ];
+        let err = override_sample_format(default_f32(), &supported, SampleFormat::I32).unwrap_err();
+        assert!(err.ends_with("(supported: f32, i16)"), "{}", err);
+    }
+}
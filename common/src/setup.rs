@@ -0,0 +1,165 @@
+// Finding the default output device and its default config, with the supported configs listed for information
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    Device, SupportedStreamConfig, SupportedStreamConfigRange,
+};
+
+use crate::logging::{log, LogLevel};
+
+// Why the default output couldn't be set up
+#[derive(Debug)]
+pub enum SetupError {
+    NoDevice,
+    NoConfig { device: String, reason: String },
+}
+
+impl std::fmt::Display for SetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SetupError::NoDevice => write!(f, "Did not find an output audio device"),
+            SetupError::NoConfig { device, reason } => {
+                write!(
+                    f,
+                    "Did not find a default output config for {}: {}",
+                    device, reason
+                )
+            }
+        }
+    }
+}
+
+// The bits of an output device the setup asks for, so it can run against a fake device in tests
+pub trait OutputDevice {
+    fn device_name(&self) -> String;
+    fn default_config(&self) -> Result<SupportedStreamConfig, String>;
+    fn supported_configs(&self) -> Result<Vec<SupportedStreamConfigRange>, String>;
+}
+
+impl OutputDevice for Device {
+    fn device_name(&self) -> String {
+        self.name().unwrap_or_else(|_| "the output device".to_string())
+    }
+
+    fn default_config(&self) -> Result<SupportedStreamConfig, String> {
+        self.default_output_config().map_err(|e| e.to_string())
+    }
+
+    fn supported_configs(&self) -> Result<Vec<SupportedStreamConfigRange>, String> {
+        self.supported_output_configs()
+            .map(|supported| supported.collect())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// The default output device, its default config and the configs it lists (empty when it can't list them)
+pub fn setup_default_device_default_config(
+    log_level: LogLevel,
+) -> Result<(Device, SupportedStreamConfig, Vec<SupportedStreamConfigRange>), SetupError> {
+    let host = cpal::default_host();
+    let dev = host.default_output_device().ok_or(SetupError::NoDevice)?;
+    let (conf, supported) = default_output_config(&dev, log_level)?;
+    Ok((dev, conf, supported))
+}
+
+// Only the default config is needed, some virtual/dummy devices can't list their supported configs
+// but still play it, so that failing is a warning
+pub fn default_output_config<D: OutputDevice>(
+    dev: &D,
+    log_level: LogLevel,
+) -> Result<(SupportedStreamConfig, Vec<SupportedStreamConfigRange>), SetupError> {
+    log(log_level, &format!("- Output device: {}", dev.device_name()));
+
+    let conf = dev.default_config().map_err(|reason| SetupError::NoConfig {
+        device: dev.device_name(),
+        reason,
+    })?;
+    log(log_level, &format!("- Default output config: {:?}", conf));
+
+    let supported = match dev.supported_configs() {
+        Ok(supported) => supported,
+        Err(e) => {
+            log(
+                log_level,
+                &format!("- WARNING: Could not list supported configs ({}), using the default", e),
+            );
+            vec![]
+        }
+    };
+    if log_level >= LogLevel::Normal {
+        eprintln!("- Supported output configs:");
+        for (n, c) in supported.iter().enumerate() {
+            eprintln!("  {}. {:?}", n, c);
+        }
+        if supported.is_empty() {
+            eprintln!("  none listed");
+        }
+    }
+    Ok((conf, supported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{SampleFormat, SampleRate, SupportedBufferSize};
+
+    struct MockDevice {
+        default: Result<SupportedStreamConfig, String>,
+        supported: Result<Vec<SupportedStreamConfigRange>, String>,
+    }
+
+    impl OutputDevice for MockDevice {
+        fn device_name(&self) -> String {
+            "Mock".to_string()
+        }
+
+        fn default_config(&self) -> Result<SupportedStreamConfig, String> {
+            self.default.clone()
+        }
+
+        fn supported_configs(&self) -> Result<Vec<SupportedStreamConfigRange>, String> {
+            self.supported.clone()
+        }
+    }
+
+    fn stereo_f32() -> SupportedStreamConfig {
+        SupportedStreamConfig::new(2, SampleRate(48000), SupportedBufferSize::Unknown, SampleFormat::F32)
+    }
+
+    #[test]
+    fn unlistable_configs_fall_back_to_the_default() {
+        let dev = MockDevice {
+            default: Ok(stereo_f32()),
+            supported: Err("not supported by this backend".to_string()),
+        };
+        let (conf, supported) = default_output_config(&dev, LogLevel::Silent).unwrap();
+        assert_eq!(conf, stereo_f32());
+        assert!(supported.is_empty());
+    }
+
+    #[test]
+    fn empty_config_list_still_uses_the_default() {
+        let dev = MockDevice {
+            default: Ok(stereo_f32()),
+            supported: Ok(vec![]),
+        };
+        let (conf, supported) = default_output_config(&dev, LogLevel::Silent).unwrap();
+        assert_eq!(conf, stereo_f32());
+        assert!(supported.is_empty());
+    }
+
+    #[test]
+    fn missing_default_config_names_the_device() {
+        let dev = MockDevice {
+            default: Err("device busy".to_string()),
+            supported: Ok(vec![]),
+        };
+        match default_output_config(&dev, LogLevel::Silent) {
+            Err(SetupError::NoConfig { device, reason }) => {
+                assert_eq!(device, "Mock");
+                assert_eq!(reason, "device busy");
+            }
+            other => panic!("expected NoConfig, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,388 @@
+// Writing wav files and reading them back
+
+// PCM header, float adds 14 bytes (cbSize and the fact chunk)
+const WAV_HEADER_SIZE: usize = 44;
+// Format tags
+const WAV_PCM: u16 = 1;
+const WAV_FLOAT: u16 = 3;
+// What the written files are allowed to be
+const SAMPLE_RATES: std::ops::RangeInclusive<u32> = 8000..=192000;
+const MAX_CHANNELS: u16 = 2;
+
+// Sample format of the written wav file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitDepth {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl BitDepth {
+    // "16", "24" or "f32"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "16" => Ok(BitDepth::Int16),
+            "24" => Ok(BitDepth::Int24),
+            "f32" => Ok(BitDepth::Float32),
+            other => Err(format!("unsupported bit depth '{}', expected 16, 24 or f32", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for BitDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BitDepth::Int16 => write!(f, "16"),
+            BitDepth::Int24 => write!(f, "24"),
+            BitDepth::Float32 => write!(f, "f32"),
+        }
+    }
+}
+
+// sample_count is the total over all channels
+fn make_wav_header(
+    o: &mut Vec<u8>,
+    format_tag: u16,
+    bits: u16,
+    channels: u16,
+    sample_rate: usize,
+    sample_count: usize,
+) {
+    let str_bytes: fn(&str) -> Vec<u8> = |v| v.chars().map(|c| c as u8).collect();
+    let u32_bytes: fn(usize) -> Vec<u8> = |u| (u as u32).to_le_bytes().to_vec();
+    let u16_bytes: fn(u16) -> Vec<u8> = |u| u.to_le_bytes().to_vec();
+    let bytes_per_sample = bits as usize / 8;
+    // Anything but plain PCM needs the extension size (cbSize) in fmt and a fact chunk with the frame count
+    let extended = format_tag != WAV_PCM;
+    let fmt_size = if extended { 18 } else { 16 };
+    let fact_size = if extended { 8 + 4 } else { 0 };
+    let data_size = sample_count * bytes_per_sample;
+    // Everything after the RIFF size, the data chunk padded to an even length
+    let riff_size = 4 + 8 + fmt_size + fact_size + 8 + data_size + data_size % 2;
+
+    o.append(&mut str_bytes("RIFF"));
+    o.append(&mut u32_bytes(riff_size));
+
+    o.append(&mut str_bytes("WAVE"));
+
+    o.append(&mut str_bytes("fmt "));
+    o.append(&mut u32_bytes(fmt_size)); // header size
+    o.append(&mut u16_bytes(format_tag)); // tag
+    o.append(&mut u16_bytes(channels)); // channels
+    o.append(&mut u32_bytes(sample_rate)); // sample rate
+    o.append(&mut u32_bytes(sample_rate * channels as usize * bytes_per_sample)); // data rate
+    o.append(&mut u16_bytes(channels * bytes_per_sample as u16)); // block size
+    o.append(&mut u16_bytes(bits)); // bits per sample
+    if extended {
+        o.append(&mut u16_bytes(0)); // extension size
+        o.append(&mut str_bytes("fact"));
+        o.append(&mut u32_bytes(4));
+        o.append(&mut u32_bytes(sample_count / channels as usize)); // frames
+    }
+
+    o.append(&mut str_bytes("data"));
+    o.append(&mut u32_bytes(data_size));
+}
+
+// 16 bit interleaved samples
+pub fn raw_audio_to_wav(samples: Vec<i16>, channels: u16, sample_rate: u32) -> Vec<u8> {
+    let parity = samples.len() % 2;
+
+    eprintln!("- Calculated length: {}", samples.len());
+
+    let mut o = Vec::with_capacity(samples.len() + WAV_HEADER_SIZE);
+    make_wav_header(&mut o, WAV_PCM, 16, channels, sample_rate as usize, samples.len());
+    o.append(
+        &mut samples
+            .into_iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>(),
+    );
+
+    // wav files use 16bit ints, so we need an even number of bytes otherwise it's technically invalid
+    if parity == 1 {
+        o.push(0);
+    }
+
+    o
+}
+
+// 24 bit interleaved samples (held in the low 3 bytes of an i32)
+pub fn raw_audio_to_wav_24(samples: Vec<i32>, channels: u16, sample_rate: u32) -> Vec<u8> {
+    eprintln!("- Calculated length: {}", samples.len());
+
+    let mut o = Vec::with_capacity(samples.len() * 3 + WAV_HEADER_SIZE);
+    make_wav_header(&mut o, WAV_PCM, 24, channels, sample_rate as usize, samples.len());
+    for s in samples.iter() {
+        o.extend_from_slice(&s.to_le_bytes()[..3]);
+    }
+    // Chunks have to be an even number of bytes
+    if o.len() % 2 == 1 {
+        o.push(0);
+    }
+    o
+}
+
+// 32 bit float interleaved samples
+pub fn raw_audio_to_wav_f32(samples: Vec<f32>, channels: u16, sample_rate: u32) -> Vec<u8> {
+    eprintln!("- Calculated length: {}", samples.len());
+
+    let mut o = Vec::with_capacity(samples.len() * 4 + WAV_HEADER_SIZE);
+    make_wav_header(&mut o, WAV_FLOAT, 32, channels, sample_rate as usize, samples.len());
+    for s in samples.iter() {
+        o.extend_from_slice(&s.to_le_bytes());
+    }
+    o
+}
+
+// What happens to samples past -1..1 when they're quantized
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipMode {
+    // Clamped to full scale, flat tops (32 bit float keeps them as they are)
+    Hard,
+    // Everything goes through tanh first, rounded tops that never reach full scale (quieter parts get a little squashed too)
+    Soft,
+}
+
+impl ClipMode {
+    // "hard" or "soft"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "hard" => Ok(ClipMode::Hard),
+            "soft" => Ok(ClipMode::Soft),
+            other => Err(format!("unknown clip mode '{}', expected hard or soft", other)),
+        }
+    }
+
+    // The samples ready for quantizing
+    pub fn apply(&self, samples: &[f32]) -> Vec<f32> {
+        match self {
+            ClipMode::Hard => samples.to_vec(),
+            ClipMode::Soft => samples.iter().map(|s| s.tanh()).collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for ClipMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ClipMode::Hard => write!(f, "hard"),
+            ClipMode::Soft => write!(f, "soft"),
+        }
+    }
+}
+
+// -1..1 to a 16 bit sample, clamped
+pub fn to_i16(f: f32) -> i16 {
+    ((f * 32768_f32).round() as i64).clamp(-32768, 32767) as i16
+}
+
+// Headerless little endian 16 bit PCM (s16le) of interleaved -1..1 samples, for piping into aplay/ffmpeg
+// The rate and channel count aren't stored, the reader has to be told them
+pub fn samples_to_raw_pcm(samples: &[f32]) -> Vec<u8> {
+    samples.iter().flat_map(|f| to_i16(*f).to_le_bytes()).collect()
+}
+
+// Quantizes interleaved -1..1 samples to `bit_depth` and writes them with the matching writer
+pub fn samples_to_wav(samples: &[f32], channels: u16, sample_rate: u32, bit_depth: BitDepth) -> Vec<u8> {
+    match bit_depth {
+        BitDepth::Int16 => raw_audio_to_wav(samples.iter().map(|f| to_i16(*f)).collect(), channels, sample_rate),
+        BitDepth::Int24 => raw_audio_to_wav_24(
+            samples
+                .iter()
+                .map(|f| ((*f as f64 * 8388608_f64).round() as i64).clamp(-8388608, 8388607) as i32)
+                .collect(),
+            channels,
+            sample_rate,
+        ),
+        BitDepth::Float32 => raw_audio_to_wav_f32(samples.to_vec(), channels, sample_rate),
+    }
+}
+
+// Reads a PCM (8/16/24/32 bit int or 32 bit float) wav file, channels are averaged down to mono
+// Returns the samples in -1..1 and the sample rate
+pub fn wav_to_raw_audio(bytes: &[u8]) -> Result<(Vec<f32>, u32), String> {
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("not a RIFF/WAVE file".to_string());
+    }
+
+    // Walk the chunks looking for the format and the data
+    let mut format: Option<(u16, u16, u32, u16)> = None;
+    let mut data: Option<&[u8]> = None;
+    let mut i = 12;
+    while i + 8 <= bytes.len() {
+        let id = &bytes[i..i + 4];
+        let size = u32_at(i + 4) as usize;
+        let body = &bytes[i + 8..(i + 8 + size).min(bytes.len())];
+        if id == b"fmt " {
+            if body.len() < 16 {
+                return Err("fmt chunk too short".to_string());
+            }
+            format = Some((u16_at(i + 8), u16_at(i + 10), u32_at(i + 12), u16_at(i + 22)));
+        } else if id == b"data" {
+            data = Some(body);
+        }
+        // chunks are padded to an even length
+        i += 8 + size + size % 2;
+    }
+    let (tag, channels, sample_rate, bits) = format.ok_or("missing fmt chunk")?;
+    let data = data.ok_or("missing data chunk")?;
+    if channels == 0 {
+        return Err("wav file has 0 channels".to_string());
+    }
+
+    let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+        (1, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+        (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+        (1, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.0,
+        (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0,
+        (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        (t, b) => return Err(format!("unsupported wav format (tag {}, {} bits)", t, b)),
+    };
+    let sample_size = bits as usize / 8;
+    let frame_size = sample_size * channels as usize;
+    let samples = data
+        .chunks_exact(frame_size)
+        .map(|frame| {
+            frame.chunks_exact(sample_size).map(decode).sum::<f32>() / channels as f32
+        })
+        .collect();
+    Ok((samples, sample_rate))
+}
+
+// Sample rate and channel count for files written without a device, nothing to ask the hardware
+pub fn check_format(sample_rate: u32, channels: u16) -> Result<(), String> {
+    if !SAMPLE_RATES.contains(&sample_rate) {
+        return Err(format!(
+            "sample rate {}Hz is outside {}..={}Hz",
+            sample_rate,
+            SAMPLE_RATES.start(),
+            SAMPLE_RATES.end()
+        ));
+    }
+    if channels == 0 || channels > MAX_CHANNELS {
+        return Err(format!("{} channels, expected 1 (mono) or 2 (stereo)", channels));
+    }
+    Ok(())
+}
+
+// A mono signal copied to every channel, interleaved
+pub fn upmix(samples: &[f32], channels: u16) -> Vec<f32> {
+    samples
+        .iter()
+        .flat_map(|s| std::iter::repeat_n(*s, channels as usize))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (id, body) of every chunk after "WAVE"
+    fn chunks(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut out = vec![];
+        let mut i = 12;
+        while i + 8 <= bytes.len() {
+            let size = u32::from_le_bytes(bytes[i + 4..i + 8].try_into().unwrap()) as usize;
+            out.push((
+                String::from_utf8(bytes[i..i + 4].to_vec()).unwrap(),
+                bytes[i + 8..i + 8 + size].to_vec(),
+            ));
+            i += 8 + size + size % 2;
+        }
+        out
+    }
+
+    #[test]
+    fn fmt_chunk_matches_the_bit_depth() {
+        let samples = [0.0, 0.5, -0.5, 1.0, -1.0, 0.25];
+        for (bit_depth, tag, bits) in [
+            (BitDepth::Int16, WAV_PCM, 16),
+            (BitDepth::Int24, WAV_PCM, 24),
+            (BitDepth::Float32, WAV_FLOAT, 32),
+        ] {
+            let bytes = samples_to_wav(&samples, 2, 48000, bit_depth);
+            assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+            let chunks = chunks(&bytes);
+            let (id, fmt) = &chunks[0];
+            assert_eq!(id, "fmt ");
+            assert_eq!(u16::from_le_bytes([fmt[0], fmt[1]]), tag, "{}", bit_depth);
+            assert_eq!(u16::from_le_bytes([fmt[14], fmt[15]]), bits, "{}", bit_depth);
+            let data = &chunks.last().unwrap();
+            assert_eq!(data.0, "data");
+            assert_eq!(data.1.len(), samples.len() * bits as usize / 8);
+            if bit_depth == BitDepth::Float32 {
+                // 18 bytes with an empty extension, then the frame count
+                assert_eq!(fmt.len(), 18);
+                assert_eq!(u16::from_le_bytes([fmt[16], fmt[17]]), 0);
+                assert_eq!(chunks[1].0, "fact");
+                assert_eq!(chunks[1].1, 3_u32.to_le_bytes());
+            } else {
+                assert_eq!(fmt.len(), 16);
+                assert_eq!(chunks.len(), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn raw_pcm_is_two_bytes_per_sample_without_a_header() {
+        // 100 stereo frames, interleaved
+        let samples = upmix(&[0.5; 100], 2);
+        let raw = samples_to_raw_pcm(&samples);
+        assert_eq!(raw.len(), 100 * 2 * 2);
+        assert_eq!(&raw[..4], &[0x00, 0x40, 0x00, 0x40]);
+    }
+
+    #[test]
+    fn hard_clips_flat_and_soft_clips_round() {
+        // Ten periods of a 1.5 amplitude sine, 100 samples each
+        let sine: Vec<f32> = (0..1000)
+            .map(|n| 1.5 * (2.0 * std::f32::consts::PI * n as f32 / 100.0).sin())
+            .collect();
+        let quantize = |mode: ClipMode| -> Vec<i16> { mode.apply(&sine).iter().map(|s| to_i16(*s)).collect() };
+        let hard = quantize(ClipMode::Hard);
+        // Flat tops: whole runs of samples pinned at full scale
+        let pinned = hard.iter().filter(|s| **s == 32767 || **s == -32768).count();
+        assert!(pinned > 400, "{}", pinned);
+        let soft = quantize(ClipMode::Soft);
+        // Rounded: the peak is below full scale and only reached once a half period
+        let peak = soft.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!(peak < 32767 && peak > 29000, "{}", peak);
+        assert_eq!(soft.iter().filter(|s| s.unsigned_abs() == peak).count(), 20);
+        assert_eq!(ClipMode::parse("Soft"), Ok(ClipMode::Soft));
+        assert!(ClipMode::parse("fold").is_err());
+    }
+
+    #[test]
+    fn headless_format_is_range_checked() {
+        assert!(check_format(48000, 2).is_ok());
+        assert!(check_format(8000, 1).is_ok() && check_format(192000, 1).is_ok());
+        assert!(check_format(7999, 1).unwrap_err().contains("7999Hz"));
+        assert!(check_format(192001, 1).is_err());
+        assert!(check_format(48000, 0).is_err() && check_format(48000, 3).is_err());
+    }
+
+    #[test]
+    fn upmix_copies_every_sample_to_each_channel() {
+        assert_eq!(upmix(&[0.5, -0.25], 2), vec![0.5, 0.5, -0.25, -0.25]);
+        assert_eq!(upmix(&[0.5, -0.25], 1), vec![0.5, -0.25]);
+    }
+
+    #[test]
+    fn written_files_read_back_as_mono() {
+        // Left and right average out to the middle
+        let stereo = [0.5, -0.5, 0.25, 0.75, -1.0, -1.0];
+        for bit_depth in [BitDepth::Int16, BitDepth::Int24, BitDepth::Float32] {
+            let (mono, rate) = wav_to_raw_audio(&samples_to_wav(&stereo, 2, 22050, bit_depth)).unwrap();
+            assert_eq!(rate, 22050);
+            assert_eq!(mono.len(), 3);
+            for (got, want) in mono.iter().zip([0.0, 0.5, -1.0]) {
+                assert!((got - want).abs() < 1e-4, "{}: {} != {}", bit_depth, got, want);
+            }
+        }
+        assert!(wav_to_raw_audio(b"RIFX").is_err());
+    }
+}
@@ -0,0 +1,78 @@
+// The plain waveforms by name, for patch files and command line flags
+// Each program has its own WaveformKind (with its own extra variants), this is only the names they share
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaveformName {
+    Silence,
+    Sine,
+    Square,
+    Sawtooth,
+    Triangle,
+    // Square with a duty cycle, the duty comes from wherever the name did
+    OnOff,
+}
+
+// Any case, "saw" is short for sawtooth
+impl std::str::FromStr for WaveformName {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "silence" => Ok(WaveformName::Silence),
+            "sine" => Ok(WaveformName::Sine),
+            "square" => Ok(WaveformName::Square),
+            "saw" | "sawtooth" => Ok(WaveformName::Sawtooth),
+            "triangle" => Ok(WaveformName::Triangle),
+            "onoff" => Ok(WaveformName::OnOff),
+            _ => Err(format!(
+                "unknown waveform '{}', expected silence, sine, square, saw/sawtooth, triangle or onoff",
+                value
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for WaveformName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WaveformName::Silence => write!(f, "silence"),
+            WaveformName::Sine => write!(f, "sine"),
+            WaveformName::Square => write!(f, "square"),
+            WaveformName::Sawtooth => write!(f, "sawtooth"),
+            WaveformName::Triangle => write!(f, "triangle"),
+            WaveformName::OnOff => write!(f, "onoff"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_case_and_the_saw_alias() {
+        assert_eq!("SAW".parse::<WaveformName>(), Ok(WaveformName::Sawtooth));
+        assert_eq!(" sawtooth ".parse::<WaveformName>(), Ok(WaveformName::Sawtooth));
+        assert_eq!("OnOff".parse::<WaveformName>(), Ok(WaveformName::OnOff));
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for name in [
+            WaveformName::Silence,
+            WaveformName::Sine,
+            WaveformName::Square,
+            WaveformName::Sawtooth,
+            WaveformName::Triangle,
+            WaveformName::OnOff,
+        ] {
+            assert_eq!(name.to_string().parse::<WaveformName>(), Ok(name));
+        }
+    }
+
+    #[test]
+    fn unknown_lists_the_names() {
+        let err = "bogus".parse::<WaveformName>().unwrap_err();
+        assert!(err.contains("'bogus'") && err.contains("saw/sawtooth"), "{}", err);
+    }
+}
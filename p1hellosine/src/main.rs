@@ -1,72 +1,74 @@
-use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    Device, FromSample, Sample, SizedSample, StreamConfig, SupportedStreamConfig,
-};
-
-fn setup_default_device_default_config(quiet: bool) -> (Device, SupportedStreamConfig) {
-    if !quiet {
-        eprintln!("SETUP");
-    }
-    let host = cpal::default_host();
-    let dev = host
-        .default_output_device()
-        .expect("Did not find output audio device!");
-    if !quiet {
-        eprintln!(
-            "- Output device: {}",
-            dev.name().expect("Device lacks a name..?")
-        );
-    }
+use common::backend::{AudioBackend, CpalBackend};
+use common::fade::{fade_out_gain, Faded, PLAY_MS};
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::safety::{no_limit_from_args, ClipGuard, DEFAULT_CEILING_DBFS};
+use common::sample_format::{force_sample_format, sample_format_from_args};
+use common::setup::setup_default_device_default_config;
+use cpal::{FromSample, Sample, SizedSample, StreamConfig, SupportedStreamConfig};
+use phase::PhaseAccumulator;
 
-    let conf = dev
-        .default_output_config()
-        .expect("Did not find default output config for device!");
-    if !quiet {
-        eprintln!("- Default output config: {:?}", conf);
-    }
+mod phase;
 
-    let supported = dev
-        .supported_output_configs()
-        .expect("Could not list supported configs");
-    if !quiet {
-        eprintln!("- Supported output configs:");
-        for (n, c) in supported.enumerate() {
-            eprintln!("  {}. {:?}", n, c);
-        }
-    }
-    (dev, conf)
+// Stereo test signals, they need a stereo output to make sense
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StereoTest {
+    // The same sine on every channel
+    Off,
+    // 440Hz left and 444Hz right, you hear the 4Hz difference as a wobble (binaural with headphones)
+    Beat,
+    // The sine left and its negation right, cancels to silence when the chain sums to mono correctly
+    Polarity,
 }
 
-fn dispatch_run(dev: &Device, conf: SupportedStreamConfig, quiet: bool) {
+fn dispatch_run<B: AudioBackend>(
+    backend: &mut B,
+    conf: SupportedStreamConfig,
+    log_level: LogLevel,
+    stereo_test: StereoTest,
+    fade_out_ms: f32,
+    safety: ClipGuard,
+) {
     use cpal::SampleFormat::*;
     match conf.sample_format() {
-        I8 => run::<i8>(dev, conf.into(), quiet),
-        I16 => run::<i16>(dev, conf.into(), quiet),
-        I32 => run::<i32>(dev, conf.into(), quiet),
-        I64 => run::<i64>(dev, conf.into(), quiet),
-        U8 => run::<u8>(dev, conf.into(), quiet),
-        U16 => run::<u16>(dev, conf.into(), quiet),
-        U32 => run::<u32>(dev, conf.into(), quiet),
-        U64 => run::<u64>(dev, conf.into(), quiet),
-        F32 => run::<f32>(dev, conf.into(), quiet),
-        F64 => run::<f64>(dev, conf.into(), quiet),
+        I8 => run::<i8, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        I16 => run::<i16, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        I32 => run::<i32, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        I64 => run::<i64, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        U8 => run::<u8, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        U16 => run::<u16, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        U32 => run::<u32, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        U64 => run::<u64, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        F32 => run::<f32, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
+        F64 => run::<f64, _>(backend, conf.into(), log_level, stereo_test, fade_out_ms, safety),
         f => panic!("Unknown sample format: {}", f),
     }
 }
 
-fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, quiet: bool) {
+fn run<T: SizedSample + FromSample<f32> + Send + 'static, B: AudioBackend>(
+    backend: &mut B,
+    conf: StreamConfig,
+    log_level: LogLevel,
+    stereo_test: StereoTest,
+    fade_out_ms: f32,
+    safety: ClipGuard,
+) where
+    f32: FromSample<T>,
+{
+    // next_sample gives a [left, right] pair, the second channel gets right and every other channel left
     fn write_data<T: Sample + FromSample<f32>>(
         output: &mut [T],
         channels: usize,
-        next_sample: &mut dyn FnMut() -> f32,
+        safety: &ClipGuard,
+        next_sample: &mut dyn FnMut() -> [f32; 2],
     ) {
         // multiply by volume=0.33... at the end to not play at full volume (save your ears!)
         let volume = 0.33333333;
 
         for frame in output.chunks_mut(channels) {
-            let value: T = (next_sample() * volume).to_sample::<T>();
-            for sample in frame.iter_mut() {
-                *sample = value;
+            let [left, right] = next_sample();
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let value = if channel == 1 { right } else { left };
+                *sample = safety.process(value * volume).to_sample::<T>();
             }
         }
     }
@@ -75,36 +77,155 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, quiet
     let sample_rate = conf.sample_rate.0 as f32;
     let channels = conf.channels as usize;
     let frequency = 440.0;
-    if !quiet {
+    // Stereo beat: a slightly different frequency on the right, you hear the difference (4Hz) as a wobble
+    let right_frequency = if stereo_test == StereoTest::Beat { 444.0 } else { frequency };
+    // Polarity: the right is the left flipped upside down
+    let right_polarity = if stereo_test == StereoTest::Polarity { -1.0 } else { 1.0 };
+    if log_level >= LogLevel::Normal {
         eprintln!("RUN");
-        eprintln!("- Frequency: {}Hz", frequency);
+        match stereo_test {
+            StereoTest::Off => eprintln!("- Frequency: {}Hz", frequency),
+            StereoTest::Beat => eprintln!(
+                "- Frequency: {}Hz left, {}Hz right ({}Hz beat)",
+                frequency,
+                right_frequency,
+                right_frequency - frequency
+            ),
+            StereoTest::Polarity => eprintln!(
+                "- Frequency: {}Hz, right channel inverted (should cancel when summed to mono)",
+                frequency
+            ),
+        }
+        if stereo_test != StereoTest::Off && channels < 2 {
+            eprintln!("- WARNING: Output is mono, only the left channel will play");
+        }
     }
 
-    // Initialize clock and sample value generator
-    let mut sample_clock = 0f32;
+    // Initialize phase and sample value generator
+    let mut left_phase = PhaseAccumulator::new(frequency, sample_rate);
+    let mut right_phase = PhaseAccumulator::new(right_frequency, sample_rate);
+    // Samples played so far, the last `fade` of them ramp down to silence so stopping doesn't click
+    let mut elapsed = 0_usize;
+    let total = conf.sample_rate.0 as usize * PLAY_MS as usize / 1000;
+    let fade = (fade_out_ms.max(0.0) / 1000.0 * sample_rate).round() as usize;
+    let faded = Faded::new();
+    let callback_faded = faded.clone();
     let mut next_value = move || {
-        sample_clock = (sample_clock + 1.0) % sample_rate;
-        ((2.0 * std::f32::consts::PI * frequency * sample_clock) / sample_rate).sin()
+        let gain = fade_out_gain(elapsed, total, fade);
+        elapsed += 1;
+        if elapsed >= total {
+            callback_faded.set();
+        }
+        let sine = |phase: f32| gain * (2.0 * std::f32::consts::PI * phase).sin();
+        [sine(left_phase.advance()), right_polarity * sine(right_phase.advance())]
     };
 
-    let stream = dev
-        .build_output_stream(
-            &conf,
-            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                write_data(data, channels, &mut next_value)
-            },
-            |err| eprintln!("An error occurred during output stream: {}", err),
-            None,
-        )
-        .expect("Failed to create stream!");
-
-    stream.play().expect("Failed to play the stream!");
-
-    std::thread::sleep(std::time::Duration::from_millis(1000));
+    verbose(log_level, &format!("- Stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(log_level, "Output", conf.sample_rate.0);
+    backend.play(
+        &conf,
+        move |data: &mut [T]| {
+            stats.record(data.len() / channels);
+            write_data(data, channels, &safety, &mut next_value)
+        },
+        PLAY_MS,
+        &faded,
+    );
 }
 
 fn main() {
-    let quiet = false;
-    let (dev, conf) = setup_default_device_default_config(quiet);
-    dispatch_run(&dev, conf, quiet);
+    let log_level = LogLevel::from_args(std::env::args().skip(1));
+    // StereoTest::Beat for 440Hz left and 444Hz right, StereoTest::Polarity for an inverted right channel
+    let stereo_test = StereoTest::Off;
+    // Ramp down over the end of the second instead of cutting off
+    let fade_out_ms = 20.0;
+    // Every sample is clamped to this before it's played, only `--no-limit` on the command line bypasses it
+    let limit_ceiling_dbfs = DEFAULT_CEILING_DBFS;
+    let no_limit = no_limit_from_args(std::env::args().skip(1));
+    if no_limit {
+        log(log_level, "- WARNING: Clip guard off (--no-limit)");
+    }
+    let safety = ClipGuard::new(limit_ceiling_dbfs, no_limit);
+    // Output sample format instead of the device default, only from `--sample-format` on the command line
+    let sample_format = sample_format_from_args(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{}", e));
+    log(log_level, "SETUP");
+    let (dev, conf, _) = setup_default_device_default_config(log_level)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let conf = force_sample_format(&dev, conf, sample_format)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(format) = sample_format {
+        log(log_level, &format!("- Sample format forced to {}", format));
+    }
+    dispatch_run(&mut CpalBackend { dev: &dev }, conf, log_level, stereo_test, fade_out_ms, safety);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::backend::MockBackend;
+    use common::sample_format::override_sample_format;
+    use cpal::{BufferSize, SampleFormat, SampleRate, SupportedBufferSize, SupportedStreamConfigRange};
+
+    #[test]
+    fn forced_format_dispatches_its_run() {
+        let default = SupportedStreamConfig::new(2, SampleRate(44100), SupportedBufferSize::Unknown, SampleFormat::F32);
+        let supported: Vec<SupportedStreamConfigRange> = [SampleFormat::F32, SampleFormat::I16]
+            .into_iter()
+            .map(|format| {
+                SupportedStreamConfigRange::new(2, SampleRate(8000), SampleRate(96000), SupportedBufferSize::Unknown, format)
+            })
+            .collect();
+        let conf = override_sample_format(default, &supported, SampleFormat::I16).unwrap();
+        let mut backend = MockBackend::new(64, 16);
+        let safety = ClipGuard::new(DEFAULT_CEILING_DBFS, false);
+        dispatch_run(&mut backend, conf, LogLevel::Silent, StereoTest::Off, 0.0, safety);
+        // Only run::<i16> lands every sample on a 1/32768 step, the f32 sine wouldn't
+        assert!(backend.captured().iter().any(|s| *s != 0.0));
+        assert!(backend.captured().iter().all(|s| (s * 32768.0).fract() == 0.0));
+    }
+
+    // One second of `stereo_test` through the mock at 44.1kHz, with no clip guard or fade in the way
+    fn render(channels: u16, stereo_test: StereoTest) -> Vec<f32> {
+        let conf = StreamConfig {
+            channels,
+            sample_rate: SampleRate(44100),
+            buffer_size: BufferSize::Default,
+        };
+        let mut backend = MockBackend::new(44100, 512);
+        run::<f32, _>(&mut backend, conf, LogLevel::Silent, stereo_test, 0.0, ClipGuard::new(0.0, true));
+        backend.captured().to_vec()
+    }
+
+    // Rising zero crossings of one channel, the frequency in Hz over one second
+    fn rising_crossings(captured: &[f32], channels: usize, channel: usize) -> usize {
+        let samples: Vec<f32> = captured.iter().skip(channel).step_by(channels).copied().collect();
+        samples.windows(2).filter(|w| w[0] <= 0.0 && w[1] > 0.0).count()
+    }
+
+    #[test]
+    fn beat_plays_a_different_frequency_on_each_channel() {
+        let captured = render(2, StereoTest::Beat);
+        assert_eq!(rising_crossings(&captured, 2, 0), 440);
+        assert_eq!(rising_crossings(&captured, 2, 1), 444);
+        let same = render(2, StereoTest::Off);
+        assert!(same.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+
+    #[test]
+    fn polarity_inverts_the_right_channel_exactly() {
+        let captured = render(2, StereoTest::Polarity);
+        assert!(captured.iter().any(|s| *s != 0.0));
+        assert!(captured.chunks(2).all(|frame| frame[1] == -frame[0]));
+    }
+
+    #[test]
+    fn mock_captures_the_analytic_sine() {
+        let captured = render(1, StereoTest::Off);
+        assert_eq!(captured.len(), 44100);
+        // The first few periods, the volume in write_data is 0.33333333
+        for (n, sample) in captured.iter().take(1000).enumerate() {
+            let expected = (2.0 * std::f64::consts::PI * 440.0 * n as f64 / 44100.0).sin() as f32 * 0.33333333;
+            assert!((sample - expected).abs() < 1e-5, "sample {}: {} != {}", n, sample, expected);
+        }
+    }
 }
@@ -0,0 +1,69 @@
+// Phase source for oscillators, kept in f64 so it doesn't drift out of tune over long playback
+// (an f32 sample clock loses precision as it grows, see the sample_clock in 4: FM Synth)
+pub struct PhaseAccumulator {
+    // Position in the current period, 0..1
+    phase: f64,
+    // Phase moved per sample, frequency / sample_rate
+    inc: f64,
+}
+
+impl PhaseAccumulator {
+    pub fn new(frequency: f32, sample_rate: f32) -> Self {
+        let mut acc = Self { phase: 0.0, inc: 0.0 };
+        acc.reset();
+        acc.set_frequency(frequency, sample_rate);
+        acc
+    }
+
+    // Takes effect from the next sample, the phase carries on so there's no jump
+    pub fn set_frequency(&mut self, frequency: f32, sample_rate: f32) {
+        self.inc = frequency as f64 / sample_rate as f64;
+    }
+
+    // Back to the start of a period, the frequency stays
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    // The current phase in [0, 1), then moves on one sample
+    pub fn advance(&mut self) -> f32 {
+        let phase = self.phase as f32;
+        self.phase = (self.phase + self.inc).fract();
+        // 0.99999999 as f64 can round up to 1.0 as f32
+        if phase >= 1.0 {
+            0.0
+        } else {
+            phase
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_second_of_phase_is_within_1e6_of_exact() {
+        let frequency = 441.3_f32;
+        let mut acc = PhaseAccumulator::new(frequency, 48000.0);
+        for _ in 0..48000 {
+            acc.advance();
+        }
+        let exact = (frequency as f64 * 48000.0 / 48000.0).fract();
+        let error = (acc.advance() as f64 - exact).abs();
+        assert!(error < 1e-6, "{}", error);
+    }
+
+    #[test]
+    fn reset_starts_the_period_again() {
+        let mut acc = PhaseAccumulator::new(440.0, 48000.0);
+        for _ in 0..1000 {
+            acc.advance();
+        }
+        acc.reset();
+        let mut fresh = PhaseAccumulator::new(440.0, 48000.0);
+        assert_eq!(acc.advance(), 0.0);
+        fresh.advance();
+        assert!((0..1000).all(|_| acc.advance() == fresh.advance()));
+    }
+}
@@ -0,0 +1,99 @@
+// Channel identification sweep, a tone on one output channel at a time (0, then 1, ...)
+// Channel n plays n whole tones above the base frequency so they can be told apart by ear too
+
+// Silence at the end of each channel's slot so consecutive tones are clearly separate
+const GAP_FRACTION: f32 = 0.25;
+// Ramp at the start and end of each tone so it doesn't click
+const RAMP_MS: f32 = 5.0;
+
+pub struct ChannelTest {
+    // Samples per channel, tone then gap
+    slot: usize,
+    tone: usize,
+    ramp: usize,
+    channels: usize,
+    frequency: f32,
+    sample_rate: f32,
+}
+
+impl ChannelTest {
+    pub fn new(frequency: f32, slot_ms: u64, channels: usize, sample_rate: f32) -> Self {
+        let slot = (slot_ms as f32 / 1000.0 * sample_rate).round().max(1.0) as usize;
+        let tone = (slot as f32 * (1.0 - GAP_FRACTION)).round() as usize;
+        Self {
+            slot,
+            tone,
+            ramp: ((RAMP_MS / 1000.0 * sample_rate).round() as usize).min(tone / 2),
+            channels,
+            frequency,
+            sample_rate,
+        }
+    }
+
+    pub fn channel_frequency(&self, channel: usize) -> f32 {
+        self.frequency * 2f32.powf(2.0 * channel as f32 / 12.0)
+    }
+
+    // The channel sounding at sample `n`, None in the gaps and once every channel has had its turn
+    pub fn active_channel(&self, n: usize) -> Option<usize> {
+        let channel = n / self.slot;
+        if channel < self.channels && n % self.slot < self.tone {
+            Some(channel)
+        } else {
+            None
+        }
+    }
+
+    // Sets every sample of the frame, the active channel gets its tone and the rest silence
+    pub fn fill_frame(&self, n: usize, frame: &mut [f32]) {
+        frame.fill(0.0);
+        let Some(channel) = self.active_channel(n) else {
+            return;
+        };
+        let t = n % self.slot;
+        let ramp = if self.ramp == 0 {
+            1.0
+        } else {
+            (t.min(self.tone - t) as f32 / self.ramp as f32).min(1.0)
+        };
+        let phase = (t as f32 * self.channel_frequency(channel) / self.sample_rate).fract();
+        if let Some(sample) = frame.get_mut(channel) {
+            *sample = ramp * (2.0 * std::f32::consts::PI * phase).sin();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_active_channel_is_nonzero() {
+        // 4 channels, 1000 sample slots at 1kHz: tone for 750, gap for 250
+        let test = ChannelTest::new(100.0, 1000, 4, 1000.0);
+        let mut frame = [0_f32; 4];
+        for channel in 0..4 {
+            let start = channel * 1000;
+            let mut loudest = 0_f32;
+            for n in start..start + 750 {
+                test.fill_frame(n, &mut frame);
+                for (index, sample) in frame.iter().enumerate() {
+                    if index != channel {
+                        assert_eq!(*sample, 0.0, "sample {} channel {}", n, index);
+                    }
+                }
+                loudest = loudest.max(frame[channel].abs());
+            }
+            assert!(loudest > 0.9, "channel {}: {}", channel, loudest);
+            test.fill_frame(start + 800, &mut frame);
+            assert_eq!(frame, [0.0; 4]);
+        }
+        assert_eq!(test.active_channel(4000), None);
+    }
+
+    #[test]
+    fn each_channel_is_a_whole_tone_higher() {
+        let test = ChannelTest::new(440.0, 500, 2, 48000.0);
+        assert!((test.channel_frequency(1) / test.channel_frequency(0) - 2f32.powf(2.0 / 12.0)).abs() < 1e-6);
+    }
+}
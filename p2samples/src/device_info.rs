@@ -0,0 +1,68 @@
+// The device and config that ended up being picked, printed as JSON for scripts
+
+use cpal::{SupportedBufferSize, SupportedStreamConfig};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct DeviceConfig {
+    pub device: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_format: String,
+    // Frames per buffer the device allows, None when it doesn't say
+    pub buffer_size_min: Option<u32>,
+    pub buffer_size_max: Option<u32>,
+}
+
+impl DeviceConfig {
+    pub fn new(device: String, conf: &SupportedStreamConfig) -> Self {
+        let (buffer_size_min, buffer_size_max) = match conf.buffer_size() {
+            SupportedBufferSize::Range { min, max } => (Some(*min), Some(*max)),
+            SupportedBufferSize::Unknown => (None, None),
+        };
+        Self {
+            device,
+            sample_rate: conf.sample_rate().0,
+            channels: conf.channels(),
+            sample_format: conf.sample_format().to_string(),
+            buffer_size_min,
+            buffer_size_max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{SampleFormat, SampleRate};
+
+    #[test]
+    fn config_serializes_every_field() {
+        let conf = SupportedStreamConfig::new(
+            2,
+            SampleRate(48000),
+            SupportedBufferSize::Range { min: 64, max: 4096 },
+            SampleFormat::F32,
+        );
+        let info = DeviceConfig::new("Mock".to_string(), &conf);
+        let json = serde_json::to_string(&info).unwrap();
+        for field in [
+            "\"device\":\"Mock\"",
+            "\"sample_rate\":48000",
+            "\"channels\":2",
+            "\"sample_format\":\"f32\"",
+            "\"buffer_size_min\":64",
+            "\"buffer_size_max\":4096",
+        ] {
+            assert!(json.contains(field), "{} missing from {}", field, json);
+        }
+    }
+
+    #[test]
+    fn unknown_buffer_size_is_none() {
+        let conf = SupportedStreamConfig::new(1, SampleRate(44100), SupportedBufferSize::Unknown, SampleFormat::I16);
+        let info = DeviceConfig::new("Mock".to_string(), &conf);
+        assert_eq!((info.buffer_size_min, info.buffer_size_max), (None, None));
+        assert_eq!(info.sample_format, "i16");
+    }
+}
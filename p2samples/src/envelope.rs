@@ -0,0 +1,123 @@
+// Shape of the decay segment
+// The envelope is attack/decay only, the decay runs straight to silence so it doubles as the release (there's no sustain to release from)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvShape {
+    // Straight line to 0 over the decay time
+    Linear,
+    // e^(-t / decay), the decay time is the time constant (down to ~37% after one)
+    // Closer to how we hear things fade, cut to 0 once it's below -60dB
+    Exponential,
+}
+
+impl EnvShape {
+    // "linear" or "exponential"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "linear" => Ok(EnvShape::Linear),
+            "exponential" => Ok(EnvShape::Exponential),
+            other => Err(format!("unknown envelope shape '{}', expected linear or exponential", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for EnvShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EnvShape::Linear => write!(f, "linear"),
+            EnvShape::Exponential => write!(f, "exponential"),
+        }
+    }
+}
+
+// -60dB, where an exponential decay is considered done
+const EXPONENTIAL_FLOOR: f32 = 0.001;
+
+// Attack/decay envelope, ramps linearly 0 -> 1 over the attack then down to 0 over the decay (see EnvShape)
+pub struct Envelope {
+    attack: usize,
+    decay: usize,
+    decay_shape: EnvShape,
+}
+
+impl Envelope {
+    pub fn new(attack_ms: f32, decay_ms: f32, decay_shape: EnvShape, sample_rate: f32) -> Self {
+        let to_samples = |ms: f32| (ms.max(0.0) / 1000.0 * sample_rate).round() as usize;
+        Self {
+            attack: to_samples(attack_ms),
+            decay: to_samples(decay_ms),
+            decay_shape,
+        }
+    }
+
+    // Envelope value `n` samples after the note started
+    pub fn value(&self, n: usize) -> f32 {
+        if n < self.attack {
+            return n as f32 / self.attack as f32;
+        }
+        // The peak is always reached, even with no decay time at all
+        if n == self.attack {
+            return 1.0;
+        }
+        let t = (n - self.attack) as f32;
+        match self.decay_shape {
+            EnvShape::Linear if t < self.decay as f32 => 1.0 - t / self.decay as f32,
+            EnvShape::Exponential if self.decay > 0 => {
+                let value = (-t / self.decay as f32).exp();
+                if value > EXPONENTIAL_FLOOR {
+                    value
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attack_ramps_from_zero_to_a_peak_of_one() {
+        // 10ms attack at 1kHz is 10 samples
+        let env = Envelope::new(10.0, 200.0, EnvShape::Linear, 1000.0);
+        assert_eq!(env.value(0), 0.0);
+        for n in 1..=10 {
+            assert!(env.value(n) > env.value(n - 1));
+        }
+        let peak = (0..1000).map(|n| env.value(n)).fold(0_f32, f32::max);
+        assert_eq!(peak, 1.0);
+    }
+
+    #[test]
+    fn zero_decay_still_peaks_at_one() {
+        for shape in [EnvShape::Linear, EnvShape::Exponential] {
+            let env = Envelope::new(10.0, 0.0, shape, 1000.0);
+            assert_eq!(env.value(10), 1.0);
+            assert_eq!(env.value(11), 0.0);
+            assert_eq!(Envelope::new(0.0, 0.0, shape, 1000.0).value(0), 1.0);
+        }
+    }
+
+    #[test]
+    fn decay_shapes() {
+        // 100ms decay at 1kHz, no attack
+        let exponential = Envelope::new(0.0, 100.0, EnvShape::Exponential, 1000.0);
+        assert!((exponential.value(100) - (-1_f32).exp()).abs() < 1e-4);
+        let linear = Envelope::new(0.0, 100.0, EnvShape::Linear, 1000.0);
+        assert_eq!(linear.value(50), 0.5);
+        assert_eq!(linear.value(100), 0.0);
+        // Cut off once it's below -60dB
+        assert_eq!(exponential.value(700), 0.0);
+    }
+
+    #[test]
+    fn shapes_parse_back_from_their_names() {
+        for shape in [EnvShape::Linear, EnvShape::Exponential] {
+            assert_eq!(EnvShape::parse(&shape.to_string()), Ok(shape));
+        }
+        assert_eq!(EnvShape::parse(" Exponential "), Ok(EnvShape::Exponential));
+        assert!(EnvShape::parse("log").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+// Effects on the generated samples
+
+// Triangular wave folder, the input is amplified by `gain` and anything past +-1 is reflected back in
+// A sine folded a few times gets lots of extra harmonics (West Coast style)
+pub struct WaveFolder {
+    gain: f32,
+}
+
+impl WaveFolder {
+    pub fn new(gain: f32) -> Self {
+        Self { gain }
+    }
+
+    pub fn process(&self, input: f32) -> f32 {
+        // Triangle wave through (-1, -1), (1, 1), (3, -1), ... which is the same as reflecting at +-1 forever
+        let t = (input * self.gain + 1.0) / 4.0;
+        1.0 - 4.0 * (t - t.floor() - 0.5).abs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unity_fold_passes_the_signal() {
+        let folder = WaveFolder::new(1.0);
+        for n in -100..=100 {
+            let x = n as f32 / 100.0;
+            assert!((folder.process(x) - x).abs() < 1e-6, "{}", x);
+        }
+    }
+
+    #[test]
+    fn folding_a_sine_adds_peaks() {
+        let folder = WaveFolder::new(3.0);
+        // One half cycle of a sine, 1000 samples
+        let out: Vec<f32> = (0..=1000)
+            .map(|n| folder.process((std::f32::consts::PI * n as f32 / 1000.0).sin()))
+            .collect();
+        let peaks = out.windows(3).filter(|w| w[1] > w[0] && w[1] >= w[2]).count();
+        let troughs = out.windows(3).filter(|w| w[1] < w[0] && w[1] <= w[2]).count();
+        // Up to 3 folds back to -1 at the top, so two peaks either side of a trough
+        assert_eq!(peaks, 2);
+        assert_eq!(troughs, 1);
+        assert!(out.iter().all(|v| v.abs() <= 1.0 + 1e-6));
+    }
+}
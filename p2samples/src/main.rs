@@ -1,79 +1,323 @@
 use std::fs::File;
 use std::io::Write;
 
+use common::fade::{fade_out_gain, Faded, PLAY_MS};
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::pan::pan;
+use common::preview::{play_preview, preview_arrays, PreviewFn};
+use common::rng::{resolve_seed, seed_from_time};
+use common::safety::{no_limit_from_args, ClipGuard, DEFAULT_CEILING_DBFS};
+use common::sample_format::{force_sample_format, sample_format_from_args};
+use common::setup::{self, SetupError};
+use common::waveform::WaveformName;
 use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
+    traits::{DeviceTrait, StreamTrait},
     Device, FromSample, Sample, SizedSample, StreamConfig, SupportedStreamConfig,
+    SupportedStreamConfigRange,
 };
+use channel_test::ChannelTest;
+use device_info::DeviceConfig;
+use envelope::{EnvShape, Envelope};
+use fx::WaveFolder;
+use notes::note_with_cents;
+use osc::{Oscillator, PluckedString};
+use tone_test::{rms_dbfs, ToneTest};
+use wavetable::Interpolation;
 
-fn setup_default_device_default_config(quiet: bool) -> (Device, SupportedStreamConfig) {
-    if !quiet {
-        eprintln!("SETUP");
-    }
-    let host = cpal::default_host();
-    let dev = host
-        .default_output_device()
-        .expect("Did not find output audio device!");
-    if !quiet {
-        eprintln!(
-            "- Output device: {}",
-            dev.name().expect("Device lacks a name..?")
-        );
-    }
+mod channel_test;
+mod device_info;
+mod envelope;
+mod fx;
+mod notes;
+mod osc;
+mod oversample;
+mod tone_test;
+mod wavetable;
 
-    let conf = dev
-        .default_output_config()
-        .expect("Did not find default output config for device!");
-    if !quiet {
-        eprintln!("- Default output config: {:?}", conf);
-    }
+// Default config with its channel count swapped for `channels`
+// Needs a supported range with that many channels, the same sample format and the default sample rate
+fn config_with_channels(
+    default: &SupportedStreamConfig,
+    supported: &[SupportedStreamConfigRange],
+    channels: u16,
+) -> Result<SupportedStreamConfig, String> {
+    let rate = default.sample_rate();
+    supported
+        .iter()
+        .find(|r| {
+            r.channels() == channels
+                && r.sample_format() == default.sample_format()
+                && r.min_sample_rate() <= rate
+                && rate <= r.max_sample_rate()
+        })
+        .map(|r| r.with_sample_rate(rate))
+        .ok_or_else(|| {
+            format!(
+                "{} channels at {}Hz ({}) is not supported by the device",
+                channels,
+                rate.0,
+                default.sample_format()
+            )
+        })
+}
 
-    let supported = dev
-        .supported_output_configs()
-        .expect("Could not list supported configs");
-    if !quiet {
-        eprintln!("- Supported output configs:");
-        for (n, c) in supported.enumerate() {
-            eprintln!("  {}. {:?}", n, c);
+// The shared setup, then `channels` instead of the default channel count when it's given
+fn setup_default_device_default_config(
+    log_level: LogLevel,
+    channels: Option<u16>,
+) -> Result<(Device, SupportedStreamConfig), SetupError> {
+    log(log_level, "SETUP");
+    let (dev, conf, supported) = setup::setup_default_device_default_config(log_level)?;
+    let conf = match channels {
+        Some(channels) if channels != conf.channels() => {
+            let conf = config_with_channels(&conf, &supported, channels)
+                .unwrap_or_else(|e| panic!("Can't use the requested channel count: {}", e));
+            log(log_level, &format!("- Overriding channels: {:?}", conf));
+            conf
         }
-    }
-    (dev, conf)
+        _ => conf,
+    };
+    Ok((dev, conf))
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 enum WaveformKind {
     Sine,
     Square,
     Sawtooth,
     Triangle,
+    // Additive synthesis, index k is the amplitude of harmonic k + 1
+    Harmonics(Vec<f32>),
+    // One period of samples, stretched/squashed to the frequency, read between samples with the interpolation
+    Wavetable(Vec<f32>, Interpolation),
+    // Sawtooth from its first N harmonics (1/k amplitude), harmonics above nyquist are left out so it can't alias
+    // More harmonics is brighter, it heads towards Sawtooth (with a ~9% overshoot at the jump)
+    SawHarmonics(u32),
+    // Sawtooth with its phase bent through phase^shape before the ramp, 1.0 is the plain Sawtooth
+    // Above 1 it holds near the top then curves down (darker), below 1 it drops steeply after the jump (brighter)
+    ShapedSaw(f32),
+}
+
+impl std::fmt::Display for WaveformKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WaveformKind::Sine => write!(f, "sine"),
+            WaveformKind::Square => write!(f, "square"),
+            WaveformKind::Sawtooth => write!(f, "sawtooth"),
+            WaveformKind::Triangle => write!(f, "triangle"),
+            WaveformKind::Harmonics(amplitudes) => write!(f, "harmonics {:?}", amplitudes),
+            WaveformKind::Wavetable(table, interpolation) => {
+                write!(f, "wavetable ({} samples, {})", table.len(), interpolation)
+            }
+            WaveformKind::SawHarmonics(count) => write!(f, "sawtooth ({} harmonics)", count),
+            WaveformKind::ShapedSaw(shape) => write!(f, "sawtooth (shape {})", shape),
+        }
+    }
+}
+
+// The shared names in any case ("SAW", "sawtooth", ...)
+impl std::str::FromStr for WaveformKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse::<WaveformName>()? {
+            WaveformName::Sine => Ok(WaveformKind::Sine),
+            WaveformName::Square => Ok(WaveformKind::Square),
+            WaveformName::Sawtooth => Ok(WaveformKind::Sawtooth),
+            WaveformName::Triangle => Ok(WaveformKind::Triangle),
+            other => Err(format!("{} isn't one of 2: Samples's waveforms, expected sine, square, saw/sawtooth or triangle", other)),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 struct RunArgs {
-    quiet: bool,
+    log_level: LogLevel,
     frequency: f32,
+    // Note name (e.g. "A4") plus cents, replaces `frequency` when set
+    note: Option<String>,
+    cents: f32,
     waveform: WaveformKind,
+    // Single cycle text file (samples.txt format) played as a wavetable, replaces `waveform` when set
+    waveform_file: Option<String>,
+    // How the wavetable is read between its samples, "nearest", "linear" or "cubic"
+    interp: Interpolation,
     generate_arrays: bool,
+    // Harmonic count of a band-limited additive sawtooth, replaces `waveform` when set
+    saw_harmonics: Option<u32>,
+    // Power curve applied to the sawtooth's phase (above 0), replaces `waveform` when set
+    saw_shape: Option<f32>,
+    // Odd/even balance of a Harmonics waveform, -1 (only odd, hollow) ..= 1 (only even, fuller), 0 leaves it alone
+    harmonic_tilt: f32,
+    // Karplus-Strong plucked string instead of the waveform
+    pluck: bool,
+    damping: f32,
+    // Wave folder gain, folds the waveform back on itself past +-1
+    fold: Option<f32>,
+    // Stereo position -1 (left) ..= 1 (right), constant power, 0 is centered
+    pan: f32,
+    // Attack/decay envelope on the note
+    envelope: bool,
+    attack_ms: f32,
+    decay_ms: f32,
+    decay_shape: EnvShape,
+    // Keep playing until Enter is pressed instead of for a fixed duration
+    hold: bool,
+    // Ramp to silence over the end of the fixed duration so stopping doesn't click (not with `hold`)
+    fade_out_ms: f32,
+    // Plays this much of the generated arrays through the device before writing them, 0 for no preview
+    preview_ms: f32,
+    // Play the sound this many times, with `gap_ms` of silence in between
+    repeat: usize,
+    gap_ms: u64,
+    // Skip the audio device entirely and only generate arrays at `sample_rate`
+    no_device: bool,
+    sample_rate: u32,
+    // Output channel count instead of the device default, must be supported at the default sample rate
+    channels: Option<u16>,
+    // Render arrays at this many times the sample rate then filter back down, 1 is off
+    oversample: usize,
+    // Length of the generated arrays instead of a period (a second for pluck/envelope), not both
+    // duration_ms rounds to the nearest sample, duration_samples is exact (FFT windows, checksums)
+    duration_ms: Option<f32>,
+    duration_samples: Option<usize>,
+    // Print the picked device and config as JSON (stdout) then exit without playing
+    print_config_json: bool,
+    // Play a tone on each output channel in turn (`channels_test_ms` each) to find which speaker is which
+    channels_test: bool,
+    channels_test_ms: u64,
+    // Play a calibrated reference signal ("1k", "997" or "pink") at full volume for `tone_test_ms` instead
+    tone_test: Option<String>,
+    tone_test_ms: u64,
+    // Seed of the pluck's noise burst and the pink noise, None picks one from the clock
+    // main prints the seed that's used, passing it back here repeats the exact same output
+    seed: Option<u32>,
+    // Every sample is clamped to this before it's played, `no_limit` (only from --no-limit on the command line) bypasses it
+    limit_ceiling_dbfs: f32,
+    no_limit: bool,
+    // Output sample format instead of the device default, only from --sample-format on the command line
+    sample_format: Option<cpal::SampleFormat>,
 }
 
 impl RunArgs {
-    fn new(quiet: bool, frequency: f32, waveform: WaveformKind, generate_arrays: bool) -> Self {
+    fn new(log_level: LogLevel, frequency: f32, waveform: WaveformKind, generate_arrays: bool) -> Self {
         Self {
-            quiet,
+            log_level,
             frequency,
             waveform,
             generate_arrays,
+            ..Default::default()
         }
     }
+
+    // Human readable summary for the startup output
+    fn describe(&self) -> String {
+        let mut out = if self.pluck {
+            format!("Plucked string @ {} Hz (damping {})", self.frequency, self.damping)
+        } else {
+            format!("Waveform: {} @ {} Hz", self.waveform, self.frequency)
+        };
+        if let Some(path) = self.waveform_file.as_ref() {
+            out += &format!(" (from {})", path);
+        }
+        if let Some(note) = self.note.as_ref() {
+            out += &format!(" ({} {:+} cents)", note, self.cents);
+        }
+        if self.harmonic_tilt != 0.0 {
+            out += &format!("; Harmonic tilt: {}", self.harmonic_tilt);
+        }
+        if let Some(gain) = self.fold {
+            out += &format!("; Fold: {}x", gain);
+        }
+        if self.pan != 0.0 {
+            out += &format!("; Pan: {}", self.pan);
+        }
+        if self.envelope {
+            out += &format!(
+                "; Envelope: attack {} ms, {} decay {} ms",
+                self.attack_ms, self.decay_shape, self.decay_ms
+            );
+        }
+        if self.repeat > 1 {
+            out += &format!("; Repeat: {}x, {} ms gap", self.repeat, self.gap_ms);
+        }
+        if let Some(channels) = self.channels {
+            out += &format!("; Channels: {}", channels);
+        }
+        if self.channels_test {
+            out += &format!("; Channel test: {} ms per channel", self.channels_test_ms);
+        }
+        if let Some(test) = self.tone_test.as_ref() {
+            out += &format!("; Tone test: {} for {} ms", test, self.tone_test_ms);
+        }
+        if self.oversample > 1 {
+            out += &format!("; Oversampling: {}x", self.oversample);
+        }
+        if let Some(ms) = self.duration_ms {
+            out += &format!("; Duration: {} ms", ms);
+        }
+        if let Some(samples) = self.duration_samples {
+            out += &format!("; Duration: {} samples", samples);
+        }
+        if self.no_device {
+            out += &format!("; Generating arrays without a device @ {} Hz", self.sample_rate);
+        } else if self.generate_arrays {
+            out += "; Generating arrays";
+        }
+        if self.no_limit {
+            out += "; Clip guard OFF";
+        } else if self.limit_ceiling_dbfs != DEFAULT_CEILING_DBFS {
+            out += &format!("; Clip guard ceiling: {} dBFS", self.limit_ceiling_dbfs);
+        }
+        if let Some(format) = self.sample_format {
+            out += &format!("; Sample format: {} (forced)", format);
+        }
+        out
+    }
 }
 
 impl Default for RunArgs {
     fn default() -> Self {
         Self {
-            quiet: false,
+            log_level: LogLevel::Normal,
             frequency: 440.0,
+            note: None,
+            cents: 0.0,
             waveform: WaveformKind::Sine,
+            waveform_file: None,
+            interp: Interpolation::Linear,
+            saw_harmonics: None,
+            saw_shape: None,
             generate_arrays: false,
+            harmonic_tilt: 0.0,
+            pluck: false,
+            damping: 0.5,
+            fold: None,
+            pan: 0.0,
+            envelope: false,
+            attack_ms: 10.0,
+            decay_ms: 200.0,
+            decay_shape: EnvShape::Linear,
+            hold: false,
+            fade_out_ms: 20.0,
+            preview_ms: 0.0,
+            repeat: 1,
+            gap_ms: 200,
+            no_device: false,
+            sample_rate: 44100,
+            channels: None,
+            oversample: 1,
+            duration_ms: None,
+            duration_samples: None,
+            print_config_json: false,
+            channels_test: false,
+            channels_test_ms: 1000,
+            tone_test: None,
+            tone_test_ms: 3000,
+            seed: None,
+            limit_ceiling_dbfs: DEFAULT_CEILING_DBFS,
+            no_limit: false,
+            sample_format: None,
         }
     }
 }
@@ -95,34 +339,42 @@ fn dispatch_run(dev: &Device, conf: SupportedStreamConfig, args: &RunArgs) {
     }
 }
 
-fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args: &RunArgs) {
-    // Initialize constants
-    let sample_rate = conf.sample_rate.0 as f32;
-    let channels = conf.channels as usize;
-    let frequency = args.frequency;
-    if !args.quiet {
-        eprintln!("RUN");
-        eprintln!("- Frequency: {}Hz", frequency);
-    }
+// Scales the harmonics of `amplitudes` (index k is harmonic k + 1) by `tilt` -1..=1
+// Negative turns the even harmonics down (gone at -1), positive the odd ones above the fundamental (gone at 1)
+// The fundamental always stays so the pitch doesn't jump an octave
+fn tilt_harmonics(amplitudes: &[f32], tilt: f32) -> Vec<f32> {
+    let tilt = tilt.clamp(-1.0, 1.0);
+    amplitudes
+        .iter()
+        .enumerate()
+        .map(|(k, a)| match k + 1 {
+            1 => *a,
+            harmonic if harmonic % 2 == 0 => a * (1.0 + tilt.min(0.0)),
+            _ => a * (1.0 - tilt.max(0.0)),
+        })
+        .collect()
+}
+
+type WaveformFn = Box<dyn Fn(f32, f32, f32) -> f32 + Send>;
 
-    // Initialize sample generator
-    let next_value: fn(f32, f32, f32) -> f32 = match args.waveform {
-        WaveformKind::Sine => |sample_clock, sample_rate, frequency| {
+fn waveform_fn(waveform: &WaveformKind) -> WaveformFn {
+    match waveform {
+        WaveformKind::Sine => Box::new(|sample_clock, sample_rate, frequency| {
             ((2.0 * std::f32::consts::PI * frequency * sample_clock) / sample_rate).sin()
-        },
-        WaveformKind::Square => |sample_clock, sample_rate, frequency| {
+        }),
+        WaveformKind::Square => Box::new(|sample_clock, sample_rate, frequency| {
             let period = sample_rate / frequency;
             if (sample_clock % period) < (period / 2.0) {
                 1.0
             } else {
                 -1.0
             }
-        },
-        WaveformKind::Sawtooth => |sample_clock, sample_rate, frequency| {
+        }),
+        WaveformKind::Sawtooth => Box::new(|sample_clock, sample_rate, frequency| {
             let period = sample_rate / frequency;
             1_f32 - (2_f32 * (sample_clock % period) / period)
-        },
-        WaveformKind::Triangle => |sample_clock, sample_rate, frequency| {
+        }),
+        WaveformKind::Triangle => Box::new(|sample_clock, sample_rate, frequency| {
             let period = sample_rate / frequency;
             let normalized_location = (sample_clock % period) / period;
             if normalized_location < 0.5 {
@@ -130,34 +382,241 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
             } else {
                 1_f32 - 4_f32 * (normalized_location - 0.5_f32)
             }
-        },
-    };
+        }),
+        WaveformKind::Harmonics(amplitudes) => {
+            // Normalize by the worst case (every harmonic peaking together) so the sum can't clip
+            let total: f32 = amplitudes.iter().map(|a| a.abs()).sum();
+            let amplitudes: Vec<f32> = if total > 0.0 {
+                amplitudes.iter().map(|a| a / total).collect()
+            } else {
+                vec![]
+            };
+            Box::new(move |sample_clock, sample_rate, frequency| {
+                let period = sample_rate / frequency;
+                let normalized_location = (sample_clock % period) / period;
+                amplitudes
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| {
+                        a * (2.0 * std::f32::consts::PI * (k + 1) as f32 * normalized_location).sin()
+                    })
+                    .sum()
+            })
+        }
+        WaveformKind::SawHarmonics(count) => {
+            let count = *count;
+            Box::new(move |sample_clock, sample_rate, frequency| {
+                let period = sample_rate / frequency;
+                let normalized_location = (sample_clock % period) / period;
+                // Fourier series of 1 - 2x (the falling Sawtooth above)
+                let below_nyquist = ((sample_rate / 2.0) / frequency).ceil() as u32 - 1;
+                let sum: f32 = (1..=count.min(below_nyquist))
+                    .map(|k| (2.0 * std::f32::consts::PI * k as f32 * normalized_location).sin() / k as f32)
+                    .sum();
+                sum * 2.0 / std::f32::consts::PI
+            })
+        }
+        WaveformKind::ShapedSaw(shape) => {
+            let shape = *shape;
+            Box::new(move |sample_clock, sample_rate, frequency| {
+                let period = sample_rate / frequency;
+                let normalized_location = (sample_clock % period) / period;
+                1_f32 - 2_f32 * normalized_location.powf(shape)
+            })
+        }
+        WaveformKind::Wavetable(table, interpolation) => {
+            let table = table.clone();
+            let interpolation = *interpolation;
+            Box::new(move |sample_clock, sample_rate, frequency| {
+                let period = sample_rate / frequency;
+                wavetable::sample_at(&table, (sample_clock % period) / period, interpolation)
+            })
+        }
+    }
+}
 
-    // Generate one second worth of samples, write to a file then exit
-    if args.generate_arrays {
-        let mut vals: Vec<f32> = Vec::with_capacity(conf.sample_rate.0 as usize);
-        for sample_num in 0..(conf.sample_rate.0 as usize / frequency as usize + 1) {
-            vals.push(next_value(sample_num as f32, sample_rate, frequency));
+// Writes one value per frame duplicated to every channel, this is what the cpal callback does
+// `gains` are the [left, right] levels from the pan, the second channel gets right and every other channel left
+fn write_frames<T: Sample + FromSample<f32>>(
+    output: &mut [T],
+    channels: usize,
+    gains: [f32; 2],
+    safety: &ClipGuard,
+    next_sample: &mut dyn FnMut() -> f32,
+) {
+    for frame in output.chunks_mut(channels) {
+        let value = next_sample();
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            let gain = if channel == 1 { gains[1] } else { gains[0] };
+            *sample = safety.process(value * gain).to_sample::<T>();
         }
-        let mut file = File::create("samples.txt").expect("Failed to create file!");
-        for val in vals {
-            write!(file, "{} ", val).expect("Failed to write file!");
+    }
+}
+
+// [left, right] gains for the pan position, a mono output is left alone
+fn pan_gains(channels: usize, position: f32) -> [f32; 2] {
+    if channels >= 2 {
+        pan([1.0, 1.0], position)
+    } else {
+        [1.0, 1.0]
+    }
+}
+
+// Drives a sample source into an owned interleaved buffer the same way the cpal callback does
+// Lets the channel handling be checked without any hardware, and generate_arrays renders through it too
+// so it isn't test only
+fn render_to_buffer(next_sample: &mut dyn FnMut() -> f32, frames: usize, channels: usize) -> Vec<f32> {
+    let mut buffer = vec![0_f32; frames * channels];
+    // Bypassed, the buffer is only checked and written to a file, never played
+    write_frames(&mut buffer, channels, [1.0, 1.0], &ClipGuard::new(0.0, true), next_sample);
+    buffer
+}
+
+// Generate one second worth of samples and write to a file
+// Doesn't need an audio device, only the sample rate
+fn generate_arrays(sample_rate: u32, args: &RunArgs, preview: Option<PreviewFn>) {
+    let vals = render_arrays(sample_rate, args);
+    preview_arrays(&vals, args.preview_ms, preview, args.log_level);
+    let mut file = File::create("samples.txt").expect("Failed to create file!");
+    for val in vals {
+        write!(file, "{} ", val).expect("Failed to write file!");
+    }
+    file.flush().expect("Failed to flush the file buffer");
+    eprintln!("FILE WRITE SUCCESS... EXITING");
+}
+
+// Number of samples to generate, `default` unless a duration was given
+fn render_len(sample_rate: u32, args: &RunArgs, default: usize) -> Result<usize, String> {
+    match (args.duration_ms, args.duration_samples) {
+        (Some(_), Some(_)) => Err("duration_ms and duration_samples can't both be set".to_string()),
+        (Some(ms), None) if ms < 0.0 => Err(format!("duration_ms can't be negative, got {}", ms)),
+        (Some(ms), None) => Ok((ms / 1000.0 * sample_rate as f32).round() as usize),
+        (None, Some(samples)) => Ok(samples),
+        (None, None) => Ok(default),
+    }
+}
+
+// What generate_arrays writes, without touching any file
+fn render_arrays(sample_rate: u32, args: &RunArgs) -> Vec<f32> {
+    let frequency = args.frequency;
+    let next_value = waveform_fn(&args.waveform);
+    // Decaying sounds change over time so a single period isn't interesting, take the whole second
+    let default_len = if args.pluck || args.envelope {
+        sample_rate as usize
+    } else {
+        sample_rate as usize / frequency as usize + 1
+    };
+    let len = render_len(sample_rate, args, default_len).unwrap_or_else(|e| panic!("Invalid duration: {}", e));
+    // Oversampling renders everything at the higher rate, the decimated result is at `sample_rate`
+    let factor = args.oversample.max(1);
+    let render_rate = (sample_rate as usize * factor) as f32;
+    let mut pluck = if args.pluck {
+        Some(PluckedString::new(render_rate, frequency, args.damping, args.seed.unwrap_or_else(seed_from_time)))
+    } else {
+        None
+    };
+    let folder = args.fold.map(WaveFolder::new);
+    let mut sample_num = 0_usize;
+    let mut next_sample = || {
+        let mut acc = match pluck.as_mut() {
+            Some(string) => string.next_sample(),
+            None => next_value(sample_num as f32, render_rate, frequency),
+        };
+        if let Some(folder) = folder.as_ref() {
+            acc = folder.process(acc);
         }
-        file.flush().expect("Failed to flush the file buffer");
-        eprintln!("FILE WRITE SUCCESS... EXITING");
-        return;
+        sample_num += 1;
+        acc
+    };
+    let start = std::time::Instant::now();
+    let mut vals = render_to_buffer(&mut next_sample, len * factor, 1);
+    if factor > 1 {
+        vals = oversample::decimate(&vals, factor);
     }
+    log(args.log_level, &format!("- Rendered {} samples ({}x oversampled) in {:?}", len, factor, start.elapsed()));
+    if args.envelope {
+        let envelope = Envelope::new(args.attack_ms, args.decay_ms, args.decay_shape, sample_rate as f32);
+        for (n, val) in vals.iter_mut().enumerate() {
+            *val *= envelope.value(n);
+        }
+    }
+    vals
+}
+
+// Tone on channel 0, then 1, ... with the others silent
+fn run_channels_test<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args: &RunArgs) {
+    let sample_rate = conf.sample_rate.0 as f32;
+    let channels = conf.channels as usize;
+    let test = ChannelTest::new(args.frequency, args.channels_test_ms, channels, sample_rate);
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("CHANNEL TEST");
+        eprintln!("- {} channels, {}ms each", channels, args.channels_test_ms);
+    }
+    let frequencies: Vec<f32> = (0..channels).map(|c| test.channel_frequency(c)).collect();
 
     let volume = 0.5;
-    let mut sample_clock = 0f32;
+    let safety = ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit);
+    let mut elapsed = 0_usize;
+    let mut values = vec![0_f32; channels];
+    verbose(args.log_level, &format!("- Stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(args.log_level, "Output", conf.sample_rate.0);
+    let stream = dev
+        .build_output_stream(
+            &conf,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                stats.record(data.len() / channels);
+                for frame in data.chunks_mut(channels) {
+                    test.fill_frame(elapsed, &mut values);
+                    elapsed += 1;
+                    for (sample, value) in frame.iter_mut().zip(values.iter()) {
+                        *sample = safety.process(value * volume).to_sample::<T>();
+                    }
+                }
+            },
+            |err| eprintln!("An error occurred during output stream: {}", err),
+            None,
+        )
+        .expect("Failed to create stream!");
+
+    stream.play().expect("Failed to play the stream!");
+    // Follows along with the callback, close enough to tell which channel is playing
+    for (channel, frequency) in frequencies.iter().enumerate() {
+        eprintln!("- Channel {} ({:.1}Hz)", channel, frequency);
+        std::thread::sleep(std::time::Duration::from_millis(args.channels_test_ms));
+    }
+    // Some backends can't pause, dropping the stream stops it either way
+    if let Err(e) = stream.pause() {
+        verbose(args.log_level, &format!("- Could not pause the stream ({}), dropping it", e));
+    }
+}
+
+// Loops the reference signal on every channel at its calibrated level, no volume scaling or fades
+fn run_tone_test<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, test: ToneTest, args: &RunArgs) {
+    // The clip guard would square off the full scale sine's peaks, a distorted reference is no reference
+    if test == ToneTest::Sine997 && !args.no_limit {
+        panic!("The {} is 0 dBFS, above the {} dBFS clip guard ceiling, it needs --no-limit", test, args.limit_ceiling_dbfs);
+    }
+    let channels = conf.channels as usize;
+    let samples = test.render(conf.sample_rate.0, args.seed.unwrap_or_else(seed_from_time));
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("TONE TEST");
+        eprintln!("- {} at {} dBFS (measured {:.2} dBFS RMS, AES17)", test, test.level_dbfs(), rms_dbfs(&samples));
+        if test == ToneTest::Sine997 {
+            eprintln!("- WARNING: Full scale, turn your speakers down first!");
+        }
+    }
+    let safety = ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit);
+    let mut n = 0_usize;
+    verbose(args.log_level, &format!("- Stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(args.log_level, "Output", conf.sample_rate.0);
     let stream = dev
         .build_output_stream(
             &conf,
             move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                stats.record(data.len() / channels);
                 for frame in data.chunks_mut(channels) {
-                    let value: T = (next_value(sample_clock, sample_rate, frequency) * volume)
-                        .to_sample::<T>();
-                    sample_clock = (sample_clock + 1.0) % sample_rate;
+                    let value: T = safety.process(samples[n % samples.len()]).to_sample::<T>();
+                    n += 1;
                     for sample in frame.iter_mut() {
                         *sample = value;
                     }
@@ -169,16 +628,561 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
         .expect("Failed to create stream!");
 
     stream.play().expect("Failed to play the stream!");
+    std::thread::sleep(std::time::Duration::from_millis(args.tone_test_ms));
+    // Some backends can't pause, dropping the stream stops it either way
+    if let Err(e) = stream.pause() {
+        verbose(args.log_level, &format!("- Could not pause the stream ({}), dropping it", e));
+    }
+}
 
-    std::thread::sleep(std::time::Duration::from_millis(1000));
+// Calls `play` `repeat` times (at least once) with `gap_ms` of silence between them, none after the last
+fn repeat_with_gaps(repeat: usize, gap_ms: u64, log_level: LogLevel, mut play: impl FnMut()) {
+    let repeat = repeat.max(1);
+    for n in 0..repeat {
+        if repeat > 1 {
+            log(log_level, &format!("- Repeat {}/{}", n + 1, repeat));
+        }
+        play();
+        if n + 1 < repeat {
+            std::thread::sleep(std::time::Duration::from_millis(gap_ms));
+        }
+    }
+}
+
+fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args: &RunArgs) {
+    // Initialize constants
+    let sample_rate = conf.sample_rate.0 as f32;
+    let channels = conf.channels as usize;
+    let frequency = args.frequency;
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("RUN");
+        eprintln!("- Frequency: {}Hz", frequency);
+        if args.pluck {
+            eprintln!("- Plucked string, damping: {}", args.damping);
+        }
+        if let Some(gain) = args.fold {
+            eprintln!("- Wave folder gain: {}", gain);
+        }
+        if args.pan != 0.0 && channels < 2 {
+            eprintln!("- WARNING: Output is mono, the pan is ignored");
+        }
+        if args.envelope {
+            eprintln!(
+                "- Envelope: attack {}ms, {} decay {}ms",
+                args.attack_ms, args.decay_shape, args.decay_ms
+            );
+        }
+    }
+
+    // Generate one second worth of samples, write to a file then exit
+    if args.generate_arrays {
+        generate_arrays(
+            conf.sample_rate.0,
+            args,
+            Some(&|samples: &[f32]| {
+                play_preview::<T>(dev, &conf, samples, args.preview_ms, ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit))
+            }),
+        );
+        return;
+    }
+
+    if args.channels_test {
+        run_channels_test::<T>(dev, conf, args);
+        return;
+    }
+
+    if let Some(test) = args.tone_test.as_ref() {
+        let test = ToneTest::parse(test).unwrap_or_else(|e| panic!("{}", e));
+        run_tone_test::<T>(dev, conf, test, args);
+        return;
+    }
+
+    // Each repeat builds a fresh generator and stream so the phase (and pluck/envelope) restart cleanly
+    verbose(args.log_level, &format!("- Stream config: {:?}", conf));
+    repeat_with_gaps(args.repeat, args.gap_ms, args.log_level, || {
+        // Initialize sample generator
+        let next_value = waveform_fn(&args.waveform);
+        let mut pluck = if args.pluck {
+            Some(PluckedString::new(sample_rate, frequency, args.damping, args.seed.unwrap_or_else(seed_from_time)))
+        } else {
+            None
+        };
+
+        let envelope = if args.envelope {
+            Some(Envelope::new(args.attack_ms, args.decay_ms, args.decay_shape, sample_rate))
+        } else {
+            None
+        };
+        let folder = args.fold.map(WaveFolder::new);
+        let gains = pan_gains(channels, args.pan);
+
+        let volume = 0.5;
+        let safety = ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit);
+        let mut sample_clock = 0f32;
+        // sample_clock wraps every second, the envelope and fade need the total time played
+        let mut elapsed = 0_usize;
+        let total = (sample_rate as u64 * PLAY_MS / 1000) as usize;
+        let fade = if args.hold {
+            0
+        } else {
+            (args.fade_out_ms.max(0.0) / 1000.0 * sample_rate).round() as usize
+        };
+        let faded = Faded::new();
+        let callback_faded = faded.clone();
+        let mut next_sample = move || {
+            let mut acc = match pluck.as_mut() {
+                Some(string) => string.next_sample(),
+                None => next_value(sample_clock, sample_rate, frequency),
+            };
+            if let Some(folder) = folder.as_ref() {
+                acc = folder.process(acc);
+            }
+            if let Some(envelope) = envelope.as_ref() {
+                acc *= envelope.value(elapsed);
+            }
+            acc *= fade_out_gain(elapsed, total, fade);
+            elapsed += 1;
+            if elapsed >= total {
+                callback_faded.set();
+            }
+            sample_clock = (sample_clock + 1.0) % sample_rate;
+            acc * volume
+        };
+        let mut stats = CallbackStats::new(args.log_level, "Output", conf.sample_rate.0);
+        let stream = dev
+            .build_output_stream(
+                &conf,
+                move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                    stats.record(data.len() / channels);
+                    write_frames(data, channels, gains, &safety, &mut next_sample)
+                },
+                |err| eprintln!("An error occurred during output stream: {}", err),
+                None,
+            )
+            .expect("Failed to create stream!");
+
+        stream.play().expect("Failed to play the stream!");
+
+        if args.hold {
+            log(args.log_level, "- Playing, press Enter to stop...");
+            let mut input = String::new();
+            std::io::stdin()
+                .read_line(&mut input)
+                .expect("Failed to read from stdin");
+        } else {
+            faded.wait(PLAY_MS);
+        }
+        // Some backends can't pause, dropping the stream stops it either way
+        if let Err(e) = stream.pause() {
+            verbose(args.log_level, &format!("- Could not pause the stream ({}), dropping it", e));
+        }
+        drop(stream);
+    });
 }
 
 fn main() {
-    let args = RunArgs::new(false, 440.0, WaveformKind::Triangle, false);
-    if !args.quiet {
+    // For additive synthesis use e.g. WaveformKind::Harmonics(vec![1.0, 0.0, 0.5, 0.0, 0.25])
+    let waveform = "triangle".parse().unwrap_or_else(|e| panic!("{}", e));
+    let mut args = RunArgs::new(LogLevel::from_args(std::env::args().skip(1)), 440.0, waveform, false);
+    args.limit_ceiling_dbfs = DEFAULT_CEILING_DBFS;
+    // Only from the command line, it can't be left switched off by an edit here
+    args.no_limit = no_limit_from_args(std::env::args().skip(1));
+    args.sample_format = sample_format_from_args(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{}", e));
+    args.note = None;
+    args.cents = 0.0;
+    if let Some(note) = args.note.as_ref() {
+        args.frequency = note_with_cents(note, args.cents).unwrap_or_else(|e| panic!("Invalid note: {}", e));
+    }
+    args.waveform_file = None;
+    args.interp = Interpolation::parse("linear").unwrap_or_else(|e| panic!("{}", e));
+    if let Some(path) = args.waveform_file.as_ref() {
+        let table = wavetable::load_cycle(path).unwrap_or_else(|e| panic!("Invalid waveform file: {}", e));
+        args.waveform = WaveformKind::Wavetable(table, args.interp);
+    }
+    args.saw_harmonics = None;
+    if let Some(count) = args.saw_harmonics {
+        args.waveform = WaveformKind::SawHarmonics(count);
+    }
+    args.saw_shape = None;
+    if let Some(shape) = args.saw_shape {
+        if shape <= 0.0 {
+            panic!("saw_shape should be above 0, got {}", shape);
+        }
+        args.waveform = WaveformKind::ShapedSaw(shape);
+    }
+    args.harmonic_tilt = 0.0;
+    if args.harmonic_tilt != 0.0 {
+        if !(-1.0..=1.0).contains(&args.harmonic_tilt) {
+            panic!("harmonic_tilt should be in -1..=1, got {}", args.harmonic_tilt);
+        }
+        match &args.waveform {
+            WaveformKind::Harmonics(amplitudes) => {
+                args.waveform = WaveformKind::Harmonics(tilt_harmonics(amplitudes, args.harmonic_tilt));
+            }
+            _ => log(args.log_level, "- WARNING: harmonic_tilt only applies to WaveformKind::Harmonics, ignoring it"),
+        }
+    }
+    args.pluck = false;
+    args.damping = 0.5;
+    args.fold = None;
+    args.pan = 0.0;
+    if !(-1.0..=1.0).contains(&args.pan) {
+        panic!("pan should be in -1..=1, got {}", args.pan);
+    }
+    args.envelope = false;
+    args.attack_ms = 10.0;
+    args.decay_ms = 200.0;
+    args.decay_shape = EnvShape::parse("linear").unwrap_or_else(|e| panic!("{}", e));
+    args.hold = false;
+    args.fade_out_ms = 20.0;
+    args.preview_ms = 0.0;
+    args.repeat = 1;
+    args.gap_ms = 200;
+    args.no_device = false;
+    args.sample_rate = 44100;
+    if !(8000..=192000).contains(&args.sample_rate) {
+        panic!("Invalid sample rate: {}Hz is outside 8000..=192000Hz", args.sample_rate);
+    }
+    args.oversample = 1;
+    args.duration_ms = None;
+    args.duration_samples = None;
+    // Checked up front so both durations being set fails before the device is opened
+    if let Err(e) = render_len(args.sample_rate, &args, 0) {
+        panic!("Invalid duration: {}", e);
+    }
+    args.channels = None;
+    args.print_config_json = false;
+    args.channels_test = false;
+    args.channels_test_ms = 1000;
+    args.tone_test = None;
+    args.tone_test_ms = 3000;
+    let tone_test = args.tone_test.as_ref().map(|test| ToneTest::parse(test).unwrap_or_else(|e| panic!("{}", e)));
+    args.seed = None;
+    // The pluck's noise burst and the pink noise are the random parts
+    args.seed = Some(resolve_seed(args.seed, args.pluck || tone_test == Some(ToneTest::Pink)));
+    if args.log_level >= LogLevel::Normal {
         eprintln!("ARGUMENTS:");
-        eprintln!("- {:?}", args);
+        eprintln!("- {}", args.describe());
+    }
+    if args.no_limit {
+        log(args.log_level, "- WARNING: Clip guard off (--no-limit)");
+    }
+    if args.no_device {
+        if args.log_level >= LogLevel::Normal {
+            eprintln!("NO DEVICE");
+            eprintln!("- Generating arrays at {}Hz", args.sample_rate);
+        }
+        generate_arrays(args.sample_rate, &args, None);
+        return;
+    }
+    let (dev, conf) = setup_default_device_default_config(args.log_level, args.channels)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let conf = force_sample_format(&dev, conf, args.sample_format)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(format) = args.sample_format {
+        log(args.log_level, &format!("- Sample format forced to {}", format));
+    }
+    if args.print_config_json {
+        let info = DeviceConfig::new(dev.name().expect("Device lacks a name..?"), &conf);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&info).expect("Failed to serialize the device config")
+        );
+        return;
     }
-    let (dev, conf) = setup_default_device_default_config(args.quiet);
     dispatch_run(&dev, conf, &args);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Magnitude of harmonic `k` over one 100 sample period of the waveform (10Hz at 1kHz)
+    fn harmonic(wave: &WaveformFn, k: usize) -> f32 {
+        let (re, im) = (0..100).fold((0_f32, 0_f32), |(re, im), n| {
+            let value = wave(n as f32, 1000.0, 10.0);
+            let angle = 2.0 * std::f32::consts::PI * (k * n) as f32 / 100.0;
+            (re + value * angle.cos(), im - value * angle.sin())
+        });
+        (re * re + im * im).sqrt() / 50.0
+    }
+
+    #[test]
+    fn single_harmonic_is_a_sine() {
+        let harmonics = waveform_fn(&WaveformKind::Harmonics(vec![1.0]));
+        let sine = waveform_fn(&WaveformKind::Sine);
+        for n in 0..1000 {
+            assert!((harmonics(n as f32, 1000.0, 10.0) - sine(n as f32, 1000.0, 10.0)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn odd_harmonics_have_a_square_spectrum() {
+        let amplitudes: Vec<f32> = (1..=9).map(|k| if k % 2 == 1 { 1.0 / k as f32 } else { 0.0 }).collect();
+        let wave = waveform_fn(&WaveformKind::Harmonics(amplitudes));
+        let fundamental = harmonic(&wave, 1);
+        // A square wave's harmonics are odd only and fall off as 1/k
+        for k in [3, 5, 7, 9] {
+            assert!((harmonic(&wave, k) / fundamental - 1.0 / k as f32).abs() < 1e-3);
+        }
+        for k in [2, 4, 6, 8] {
+            assert!(harmonic(&wave, k) / fundamental < 1e-3);
+        }
+    }
+
+    #[test]
+    fn waveform_display_is_lowercase() {
+        assert_eq!(WaveformKind::Sine.to_string(), "sine");
+        assert_eq!(WaveformKind::Square.to_string(), "square");
+        assert_eq!(WaveformKind::Sawtooth.to_string(), "sawtooth");
+        assert_eq!(WaveformKind::Triangle.to_string(), "triangle");
+        assert_eq!(WaveformKind::SawHarmonics(8).to_string(), "sawtooth (8 harmonics)");
+    }
+
+    #[test]
+    fn mono_source_is_duplicated_to_every_channel() {
+        let wave = waveform_fn(&WaveformKind::Sine);
+        for channels in [1, 2, 6] {
+            let mut n = 0_f32;
+            let mut next_sample = || {
+                n += 1.0;
+                wave(n, 44100.0, 441.0)
+            };
+            let buffer = render_to_buffer(&mut next_sample, 256, channels);
+            assert_eq!(buffer.len(), 256 * channels);
+            for (i, frame) in buffer.chunks(channels).enumerate() {
+                assert!(frame.iter().all(|v| *v == frame[0]));
+                assert_eq!(frame[0], wave((i + 1) as f32, 44100.0, 441.0));
+            }
+        }
+    }
+
+    fn range(channels: u16, min: u32, max: u32, format: cpal::SampleFormat) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            cpal::SampleRate(min),
+            cpal::SampleRate(max),
+            cpal::SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    fn stereo_f32() -> SupportedStreamConfig {
+        SupportedStreamConfig::new(2, cpal::SampleRate(48000), cpal::SupportedBufferSize::Unknown, cpal::SampleFormat::F32)
+    }
+
+    #[test]
+    fn channel_override_keeps_the_default_rate_and_format() {
+        let supported = [
+            range(2, 8000, 192000, cpal::SampleFormat::F32),
+            range(1, 8000, 192000, cpal::SampleFormat::I16),
+            range(1, 8000, 192000, cpal::SampleFormat::F32),
+        ];
+        let conf = config_with_channels(&stereo_f32(), &supported, 1).unwrap();
+        assert_eq!(conf.channels(), 1);
+        assert_eq!(conf.sample_rate().0, 48000);
+        assert_eq!(conf.sample_format(), cpal::SampleFormat::F32);
+    }
+
+    #[test]
+    fn unsupported_channel_count_is_an_error() {
+        // Mono only in another format or below the default rate
+        let supported = [
+            range(2, 8000, 192000, cpal::SampleFormat::F32),
+            range(1, 8000, 192000, cpal::SampleFormat::I16),
+            range(1, 8000, 44100, cpal::SampleFormat::F32),
+        ];
+        assert!(config_with_channels(&stereo_f32(), &supported, 1).is_err());
+        assert!(config_with_channels(&stereo_f32(), &supported, 6).is_err());
+    }
+
+    #[test]
+    fn repeats_play_the_count_with_gaps_between() {
+        let start = std::time::Instant::now();
+        let mut plays = 0;
+        repeat_with_gaps(3, 20, LogLevel::Silent, || {
+            plays += 1;
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        });
+        assert_eq!(plays, 3);
+        // Three plays and two gaps, no gap after the last
+        let elapsed = start.elapsed().as_millis();
+        assert!((70..500).contains(&elapsed), "{}ms", elapsed);
+    }
+
+    #[test]
+    fn zero_repeats_still_plays_once() {
+        let mut plays = 0;
+        repeat_with_gaps(0, 1000, LogLevel::Silent, || plays += 1);
+        assert_eq!(plays, 1);
+    }
+
+    #[test]
+    fn sine_cycle_file_plays_a_sine_at_the_frequency() {
+        let cycle: Vec<String> = (0..256)
+            .map(|n| (2.0 * std::f32::consts::PI * n as f32 / 256.0).sin().to_string())
+            .collect();
+        let path = std::env::temp_dir().join("p2samples_sine_cycle.txt");
+        std::fs::write(&path, cycle.join(" ")).unwrap();
+        let table = wavetable::load_cycle(path.to_str().unwrap()).unwrap();
+        assert_eq!(table.len(), 256);
+        let wave = waveform_fn(&WaveformKind::Wavetable(table, Interpolation::Linear));
+        for n in 0..44100 {
+            let expected = (2.0 * std::f32::consts::PI * 440.0 * n as f32 / 44100.0).sin();
+            assert!((wave(n as f32, 44100.0, 440.0) - expected).abs() < 1e-3, "sample {}", n);
+        }
+    }
+
+    #[test]
+    fn cycle_needs_two_samples() {
+        assert!(wavetable::parse_cycle("0.5").is_err());
+        assert!(wavetable::parse_cycle("").is_err());
+        assert!(wavetable::parse_cycle("0.5 x").is_err());
+        assert_eq!(wavetable::parse_cycle("0.5 -0.5\n"), Ok(vec![0.5, -0.5]));
+    }
+
+    #[test]
+    fn more_saw_harmonics_get_closer_to_the_saw() {
+        let saw = waveform_fn(&WaveformKind::Sawtooth);
+        // RMS difference over one 100 sample period, leaving out the jump itself where the series is 0
+        let error = |count: u32| {
+            let wave = waveform_fn(&WaveformKind::SawHarmonics(count));
+            let sum: f32 = (1..100).map(|n| (wave(n as f32, 1000.0, 10.0) - saw(n as f32, 1000.0, 10.0)).powi(2)).sum();
+            (sum / 99.0).sqrt()
+        };
+        assert!(error(4) > error(16) && error(16) > error(48), "{} {} {}", error(4), error(16), error(48));
+        assert!(error(48) < 0.1);
+    }
+
+    #[test]
+    fn saw_harmonics_stop_below_nyquist() {
+        // 10Hz at 1kHz, harmonic 49 (490Hz) is the last one below 500Hz
+        let capped = waveform_fn(&WaveformKind::SawHarmonics(49));
+        let asked = waveform_fn(&WaveformKind::SawHarmonics(200));
+        for n in 0..100 {
+            assert_eq!(asked(n as f32, 1000.0, 10.0), capped(n as f32, 1000.0, 10.0));
+        }
+        assert!((harmonic(&asked, 49) - 2.0 / std::f32::consts::PI / 49.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn duration_samples_renders_exactly_that_many() {
+        let mut args = RunArgs::new(LogLevel::Silent, 440.0, WaveformKind::Sine, true);
+        assert_eq!(render_arrays(44100, &args).len(), 44100 / 440 + 1);
+        args.duration_samples = Some(1001);
+        assert_eq!(render_arrays(44100, &args).len(), 1001);
+        // Still exact through the oversampling
+        args.oversample = 4;
+        assert_eq!(render_arrays(44100, &args).len(), 1001);
+    }
+
+    #[test]
+    fn durations_round_and_exclude_each_other() {
+        let mut args = RunArgs::new(LogLevel::Silent, 440.0, WaveformKind::Sine, true);
+        args.duration_ms = Some(10.01);
+        assert_eq!(render_len(44100, &args, 100), Ok(441));
+        args.duration_samples = Some(441);
+        assert!(render_len(44100, &args, 100).is_err());
+    }
+
+    #[test]
+    fn saw_shape_one_is_the_plain_saw() {
+        let saw = waveform_fn(&WaveformKind::Sawtooth);
+        let shaped = waveform_fn(&WaveformKind::ShapedSaw(1.0));
+        for n in 0..1000 {
+            assert_eq!(shaped(n as f32, 1000.0, 10.0), saw(n as f32, 1000.0, 10.0));
+        }
+    }
+
+    #[test]
+    fn saw_shape_changes_the_harmonic_balance() {
+        // A saw's harmonics fall as 1/k, so 5 times the 5th harmonic matches the fundamental
+        let balance = |shape: f32| {
+            let wave = waveform_fn(&WaveformKind::ShapedSaw(shape));
+            5.0 * harmonic(&wave, 5) / harmonic(&wave, 1)
+        };
+        assert!((balance(1.0) - 1.0).abs() < 0.01, "{}", balance(1.0));
+        assert!(balance(2.0) < 0.97, "{}", balance(2.0));
+        assert!(balance(0.5) > 1.1, "{}", balance(0.5));
+    }
+
+    // Four frames of a constant 1.0 written with the gains of `position`
+    fn panned(channels: usize, position: f32) -> Vec<f32> {
+        let mut buffer = vec![0_f32; 4 * channels];
+        write_frames(&mut buffer, channels, pan_gains(channels, position), &ClipGuard::new(0.0, true), &mut || 1.0);
+        buffer
+    }
+
+    #[test]
+    fn hard_pan_only_plays_on_its_side() {
+        for frame in panned(2, -1.0).chunks(2) {
+            assert!((frame[0] - 1.0).abs() < 1e-6 && frame[1].abs() < 1e-6, "{:?}", frame);
+        }
+        for frame in panned(2, 1.0).chunks(2) {
+            assert!(frame[0].abs() < 1e-6 && (frame[1] - 1.0).abs() < 1e-6, "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn center_pan_keeps_the_power_and_mono_ignores_it() {
+        for position in [-1.0, -0.3, 0.0, 0.6, 1.0] {
+            let frame = panned(2, position);
+            assert!((frame[0] * frame[0] + frame[1] * frame[1] - 1.0).abs() < 1e-5, "{}", position);
+        }
+        let center = panned(2, 0.0);
+        assert_eq!(center[0], center[1]);
+        assert!(panned(1, -1.0).iter().all(|s| *s == 1.0));
+    }
+
+    #[test]
+    fn odd_tilt_leaves_no_even_harmonics() {
+        let saw: Vec<f32> = (1..=10).map(|k| 1.0 / k as f32).collect();
+        assert_eq!(tilt_harmonics(&saw, 0.0), saw);
+        let wave = waveform_fn(&WaveformKind::Harmonics(tilt_harmonics(&saw, -1.0)));
+        let fundamental = harmonic(&wave, 1);
+        for k in (2..=10).step_by(2) {
+            assert!(harmonic(&wave, k) < 1e-3 * fundamental, "harmonic {}", k);
+        }
+        for k in (3..=9).step_by(2) {
+            assert!((harmonic(&wave, k) * k as f32 / fundamental - 1.0).abs() < 0.01, "harmonic {}", k);
+        }
+    }
+
+    #[test]
+    fn even_tilt_keeps_the_fundamental() {
+        let tilted = tilt_harmonics(&[1.0, 0.5, 0.25, 0.125], 1.0);
+        assert_eq!(tilted, vec![1.0, 0.5, 0.0, 0.125]);
+        // Past the range it's the same as the end
+        assert_eq!(tilt_harmonics(&[1.0, 0.5], -3.0), vec![1.0, 0.0]);
+    }
+
+    #[test]
+    fn reusing_the_seed_repeats_the_pluck() {
+        let mut args = RunArgs::new(LogLevel::Silent, 440.0, WaveformKind::Sine, true);
+        args.pluck = true;
+        args.seed = Some(1234);
+        let first = render_arrays(44100, &args);
+        assert_eq!(render_arrays(44100, &args), first);
+        args.seed = Some(1235);
+        assert_ne!(render_arrays(44100, &args), first);
+    }
+
+    #[test]
+    fn reusing_the_seed_repeats_the_pink_noise() {
+        let first = ToneTest::Pink.render(8000, 1234);
+        assert_eq!(ToneTest::Pink.render(8000, 1234), first);
+        assert_ne!(ToneTest::Pink.render(8000, 1235), first);
+        // The sines don't use it
+        assert_eq!(ToneTest::Sine1k.render(8000, 1), ToneTest::Sine1k.render(8000, 2));
+    }
+
+    #[test]
+    fn waveform_kind_parses_the_shared_names() {
+        assert_eq!("SAW".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("sawtooth".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("Sine".parse::<WaveformKind>(), Ok(WaveformKind::Sine));
+        let err = "bogus".parse::<WaveformKind>().unwrap_err();
+        assert!(err.contains("saw/sawtooth"), "{}", err);
+        assert!("onoff".parse::<WaveformKind>().unwrap_err().contains("2: Samples"));
+    }
+}
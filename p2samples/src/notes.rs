@@ -0,0 +1,29 @@
+// Note names shifted by cents, the names themselves are common's
+
+use common::notes::note_name_to_freq;
+
+// A note name shifted by some cents (100 cents = 1 semitone, 1200 = 1 octave)
+pub fn note_with_cents(name: &str, cents: f32) -> Result<f32, String> {
+    Ok(note_name_to_freq(name)? * 2f32.powf(cents / 1200.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cents_shift_the_note() {
+        assert_eq!(note_with_cents("A4", 0.0), Ok(440.0));
+        assert!((note_with_cents("A4", 1200.0).unwrap() - 880.0).abs() < 1e-3);
+        assert!((note_with_cents("A4", -1200.0).unwrap() - 220.0).abs() < 1e-3);
+        // A quarter tone is halfway between A4 and A#4 in cents, not in Hz
+        let a_sharp = note_name_to_freq("A#4").unwrap();
+        assert!((note_with_cents("A4", 50.0).unwrap() - (440.0 * a_sharp).sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn unknown_note_is_an_error() {
+        assert!(note_with_cents("H4", 0.0).is_err());
+        assert!(note_with_cents("A", 0.0).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use common::rng::XorShift;
+
+// Stateful sample sources, for sounds that can't be computed from the sample clock alone
+pub trait Oscillator {
+    fn next_sample(&mut self) -> f32;
+}
+
+// Karplus-Strong plucked string
+// A delay line one period long is filled with noise, then every sample the averaged (lowpassed) and
+// slightly damped value is fed back in. The noise quickly settles into a decaying pitched tone.
+pub struct PluckedString {
+    delay_line: Vec<f32>,
+    index: usize,
+    feedback: f32,
+}
+
+impl PluckedString {
+    // damping: 0 rings for a long time, 1 dies out quickly
+    pub fn new(sample_rate: f32, frequency: f32, damping: f32, seed: u32) -> Self {
+        // The averaging filter adds half a sample of delay to the loop, take it off the line length
+        let length = ((sample_rate / frequency) - 0.5).round().max(2.0) as usize;
+        let mut rng = XorShift::new(seed);
+        let delay_line = (0..length).map(|_| rng.next_f32()).collect();
+        Self {
+            delay_line,
+            index: 0,
+            feedback: 1.0 - 0.01 * damping.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Oscillator for PluckedString {
+    fn next_sample(&mut self) -> f32 {
+        let length = self.delay_line.len();
+        let next_index = (self.index + 1) % length;
+        let out = self.delay_line[self.index];
+        self.delay_line[self.index] = self.feedback * 0.5 * (out + self.delay_line[next_index]);
+        self.index = next_index;
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn plucked_string_decays() {
+        let mut string = PluckedString::new(44100.0, 220.0, 0.5, 1);
+        let samples: Vec<f32> = (0..44100).map(|_| string.next_sample()).collect();
+        assert!(rms(&samples[40000..]) < 0.5 * rms(&samples[..4410]));
+    }
+
+    #[test]
+    fn plucked_string_pitch_from_zero_crossings() {
+        let mut string = PluckedString::new(44100.0, 441.0, 1.0, 1);
+        let samples: Vec<f32> = (0..44100).map(|_| string.next_sample()).collect();
+        // Once the noise has settled only the fundamental is left crossing zero
+        let rising: Vec<usize> = (22050..44100).filter(|&n| samples[n - 1] < 0.0 && samples[n] >= 0.0).collect();
+        let period = (rising[rising.len() - 1] - rising[0]) as f32 / (rising.len() - 1) as f32;
+        assert!((44100.0 / period - 441.0).abs() < 441.0 * 0.01, "{}", 44100.0 / period);
+    }
+}
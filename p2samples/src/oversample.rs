@@ -0,0 +1,108 @@
+// Oversampling: render at `factor` times the sample rate, lowpass, keep every `factor`th sample
+// Harmonics that would fold back (alias) at the real sample rate are still below the oversampled
+// nyquist, so the lowpass can remove them before they turn into inharmonic junk
+
+// Taps on each side of the center per unit of factor, more is a steeper filter but slower
+const HALF_TAPS_PER_FACTOR: usize = 16;
+
+// Blackman windowed sinc lowpass, cut off just under the nyquist of the decimated signal
+fn lowpass_kernel(factor: usize) -> Vec<f32> {
+    let half = HALF_TAPS_PER_FACTOR * factor;
+    let len = 2 * half + 1;
+    let cutoff = 0.45 / factor as f32;
+    let mut kernel: Vec<f32> = (0..len)
+        .map(|i| {
+            let x = i as f32 - half as f32;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * std::f32::consts::PI * cutoff * x).sin() / (std::f32::consts::PI * x)
+            };
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / (len - 1) as f32;
+            let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            sinc * window
+        })
+        .collect();
+    // Unity gain at DC
+    let sum: f32 = kernel.iter().sum();
+    for k in kernel.iter_mut() {
+        *k /= sum;
+    }
+    kernel
+}
+
+// Filters and decimates an oversampled signal back down, the output is `input.len() / factor` long
+// The filter is centered on each kept sample so the output isn't delayed
+pub fn decimate(input: &[f32], factor: usize) -> Vec<f32> {
+    if factor <= 1 {
+        return input.to_vec();
+    }
+    let kernel = lowpass_kernel(factor);
+    let half = (kernel.len() / 2) as isize;
+    (0..input.len() / factor)
+        .map(|n| {
+            let center = (n * factor) as isize;
+            kernel
+                .iter()
+                .enumerate()
+                .map(|(k, h)| {
+                    let i = center + k as isize - half;
+                    if i >= 0 && (i as usize) < input.len() {
+                        h * input[i as usize]
+                    } else {
+                        0.0
+                    }
+                })
+                .sum()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+    // 5kHz fits 250 whole periods into 2400 samples, so every harmonic and every alias lands on a bin
+    const FREQ: f32 = 5000.0;
+    const LEN: usize = 2400;
+
+    // LEN samples of a naive sawtooth rendered at `factor` times the rate and decimated back down
+    // Starts well past the filter's edge so the zero padding doesn't leak into the measurement
+    fn sawtooth(factor: usize) -> Vec<f32> {
+        let pad = HALF_TAPS_PER_FACTOR * 2;
+        let rate = RATE * factor as f32;
+        let rendered: Vec<f32> = (0..(LEN + 2 * pad) * factor)
+            .map(|n| 1.0 - 2.0 * (FREQ * n as f32 / rate).fract())
+            .collect();
+        decimate(&rendered, factor)[pad..pad + LEN].to_vec()
+    }
+
+    // Energy in the bins that aren't harmonics of FREQ
+    fn aliased_energy(signal: &[f32]) -> f32 {
+        let harmonic_spacing = (FREQ / RATE * LEN as f32).round() as usize;
+        (1..LEN / 2)
+            .filter(|bin| bin % harmonic_spacing != 0)
+            .map(|bin| {
+                let (re, im) = signal.iter().enumerate().fold((0_f32, 0_f32), |(re, im), (n, v)| {
+                    let angle = 2.0 * std::f32::consts::PI * (bin * n % LEN) as f32 / LEN as f32;
+                    (re + v * angle.cos(), im - v * angle.sin())
+                });
+                re * re + im * im
+            })
+            .sum()
+    }
+
+    #[test]
+    fn oversampling_reduces_aliasing() {
+        let naive = aliased_energy(&sawtooth(1));
+        let oversampled = aliased_energy(&sawtooth(4));
+        assert!(oversampled < naive / 10.0, "{} vs {}", oversampled, naive);
+    }
+
+    #[test]
+    fn decimated_length_is_divided_by_the_factor() {
+        assert_eq!(decimate(&[0.5; 400], 4).len(), 100);
+        assert_eq!(decimate(&[0.5; 400], 1), vec![0.5; 400]);
+    }
+}
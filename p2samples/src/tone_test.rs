@@ -0,0 +1,133 @@
+// Calibrated reference signals, levels are dBFS as in AES17: a full scale sine is 0 dBFS (so RMS + 3.01dB)
+
+use common::rng::XorShift;
+
+// Pink noise is rendered this long then looped, long enough that the loop isn't heard
+const PINK_LOOP_SECONDS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneTest {
+    // 1kHz sine at -20 dBFS, the usual alignment tone
+    Sine1k,
+    // Full scale 997Hz sine, the test standard (not a divisor of common sample rates, so every sample value is hit)
+    Sine997,
+    // Pink noise at -20 dBFS RMS
+    Pink,
+}
+
+impl ToneTest {
+    // "1k", "997" or "pink"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "1k" => Ok(ToneTest::Sine1k),
+            "997" => Ok(ToneTest::Sine997),
+            "pink" => Ok(ToneTest::Pink),
+            other => Err(format!("unknown tone test '{}', expected 1k, 997 or pink", other)),
+        }
+    }
+
+    pub fn level_dbfs(&self) -> f32 {
+        match self {
+            ToneTest::Sine1k | ToneTest::Pink => -20.0,
+            ToneTest::Sine997 => 0.0,
+        }
+    }
+
+    // A buffer that loops seamlessly, one second for the sines (a whole number of periods) or a stretch of noise
+    // `seed` only changes the noise, the same seed is the same noise
+    pub fn render(&self, sample_rate: u32, seed: u32) -> Vec<f32> {
+        let sample_rate_f = sample_rate as f32;
+        let sine = |frequency: f32| -> Vec<f32> {
+            let amplitude = 10_f32.powf(self.level_dbfs() / 20.0);
+            (0..sample_rate as usize)
+                .map(|n| amplitude * (2.0 * std::f32::consts::PI * frequency * n as f32 / sample_rate_f).sin())
+                .collect()
+        };
+        match self {
+            ToneTest::Sine1k => sine(1000.0),
+            ToneTest::Sine997 => sine(997.0),
+            ToneTest::Pink => {
+                let mut noise = pink_noise(sample_rate as usize * PINK_LOOP_SECONDS, seed);
+                // Scaled to the exact level, the filter's gain is only roughly known
+                let gain = 10_f32.powf((self.level_dbfs() - rms_dbfs(&noise)) / 20.0);
+                for sample in noise.iter_mut() {
+                    *sample *= gain;
+                }
+                noise
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ToneTest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ToneTest::Sine1k => write!(f, "1kHz sine"),
+            ToneTest::Sine997 => write!(f, "997Hz sine"),
+            ToneTest::Pink => write!(f, "pink noise"),
+        }
+    }
+}
+
+// White noise through Paul Kellet's pinking filter (-3dB per octave within ~0.05dB above 10Hz), unscaled
+fn pink_noise(len: usize, seed: u32) -> Vec<f32> {
+    let mut rng = XorShift::new(seed);
+    let mut b = [0_f32; 7];
+    (0..len)
+        .map(|_| {
+            let white = rng.next_f32();
+            b[0] = 0.99886 * b[0] + white * 0.0555179;
+            b[1] = 0.99332 * b[1] + white * 0.0750759;
+            b[2] = 0.96900 * b[2] + white * 0.153852;
+            b[3] = 0.86650 * b[3] + white * 0.3104856;
+            b[4] = 0.55000 * b[4] + white * 0.5329522;
+            b[5] = -0.7616 * b[5] - white * 0.0168980;
+            let pink = b.iter().sum::<f32>() + white * 0.5362;
+            b[6] = white * 0.115926;
+            pink
+        })
+        .collect()
+}
+
+// RMS level in AES17 dBFS (a full scale sine reads 0), -inf for silence
+pub fn rms_dbfs(samples: &[f32]) -> f32 {
+    let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+    20.0 * (mean_square.sqrt() * std::f32::consts::SQRT_2).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alignment_tone_measures_minus_20_dbfs() {
+        for rate in [44100, 48000] {
+            let level = rms_dbfs(&ToneTest::Sine1k.render(rate, 1));
+            assert!((level + 20.0).abs() < 0.05, "{} Hz: {} dBFS", rate, level);
+        }
+    }
+
+    #[test]
+    fn full_scale_997_and_pink_hit_their_levels() {
+        let sine = ToneTest::Sine997.render(48000, 1);
+        assert!(rms_dbfs(&sine).abs() < 0.05, "{}", rms_dbfs(&sine));
+        assert!(sine.iter().all(|s| s.abs() <= 1.0));
+        let pink = ToneTest::Pink.render(8000, 1);
+        assert!((rms_dbfs(&pink) + 20.0).abs() < 0.05, "{}", rms_dbfs(&pink));
+    }
+
+    #[test]
+    fn full_scale_sine_reads_zero_and_silence_minus_infinity() {
+        let sine: Vec<f32> = (0..1000).map(|n| (2.0 * std::f32::consts::PI * n as f32 / 100.0).sin()).collect();
+        assert!(rms_dbfs(&sine).abs() < 0.01);
+        assert_eq!(rms_dbfs(&[0.0; 100]), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn parse_names_and_errors() {
+        assert_eq!(ToneTest::parse("1k"), Ok(ToneTest::Sine1k));
+        assert_eq!(ToneTest::parse(" 997 "), Ok(ToneTest::Sine997));
+        assert_eq!(ToneTest::parse("PINK"), Ok(ToneTest::Pink));
+        assert!(ToneTest::parse("white").unwrap_err().contains("white"));
+    }
+}
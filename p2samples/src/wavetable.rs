@@ -0,0 +1,135 @@
+// Single cycle waveforms loaded from text, the same space separated format as samples.txt
+
+// Parses one period of samples, needs at least 2 to interpolate between
+pub fn parse_cycle(text: &str) -> Result<Vec<f32>, String> {
+    let samples = text
+        .split_whitespace()
+        .enumerate()
+        .map(|(n, v)| {
+            v.parse::<f32>()
+                .map_err(|_| format!("sample {} ('{}') is not a number", n, v))
+        })
+        .collect::<Result<Vec<f32>, String>>()?;
+    if samples.len() < 2 {
+        return Err(format!("a cycle needs at least 2 samples, found {}", samples.len()));
+    }
+    Ok(samples)
+}
+
+pub fn load_cycle(path: &str) -> Result<Vec<f32>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    parse_cycle(&text)
+}
+
+// How values between the table's samples are made up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    // Closest sample, steps (and aliases) but costs nothing
+    Nearest,
+    // Straight line between the two neighbours
+    Linear,
+    // Catmull-Rom through the four nearest samples, smoothest for the cost
+    Cubic,
+}
+
+impl Interpolation {
+    // "nearest", "linear" or "cubic"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "nearest" => Ok(Interpolation::Nearest),
+            "linear" => Ok(Interpolation::Linear),
+            "cubic" => Ok(Interpolation::Cubic),
+            other => Err(format!("unknown interpolation '{}', expected nearest, linear or cubic", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Interpolation::Nearest => write!(f, "nearest"),
+            Interpolation::Linear => write!(f, "linear"),
+            Interpolation::Cubic => write!(f, "cubic"),
+        }
+    }
+}
+
+// Value at `phase` (0..1) through the cycle, wrapping back to the first sample
+pub fn sample_at(table: &[f32], phase: f32, interpolation: Interpolation) -> f32 {
+    let len = table.len();
+    let position = phase.rem_euclid(1.0) * len as f32;
+    let index = position.floor() as usize % len;
+    let frac = position - position.floor();
+    let at = |offset: usize| table[(index + offset) % len];
+    match interpolation {
+        Interpolation::Nearest => {
+            if frac < 0.5 {
+                at(0)
+            } else {
+                at(1)
+            }
+        }
+        Interpolation::Linear => at(0) + (at(1) - at(0)) * frac,
+        Interpolation::Cubic => {
+            // The sample before `index`, wrapped
+            let (p0, p1, p2, p3) = (at(len - 1), at(0), at(1), at(2));
+            let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+            let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+            let c = -0.5 * p0 + 0.5 * p2;
+            ((a * frac + b) * frac + c) * frac + p1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RMS error against the real sine of reading a 32 sample sine table at a non-integer step
+    fn sine_error(interpolation: Interpolation) -> f32 {
+        let table: Vec<f32> = (0..32)
+            .map(|n| (2.0 * std::f32::consts::PI * n as f32 / 32.0).sin())
+            .collect();
+        let count = 1000;
+        let sum: f32 = (0..count)
+            .map(|n| {
+                let phase = n as f32 * 0.0137;
+                let expected = (2.0 * std::f32::consts::PI * phase).sin();
+                (sample_at(&table, phase, interpolation) - expected).powi(2)
+            })
+            .sum();
+        (sum / count as f32).sqrt()
+    }
+
+    #[test]
+    fn cubic_is_closer_to_the_sine_than_nearest_or_linear() {
+        let (nearest, linear, cubic) = (
+            sine_error(Interpolation::Nearest),
+            sine_error(Interpolation::Linear),
+            sine_error(Interpolation::Cubic),
+        );
+        assert!(cubic < linear && linear < nearest, "{} {} {}", nearest, linear, cubic);
+        assert!(cubic < nearest / 10.0, "{} {}", nearest, cubic);
+    }
+
+    #[test]
+    fn every_interpolation_hits_the_table_samples() {
+        let table = [0.0, 1.0, 0.5, -1.0];
+        for interpolation in [Interpolation::Nearest, Interpolation::Linear, Interpolation::Cubic] {
+            for (n, value) in table.iter().enumerate() {
+                assert!((sample_at(&table, n as f32 / 4.0, interpolation) - value).abs() < 1e-6);
+            }
+            // Wraps past the end back to the start
+            assert!(sample_at(&table, 1.0, interpolation).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn interpolation_names_parse() {
+        assert_eq!(Interpolation::parse(" Cubic "), Ok(Interpolation::Cubic));
+        assert_eq!(Interpolation::parse("nearest"), Ok(Interpolation::Nearest));
+        assert!(Interpolation::parse("sinc").is_err());
+        assert!(parse_cycle("0.5").is_err());
+        assert!(parse_cycle("0 x").unwrap_err().contains("sample 1"));
+    }
+}
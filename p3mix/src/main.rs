@@ -1,47 +1,19 @@
 use std::fs::File;
 use std::io::Write;
 
+use common::fade::{fade_out_gain, Faded, PLAY_MS};
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::preview::{play_preview, preview_arrays, PreviewFn};
+use common::safety::{no_limit_from_args, ClipGuard, DEFAULT_CEILING_DBFS};
+use common::sample_format::{force_sample_format, sample_format_from_args};
+use common::setup::setup_default_device_default_config;
+use common::wav::{check_format, samples_to_raw_pcm, samples_to_wav, to_i16, upmix, BitDepth};
+use common::waveform::WaveformName;
 use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
+    traits::{DeviceTrait, StreamTrait},
     Device, FromSample, Sample, SizedSample, StreamConfig, SupportedStreamConfig,
 };
-use wav::raw_audio_to_wav;
 
-mod wav;
-
-fn setup_default_device_default_config(quiet: bool) -> (Device, SupportedStreamConfig) {
-    if !quiet {
-        eprintln!("SETUP");
-    }
-    let host = cpal::default_host();
-    let dev = host
-        .default_output_device()
-        .expect("Did not find output audio device!");
-    if !quiet {
-        eprintln!(
-            "- Output device: {}",
-            dev.name().expect("Device lacks a name..?")
-        );
-    }
-
-    let conf = dev
-        .default_output_config()
-        .expect("Did not find default output config for device!");
-    if !quiet {
-        eprintln!("- Default output config: {:?}", conf);
-    }
-
-    let supported = dev
-        .supported_output_configs()
-        .expect("Could not list supported configs");
-    if !quiet {
-        eprintln!("- Supported output configs:");
-        for (n, c) in supported.enumerate() {
-            eprintln!("  {}. {:?}", n, c);
-        }
-    }
-    (dev, conf)
-}
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum WaveformKind {
@@ -52,29 +24,134 @@ enum WaveformKind {
     Triangle,
 }
 
+impl std::fmt::Display for WaveformKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WaveformKind::Silence => write!(f, "silence"),
+            WaveformKind::Sine => write!(f, "sine"),
+            WaveformKind::Square => write!(f, "square"),
+            WaveformKind::Sawtooth => write!(f, "sawtooth"),
+            WaveformKind::Triangle => write!(f, "triangle"),
+        }
+    }
+}
+
+// The shared names in any case ("SAW", "sawtooth", ...)
+impl std::str::FromStr for WaveformKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse::<WaveformName>()? {
+            WaveformName::Silence => Ok(WaveformKind::Silence),
+            WaveformName::Sine => Ok(WaveformKind::Sine),
+            WaveformName::Square => Ok(WaveformKind::Square),
+            WaveformName::Sawtooth => Ok(WaveformKind::Sawtooth),
+            WaveformName::Triangle => Ok(WaveformKind::Triangle),
+            other => Err(format!("{} isn't one of 3: Mix's waveforms, expected silence, sine, square, saw/sawtooth or triangle", other)),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct RunArgs {
-    quiet: bool,
+    log_level: LogLevel,
     waveforms: Vec<(WaveformKind, f32)>,
     generate_arrays: bool,
+    // Render the fixed selftest patch and compare its checksum, exits 1 on a mismatch (for CI)
+    selftest: bool,
+    // Sample format of samples.wav
+    bit_depth: BitDepth,
+    // Also write the samples as headerless s16le (no wav header) to this file
+    raw_file: Option<String>,
+    // Length of the generated arrays instead of one combined period, not both
+    // duration_ms rounds to the nearest sample, duration_samples is exact (FFT windows, checksums)
+    duration_ms: Option<f32>,
+    duration_samples: Option<usize>,
+    // Ramp to silence over the end of the playback so stopping doesn't click
+    fade_out_ms: f32,
+    // Plays this much of the generated arrays through the device before writing them, 0 for no preview
+    preview_ms: f32,
+    // Skip the audio device entirely and only generate arrays at `sample_rate`
+    no_device: bool,
+    sample_rate: u32,
+    // Channel count of samples.wav and the raw file (1 or 2), a mono render is copied to each channel
+    channels: u16,
+    // Every sample is clamped to this before it's played, `no_limit` (only from --no-limit on the command line) bypasses it
+    limit_ceiling_dbfs: f32,
+    no_limit: bool,
+    // Output sample format instead of the device default, only from --sample-format on the command line
+    sample_format: Option<cpal::SampleFormat>,
 }
 
 impl RunArgs {
-    fn new(quiet: bool, waveforms: Vec<(WaveformKind, f32)>, generate_arrays: bool) -> Self {
+    fn new(log_level: LogLevel, waveforms: Vec<(WaveformKind, f32)>, generate_arrays: bool) -> Self {
         Self {
-            quiet,
+            log_level,
             waveforms,
             generate_arrays,
+            ..Default::default()
+        }
+    }
+
+    // Human readable summary for the startup output
+    fn describe(&self) -> String {
+        let mix: Vec<String> = self
+            .waveforms
+            .iter()
+            .map(|(k, f)| format!("{} @ {} Hz", k, f))
+            .collect();
+        let mut out = format!("Mix: {}", mix.join(", "));
+        if self.bit_depth != BitDepth::Int16 {
+            out += &format!("; Bit depth: {}", self.bit_depth);
+        }
+        if let Some(path) = self.raw_file.as_ref() {
+            out += &format!("; Raw s16le: {}", path);
+        }
+        if let Some(ms) = self.duration_ms {
+            out += &format!("; Duration: {} ms", ms);
+        }
+        if let Some(samples) = self.duration_samples {
+            out += &format!("; Duration: {} samples", samples);
+        }
+        if self.no_device {
+            out += &format!("; Generating arrays without a device @ {} Hz", self.sample_rate);
+        } else if self.generate_arrays {
+            out += "; Generating arrays";
+        }
+        if self.channels != 1 {
+            out += &format!("; Files: {} channels", self.channels);
+        }
+        if self.no_limit {
+            out += "; Clip guard OFF";
+        } else if self.limit_ceiling_dbfs != DEFAULT_CEILING_DBFS {
+            out += &format!("; Clip guard ceiling: {} dBFS", self.limit_ceiling_dbfs);
+        }
+        if let Some(format) = self.sample_format {
+            out += &format!("; Sample format: {} (forced)", format);
         }
+        out
     }
 }
 
 impl Default for RunArgs {
     fn default() -> Self {
         Self {
-            quiet: false,
+            log_level: LogLevel::Normal,
             waveforms: vec![(WaveformKind::Silence, 0_f32)],
             generate_arrays: false,
+            selftest: false,
+            bit_depth: BitDepth::Int16,
+            raw_file: None,
+            duration_ms: None,
+            duration_samples: None,
+            fade_out_ms: 20.0,
+            preview_ms: 0.0,
+            no_device: false,
+            sample_rate: 44100,
+            channels: 1,
+            limit_ceiling_dbfs: DEFAULT_CEILING_DBFS,
+            no_limit: false,
+            sample_format: None,
         }
     }
 }
@@ -147,22 +224,7 @@ fn calculate_amplitude(
     }
 }
 
-const CAP_ARRAY_GENERATION_SIZE: bool = true;
-fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args: RunArgs) {
-    // Initialize constants
-    let sample_rate = conf.sample_rate.0 as f32;
-    let channels = conf.channels as usize;
-    if !args.quiet {
-        eprintln!("RUN");
-        for sample in args.waveforms.iter() {
-            eprintln!("- {:?} @ {}", sample.0, sample.1)
-        }
-        if args.waveforms.is_empty() {
-            eprintln!("- You didn't add any samples to play...");
-        }
-    }
-
-    // Initialize sample generator
+fn waveform_fns(args: &RunArgs) -> Vec<fn(f32, f32, f32) -> f32> {
     let mut next_value: Vec<fn(f32, f32, f32) -> f32> = Vec::with_capacity(args.waveforms.len());
     for sample in args.waveforms.iter() {
         next_value.push(match sample.0 {
@@ -193,43 +255,45 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
             },
         });
     }
+    next_value
+}
 
+const CAP_ARRAY_GENERATION_SIZE: bool = true;
+// Returns the (capped) period of the mix and its amplitude
+fn find_amplitude(
+    sample_rate: u32,
+    next_value: &Vec<fn(f32, f32, f32) -> f32>,
+    args: &RunArgs,
+) -> (usize, f32) {
+    let sample_rate_f = sample_rate as f32;
     // Find the max amplitude of the new waveform
-    if !args.quiet {
-        eprintln!("FIND AMPLITUDE:");
-    }
+    log(args.log_level, "FIND AMPLITUDE:");
     // 1 - Figure out the length of the new waveform:
     let mut combined_period = args
         .waveforms
         .iter()
         .filter_map(|(k, f)| {
             if k != &WaveformKind::Silence && f > &0.0_f32 {
-                Some((sample_rate / *f).ceil() as usize) // use ceil to ensure the combined waveform period is AT LEAST the entire length of the waveform, without ceil it could be to short from integer truncation
+                Some((sample_rate_f / *f).ceil() as usize) // use ceil to ensure the combined waveform period is AT LEAST the entire length of the waveform, without ceil it could be to short from integer truncation
             } else {
                 None
             }
         })
         .reduce(lcm)
         .unwrap_or(1);
-    if !args.quiet {
-        eprintln!("- Period: {}", combined_period);
-    }
-    if combined_period as f32 >= sample_rate {
-        if !args.quiet {
-            eprintln!("- WARNING: Period is > 1 second worth of samples. Generation may be slow.");
-        }
+    log(args.log_level, &format!("- Period: {}", combined_period));
+    if combined_period as f32 >= sample_rate_f {
+        log(args.log_level, "- WARNING: Period is > 1 second worth of samples. Generation may be slow.");
         if CAP_ARRAY_GENERATION_SIZE {
-            if !args.quiet {
-                eprintln!("- CAP ARRAY SIZE SET: Bounding the maximum sample count to 1 second worth of samples.");
-            }
-            combined_period = conf.sample_rate.0 as usize;
+            log(args.log_level, "- CAP ARRAY SIZE SET: Bounding the maximum sample count to 1 second worth of samples.");
+            combined_period = sample_rate as usize;
         }
     }
 
     // 2 - Calculate one period worth of samples and find the maximum amplitude
-    let amplitude = calculate_amplitude(combined_period, &next_value, sample_rate, &args);
+    let amplitude = calculate_amplitude(combined_period, next_value, sample_rate_f, args);
     // 3 - Debug print the max amplitude
-    if !args.quiet {
+    if args.log_level >= LogLevel::Normal {
         eprintln!("- Amplitude: {}", amplitude);
         if amplitude > 1.0 {
             eprintln!("- Amplitude is above threshold. Mix will be normalized.");
@@ -237,51 +301,148 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
             eprintln!("- Amplitude is below threshold. Mix does not need normalization.");
         }
     }
+    (combined_period, amplitude)
+}
 
-    // Generate one second worth of samples, write to a file then exit
-    if args.generate_arrays {
-        // Get the lowest freq (we are only taking up to that many samples for the array generation)
-        // On failure we will only have 1 sample, you shouldn't pass this an empty vec...
-        let mut vals: Vec<f32> = Vec::with_capacity(conf.sample_rate.0 as usize);
-        for sample_num in 0..combined_period {
-            // Calculate current sample value
-            let mut acc = 0_f32;
-            for (i, (_, f)) in args.waveforms.iter().enumerate() {
-                acc += next_value[i](sample_num as f32, sample_rate, *f);
-            }
-            // Normalize sample if necessary
-            if amplitude > 1.0 {
-                acc /= amplitude;
-            }
-            // Push
-            vals.push(acc);
+// One (capped) period of the normalized mix
+fn render_mix(sample_rate: u32, args: &RunArgs) -> Vec<f32> {
+    let next_value = waveform_fns(args);
+    let (combined_period, amplitude) = find_amplitude(sample_rate, &next_value, args);
+    // Get the lowest freq (we are only taking up to that many samples for the array generation)
+    // On failure we will only have 1 sample, you shouldn't pass this an empty vec...
+    let len = render_len(sample_rate, args, combined_period).unwrap_or_else(|e| panic!("Invalid duration: {}", e));
+    let mut vals: Vec<f32> = Vec::with_capacity(len);
+    for sample_num in 0..len {
+        // Calculate current sample value
+        let mut acc = 0_f32;
+        for (i, (_, f)) in args.waveforms.iter().enumerate() {
+            acc += next_value[i](sample_num as f32, sample_rate as f32, *f);
+        }
+        // Normalize sample if necessary
+        if amplitude > 1.0 {
+            acc /= amplitude;
+        }
+        // Push
+        vals.push(acc);
+    }
+    vals
+}
+
+// Number of samples to generate, `default` unless a duration was given
+fn render_len(sample_rate: u32, args: &RunArgs, default: usize) -> Result<usize, String> {
+    match (args.duration_ms, args.duration_samples) {
+        (Some(_), Some(_)) => Err("duration_ms and duration_samples can't both be set".to_string()),
+        (Some(ms), None) if ms < 0.0 => Err(format!("duration_ms can't be negative, got {}", ms)),
+        (Some(ms), None) => Ok((ms / 1000.0 * sample_rate as f32).round() as usize),
+        (None, Some(samples)) => Ok(samples),
+        (None, None) => Ok(default),
+    }
+}
+
+// Fixed patch for the selftest, there is no randomness in the mix so no seed is needed
+const SELFTEST_SAMPLE_RATE: u32 = 44100;
+const SELFTEST_WAVEFORMS: [(WaveformKind, f32); 2] = [(WaveformKind::Sine, 440.0), (WaveformKind::Square, 110.0)];
+// FNV-1a of the quantized i16 render (little endian bytes), update this when the waveform formulas change on purpose
+// sin() comes from the platform libm so another OS/CPU may need its own value
+const SELFTEST_EXPECTED_CHECKSUM: u64 = 0xa0d8dd450151eca9;
+
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+// Renders the selftest patch and checks it against the expected checksum
+fn selftest() -> bool {
+    let args = RunArgs::new(LogLevel::Silent, SELFTEST_WAVEFORMS.to_vec(), true);
+    let vals = render_mix(SELFTEST_SAMPLE_RATE, &args);
+    let checksum = fnv1a(vals.iter().flat_map(|v| to_i16(*v).to_le_bytes()));
+    eprintln!("SELFTEST");
+    eprintln!("- Rendered {} samples @ {}Hz", vals.len(), SELFTEST_SAMPLE_RATE);
+    if checksum == SELFTEST_EXPECTED_CHECKSUM {
+        eprintln!("- PASS: checksum {:016x}", checksum);
+        true
+    } else {
+        eprintln!(
+            "- FAIL: checksum {:016x}, expected {:016x}",
+            checksum, SELFTEST_EXPECTED_CHECKSUM
+        );
+        false
+    }
+}
+
+// Generate one second worth of samples and write to files
+// Doesn't need an audio device, only the sample rate
+fn generate_arrays(sample_rate: u32, args: &RunArgs, preview: Option<PreviewFn>) {
+    let vals = render_mix(sample_rate, args);
+    preview_arrays(&vals, args.preview_ms, preview, args.log_level);
+    let mut file = File::create("samples.txt").expect("Failed to create file!");
+    for val in vals.iter() {
+        write!(file, "{} ", val).expect("Failed to write file!");
+    }
+    file.flush().expect("Failed to flush the file buffer");
+    eprintln!("FILE WRITE SUCCESS...");
+
+    let vals = upmix(&vals, args.channels);
+    let bytes = samples_to_wav(&vals, args.channels, sample_rate, args.bit_depth);
+    let mut wavefile = File::create("samples.wav").expect("Failed to create file!");
+    wavefile
+        .write(&bytes)
+        .expect("Failed to write the samples to wave!");
+    eprintln!("WAVE FILE WRITE SUCCESS...");
+    if let Some(path) = args.raw_file.as_ref() {
+        let mut rawfile = File::create(path).expect("Failed to create file!");
+        rawfile
+            .write_all(&samples_to_raw_pcm(&vals))
+            .expect("Failed to write the raw samples!");
+        eprintln!("RAW FILE WRITE SUCCESS... (s16le, {}Hz, {} channel(s))", sample_rate, args.channels);
+    }
+}
+
+fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args: RunArgs) {
+    // Initialize constants
+    let sample_rate = conf.sample_rate.0 as f32;
+    let channels = conf.channels as usize;
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("RUN");
+        for sample in args.waveforms.iter() {
+            eprintln!("- {} @ {}", sample.0, sample.1)
         }
-        let mut file = File::create("samples.txt").expect("Failed to create file!");
-        for val in vals.iter() {
-            write!(file, "{} ", val).expect("Failed to write file!");
+        if args.waveforms.is_empty() {
+            eprintln!("- You didn't add any samples to play...");
         }
-        file.flush().expect("Failed to flush the file buffer");
-        eprintln!("FILE WRITE SUCCESS...");
+    }
 
-        let vals_u16: Vec<i16> = vals
-            .iter()
-            .map(|f| ((*f * 32768_f32).round() as i64).clamp(-32768, 32767) as i16)
-            .collect();
-        let bytes = raw_audio_to_wav(vals_u16, conf.sample_rate.0);
-        let mut wavefile = File::create("samples.wav").expect("Failed to create file!");
-        wavefile
-            .write(&bytes)
-            .expect("Failed to write the samples to wave!");
-        eprintln!("WAVE FILE WRITE SUCCESS...");
+    // Generate one second worth of samples, write to a file then exit
+    if args.generate_arrays {
+        generate_arrays(
+            conf.sample_rate.0,
+            &args,
+            Some(&|samples: &[f32]| {
+                play_preview::<T>(dev, &conf, samples, args.preview_ms, ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit))
+            }),
+        );
         return;
     }
 
+    // Initialize sample generator
+    let next_value = waveform_fns(&args);
+    let (_, amplitude) = find_amplitude(conf.sample_rate.0, &next_value, &args);
+
     let volume = 0.3333;
+    let safety = ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit);
     let mut sample_clock = 0f32;
+    // Samples played so far, for the fade out
+    let mut elapsed = 0_usize;
+    let total = conf.sample_rate.0 as usize * PLAY_MS as usize / 1000;
+    let fade = (args.fade_out_ms.max(0.0) / 1000.0 * sample_rate).round() as usize;
+    let faded = Faded::new();
+    let callback_faded = faded.clone();
+    verbose(args.log_level, &format!("- Stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(args.log_level, "Output", conf.sample_rate.0);
     let stream = dev
         .build_output_stream(
             &conf,
             move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                stats.record(data.len() / channels);
                 for frame in data.chunks_mut(channels) {
                     // COPIED: Calculate current sample value
                     let mut acc = 0_f32;
@@ -292,7 +453,11 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
                     if amplitude > 1.0 {
                         acc /= amplitude;
                     }
-                    let value: T = (acc * volume).to_sample::<T>();
+                    let value: T = safety.process(acc * volume * fade_out_gain(elapsed, total, fade)).to_sample::<T>();
+                    elapsed += 1;
+                    if elapsed >= total {
+                        callback_faded.set();
+                    }
                     sample_clock = (sample_clock + 1.0) % sample_rate;
                     for sample in frame.iter_mut() {
                         *sample = value;
@@ -306,24 +471,133 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
 
     stream.play().expect("Failed to play the stream!");
 
-    std::thread::sleep(std::time::Duration::from_millis(1000));
+    faded.wait(PLAY_MS);
 }
 
 fn main() {
-    let args = RunArgs::new(
-        false,
+    let wave = |name: &str| name.parse::<WaveformKind>().unwrap_or_else(|e| panic!("{}", e));
+    let mut args = RunArgs::new(
+        LogLevel::from_args(std::env::args().skip(1)),
         vec![
-            (WaveformKind::Square, 110.0),
-            (WaveformKind::Triangle, 440.0),
-            (WaveformKind::Triangle, 554.37),
-            (WaveformKind::Triangle, 659.25),
+            (wave("square"), 110.0),
+            (wave("triangle"), 440.0),
+            (wave("triangle"), 554.37),
+            (wave("triangle"), 659.25),
         ],
         true,
     );
-    if !args.quiet {
+    args.limit_ceiling_dbfs = DEFAULT_CEILING_DBFS;
+    // Only from the command line, it can't be left switched off by an edit here
+    args.no_limit = no_limit_from_args(std::env::args().skip(1));
+    args.sample_format = sample_format_from_args(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{}", e));
+    args.bit_depth = BitDepth::parse("16").unwrap_or_else(|e| panic!("{}", e));
+    args.raw_file = None;
+    args.duration_ms = None;
+    args.duration_samples = None;
+    // Checked up front so both durations being set fails before the device is opened
+    if let Err(e) = render_len(args.sample_rate, &args, 0) {
+        panic!("Invalid duration: {}", e);
+    }
+    args.fade_out_ms = 20.0;
+    args.preview_ms = 0.0;
+    args.no_device = false;
+    args.sample_rate = 44100;
+    args.channels = 1;
+    if let Err(e) = check_format(args.sample_rate, args.channels) {
+        panic!("Invalid file format: {}", e);
+    }
+    args.selftest = false;
+    // Hidden, doesn't print the arguments or touch the device
+    if args.selftest {
+        std::process::exit(if selftest() { 0 } else { 1 });
+    }
+    if args.log_level >= LogLevel::Normal {
         eprintln!("ARGUMENTS:");
-        eprintln!("- {:?}", args);
+        eprintln!("- {}", args.describe());
+    }
+    if args.no_limit {
+        log(args.log_level, "- WARNING: Clip guard off (--no-limit)");
+    }
+    if args.no_device {
+        if args.log_level >= LogLevel::Normal {
+            eprintln!("NO DEVICE");
+            eprintln!("- Generating arrays at {}Hz", args.sample_rate);
+        }
+        generate_arrays(args.sample_rate, &args, None);
+        return;
+    }
+    log(args.log_level, "SETUP");
+    let (dev, conf, _) = setup_default_device_default_config(args.log_level)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let conf = force_sample_format(&dev, conf, args.sample_format)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(format) = args.sample_format {
+        log(args.log_level, &format!("- Sample format forced to {}", format));
     }
-    let (dev, conf) = setup_default_device_default_config(args.quiet);
     dispatch_run(&dev, conf, args);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveform_display_is_lowercase() {
+        assert_eq!(WaveformKind::Silence.to_string(), "silence");
+        assert_eq!(WaveformKind::Sine.to_string(), "sine");
+        assert_eq!(WaveformKind::Square.to_string(), "square");
+        assert_eq!(WaveformKind::Sawtooth.to_string(), "sawtooth");
+        assert_eq!(WaveformKind::Triangle.to_string(), "triangle");
+    }
+
+    #[test]
+    fn selftest_render_matches_the_expected_checksum() {
+        assert!(selftest());
+    }
+
+    #[test]
+    fn selftest_checksum_catches_a_one_bit_change() {
+        let args = RunArgs::new(LogLevel::Silent, SELFTEST_WAVEFORMS.to_vec(), true);
+        let mut vals: Vec<i16> = render_mix(SELFTEST_SAMPLE_RATE, &args).iter().map(|v| to_i16(*v)).collect();
+        vals[1000] ^= 1;
+        assert_ne!(fnv1a(vals.iter().flat_map(|v| v.to_le_bytes())), SELFTEST_EXPECTED_CHECKSUM);
+    }
+
+    // Size of the data chunk, from its header
+    fn data_chunk_len(bytes: &[u8]) -> usize {
+        let at = bytes.windows(4).position(|w| w == b"data").unwrap();
+        u32::from_le_bytes(bytes[at + 4..at + 8].try_into().unwrap()) as usize
+    }
+
+    #[test]
+    fn duration_samples_sets_the_exact_data_length() {
+        let mut args = RunArgs::new(LogLevel::Silent, SELFTEST_WAVEFORMS.to_vec(), true);
+        args.duration_samples = Some(1001);
+        let vals = render_mix(44100, &args);
+        assert_eq!(vals.len(), 1001);
+        for (bit_depth, bytes_per_sample) in [(BitDepth::Int16, 2), (BitDepth::Int24, 3), (BitDepth::Float32, 4)] {
+            let bytes = samples_to_wav(&upmix(&vals, 2), 2, 44100, bit_depth);
+            assert_eq!(data_chunk_len(&bytes), 1001 * 2 * bytes_per_sample, "{}", bit_depth);
+        }
+    }
+
+    #[test]
+    fn durations_round_and_exclude_each_other() {
+        let mut args = RunArgs::new(LogLevel::Silent, SELFTEST_WAVEFORMS.to_vec(), true);
+        assert_eq!(render_len(44100, &args, 100), Ok(100));
+        args.duration_ms = Some(10.01);
+        assert_eq!(render_len(44100, &args, 100), Ok(441));
+        args.duration_samples = Some(441);
+        assert!(render_len(44100, &args, 100).is_err());
+    }
+
+    #[test]
+    fn waveform_kind_parses_the_shared_names() {
+        assert_eq!("SAW".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("sawtooth".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("Sine".parse::<WaveformKind>(), Ok(WaveformKind::Sine));
+        let err = "bogus".parse::<WaveformKind>().unwrap_err();
+        assert!(err.contains("saw/sawtooth"), "{}", err);
+        assert!("onoff".parse::<WaveformKind>().unwrap_err().contains("3: Mix"));
+    }
+}
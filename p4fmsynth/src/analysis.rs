@@ -0,0 +1,140 @@
+// Measurements of a rendered buffer, written as JSON so test pipelines can assert on them
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct Report {
+    // Floored at DB_FLOOR, silence would be -inf which JSON can't hold
+    pub peak_dbfs: f32,
+    pub rms_dbfs: f32,
+    // None when no clear pitch was found (silence, noise, very inharmonic FM)
+    pub fundamental_hz: Option<f32>,
+    pub sample_count: usize,
+    pub duration_ms: f32,
+}
+
+// Lowest pitch searched for
+const MIN_FUNDAMENTAL_HZ: f32 = 20.0;
+// Autocorrelation is O(n * lags), only look at the start of long buffers
+const MAX_PITCH_WINDOW: usize = 16384;
+// Normalized autocorrelation needed for a lag to count as the period
+const PITCH_CONFIDENCE: f32 = 0.5;
+
+// What silence is reported as, far below anything a 32 bit float render can hold that isn't 0
+pub const DB_FLOOR: f32 = -200.0;
+
+fn to_db(level: f32) -> f32 {
+    (20.0 * level.log10()).max(DB_FLOOR)
+}
+
+// Period found by normalized autocorrelation
+// Takes the shortest lag that is nearly as good as the best so a 440Hz tone isn't reported as 220Hz
+pub fn detect_fundamental(samples: &[f32], sample_rate: f32) -> Option<f32> {
+    let window = &samples[..samples.len().min(MAX_PITCH_WINDOW)];
+    let max_lag = ((sample_rate / MIN_FUNDAMENTAL_HZ) as usize).min(window.len() / 2);
+    if max_lag < 2 {
+        return None;
+    }
+    let correlations: Vec<f32> = (0..=max_lag)
+        .map(|lag| {
+            let (a, b) = (&window[..window.len() - lag], &window[lag..]);
+            let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+            let energy = (a.iter().map(|x| x * x).sum::<f32>() * b.iter().map(|y| y * y).sum::<f32>()).sqrt();
+            if energy > 0.0 {
+                dot / energy
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    // Skip the peak around lag 0
+    let start = correlations.iter().position(|c| *c < 0.0)?;
+    let (best_lag, best) = correlations[start..]
+        .iter()
+        .enumerate()
+        .fold((0, f32::MIN), |(bl, b), (i, c)| if *c > b { (i + start, *c) } else { (bl, b) });
+    if best < PITCH_CONFIDENCE {
+        return None;
+    }
+    let lag = (start..=best_lag).find(|l| {
+        let c = correlations[*l];
+        c >= 0.9 * best && c >= correlations[l - 1] && c >= correlations[(l + 1).min(max_lag)]
+    })?;
+    // Fit a parabola through the peak and its neighbours for a fractional lag
+    let (before, at, after) = (correlations[lag - 1], correlations[lag], correlations[(lag + 1).min(max_lag)]);
+    let curvature = before - 2.0 * at + after;
+    let offset = if curvature < 0.0 {
+        0.5 * (before - after) / curvature
+    } else {
+        0.0
+    };
+    Some(sample_rate / (lag as f32 + offset))
+}
+
+// Mean of the samples, the DC component (0 for anything symmetric around 0)
+pub fn dc_offset(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| *s as f64).sum::<f64>() / samples.len() as f64) as f32
+}
+
+pub fn analyse(samples: &[f32], sample_rate: u32) -> Report {
+    let peak = samples.iter().fold(0_f32, |m, s| m.max(s.abs()));
+    let rms = if samples.is_empty() {
+        0.0
+    } else {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    };
+    Report {
+        peak_dbfs: to_db(peak),
+        rms_dbfs: to_db(rms),
+        fundamental_hz: detect_fundamental(samples, sample_rate as f32),
+        sample_count: samples.len(),
+        duration_ms: samples.len() as f32 / sample_rate as f32 * 1000.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|n| 0.5 * (2.0 * std::f32::consts::PI * 441.0 * n as f32 / 44100.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn report_round_trips_through_json() {
+        let report = analyse(&sine(44100), 44100);
+        assert!((report.peak_dbfs + 6.02).abs() < 0.01);
+        assert!((report.rms_dbfs + 9.03).abs() < 0.01);
+        assert!((report.fundamental_hz.unwrap() - 441.0).abs() < 0.5);
+        assert_eq!(report.sample_count, 44100);
+        assert_eq!(report.duration_ms, 1000.0);
+        let json = serde_json::to_string(&report).unwrap();
+        for field in ["peak_dbfs", "rms_dbfs", "fundamental_hz", "sample_count", "duration_ms"] {
+            assert!(json.contains(field), "{} missing from {}", field, json);
+        }
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn silence_is_floored_so_it_still_round_trips() {
+        let report = analyse(&[0.0; 100], 44100);
+        assert_eq!(report.peak_dbfs, DB_FLOOR);
+        assert_eq!(report.rms_dbfs, DB_FLOOR);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn sine_has_no_dc_and_an_offset_is_measured() {
+        assert!(dc_offset(&sine(44100)).abs() < 1e-6);
+        let offset: Vec<f32> = sine(44100).iter().map(|s| s + 0.25).collect();
+        assert!((dc_offset(&offset) - 0.25).abs() < 1e-6);
+        assert!((dc_offset(&[-0.1; 1000]) + 0.1).abs() < 1e-6);
+        assert_eq!(dc_offset(&[]), 0.0);
+    }
+}
@@ -0,0 +1,158 @@
+// Post processing on the rendered samples
+
+// One-pole DC blocking highpass: y = x - x1 + r * y1
+// OnOff and uneven duty waveforms sit above/below 0, this pulls them back to centered
+// r close to 1 keeps the cutoff low (~35Hz at 44.1kHz for 0.995), much cheaper than a biquad
+pub struct DcBlocker {
+    x1: f32,
+    y1: f32,
+    r: f32,
+}
+
+impl DcBlocker {
+    pub fn new(r: f32) -> Self {
+        Self { x1: 0.0, y1: 0.0, r }
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = x - self.x1 + self.r * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
+}
+
+impl Default for DcBlocker {
+    fn default() -> Self {
+        Self::new(0.995)
+    }
+}
+
+// What the envelope follower tracks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FollowerMode {
+    // Jumps up to every new peak, falls back with the time constant, settles on a sine's amplitude
+    Peak,
+    // One-pole average of the squared signal, square rooted, settles on a sine's RMS (amplitude / sqrt 2)
+    Rms,
+}
+
+impl FollowerMode {
+    // "peak" or "rms"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "peak" => Ok(FollowerMode::Peak),
+            "rms" => Ok(FollowerMode::Rms),
+            other => Err(format!("unknown follower mode '{}', expected peak or rms", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for FollowerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FollowerMode::Peak => write!(f, "peak"),
+            FollowerMode::Rms => write!(f, "rms"),
+        }
+    }
+}
+
+// Amplitude contour of a signal, rectified then smoothed by a one-pole lowpass
+// `time_ms` is the time constant, ~63% of the way to a new level in that time
+pub struct EnvelopeFollower {
+    mode: FollowerMode,
+    coeff: f32,
+    level: f32,
+}
+
+impl EnvelopeFollower {
+    pub fn new(mode: FollowerMode, time_ms: f32, sample_rate: f32) -> Self {
+        let samples = (time_ms / 1000.0 * sample_rate).max(1.0);
+        Self {
+            mode,
+            coeff: 1.0 - (-1.0 / samples).exp(),
+            level: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        match self.mode {
+            FollowerMode::Peak => {
+                let rectified = x.abs();
+                if rectified > self.level {
+                    self.level = rectified;
+                } else {
+                    self.level += self.coeff * (rectified - self.level);
+                }
+                self.level
+            }
+            FollowerMode::Rms => {
+                self.level += self.coeff * (x * x - self.level);
+                self.level.sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dc_blocker_step_decays_to_zero() {
+        let mut blocker = DcBlocker::default();
+        assert_eq!(blocker.process(1.0), 1.0);
+        let mut out = 1.0;
+        // r = 0.995 is a ~200 sample time constant
+        for _ in 0..2000 {
+            out = blocker.process(1.0);
+        }
+        assert!(out.abs() < 0.001, "{}", out);
+    }
+
+    #[test]
+    fn dc_blocker_passes_a_sine() {
+        let mut blocker = DcBlocker::default();
+        // 440Hz at 44.1kHz, measured after the start has settled
+        let out: Vec<f32> = (0..44100)
+            .map(|n| blocker.process((2.0 * std::f32::consts::PI * 440.0 * n as f32 / 44100.0).sin()))
+            .collect();
+        let peak = out[22050..].iter().fold(0_f32, |m, v| m.max(v.abs()));
+        assert!((peak - 1.0).abs() < 0.01, "{}", peak);
+    }
+
+    // 440Hz at 44.1kHz with the amplitude from `amplitude(seconds)`
+    fn sine(len: usize, amplitude: impl Fn(f32) -> f32) -> Vec<f32> {
+        (0..len)
+            .map(|n| {
+                let t = n as f32 / 44100.0;
+                amplitude(t) * (2.0 * std::f32::consts::PI * 440.0 * t).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follower_settles_on_the_peak_or_the_rms() {
+        let input = sine(44100, |_| 0.5);
+        let mut peak = EnvelopeFollower::new(FollowerMode::Peak, 100.0, 44100.0);
+        let mut rms = EnvelopeFollower::new(FollowerMode::Rms, 100.0, 44100.0);
+        let (peaks, rmss): (Vec<f32>, Vec<f32>) = input.iter().map(|x| (peak.process(*x), rms.process(*x))).unzip();
+        // After the first half second has settled it, only the ripple between peaks is left
+        assert!(peaks[22050..].iter().all(|v| *v <= 0.5 + 1e-6 && *v > 0.48), "{:?}", &peaks[22050..22060]);
+        let target = 0.5 / std::f32::consts::SQRT_2;
+        assert!(rmss[22050..].iter().all(|v| (v - target).abs() < target * 0.01), "{:?}", &rmss[22050..22060]);
+    }
+
+    #[test]
+    fn follower_tracks_a_fade() {
+        // Full level down to silence over a second
+        let input = sine(44100, |t| 1.0 - t);
+        for (mode, time_ms, expected) in [(FollowerMode::Peak, 50.0, 0.5), (FollowerMode::Rms, 10.0, 0.5 / std::f32::consts::SQRT_2)] {
+            let mut follower = EnvelopeFollower::new(mode, time_ms, 44100.0);
+            let out: Vec<f32> = input.iter().map(|x| follower.process(*x)).collect();
+            for (at, expected) in [(11025, expected * 1.5), (22050, expected), (33075, expected * 0.5)] {
+                assert!((out[at] - expected).abs() < expected * 0.1, "{} at {}: {} not {}", mode, at, out[at], expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Step sequencer gate, a pattern like "x.x.xx.." switching the output on (x) and off (.) in time
+
+pub struct Gate {
+    steps: Vec<bool>,
+    // Samples per step, fractional so any rate stays in time
+    step_len: f64,
+    // Ramp length at the edges of an on step, so switching doesn't click
+    fade: f64,
+}
+
+impl Gate {
+    pub fn new(pattern: &str, steps_per_second: f32, fade_ms: f32, sample_rate: f32) -> Result<Self, String> {
+        let steps = pattern
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                'x' | 'X' => Ok(true),
+                '.' => Ok(false),
+                c => Err(format!("'{}' in gate pattern '{}' should be x (on) or . (off)", c, pattern)),
+            })
+            .collect::<Result<Vec<bool>, String>>()?;
+        if steps.is_empty() {
+            return Err("gate pattern is empty".to_string());
+        }
+        if steps_per_second <= 0.0 {
+            return Err(format!("gate rate should be above 0 steps per second, got {}", steps_per_second));
+        }
+        let step_len = sample_rate as f64 / steps_per_second as f64;
+        Ok(Self {
+            steps,
+            step_len,
+            fade: (fade_ms.max(0.0) as f64 / 1000.0 * sample_rate as f64).min(step_len / 2.0),
+        })
+    }
+
+    // Gain 0..1 for sample `n`, only depends on n so it works for chunks rendered in parallel
+    pub fn gain(&self, n: usize) -> f32 {
+        let position = n as f64 / self.step_len;
+        let count = self.steps.len();
+        let step = position.floor() as usize % count;
+        if !self.steps[step] {
+            return 0.0;
+        }
+        // Only fade in/out where the neighbouring step is off, x's next to each other stay on
+        let into = position.fract() * self.step_len;
+        let left = self.step_len - into;
+        let mut gain = 1_f64;
+        if self.fade > 0.0 && !self.steps[(step + count - 1) % count] {
+            gain = gain.min(into / self.fade);
+        }
+        if self.fade > 0.0 && !self.steps[(step + 1) % count] {
+            gain = gain.min(left / self.fade);
+        }
+        gain as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_off_pattern_alternates_loud_and_silent_steps() {
+        // 8 steps a second at 48kHz is 6000 samples a step, 5ms fades are 240 samples
+        let gate = Gate::new("x.", 8.0, 5.0, 48000.0).unwrap();
+        for step in 0..8 {
+            let start = step * 6000;
+            if step % 2 == 0 {
+                assert!((start + 240..start + 6000 - 240).all(|n| gate.gain(n) == 1.0), "step {}", step);
+                assert!(gate.gain(start) < 0.01 && gate.gain(start + 120) > 0.4 && gate.gain(start + 120) < 0.6);
+            } else {
+                assert!((start..start + 6000).all(|n| gate.gain(n) == 0.0), "step {}", step);
+            }
+        }
+    }
+
+    #[test]
+    fn neighbouring_on_steps_do_not_fade() {
+        let gate = Gate::new("xx.", 8.0, 5.0, 48000.0).unwrap();
+        assert!((5000..7000).all(|n| gate.gain(n) == 1.0));
+    }
+
+    #[test]
+    fn bad_patterns_are_errors() {
+        assert!(Gate::new("x-x", 8.0, 5.0, 48000.0).err().unwrap().contains("'-'"));
+        assert!(Gate::new(" ", 8.0, 5.0, 48000.0).is_err());
+        assert!(Gate::new("x.", 0.0, 5.0, 48000.0).is_err());
+    }
+}
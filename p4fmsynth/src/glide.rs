@@ -0,0 +1,40 @@
+// Carrier glide (portamento), the pitch moves from the start to the end frequency linearly in semitones
+
+pub struct Glide {
+    // End frequency / start frequency
+    ratio: f64,
+    // Samples the glide takes, the end frequency is held after that
+    len: f64,
+}
+
+impl Glide {
+    pub fn new(start_freq: f32, end_freq: f32, len: usize) -> Result<Self, String> {
+        if start_freq <= 0.0 || end_freq <= 0.0 {
+            return Err(format!(
+                "glide frequencies should be above 0 Hz, got {} -> {}",
+                start_freq, end_freq
+            ));
+        }
+        Ok(Self {
+            ratio: end_freq as f64 / start_freq as f64,
+            len: len.max(1) as f64,
+        })
+    }
+
+    // Warped sample clock for sample `n`
+    // The waveforms take their phase from clock * frequency, so the clock has to advance by the
+    // current frequency ratio each sample (the integral of ratio^(n / len)), not just be multiplied
+    // Passing it in place of the sample clock makes the carrier and every modulator follow the glide
+    // Only depends on n so it works for chunks rendered in parallel
+    pub fn clock(&self, n: usize) -> f32 {
+        let n = n as f64;
+        let gliding = n.min(self.len);
+        let ln_ratio = self.ratio.ln();
+        let warped = if ln_ratio.abs() < 1e-12 {
+            gliding
+        } else {
+            self.len / ln_ratio * (self.ratio.powf(gliding / self.len) - 1.0)
+        };
+        (warped + (n - gliding) * self.ratio) as f32
+    }
+}
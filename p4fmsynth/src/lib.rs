@@ -0,0 +1,1110 @@
+// FM synth as a library, so another program can hold on to the stream (or a mock) and do other work
+// The p4fmsynth binary is just `run_cli`
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use common::backend::{AudioBackend, CpalBackend};
+use common::fade::{fade_out_gain, Faded};
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::notes::freq_to_note_name;
+use common::preview::{play_preview, preview_arrays, PreviewFn};
+use common::rng::resolve_seed;
+use common::safety::{no_limit_from_args, ClipGuard, DEFAULT_CEILING_DBFS};
+use common::sample_format::{force_sample_format, sample_format_from_args};
+use common::setup::setup_default_device_default_config;
+use common::wav::{check_format, samples_to_raw_pcm, samples_to_wav, upmix, BitDepth, ClipMode};
+use common::waveform::WaveformName;
+use cpal::{FromSample, Sample, SizedSample, StreamConfig, SupportedStreamConfig};
+use analysis::{analyse, dc_offset};
+use fx::{DcBlocker, EnvelopeFollower, FollowerMode};
+use gate::Gate;
+use glide::Glide;
+use notes::parse_chord;
+use patch::{load_patch, patch_to_toml, random_patch};
+
+mod analysis;
+mod fx;
+mod gate;
+mod glide;
+mod notes;
+mod patch;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaveformKind {
+    Silence,
+    Sine,
+    Square,
+    Sawtooth,
+    Triangle,
+    // Gate between 0 and 1, on for the first `duty` fraction of each period
+    // As a modulator it gates the frequency modulation of the other modulators instead of adding to it
+    OnOff(f32),
+}
+
+impl std::fmt::Display for WaveformKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WaveformKind::Silence => write!(f, "silence"),
+            WaveformKind::Sine => write!(f, "sine"),
+            WaveformKind::Square => write!(f, "square"),
+            WaveformKind::Sawtooth => write!(f, "sawtooth"),
+            WaveformKind::Triangle => write!(f, "triangle"),
+            WaveformKind::OnOff(duty) => write!(f, "onoff (duty {})", duty),
+        }
+    }
+}
+
+// The shared names in any case ("SAW", "sawtooth", ...), onoff has a 0.5 duty
+impl std::str::FromStr for WaveformKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse::<WaveformName>()? {
+            WaveformName::Silence => Ok(WaveformKind::Silence),
+            WaveformName::Sine => Ok(WaveformKind::Sine),
+            WaveformName::Square => Ok(WaveformKind::Square),
+            WaveformName::Sawtooth => Ok(WaveformKind::Sawtooth),
+            WaveformName::Triangle => Ok(WaveformKind::Triangle),
+            WaveformName::OnOff => Ok(WaveformKind::OnOff(0.5)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RunArgs {
+    log_level: LogLevel,
+    carrier: (WaveformKind, f32),
+    modulators: Vec<(WaveformKind, f32, f32)>,
+    generate_arrays: bool,
+    // TOML patch file replacing the carrier and modulators, see patches/
+    patch_file: Option<String>,
+    // Roll a random patch (carrier wave, 1-3 modulators at simple ratios), printed as a patch file to stdout
+    random_patch: bool,
+    // Seed for the random patch, None picks one from the clock (printed at startup)
+    seed: Option<u32>,
+    // Plays the patch on every note of a chord like "maj:A4" instead of at the carrier frequency
+    fm_chord: Option<String>,
+    // Glides the carrier (and its modulators) to this frequency over the duration, linearly in semitones
+    carrier_freq_end: Option<f32>,
+    // Self feedback of each modulator (same order as `modulators`, missing ones get 0) in radians per unit of output
+    modulator_feedback: Vec<f32>,
+    // Step sequencer, "x" steps play and "." steps are silent, `gate_rate` steps per second (8 = 16ths at 120bpm)
+    gate: Option<String>,
+    gate_rate: f32,
+    gate_fade_ms: f32,
+    // Remove any DC offset from the generated arrays before they are written
+    dc_block: bool,
+    // Print the DC offset of the written arrays, warns above `dc_threshold` (0.01 is 1% of full scale)
+    dc_report: bool,
+    dc_threshold: f32,
+    // Text file of the written arrays' amplitude envelope, "t_seconds,amplitude" per sample
+    // `envelope_mode` "peak" follows the peaks, "rms" the RMS, `envelope_time_ms` is the follower's time constant
+    envelope_out: Option<String>,
+    envelope_mode: FollowerMode,
+    envelope_time_ms: f32,
+    // Cut near silent samples off the start and end of the generated arrays
+    trim_silence: bool,
+    trim_threshold_db: f32,
+    // JSON file with measurements (levels, pitch, length) of the generated arrays
+    report: Option<String>,
+    // Length of the generated arrays (default one second), at most one of the two can be set
+    // duration_ms rounds to the nearest sample, duration_samples is exact (FFT windows, checksums)
+    duration_ms: Option<f32>,
+    duration_samples: Option<usize>,
+    // Threads used for generate_arrays, 0 uses every available core
+    render_threads: usize,
+    // Sample format of samples.wav
+    bit_depth: BitDepth,
+    // Over full scale samples in samples.wav and the raw file, clamped (hard) or through tanh (soft)
+    clip_mode: ClipMode,
+    // Also write the samples as headerless s16le (no wav header) to this file
+    raw_file: Option<String>,
+    // Directory samples.txt and samples.wav are written to
+    out_dir: String,
+    // Ramp to silence over the end of the playback so stopping doesn't click
+    fade_out_ms: f32,
+    // Plays this much of the generated arrays through the device before writing them, 0 for no preview
+    preview_ms: f32,
+    // Skip the audio device entirely and only generate arrays at `sample_rate`
+    no_device: bool,
+    sample_rate: u32,
+    // Channel count of samples.wav and the raw file (1 or 2), a mono render is copied to each channel
+    channels: u16,
+    // Every sample is clamped to this before it's played, `no_limit` (only from --no-limit on the command line) bypasses it
+    limit_ceiling_dbfs: f32,
+    no_limit: bool,
+    // Output sample format instead of the device default, only from --sample-format on the command line
+    sample_format: Option<cpal::SampleFormat>,
+}
+
+impl RunArgs {
+    pub fn new(log_level: LogLevel, carrier: (WaveformKind, f32), modulators: Vec<(WaveformKind, f32, f32)>, generate_arrays: bool) -> Self {
+        Self {
+            log_level,
+            carrier,
+            modulators,
+            generate_arrays,
+            ..Default::default()
+        }
+    }
+
+    // Human readable summary for the startup output
+    pub fn describe(&self) -> String {
+        let modulators: Vec<String> = self
+            .modulators
+            .iter()
+            .map(|(k, f, depth)| format!("{} @ {} Hz (depth {})", k, f, depth))
+            .collect();
+        let mut out = format!(
+            "Carrier: {} @ {} Hz; Modulators: {}",
+            self.carrier.0,
+            self.carrier.1,
+            if modulators.is_empty() {
+                "none".to_string()
+            } else {
+                modulators.join(", ")
+            }
+        );
+        if let Some(path) = self.patch_file.as_ref() {
+            out += &format!("; Patch file: {}", path);
+        }
+        if self.random_patch {
+            out += "; Random patch";
+        }
+        if let Some(chord) = self.fm_chord.as_ref() {
+            out += &format!("; Chord: {}", chord);
+        }
+        if let Some(freq) = self.carrier_freq_end {
+            out += &format!("; Glide to {} Hz", freq);
+        }
+        if has_feedback(self) {
+            out += &format!("; Modulator feedback: {:?}", self.modulator_feedback);
+        }
+        if let Some(pattern) = self.gate.as_ref() {
+            out += &format!("; Gate: {} @ {} steps/s", pattern, self.gate_rate);
+        }
+        if !self.dc_block {
+            out += "; DC blocker off";
+        }
+        if self.dc_report {
+            out += &format!("; DC report (warn above {})", self.dc_threshold);
+        }
+        if let Some(path) = self.envelope_out.as_ref() {
+            out += &format!("; Envelope: {} ({}, {} ms)", path, self.envelope_mode, self.envelope_time_ms);
+        }
+        if let Some(ms) = self.duration_ms {
+            out += &format!("; Duration: {} ms", ms);
+        }
+        if let Some(samples) = self.duration_samples {
+            out += &format!("; Duration: {} samples", samples);
+        }
+        if let Some(path) = self.report.as_ref() {
+            out += &format!("; Report: {}", path);
+        }
+        if self.trim_silence {
+            out += &format!("; Trimming silence below {} dBFS", self.trim_threshold_db);
+        }
+        if self.bit_depth != BitDepth::Int16 {
+            out += &format!("; Bit depth: {}", self.bit_depth);
+        }
+        if self.clip_mode != ClipMode::Hard {
+            out += &format!("; Clip: {}", self.clip_mode);
+        }
+        if let Some(path) = self.raw_file.as_ref() {
+            out += &format!("; Raw s16le: {}", path);
+        }
+        if self.out_dir != "." {
+            out += &format!("; Writing to {}", self.out_dir);
+        }
+        if self.no_device {
+            out += &format!("; Generating arrays without a device @ {} Hz", self.sample_rate);
+        } else if self.generate_arrays {
+            out += "; Generating arrays";
+        }
+        if self.channels != 1 {
+            out += &format!("; Files: {} channels", self.channels);
+        }
+        if self.no_limit {
+            out += "; Clip guard OFF";
+        } else if self.limit_ceiling_dbfs != DEFAULT_CEILING_DBFS {
+            out += &format!("; Clip guard ceiling: {} dBFS", self.limit_ceiling_dbfs);
+        }
+        if let Some(format) = self.sample_format {
+            out += &format!("; Sample format: {} (forced)", format);
+        }
+        out
+    }
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        Self {
+            log_level: LogLevel::Normal,
+            carrier: (WaveformKind::Silence, 0_f32),
+            modulators: vec![],
+            generate_arrays: false,
+            patch_file: None,
+            random_patch: false,
+            seed: None,
+            fm_chord: None,
+            carrier_freq_end: None,
+            modulator_feedback: vec![],
+            gate: None,
+            gate_rate: 8.0,
+            gate_fade_ms: 5.0,
+            dc_block: true,
+            dc_report: false,
+            dc_threshold: 0.01,
+            envelope_out: None,
+            envelope_mode: FollowerMode::Peak,
+            envelope_time_ms: 10.0,
+            trim_silence: false,
+            trim_threshold_db: -60.0,
+            report: None,
+            duration_ms: None,
+            duration_samples: None,
+            render_threads: 0,
+            bit_depth: BitDepth::Int16,
+            clip_mode: ClipMode::Hard,
+            raw_file: None,
+            out_dir: ".".to_string(),
+            fade_out_ms: 20.0,
+            preview_ms: 0.0,
+            no_device: false,
+            sample_rate: 44100,
+            channels: 1,
+            limit_ceiling_dbfs: DEFAULT_CEILING_DBFS,
+            no_limit: false,
+            sample_format: None,
+        }
+    }
+}
+
+pub fn dispatch_run<B: AudioBackend>(
+    backend: &mut B,
+    conf: SupportedStreamConfig,
+    args: RunArgs,
+) -> Option<(B::Stream, Faded)> {
+    use cpal::SampleFormat::*;
+    match conf.sample_format() {
+        I8 => run::<i8, _>(backend, conf.into(), args),
+        I16 => run::<i16, _>(backend, conf.into(), args),
+        I32 => run::<i32, _>(backend, conf.into(), args),
+        I64 => run::<i64, _>(backend, conf.into(), args),
+        U8 => run::<u8, _>(backend, conf.into(), args),
+        U16 => run::<u16, _>(backend, conf.into(), args),
+        U32 => run::<u32, _>(backend, conf.into(), args),
+        U64 => run::<u64, _>(backend, conf.into(), args),
+        F32 => run::<f32, _>(backend, conf.into(), args),
+        F64 => run::<f64, _>(backend, conf.into(), args),
+        f => panic!("Unknown sample format: {}", f),
+    }
+}
+
+// Sample function, takes (sample_clock, sample_rate, frequency)
+type WaveformFn = Box<dyn Fn(f32, f32, f32) -> f32 + Send + Sync>;
+
+// Operator self feedback of one patch, each modulator's last output shifts its own phase by `amount * prev` radians
+// More feedback turns a sine modulator towards a saw (more harmonics), past ~1.5 it gets noisy
+struct FeedbackState {
+    // Feedback amount of every modulator, missing ones have none
+    amounts: Vec<f32>,
+    // Last output of every modulator
+    prev: Vec<f32>,
+}
+
+impl FeedbackState {
+    fn new(amounts: &[f32], modulator_count: usize) -> Self {
+        Self {
+            amounts: amounts.to_vec(),
+            prev: vec![0_f32; modulator_count],
+        }
+    }
+
+    // The feedback as a sample clock offset for modulator `i`, the waveforms take their phase from the clock
+    // A 0Hz modulator has no period to shift, so no offset rather than an infinite one
+    fn clock_offset(&self, i: usize, sample_rate: f32, frequency: f32) -> f32 {
+        match self.amounts.get(i) {
+            Some(amount) if *amount != 0.0 && frequency != 0.0 => {
+                amount * self.prev[i] / (2.0 * std::f32::consts::PI) * sample_rate / frequency
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+// FM synthesis of a single sample
+// Without feedback it only depends on the sample clock so any range of samples can be rendered independently
+fn fm_sample(
+    sample_clock: f32,
+    sample_rate: f32,
+    carrier_wave: &WaveformFn,
+    carrier_freq: f32,
+    modulators: &[(WaveformKind, f32, f32)],
+    next_value: &[WaveformFn],
+    feedback: &mut FeedbackState,
+) -> f32 {
+    let mut deviation = 0_f32;
+    let mut gate = 1_f32;
+    // Sum the samples, OnOff modulators gate the sum instead, depth (0..1) being how much
+    for (i, (kind, f, depth)) in modulators.iter().enumerate() {
+        let value = next_value[i](sample_clock + feedback.clock_offset(i, sample_rate, *f), sample_rate, *f);
+        feedback.prev[i] = value;
+        if let WaveformKind::OnOff(_) = kind {
+            gate *= 1_f32 - depth.clamp(0_f32, 1_f32) * (1_f32 - value);
+        } else {
+            deviation += value * depth;
+        }
+    }
+    carrier_wave(sample_clock, sample_rate, carrier_freq + deviation * gate)
+}
+
+// Carrier frequency and its modulators (Wave, Freq, Depth)
+type Patch = (f32, Vec<(WaveformKind, f32, f32)>);
+
+// The patch for every note that is played
+// With a chord the modulator frequencies and depths are scaled with each note so it keeps the same timbre
+// The scale is the note over the carrier frequency, so the carrier has to be a positive frequency then
+fn fm_patches(args: &RunArgs) -> Result<Vec<Patch>, String> {
+    let chord = match args.fm_chord.as_ref() {
+        Some(spec) => parse_chord(spec)?,
+        None => return Ok(vec![(args.carrier.1, args.modulators.clone())]),
+    };
+    if !args.carrier.1.is_finite() || args.carrier.1 <= 0.0 {
+        return Err(format!(
+            "the carrier frequency ({} Hz) has to be positive to scale the modulators to each note",
+            args.carrier.1
+        ));
+    }
+    Ok(chord
+        .into_iter()
+        .map(|freq| {
+            let ratio = freq / args.carrier.1;
+            let modulators = args
+                .modulators
+                .iter()
+                .map(|(kind, f, depth)| match kind {
+                    // Gates are rhythmic, they don't follow the pitch
+                    WaveformKind::OnOff(_) => (*kind, *f, *depth),
+                    _ => (*kind, f * ratio, depth * ratio),
+                })
+                .collect();
+            (freq, modulators)
+        })
+        .collect())
+}
+
+// Sums the FM sample of every patch, normalized by the number of notes
+fn fm_chord_sample(
+    sample_clock: f32,
+    sample_rate: f32,
+    carrier_wave: &WaveformFn,
+    patches: &[Patch],
+    next_value: &[WaveformFn],
+    feedback: &mut [FeedbackState],
+) -> f32 {
+    let mut acc = 0_f32;
+    for ((carrier_freq, modulators), feedback) in patches.iter().zip(feedback.iter_mut()) {
+        acc += fm_sample(sample_clock, sample_rate, carrier_wave, *carrier_freq, modulators, next_value, feedback);
+    }
+    acc / patches.len().max(1) as f32
+}
+
+fn has_feedback(args: &RunArgs) -> bool {
+    args.modulator_feedback.iter().any(|amount| *amount != 0.0)
+}
+
+// Fresh feedback state (no previous output) for every patch
+fn feedback_states(patches: &[Patch], args: &RunArgs) -> Vec<FeedbackState> {
+    patches
+        .iter()
+        .map(|(_, modulators)| FeedbackState::new(&args.modulator_feedback, modulators.len()))
+        .collect()
+}
+
+// The carrier glide over `len` samples, None without carrier_freq_end
+fn make_glide(len: usize, args: &RunArgs) -> Option<Glide> {
+    args.carrier_freq_end.map(|end_freq| {
+        Glide::new(args.carrier.1, end_freq, len).unwrap_or_else(|e| panic!("Invalid glide: {}", e))
+    })
+}
+
+// Renders `len` samples split into chunks across threads, bit-identical to rendering them one by one
+// Feedback carries over from sample to sample, so with any feedback there has to be a single thread
+fn render_fm_parallel(
+    len: usize,
+    threads: usize,
+    sample_rate: f32,
+    carrier_wave: &WaveformFn,
+    patches: &[Patch],
+    next_value: &[WaveformFn],
+    args: &RunArgs,
+) -> Vec<f32> {
+    let mut vals = vec![0_f32; len];
+    if len == 0 {
+        return vals;
+    }
+    let glide = make_glide(len, args);
+    let glide = glide.as_ref();
+    let chunk_len = len.div_ceil(threads.max(1));
+    std::thread::scope(|s| {
+        for (n, chunk) in vals.chunks_mut(chunk_len).enumerate() {
+            s.spawn(move || {
+                let start = n * chunk_len;
+                let mut feedback = feedback_states(patches, args);
+                for (i, val) in chunk.iter_mut().enumerate() {
+                    let sample_clock = glide.map_or((start + i) as f32, |glide| glide.clock(start + i));
+                    *val = fm_chord_sample(sample_clock, sample_rate, carrier_wave, patches, next_value, &mut feedback);
+                }
+            });
+        }
+    });
+    vals
+}
+
+fn waveform_fn(waveform: WaveformKind) -> WaveformFn {
+    match waveform {
+        WaveformKind::Silence => Box::new(|_, _, _| 0_f32),
+        WaveformKind::Sine => Box::new(|sample_clock, sample_rate, frequency| {
+            let period = sample_rate / frequency;
+            let normalized_location = (sample_clock % period) / period;
+            (2.0 * std::f32::consts::PI * normalized_location).sin()
+        }),
+        WaveformKind::Square => Box::new(|sample_clock, sample_rate, frequency| {
+            let period = sample_rate / frequency;
+            if (sample_clock % period) < (period / 2.0) {
+                1.0
+            } else {
+                -1.0
+            }
+        }),
+        WaveformKind::Sawtooth => Box::new(|sample_clock, sample_rate, frequency| {
+            let period = sample_rate / frequency;
+            1_f32 - (2_f32 * (sample_clock % period) / period)
+        }),
+        WaveformKind::Triangle => Box::new(|sample_clock, sample_rate, frequency| {
+            let period = sample_rate / frequency;
+            let normalized_location = (sample_clock % period) / period;
+            if normalized_location < 0.5 {
+                4_f32 * (normalized_location - 0.25_f32)
+            } else {
+                1_f32 - 4_f32 * (normalized_location - 0.5_f32)
+            }
+        }),
+        WaveformKind::OnOff(duty) => Box::new(move |sample_clock, sample_rate, frequency| {
+            let period = sample_rate / frequency;
+            if (sample_clock % period) < (period * duty) {
+                1_f32
+            } else {
+                0_f32
+            }
+        }),
+    }
+}
+
+fn make_gate(sample_rate: f32, args: &RunArgs) -> Option<Gate> {
+    args.gate.as_ref().map(|pattern| {
+        Gate::new(pattern, args.gate_rate, args.gate_fade_ms, sample_rate)
+            .unwrap_or_else(|e| panic!("Invalid gate: {}", e))
+    })
+}
+
+// Range of `vals` between the first and last sample louder than `threshold_db` (dBFS)
+// Only the ends are cut, quiet parts in the middle are kept. None if everything is below the threshold
+fn non_silent_range(vals: &[f32], threshold_db: f32) -> Option<std::ops::Range<usize>> {
+    let threshold = 10_f32.powf(threshold_db / 20.0);
+    let start = vals.iter().position(|v| v.abs() >= threshold)?;
+    let end = vals.iter().rposition(|v| v.abs() >= threshold)? + 1;
+    Some(start..end)
+}
+
+// Number of samples to generate, one second unless a duration was given
+fn render_len(sample_rate: u32, args: &RunArgs) -> Result<usize, String> {
+    match (args.duration_ms, args.duration_samples) {
+        (Some(_), Some(_)) => Err("duration_ms and duration_samples can't both be set".to_string()),
+        (Some(ms), None) if ms < 0.0 => Err(format!("duration_ms can't be negative, got {}", ms)),
+        (Some(ms), None) => Ok((ms / 1000.0 * sample_rate as f32).round() as usize),
+        (None, Some(samples)) => Ok(samples),
+        (None, None) => Ok(sample_rate as usize),
+    }
+}
+
+// DC offset of the arrays as they're written (printed even when Silent, it was asked for)
+fn print_dc_report(vals: &[f32], threshold: f32) {
+    let dc = dc_offset(vals);
+    eprintln!("- DC offset: {:.6} ({:.3}% of full scale)", dc, dc * 100.0);
+    if dc.abs() > threshold {
+        eprintln!(
+            "- WARNING: DC offset is above {:.3}% of full scale, turn on dc_block or check the duty/patch",
+            threshold * 100.0
+        );
+    }
+}
+
+// Amplitude envelope of the arrays as "t_seconds,amplitude" lines
+fn write_envelope(path: &str, vals: &[f32], sample_rate: u32, mode: FollowerMode, time_ms: f32) {
+    let mut follower = EnvelopeFollower::new(mode, time_ms, sample_rate as f32);
+    let mut file = File::create(path).expect("Failed to create the envelope file!");
+    for (n, val) in vals.iter().enumerate() {
+        writeln!(file, "{},{}", n as f32 / sample_rate as f32, follower.process(*val))
+            .expect("Failed to write the envelope file!");
+    }
+    file.flush().expect("Failed to flush the envelope file");
+    eprintln!("ENVELOPE FILE WRITE SUCCESS...");
+}
+
+// Generate one second (or the given duration) worth of samples and write to files in `out_dir`
+// Doesn't need an audio device, only the sample rate
+fn generate_arrays(sample_rate: u32, args: &RunArgs, out_dir: &Path, preview: Option<PreviewFn>) {
+    let carrier_wave = waveform_fn(args.carrier.0);
+    let next_value: Vec<WaveformFn> =
+        args.modulators.iter().map(|m| waveform_fn(m.0)).collect();
+    let play_period = render_len(sample_rate, args).unwrap_or_else(|e| panic!("Invalid duration: {}", e));
+
+    let threads = if has_feedback(args) {
+        1
+    } else if args.render_threads == 0 {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        args.render_threads
+    };
+    log(args.log_level, &format!("- Rendering {} samples on {} threads", play_period, threads));
+    let mut vals = render_fm_parallel(
+        play_period,
+        threads,
+        sample_rate as f32,
+        &carrier_wave,
+        &fm_patches(args).unwrap_or_else(|e| panic!("Invalid fm_chord: {}", e)),
+        &next_value,
+        args,
+    );
+    for val in vals.iter_mut() {
+        *val *= 0.333333;
+    }
+    if let Some(gate) = make_gate(sample_rate as f32, args) {
+        for (n, val) in vals.iter_mut().enumerate() {
+            *val *= gate.gain(n);
+        }
+    }
+    if args.dc_block {
+        let mut dc_blocker = DcBlocker::default();
+        for val in vals.iter_mut() {
+            *val = dc_blocker.process(*val);
+        }
+    }
+    if args.trim_silence {
+        match non_silent_range(&vals, args.trim_threshold_db) {
+            Some(range) => {
+                if args.log_level >= LogLevel::Normal {
+                    eprintln!(
+                        "- Trimmed {} samples from the start and {} from the end (below {} dBFS)",
+                        range.start,
+                        vals.len() - range.end,
+                        args.trim_threshold_db
+                    );
+                }
+                vals = vals[range].to_vec();
+            }
+            None => {
+                log(args.log_level, &format!("- Everything is below {} dBFS, not trimming", args.trim_threshold_db));
+            }
+        }
+    }
+    if args.dc_report {
+        print_dc_report(&vals, args.dc_threshold);
+    }
+    if let Some(path) = args.envelope_out.as_ref() {
+        write_envelope(path, &vals, sample_rate, args.envelope_mode, args.envelope_time_ms);
+    }
+    preview_arrays(&vals, args.preview_ms, preview, args.log_level);
+    let mut file = File::create(out_dir.join("samples.txt")).expect("Failed to create file!");
+    for val in vals.iter() {
+        write!(file, "{} ", val).expect("Failed to write file!");
+    }
+    file.flush().expect("Failed to flush the file buffer");
+    eprintln!("FILE WRITE SUCCESS...");
+
+    if let Some(path) = args.report.as_ref() {
+        // Silence is -inf dBFS, which JSON can't hold so the levels are floored at analysis::DB_FLOOR
+        let report = analyse(&vals, sample_rate);
+        let json = serde_json::to_string_pretty(&report).expect("Failed to serialize the report!");
+        std::fs::write(path, json).expect("Failed to write the report!");
+        eprintln!("REPORT WRITE SUCCESS... {:?}", report);
+    }
+
+    // samples.txt and the report keep the unclipped values
+    let vals = args.clip_mode.apply(&vals);
+    let vals = upmix(&vals, args.channels);
+    let bytes = samples_to_wav(&vals, args.channels, sample_rate, args.bit_depth);
+    let mut wavefile = File::create(out_dir.join("samples.wav")).expect("Failed to create file!");
+    wavefile
+        .write(&bytes)
+        .expect("Failed to write the samples to wave!");
+    eprintln!("WAVE FILE WRITE SUCCESS...");
+    if let Some(path) = args.raw_file.as_ref() {
+        let mut rawfile = File::create(path).expect("Failed to create file!");
+        rawfile
+            .write_all(&samples_to_raw_pcm(&vals))
+            .expect("Failed to write the raw samples!");
+        eprintln!("RAW FILE WRITE SUCCESS... (s16le, {}Hz, {} channel(s))", sample_rate, args.channels);
+    }
+}
+
+// How long the playback lasts
+pub const PLAY_MS: u64 = 30000;
+
+// Starts the patch playing and hands back the stream, it plays until the stream is dropped, and the flag
+// set once it has faded out. None when generating arrays, that's done by the time this returns
+fn run<T, B>(backend: &mut B, conf: StreamConfig, args: RunArgs) -> Option<(B::Stream, Faded)>
+where
+    T: SizedSample + FromSample<f32> + Send + 'static,
+    f32: FromSample<T>,
+    B: AudioBackend,
+{
+    // Initialize constants
+    let sample_rate = conf.sample_rate.0 as f32;
+    let channels = conf.channels as usize;
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("RUN");
+        eprintln!("Carrier:");
+        match freq_to_note_name(args.carrier.1) {
+            Some((note, cents)) => {
+                eprintln!("- {} @ {} ({} {:+.1} cents)", args.carrier.0, args.carrier.1, note, cents)
+            }
+            None => eprintln!("- {} @ {}", args.carrier.0, args.carrier.1),
+        }
+        eprintln!("Modulators:");
+        for sample in args.modulators.iter() {
+            eprintln!("- {} @ {}, Modulation Depth: {}", sample.0, sample.1, sample.2);
+        }
+        if args.modulators.is_empty() {
+            eprintln!("- You didn't add any modulators...");
+        }
+    }
+
+    // Generate one second worth of samples, write to a file then exit
+    if args.generate_arrays {
+        // Only a real device can play the preview
+        let (conf, args) = (&conf, &args);
+        let preview = backend.device().map(|dev| {
+            move |samples: &[f32]| {
+                play_preview::<T>(dev, conf, samples, args.preview_ms, ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit))
+            }
+        });
+        generate_arrays(
+            conf.sample_rate.0,
+            args,
+            Path::new(&args.out_dir),
+            preview.as_ref().map(|play| play as PreviewFn),
+        );
+        return None;
+    }
+
+    // Initialize carrier and modulator waveforms
+    let carrier_wave = waveform_fn(args.carrier.0);
+    let next_value: Vec<WaveformFn> =
+        args.modulators.iter().map(|m| waveform_fn(m.0)).collect();
+
+    let patches = fm_patches(&args).unwrap_or_else(|e| panic!("Invalid fm_chord: {}", e));
+    let mut feedback = feedback_states(&patches, &args);
+    let gate = make_gate(sample_rate, &args);
+
+    let volume = 0.3333;
+    let safety = ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit);
+    let mut sample_clock = 0f32;
+    // Samples played so far, for the fade out
+    let mut elapsed = 0_usize;
+    let total = conf.sample_rate.0 as usize * PLAY_MS as usize / 1000;
+    // Live playback glides over the whole play time
+    let glide = make_glide(total, &args);
+    let fade = (args.fade_out_ms.max(0.0) / 1000.0 * sample_rate).round() as usize;
+    let faded = Faded::new();
+    let callback_faded = faded.clone();
+    verbose(args.log_level, &format!("- Stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(args.log_level, "Output", conf.sample_rate.0);
+    let stream = backend.start(
+        &conf,
+        move |data: &mut [T]| {
+            stats.record(data.len() / channels);
+            for frame in data.chunks_mut(channels) {
+                let acc = fm_chord_sample(
+                    glide.as_ref().map_or(sample_clock, |glide| glide.clock(elapsed)),
+                    sample_rate,
+                    &carrier_wave,
+                    &patches,
+                    &next_value,
+                    &mut feedback,
+                );
+                let gate_gain = gate.as_ref().map_or(1.0, |gate| gate.gain(elapsed));
+                // Push
+                let value: T = safety.process(acc * volume * gate_gain * fade_out_gain(elapsed, total, fade)).to_sample::<T>();
+                elapsed += 1;
+                if elapsed >= total {
+                    callback_faded.set();
+                }
+                sample_clock = (sample_clock + 1.0) /*% sample_rate*/;
+                for sample in frame.iter_mut() {
+                    *sample = value;
+                }
+            }
+        },
+    );
+    Some((stream, faded))
+}
+
+// Everything the p4fmsynth binary does, the patch is set up here
+pub fn run_cli() {
+    let wave = |name: &str| name.parse::<WaveformKind>().unwrap_or_else(|e| panic!("{}", e));
+    let mut args = RunArgs::new(
+        LogLevel::from_args(std::env::args().skip(1)),
+        (wave("sine"), 440.0),
+        vec![
+            (wave("sine"), 1760.0, 22.0),
+            (wave("sine"), 480.0, 22.0),
+            (wave("sine"), 350.0, 22.0),
+            //(wave("sine"), 10.0, 220.0),
+        ],
+        false,
+    );
+    args.limit_ceiling_dbfs = DEFAULT_CEILING_DBFS;
+    // Only from the command line, it can't be left switched off by an edit here
+    args.no_limit = no_limit_from_args(std::env::args().skip(1));
+    args.sample_format = sample_format_from_args(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{}", e));
+    args.patch_file = None;
+    args.fm_chord = None;
+    args.carrier_freq_end = None;
+    args.modulator_feedback = vec![];
+    args.trim_silence = false;
+    args.dc_report = false;
+    args.dc_threshold = 0.01;
+    args.envelope_out = None;
+    args.envelope_mode = FollowerMode::parse("peak").unwrap_or_else(|e| panic!("{}", e));
+    args.envelope_time_ms = 10.0;
+    args.report = None;
+    // Without a patch file the carrier/modulators above are used
+    if let Some(path) = args.patch_file.as_ref() {
+        let (carrier, modulators) =
+            load_patch(path).unwrap_or_else(|e| panic!("Failed to load the patch: {}", e));
+        args.carrier = carrier;
+        args.modulators = modulators;
+    }
+    args.random_patch = false;
+    args.seed = None;
+    let seed = resolve_seed(args.seed, args.random_patch);
+    args.seed = Some(seed);
+    // Keeps the carrier frequency, replaces everything else
+    if args.random_patch {
+        let patch = random_patch(seed, args.carrier.1);
+        print!("{}", patch_to_toml(&patch));
+        (args.carrier, args.modulators) = patch;
+    }
+    args.bit_depth = BitDepth::parse("16").unwrap_or_else(|e| panic!("{}", e));
+    args.clip_mode = ClipMode::parse("hard").unwrap_or_else(|e| panic!("{}", e));
+    args.raw_file = None;
+    args.out_dir = ".".to_string();
+    args.duration_ms = None;
+    args.duration_samples = None;
+    // Checked up front so both durations being set fails before the device is opened
+    if let Err(e) = render_len(args.sample_rate, &args) {
+        panic!("Invalid duration: {}", e);
+    }
+    args.gate = None;
+    args.gate_rate = 8.0;
+    args.fade_out_ms = 20.0;
+    args.preview_ms = 0.0;
+    args.no_device = false;
+    args.sample_rate = 44100;
+    args.channels = 1;
+    if let Err(e) = check_format(args.sample_rate, args.channels) {
+        panic!("Invalid file format: {}", e);
+    }
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("ARGUMENTS:");
+        eprintln!("- {}", args.describe());
+    }
+    if args.no_limit {
+        log(args.log_level, "- WARNING: Clip guard off (--no-limit)");
+    }
+    if args.no_device {
+        if args.log_level >= LogLevel::Normal {
+            eprintln!("NO DEVICE");
+            eprintln!("- Generating arrays at {}Hz", args.sample_rate);
+        }
+        generate_arrays(args.sample_rate, &args, Path::new(&args.out_dir), None);
+        return;
+    }
+    // The device and its configs are only interesting when debugging, keep them to verbose
+    let setup_level = if args.log_level >= LogLevel::Verbose { LogLevel::Normal } else { LogLevel::Silent };
+    log(setup_level, "SETUP");
+    let (dev, conf, _) = setup_default_device_default_config(setup_level)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let conf = force_sample_format(&dev, conf, args.sample_format)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(format) = args.sample_format {
+        log(args.log_level, &format!("- Sample format forced to {}", format));
+    }
+    // The stream only lives as long as it's held, keep it until the callback has faded out
+    if let Some((_stream, faded)) = dispatch_run(&mut CpalBackend { dev: &dev }, conf, args) {
+        faded.wait(PLAY_MS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::backend::MockBackend;
+    use common::sample_format::override_sample_format;
+    use cpal::{SampleFormat, SampleRate, SupportedBufferSize, SupportedStreamConfigRange};
+
+    fn render(len: usize, threads: usize, args: &RunArgs) -> Vec<f32> {
+        let carrier_wave = waveform_fn(args.carrier.0);
+        let next_value: Vec<WaveformFn> = args.modulators.iter().map(|m| waveform_fn(m.0)).collect();
+        render_fm_parallel(len, threads, 44100.0, &carrier_wave, &fm_patches(args).unwrap(), &next_value, args)
+    }
+
+    #[test]
+    fn parallel_render_matches_serial() {
+        let mut args = RunArgs::new(
+            LogLevel::Silent,
+            (WaveformKind::Sine, 440.0),
+            vec![(WaveformKind::Sine, 220.0, 100.0), (WaveformKind::OnOff(0.5), 4.0, 0.5)],
+            true,
+        );
+        args.carrier_freq_end = Some(880.0);
+        let serial = render(1_000_000, 1, &args);
+        let parallel = render(1_000_000, 7, &args);
+        // Bit-identical, not just close
+        assert!(serial.iter().zip(parallel.iter()).all(|(a, b)| a.to_bits() == b.to_bits()));
+        assert_eq!(serial.len(), parallel.len());
+    }
+
+    #[test]
+    fn no_device_generates_a_wav() {
+        let mut args = RunArgs::new(LogLevel::Silent, (WaveformKind::Sine, 440.0), vec![], true);
+        args.no_device = true;
+        args.sample_rate = 22050;
+        let dir = std::env::temp_dir().join("p4fmsynth-no-device");
+        std::fs::create_dir_all(&dir).unwrap();
+        // Same path main takes with --no-device, there is no device or stream anywhere in it
+        generate_arrays(args.sample_rate, &args, &dir, None);
+        let wav = std::fs::read(dir.join("samples.wav")).unwrap();
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..12], b"WAVE");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 22050);
+        // One second of 16 bit mono after the 44 byte header
+        assert_eq!(wav.len(), 44 + 22050 * 2);
+        // The file's format is only what was asked for, here 48kHz stereo
+        args.sample_rate = 48000;
+        args.channels = 2;
+        generate_arrays(args.sample_rate, &args, &dir, None);
+        let wav = std::fs::read(dir.join("samples.wav")).unwrap();
+        assert_eq!(u16::from_le_bytes(wav[22..24].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 48000);
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 48000 * 2 * 2);
+        assert_eq!(wav.len(), 44 + 48000 * 2 * 2);
+    }
+
+    #[test]
+    fn waveform_display_is_lowercase() {
+        assert_eq!(WaveformKind::Silence.to_string(), "silence");
+        assert_eq!(WaveformKind::Sine.to_string(), "sine");
+        assert_eq!(WaveformKind::Square.to_string(), "square");
+        assert_eq!(WaveformKind::Sawtooth.to_string(), "sawtooth");
+        assert_eq!(WaveformKind::Triangle.to_string(), "triangle");
+        assert_eq!(WaveformKind::OnOff(0.25).to_string(), "onoff (duty 0.25)");
+    }
+
+    #[test]
+    fn describe_reads_like_the_patch() {
+        let args = RunArgs::new(LogLevel::Silent, (WaveformKind::Sine, 440.0), vec![(WaveformKind::Sine, 1760.0, 22.0)], false);
+        assert!(args.describe().starts_with("Carrier: sine @ 440 Hz; Modulators: sine @ 1760 Hz (depth 22)"));
+    }
+
+    #[test]
+    fn onoff_duty_is_the_fraction_of_the_period_that_is_on() {
+        let onoff = waveform_fn(WaveformKind::OnOff(0.25));
+        // 10Hz at 1kHz, 100 samples a period
+        for n in 0..300 {
+            let expected = if n % 100 < 25 { 1.0 } else { 0.0 };
+            assert_eq!(onoff(n as f32, 1000.0, 10.0), expected, "sample {}", n);
+        }
+    }
+
+    // Magnitude of the `hz` bin of one second at 44.1kHz
+    fn magnitude(vals: &[f32], hz: usize) -> f32 {
+        let (re, im) = vals.iter().enumerate().fold((0_f32, 0_f32), |(re, im), (n, v)| {
+            let angle = 2.0 * std::f32::consts::PI * ((hz * n) % 44100) as f32 / 44100.0;
+            (re + v * angle.cos(), im - v * angle.sin())
+        });
+        (re * re + im * im).sqrt() / 22050.0
+    }
+
+    #[test]
+    fn fm_chord_has_the_sidebands_of_every_note() {
+        // Two notes with the modulator at a quarter of each, light enough that the first sidebands stand out
+        let patches: Vec<Patch> = vec![
+            (440.0, vec![(WaveformKind::Sine, 110.0, 0.05)]),
+            (660.0, vec![(WaveformKind::Sine, 165.0, 0.05)]),
+        ];
+        let sine = waveform_fn(WaveformKind::Sine);
+        let next_value = vec![waveform_fn(WaveformKind::Sine)];
+        let mut feedback: Vec<FeedbackState> = patches.iter().map(|_| FeedbackState::new(&[], 1)).collect();
+        let vals: Vec<f32> = (0..44100)
+            .map(|n| fm_chord_sample(n as f32, 44100.0, &sine, &patches, &next_value, &mut feedback))
+            .collect();
+        let floor = magnitude(&vals, 1000);
+        for hz in [330, 550, 495, 825] {
+            assert!(magnitude(&vals, hz) > 10.0 * floor, "{}Hz", hz);
+        }
+    }
+
+    #[test]
+    fn fm_chord_tracks_the_modulators_and_needs_a_carrier() {
+        let mut args = RunArgs::new(LogLevel::Silent, (WaveformKind::Sine, 440.0), vec![(WaveformKind::Sine, 880.0, 10.0)], true);
+        args.fm_chord = Some("maj:A4".to_string());
+        let patches = fm_patches(&args).unwrap();
+        assert_eq!(patches.len(), 3);
+        for (freq, modulators) in patches.iter() {
+            assert!((modulators[0].1 / freq - 2.0).abs() < 1e-4);
+        }
+        args.carrier.1 = 0.0;
+        assert!(fm_patches(&args).is_err());
+    }
+
+    #[test]
+    fn feedback_on_a_zero_hz_modulator_has_no_offset() {
+        let mut feedback = FeedbackState::new(&[1.0], 1);
+        feedback.prev[0] = 1.0;
+        assert!(feedback.clock_offset(0, 44100.0, 440.0) > 0.0);
+        assert_eq!(feedback.clock_offset(0, 44100.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn trimming_keeps_exactly_the_non_silent_region() {
+        let mut vals = vec![0.0001_f32; 100];
+        // Quiet in the middle too, that has to stay
+        vals.extend([0.5, 0.0, 0.0, -0.5]);
+        vals.extend(vec![0_f32; 50]);
+        assert_eq!(non_silent_range(&vals, -60.0), Some(100..104));
+        assert_eq!(non_silent_range(&[0.0001_f32; 10], -60.0), None);
+    }
+
+    // Rising zero crossings, interpolated between the samples either side
+    fn rising_crossings(vals: &[f32]) -> Vec<f32> {
+        vals.windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] <= 0.0 && w[1] > 0.0)
+            .map(|(n, w)| n as f32 + w[0] / (w[0] - w[1]))
+            .collect()
+    }
+
+    #[test]
+    fn glide_starts_and_ends_at_its_frequencies() {
+        let mut args = RunArgs::new(LogLevel::Silent, (WaveformKind::Sine, 220.0), vec![], true);
+        args.carrier_freq_end = Some(880.0);
+        let vals = render(44100, 1, &args);
+        let crossings = rising_crossings(&vals);
+        let first = 44100.0 / (crossings[1] - crossings[0]);
+        let last = 44100.0 / (crossings[crossings.len() - 1] - crossings[crossings.len() - 2]);
+        // One period in, the glide has only moved a fraction of a semitone
+        assert!((first / 220.0 - 1.0).abs() < 0.01, "{}", first);
+        assert!((last / 880.0 - 1.0).abs() < 0.01, "{}", last);
+    }
+
+    #[test]
+    fn zero_feedback_matches_no_feedback() {
+        let mut args = RunArgs::new(LogLevel::Silent, (WaveformKind::Sine, 440.0), vec![(WaveformKind::Sine, 220.0, 100.0)], true);
+        let plain = render(44100, 1, &args);
+        args.modulator_feedback = vec![0.0];
+        let zero = render(44100, 1, &args);
+        assert!(plain.iter().zip(zero.iter()).all(|(a, b)| a.to_bits() == b.to_bits()));
+    }
+
+    #[test]
+    fn more_feedback_adds_higher_harmonics() {
+        // The modulator's own output (what the feedback works on) for one second of a 100Hz sine operator
+        let operator = |amount: f32| {
+            let modulators = vec![(WaveformKind::Sine, 100.0, 1.0)];
+            let sine = waveform_fn(WaveformKind::Sine);
+            let next_value = vec![waveform_fn(WaveformKind::Sine)];
+            let mut feedback = FeedbackState::new(&[amount], 1);
+            (0..44100)
+                .map(|n| {
+                    fm_sample(n as f32, 44100.0, &sine, 440.0, &modulators, &next_value, &mut feedback);
+                    feedback.prev[0]
+                })
+                .collect::<Vec<f32>>()
+        };
+        // Energy from the 2nd to the 5th harmonic against the fundamental
+        let upper = |vals: &[f32]| (2..=5).map(|k| magnitude(vals, 100 * k)).sum::<f32>() / magnitude(vals, 100);
+        let none = upper(&operator(0.0));
+        let some = upper(&operator(0.5));
+        let more = upper(&operator(1.0));
+        assert!(none < 0.01, "{}", none);
+        assert!(some > none + 0.1, "{}", some);
+        assert!(more > some + 0.1, "{}", more);
+    }
+
+    fn mono_f32() -> SupportedStreamConfig {
+        SupportedStreamConfig::new(1, SampleRate(44100), SupportedBufferSize::Unknown, SampleFormat::F32)
+    }
+
+    #[test]
+    fn dispatch_run_plays_the_patch_into_a_mock() {
+        let args = RunArgs::new(LogLevel::Silent, (WaveformKind::Sine, 440.0), vec![(WaveformKind::Sine, 220.0, 100.0)], false);
+        let mut backend = MockBackend::new(4410, 512);
+        let (_stream, faded) = dispatch_run(&mut backend, mono_f32(), args.clone()).unwrap();
+        // A tenth of a second in, far from the end of the playback
+        assert!(!faded.is_set());
+        // The FM patch itself at the playback volume
+        let expected = render(4410, 1, &args);
+        assert_eq!(backend.captured().len(), 4410);
+        for (n, (sample, expected)) in backend.captured().iter().zip(expected.iter()).enumerate() {
+            assert!((sample - expected * 0.3333).abs() < 1e-6, "sample {}", n);
+        }
+    }
+
+    #[test]
+    fn generating_arrays_returns_no_stream() {
+        let mut args = RunArgs::new(LogLevel::Silent, (WaveformKind::Sine, 440.0), vec![], true);
+        args.preview_ms = 100.0;
+        let dir = std::env::temp_dir().join("p4fmsynth-no-stream");
+        std::fs::create_dir_all(&dir).unwrap();
+        args.out_dir = dir.to_string_lossy().into_owned();
+        // The mock has no device so the preview is skipped
+        let mut backend = MockBackend::new(64, 64);
+        assert!(dispatch_run(&mut backend, mono_f32(), args).is_none());
+        assert!(backend.captured().is_empty());
+        assert!(dir.join("samples.wav").exists());
+    }
+
+    #[test]
+    fn forced_sample_format_picks_the_run_for_that_type() {
+        let args = RunArgs::new(LogLevel::Silent, (WaveformKind::Sine, 440.0), vec![], false);
+        let range = |format| SupportedStreamConfigRange::new(1, SampleRate(8000), SampleRate(96000), SupportedBufferSize::Unknown, format);
+        // A device whose default is f32 that also takes i16
+        let supported = [range(SampleFormat::F32), range(SampleFormat::I16)];
+        let mut backend = MockBackend::new(441, 441);
+        dispatch_run(&mut backend, mono_f32(), args.clone()).unwrap();
+        assert_eq!(backend.sample_format(), Some(SampleFormat::F32));
+        let forced = override_sample_format(mono_f32(), &supported, SampleFormat::I16).unwrap();
+        let mut backend = MockBackend::new(441, 441);
+        dispatch_run(&mut backend, forced, args).unwrap();
+        assert_eq!(backend.sample_format(), Some(SampleFormat::I16));
+        // Went through i16, every sample is on its grid
+        assert!(backend.captured().iter().all(|s| (s * 32768.0).fract() == 0.0));
+        assert!(override_sample_format(mono_f32(), &supported, SampleFormat::U8).is_err());
+    }
+
+    #[test]
+    fn waveform_kind_parses_the_shared_names() {
+        assert_eq!("SAW".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("sawtooth".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("Sine".parse::<WaveformKind>(), Ok(WaveformKind::Sine));
+        let err = "bogus".parse::<WaveformKind>().unwrap_err();
+        assert!(err.contains("saw/sawtooth"), "{}", err);
+        assert_eq!("onoff".parse::<WaveformKind>(), Ok(WaveformKind::OnOff(0.5)));
+    }
+}
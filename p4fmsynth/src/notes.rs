@@ -0,0 +1,51 @@
+// Chords on top of the shared note names
+
+use common::notes::note_name_to_freq;
+
+// Semitones above the root for each chord quality
+fn chord_intervals(quality: &str) -> Option<&'static [i32]> {
+    match quality {
+        "maj" => Some(&[0, 4, 7]),
+        "min" => Some(&[0, 3, 7]),
+        "dim" => Some(&[0, 3, 6]),
+        "aug" => Some(&[0, 4, 8]),
+        "sus2" => Some(&[0, 2, 7]),
+        "sus4" => Some(&[0, 5, 7]),
+        "7" => Some(&[0, 4, 7, 10]),
+        "maj7" => Some(&[0, 4, 7, 11]),
+        "min7" => Some(&[0, 3, 7, 10]),
+        _ => None,
+    }
+}
+
+// Parses a chord like "maj:A4" (quality:root) into the frequency of each note
+pub fn parse_chord(spec: &str) -> Result<Vec<f32>, String> {
+    let (quality, root) = spec
+        .split_once(':')
+        .ok_or_else(|| format!("chord '{}' should look like quality:root, e.g. maj:A4", spec))?;
+    let intervals = chord_intervals(quality.trim()).ok_or_else(|| {
+        format!(
+            "unknown chord quality '{}', expected one of maj, min, dim, aug, sus2, sus4, 7, maj7, min7",
+            quality
+        )
+    })?;
+    let root = note_name_to_freq(root)?;
+    Ok(intervals
+        .iter()
+        .map(|i| root * 2f32.powf(*i as f32 / 12.0))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chord_notes_from_the_root() {
+        let chord = parse_chord("maj:A4").unwrap();
+        assert_eq!(chord.len(), 3);
+        assert_eq!(chord[0], 440.0);
+        assert!((chord[2] - 659.255).abs() < 1e-2);
+        assert!(parse_chord("A4").is_err() && parse_chord("wide:A4").is_err());
+    }
+}
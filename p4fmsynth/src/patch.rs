@@ -0,0 +1,201 @@
+// Loads the carrier and modulators from a TOML patch file instead of editing main and recompiling
+//
+// [carrier]
+// wave = "sine"
+// freq = 440.0
+//
+// [[modulators]]
+// wave = "onoff"
+// freq = 4.0
+// depth = 1.0
+// duty = 0.25
+//
+// An onoff modulator gates the other modulators, it doesn't add to the carrier frequency like it did before
+// it had a duty, see patches/gated.toml
+
+use common::rng::XorShift;
+use serde::Deserialize;
+
+use crate::WaveformKind;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatchFile {
+    carrier: WaveSpec,
+    #[serde(default)]
+    modulators: Vec<WaveSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WaveSpec {
+    wave: String,
+    freq: f32,
+    // Only used by modulators
+    #[serde(default)]
+    depth: f32,
+    // Only used by onoff, defaults to 0.5 (the duty it had before it was configurable)
+    duty: Option<f32>,
+}
+
+fn parse_waveform(field: &str, spec: &WaveSpec) -> Result<WaveformKind, String> {
+    let kind = match spec.wave.parse::<WaveformKind>().map_err(|e| format!("{}.wave: {}", field, e))? {
+        WaveformKind::OnOff(default) => {
+            let duty = spec.duty.unwrap_or(default);
+            if !(0.0..=1.0).contains(&duty) {
+                return Err(format!("{}.duty: {} is outside 0..=1", field, duty));
+            }
+            WaveformKind::OnOff(duty)
+        }
+        kind => kind,
+    };
+    if spec.duty.is_some() && !matches!(kind, WaveformKind::OnOff(_)) {
+        return Err(format!("{}.duty: only onoff has a duty", field));
+    }
+    if !spec.freq.is_finite() || spec.freq <= 0.0 {
+        return Err(format!("{}.freq: {} should be a positive frequency", field, spec.freq));
+    }
+    if !spec.depth.is_finite() {
+        return Err(format!("{}.depth: {} is not a number", field, spec.depth));
+    }
+    Ok(kind)
+}
+
+// Carrier (Wave, Freq) and modulators (Wave, Freq, Depth) as they go into RunArgs
+pub type LoadedPatch = ((WaveformKind, f32), Vec<(WaveformKind, f32, f32)>);
+
+pub fn load_patch(path: &str) -> Result<LoadedPatch, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let patch: PatchFile = toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let carrier = (parse_waveform("carrier", &patch.carrier)?, patch.carrier.freq);
+    let modulators = patch
+        .modulators
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let kind = parse_waveform(&format!("modulators[{}]", i), m)?;
+            Ok((kind, m.freq, m.depth))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok((carrier, modulators))
+}
+
+// Modulator to carrier frequency ratios a random patch picks from, simple ratios keep the sidebands harmonic
+pub const RANDOM_RATIOS: [f32; 7] = [0.5, 1.0, 1.5, 2.0, 3.0, 4.0, 5.0];
+// Range of the random modulator depths in Hz, the telephone patch uses 22
+const RANDOM_DEPTH: (f32, f32) = (2.0, 40.0);
+
+// Random carrier wave at `carrier_freq` with 1-3 sine modulators at ratios from RANDOM_RATIOS, the same seed is the same patch
+pub fn random_patch(seed: u32, carrier_freq: f32) -> LoadedPatch {
+    let mut rng = XorShift::new(seed);
+    let waves = [
+        WaveformKind::Sine,
+        WaveformKind::Square,
+        WaveformKind::Sawtooth,
+        WaveformKind::Triangle,
+    ];
+    let carrier = (waves[rng.below(waves.len())], carrier_freq);
+    let count = 1 + rng.below(3);
+    let modulators = (0..count)
+        .map(|_| {
+            let ratio = RANDOM_RATIOS[rng.below(RANDOM_RATIOS.len())];
+            let depth = rng.range(RANDOM_DEPTH.0, RANDOM_DEPTH.1);
+            (WaveformKind::Sine, carrier_freq * ratio, (depth * 10.0).round() / 10.0)
+        })
+        .collect();
+    (carrier, modulators)
+}
+
+fn wave_spec_toml(table: &str, kind: &WaveformKind, freq: f32) -> String {
+    match kind {
+        WaveformKind::OnOff(duty) => format!("[{}]\nwave = \"onoff\"\nfreq = {:?}\nduty = {:?}\n", table, freq, duty),
+        other => format!("[{}]\nwave = \"{}\"\nfreq = {:?}\n", table, other, freq),
+    }
+}
+
+// The patch in the patch file format, so a random one can be saved and loaded with `patch_file`
+pub fn patch_to_toml(patch: &LoadedPatch) -> String {
+    let ((carrier, carrier_freq), modulators) = patch;
+    let mut out = wave_spec_toml("carrier", carrier, *carrier_freq);
+    for (kind, freq, depth) in modulators {
+        out += "\n";
+        out += &wave_spec_toml("[modulators]", kind, *freq);
+        out += &format!("depth = {:?}\n", depth);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(wave: &str, duty: Option<f32>) -> WaveSpec {
+        WaveSpec {
+            wave: wave.to_string(),
+            freq: 4.0,
+            depth: 1.0,
+            duty,
+        }
+    }
+
+    #[test]
+    fn onoff_without_a_duty_keeps_the_old_half_duty() {
+        assert_eq!(parse_waveform("m", &spec("onoff", None)), Ok(WaveformKind::OnOff(0.5)));
+        assert_eq!(parse_waveform("m", &spec("onoff", Some(0.25))), Ok(WaveformKind::OnOff(0.25)));
+    }
+
+    #[test]
+    fn duty_is_checked() {
+        assert!(parse_waveform("m", &spec("onoff", Some(1.5))).is_err());
+        assert!(parse_waveform("m", &spec("sine", Some(0.5))).is_err());
+    }
+
+    // Absolute so it doesn't matter where the tests run from
+    fn patch_path(name: &str) -> String {
+        format!("{}/patches/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn sample_patch_loads() {
+        let patch = load_patch(&patch_path("telephone.toml")).unwrap();
+        assert_eq!(patch, ((WaveformKind::Sine, 440.0), vec![(WaveformKind::Square, 1760.0, 22.0)]));
+        let gated = load_patch(&patch_path("gated.toml")).unwrap();
+        assert_eq!(gated.1[1], (WaveformKind::OnOff(0.25), 4.0, 1.0));
+    }
+
+    #[test]
+    fn bad_wave_name_names_the_field() {
+        let err = parse_waveform("modulators[1]", &spec("sinus", None)).unwrap_err();
+        assert!(err.starts_with("modulators[1].wave"), "{}", err);
+    }
+
+    #[test]
+    fn same_seed_rolls_the_same_patch() {
+        assert_eq!(random_patch(1234, 440.0), random_patch(1234, 440.0));
+        let patches: Vec<LoadedPatch> = (0..20).map(|seed| random_patch(seed, 440.0)).collect();
+        assert!(patches.iter().any(|patch| *patch != patches[0]));
+    }
+
+    #[test]
+    fn random_ratios_and_depths_stay_in_range() {
+        for seed in 0..200 {
+            let ((_, carrier_freq), modulators) = random_patch(seed, 220.0);
+            assert_eq!(carrier_freq, 220.0);
+            assert!((1..=3).contains(&modulators.len()), "seed {}", seed);
+            for (kind, freq, depth) in modulators {
+                assert_eq!(kind, WaveformKind::Sine);
+                assert!(RANDOM_RATIOS.contains(&(freq / 220.0)), "seed {}: {}", seed, freq);
+                assert!((RANDOM_DEPTH.0..=RANDOM_DEPTH.1).contains(&depth), "seed {}: {}", seed, depth);
+            }
+        }
+    }
+
+    #[test]
+    fn patch_prints_as_patch_file_tables() {
+        let patch = ((WaveformKind::Sine, 440.0), vec![(WaveformKind::Square, 1760.0, 22.0)]);
+        assert_eq!(
+            patch_to_toml(&patch),
+            "[carrier]\nwave = \"sine\"\nfreq = 440.0\n\n[[modulators]]\nwave = \"square\"\nfreq = 1760.0\ndepth = 22.0\n"
+        );
+    }
+}
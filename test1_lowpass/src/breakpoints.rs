@@ -0,0 +1,96 @@
+// Amplitude automation from a breakpoint file, one "time_ms,value" pair per line, linearly interpolated
+//
+// # fade in over half a second, hold, then out
+// 0,0
+// 500,1
+// 1500,1
+// 2000,0
+
+pub struct Breakpoints {
+    // (sample, gain) sorted by sample, gains clamped to 0..=1
+    points: Vec<(f32, f32)>,
+}
+
+impl Breakpoints {
+    // Blank lines and lines starting with '#' are skipped, times have to go up (equal times make a step)
+    pub fn parse(text: &str, sample_rate: f32) -> Result<Self, String> {
+        let mut points: Vec<(f32, f32)> = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (time, value) = line
+                .split_once(',')
+                .ok_or(format!("line {}: expected time_ms,value, got '{}'", n + 1, line))?;
+            let parse = |v: &str| {
+                v.trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|v| v.is_finite())
+                    .ok_or(format!("line {}: '{}' is not a number", n + 1, v.trim()))
+            };
+            let sample = parse(time)? / 1000.0 * sample_rate;
+            if points.last().is_some_and(|(last, _)| sample < *last) {
+                return Err(format!("line {}: time {} goes back from the line before", n + 1, time.trim()));
+            }
+            points.push((sample, parse(value)?.clamp(0.0, 1.0)));
+        }
+        if points.is_empty() {
+            return Err("no breakpoints".to_string());
+        }
+        Ok(Self { points })
+    }
+
+    pub fn load(path: &str, sample_rate: f32) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&text, sample_rate).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Gain at sample `n`, before the first and after the last breakpoint it holds their value
+    pub fn gain(&self, n: usize) -> f32 {
+        let n = n as f32;
+        let after = self.points.partition_point(|(sample, _)| *sample <= n);
+        if after == 0 {
+            return self.points[0].1;
+        }
+        if after == self.points.len() {
+            return self.points[after - 1].1;
+        }
+        let (start, from) = self.points[after - 1];
+        let (end, to) = self.points[after];
+        from + (to - from) * (n - start) / (end - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_point_ramp_is_a_linear_fade_in() {
+        // One second at 1kHz
+        let envelope = Breakpoints::parse("0,0\n1000,1\n", 1000.0).unwrap();
+        for n in 0..=1000 {
+            assert!((envelope.gain(n) - n as f32 / 1000.0).abs() < 1e-6, "sample {}", n);
+        }
+        // Past the end it holds the last value
+        assert_eq!(envelope.gain(5000), 1.0);
+    }
+
+    #[test]
+    fn values_clamp_and_hold_before_the_first_point() {
+        let envelope = Breakpoints::parse("# comment\n\n100,2\n200,-1\n", 1000.0).unwrap();
+        assert_eq!(envelope.gain(0), 1.0);
+        assert_eq!(envelope.gain(150), 0.5);
+        assert_eq!(envelope.gain(300), 0.0);
+    }
+
+    #[test]
+    fn bad_files_are_errors() {
+        assert!(Breakpoints::parse("", 1000.0).is_err());
+        assert!(Breakpoints::parse("0 0\n", 1000.0).err().unwrap().contains("line 1"));
+        assert!(Breakpoints::parse("0,x\n", 1000.0).is_err());
+        assert!(Breakpoints::parse("100,0\n50,1\n", 1000.0).err().unwrap().contains("goes back"));
+    }
+}
@@ -0,0 +1,120 @@
+// FFT shared by the THD measurement and the brickwall lowpass
+
+// In place radix-2 FFT, re.len() must be a power of two
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f32::consts::PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Inverse of `fft`, same length rules
+fn ifft(re: &mut [f32], im: &mut [f32]) {
+    // Conjugate, forward transform, conjugate again and scale
+    for v in im.iter_mut() {
+        *v = -*v;
+    }
+    fft(re, im);
+    let n = re.len() as f32;
+    for (r, i) in re.iter_mut().zip(im.iter_mut()) {
+        *r /= n;
+        *i = -*i / n;
+    }
+}
+
+// Zeroes every bin above `cutoff` Hz of the whole buffer, as steep as a filter gets
+// Offline only as it needs all the samples, they're zero padded to a power of two and trimmed back after
+// Expect some ringing (Gibbs) around sharp edges and at the ends where the padding starts
+pub fn brickwall_lowpass(samples: &[f32], sample_rate: f32, cutoff: f32) -> Vec<f32> {
+    if samples.is_empty() {
+        return vec![];
+    }
+    let n = samples.len().next_power_of_two();
+    let mut re = samples.to_vec();
+    re.resize(n, 0.0);
+    let mut im = vec![0_f32; n];
+    fft(&mut re, &mut im);
+    let bin_hz = sample_rate / n as f32;
+    for k in 0..n {
+        // Bins past the middle are the negative frequencies, mirrored
+        if k.min(n - k) as f32 * bin_hz > cutoff {
+            re[k] = 0.0;
+            im[k] = 0.0;
+        }
+    }
+    ifft(&mut re, &mut im);
+    re.truncate(samples.len());
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One second of a sine at 44.1kHz, not a power of two long so it gets padded
+    fn sine(hz: f32) -> Vec<f32> {
+        (0..44100)
+            .map(|n| (2.0 * std::f32::consts::PI * hz * n as f32 / 44100.0).sin())
+            .collect()
+    }
+
+    // RMS away from the ends, where the padding rings
+    fn middle_rms(samples: &[f32]) -> f32 {
+        let middle = &samples[4410..samples.len() - 4410];
+        (middle.iter().map(|v| v * v).sum::<f32>() / middle.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn sine_above_the_cutoff_is_removed() {
+        let filtered = brickwall_lowpass(&sine(12000.0), 44100.0, 8000.0);
+        assert_eq!(filtered.len(), 44100);
+        assert!(middle_rms(&filtered) < 0.01, "{}", middle_rms(&filtered));
+    }
+
+    #[test]
+    fn sine_below_the_cutoff_passes() {
+        let input = sine(1000.0);
+        let filtered = brickwall_lowpass(&input, 44100.0, 8000.0);
+        let error: Vec<f32> = input.iter().zip(filtered.iter()).map(|(a, b)| a - b).collect();
+        assert!(middle_rms(&error) < 0.01, "{}", middle_rms(&error));
+        assert!(brickwall_lowpass(&[], 44100.0, 8000.0).is_empty());
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let samples: Vec<f32> = (0..64).map(|n| ((n * 7) % 13) as f32 - 6.0).collect();
+        let (mut re, mut im) = (samples.clone(), vec![0_f32; 64]);
+        fft(&mut re, &mut im);
+        ifft(&mut re, &mut im);
+        assert!(re.iter().zip(samples.iter()).all(|(a, b)| (a - b).abs() < 1e-4));
+        assert!(im.iter().all(|v| v.abs() < 1e-4));
+    }
+}
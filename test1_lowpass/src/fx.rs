@@ -0,0 +1,457 @@
+// Effects applied after the lowpass
+
+use common::pan::pan;
+
+// Circular buffer, reading gives back what was written `len` samples ago
+struct DelayLine {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl DelayLine {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len.max(1)],
+            index: 0,
+        }
+    }
+
+    // The sample written `len` samples ago
+    fn read(&self) -> f32 {
+        self.buffer[self.index]
+    }
+
+    // The sample written `delay` (1..=len, fractional) samples ago, linearly interpolated
+    fn read_at(&self, delay: f32) -> f32 {
+        let len = self.buffer.len();
+        let delay = delay.clamp(1.0, len as f32);
+        let whole = delay.floor() as usize;
+        let frac = delay - whole as f32;
+        let newer = self.buffer[(self.index + len - whole) % len];
+        let older = self.buffer[(self.index + len - (whole + 1).min(len)) % len];
+        newer + (older - newer) * frac
+    }
+
+    // Replaces the sample just read and moves on
+    fn write(&mut self, input: f32) {
+        self.buffer[self.index] = input;
+        self.index = (self.index + 1) % self.buffer.len();
+    }
+}
+
+// Number of samples in `delay_ms`
+pub fn delay_samples(delay_ms: f32, sample_rate: f32) -> usize {
+    (delay_ms / 1000.0 * sample_rate).round() as usize
+}
+
+// Delay time of a note value at `bpm`, "1/8" is an eighth note: (60 / bpm) * 1/8 * 4 seconds
+// A trailing '.' makes it dotted (x1.5), a trailing 't' a triplet (x2/3), e.g. "1/8." or "1/4t"
+pub fn note_delay_ms(note: &str, bpm: f32) -> Result<f32, String> {
+    if !(bpm.is_finite() && bpm > 0.0) {
+        return Err(format!("bpm should be above 0, got {}", bpm));
+    }
+    let note = note.trim();
+    let (value, modifier) = if let Some(value) = note.strip_suffix('.') {
+        (value, 1.5)
+    } else if let Some(value) = note.strip_suffix('t') {
+        (value, 2.0 / 3.0)
+    } else {
+        (note, 1.0)
+    };
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|v| *v > 0)
+            .ok_or(format!("'{}' is not a note value like 1/4, 1/8. or 1/8t", note))
+    };
+    let fraction = parse(numerator)? as f32 / parse(denominator)? as f32;
+    Ok(60.0 / bpm * fraction * 4.0 * modifier * 1000.0)
+}
+
+// Feedback echo, every echo is `feedback` times quieter than the last
+// `mix` crossfades dry (0) to wet (1) so turning it up doesn't make the whole thing louder
+pub struct Delay {
+    line: DelayLine,
+    feedback: f32,
+    mix: f32,
+}
+
+impl Delay {
+    pub fn new(delay_samples: usize, feedback: f32, mix: f32) -> Self {
+        Self {
+            line: DelayLine::new(delay_samples),
+            feedback,
+            mix,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.line.read();
+        self.line.write(input + self.feedback * delayed);
+        (1.0 - self.mix) * input + self.mix * delayed
+    }
+}
+
+// Stereo echo bouncing between the channels
+// Each line feeds the other, so a hit on the left echoes on the right, then back on the left, ...
+pub struct PingPongDelay {
+    left: DelayLine,
+    right: DelayLine,
+    feedback: f32,
+    mix: f32,
+}
+
+impl PingPongDelay {
+    pub fn new(delay_samples: usize, feedback: f32, mix: f32) -> Self {
+        Self {
+            left: DelayLine::new(delay_samples),
+            right: DelayLine::new(delay_samples),
+            feedback,
+            mix,
+        }
+    }
+
+    pub fn process(&mut self, input: [f32; 2]) -> [f32; 2] {
+        let delayed_left = self.left.read();
+        let delayed_right = self.right.read();
+        // Input is crossed over too, the first echo is on the opposite side
+        self.right.write(input[0] + self.feedback * delayed_left);
+        self.left.write(input[1] + self.feedback * delayed_right);
+        // Dry/wet crossfade like Delay
+        [
+            (1.0 - self.mix) * input[0] + self.mix * delayed_left,
+            (1.0 - self.mix) * input[1] + self.mix * delayed_right,
+        ]
+    }
+}
+
+// Sine low frequency oscillator, -depth..depth
+pub struct Lfo {
+    phase: f32,
+    increment: f32,
+    depth: f32,
+}
+
+impl Lfo {
+    pub fn new(rate_hz: f32, depth: f32, sample_rate: f32) -> Self {
+        Self {
+            phase: 0.0,
+            increment: rate_hz / sample_rate,
+            depth,
+        }
+    }
+
+    pub fn next(&mut self) -> f32 {
+        let value = self.depth * (2.0 * std::f32::consts::PI * self.phase).sin();
+        self.phase = (self.phase + self.increment).fract();
+        value
+    }
+}
+
+// Sweeps the sound between the speakers, the LFO is the pan position (-1 left, 1 right)
+pub struct AutoPan {
+    lfo: Lfo,
+}
+
+impl AutoPan {
+    pub fn new(rate_hz: f32, depth: f32, sample_rate: f32) -> Self {
+        Self {
+            lfo: Lfo::new(rate_hz, depth.clamp(0.0, 1.0), sample_rate),
+        }
+    }
+
+    pub fn process(&mut self, input: [f32; 2]) -> [f32; 2] {
+        pan(input, self.lfo.next())
+    }
+}
+
+// Schroeder allpass, every frequency comes out at the same level but delayed by a different amount
+// (z^-M - g) / (1 - g * z^-M), a short M smears the phase without an audible echo
+struct Allpass {
+    line: DelayLine,
+    gain: f32,
+}
+
+impl Allpass {
+    fn new(delay_samples: usize, gain: f32) -> Self {
+        Self {
+            line: DelayLine::new(delay_samples),
+            gain,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.line.read();
+        let output = delayed - self.gain * input;
+        self.line.write(input + self.gain * output);
+        output
+    }
+}
+
+// Allpass delays of each side, different on the left and right so the phase ends up different at every frequency
+const DECORRELATE_LEFT_MS: [f32; 3] = [1.3, 2.9, 4.7];
+const DECORRELATE_RIGHT_MS: [f32; 3] = [1.7, 3.7, 5.3];
+const DECORRELATE_GAIN: f32 = 0.5;
+
+// Makes a mono sound wide without an obvious delay, each side goes through its own allpass chain
+// The levels stay the same, only the phase changes, `mix` 1 is fully decorrelated and 0 a bypass
+// In between the dry and wet add up to a mild comb filter, the magnitude is only flat at the ends
+pub struct Decorrelator {
+    left: Vec<Allpass>,
+    right: Vec<Allpass>,
+    mix: f32,
+}
+
+impl Decorrelator {
+    pub fn new(mix: f32, sample_rate: f32) -> Self {
+        let chain = |delays: &[f32]| {
+            delays
+                .iter()
+                .map(|ms| Allpass::new(delay_samples(*ms, sample_rate), DECORRELATE_GAIN))
+                .collect()
+        };
+        Self {
+            left: chain(&DECORRELATE_LEFT_MS),
+            right: chain(&DECORRELATE_RIGHT_MS),
+            mix: mix.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn process(&mut self, input: [f32; 2]) -> [f32; 2] {
+        let left = self.left.iter_mut().fold(input[0], |x, allpass| allpass.process(x));
+        let right = self.right.iter_mut().fold(input[1], |x, allpass| allpass.process(x));
+        [
+            input[0] + self.mix * (left - input[0]),
+            input[1] + self.mix * (right - input[1]),
+        ]
+    }
+}
+
+// Stereo widener, splits a frame into mid (what both sides share) and side (the difference) and scales the side
+// Width 1 leaves it as it is, 0 is mono, above 1 pushes the sides further apart
+pub struct MidSide {
+    width: f32,
+}
+
+impl MidSide {
+    pub fn new(width: f32) -> Self {
+        Self { width: width.max(0.0) }
+    }
+
+    pub fn encode(input: [f32; 2]) -> [f32; 2] {
+        [(input[0] + input[1]) / 2.0, (input[0] - input[1]) / 2.0]
+    }
+
+    pub fn decode(input: [f32; 2]) -> [f32; 2] {
+        [input[0] + input[1], input[0] - input[1]]
+    }
+
+    pub fn process(&self, input: [f32; 2]) -> [f32; 2] {
+        let [mid, side] = Self::encode(input);
+        Self::decode([mid, side * self.width])
+    }
+}
+
+// Collapses interleaved stereo to mono, the average of the two channels turned by `gain_db`
+// 0dB gives back the original of two identical channels, -6dB is about half of it
+pub fn mix_to_mono(interleaved: &[f32], gain_db: f32) -> Vec<f32> {
+    let gain = 10_f32.powf(gain_db / 20.0);
+    interleaved
+        .chunks_exact(2)
+        .map(|frame| (frame[0] + frame[1]) / 2.0 * gain)
+        .collect()
+}
+
+// Shortest delay the flanger sweeps around, it's pushed up when the depth needs more room
+const FLANGER_BASE_MS: f32 = 3.0;
+
+// Short delay swept by an LFO, mixed with the dry signal it makes moving comb filter notches (jet sound)
+pub struct Flanger {
+    delay_line: DelayLine,
+    lfo: Lfo,
+    base_samples: f32,
+    depth_samples: f32,
+    feedback: f32,
+    mix: f32,
+}
+
+impl Flanger {
+    // The delay moves between base - depth and base + depth
+    pub fn new(rate_hz: f32, depth_ms: f32, feedback: f32, mix: f32, sample_rate: f32) -> Self {
+        let depth_samples = depth_ms.abs() / 1000.0 * sample_rate;
+        let base_samples = (FLANGER_BASE_MS / 1000.0 * sample_rate).max(depth_samples + 1.0);
+        Self {
+            delay_line: DelayLine::new((base_samples + depth_samples).ceil() as usize + 1),
+            lfo: Lfo::new(rate_hz, 1.0, sample_rate),
+            base_samples,
+            depth_samples,
+            feedback,
+            mix,
+        }
+    }
+
+    // Current delay in samples, steps the LFO
+    fn next_delay(&mut self) -> f32 {
+        self.base_samples + self.depth_samples * self.lfo.next()
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let delay = self.next_delay();
+        let delayed = self.delay_line.read_at(delay);
+        self.delay_line.write(input + self.feedback * delayed);
+        (1.0 - self.mix) * input + self.mix * delayed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_pong_echoes_right_then_left() {
+        let mut delay = PingPongDelay::new(100, 0.5, 1.0);
+        let out: Vec<[f32; 2]> = (0..301)
+            .map(|n| delay.process(if n == 0 { [1.0, 0.0] } else { [0.0, 0.0] }))
+            .collect();
+        assert_eq!(out[100], [0.0, 1.0]);
+        assert_eq!(out[200], [0.5, 0.0]);
+        assert_eq!(out[300], [0.0, 0.25]);
+        // Nothing in between
+        let heard: Vec<usize> = (0..301).filter(|n| out[*n] != [0.0, 0.0]).collect();
+        assert_eq!(heard, vec![100, 200, 300]);
+    }
+
+    #[test]
+    fn delay_mix_crossfades_dry_and_wet() {
+        let mut dry = PingPongDelay::new(10, 0.5, 0.0);
+        let mut half = PingPongDelay::new(10, 0.5, 0.5);
+        assert_eq!(dry.process([1.0, 1.0]), [1.0, 1.0]);
+        assert_eq!(half.process([1.0, 1.0]), [0.5, 0.5]);
+        let mut mono = Delay::new(10, 0.5, 0.5);
+        assert_eq!(mono.process(1.0), 0.5);
+    }
+
+    #[test]
+    fn autopan_is_fully_left_and_right_at_the_lfo_extremes() {
+        // 1Hz at 4Hz, one sample each at the center, the right peak, the center and the left peak
+        let mut autopan = AutoPan::new(1.0, 1.0, 4.0);
+        let frames: Vec<[f32; 2]> = (0..4).map(|_| autopan.process([1.0, 1.0])).collect();
+        let balanced = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((frames[0][0] - balanced).abs() < 1e-6 && (frames[0][1] - balanced).abs() < 1e-6);
+        assert!(frames[1][0].abs() < 1e-6 && (frames[1][1] - 1.0).abs() < 1e-6);
+        assert!(frames[3][1].abs() < 1e-6 && (frames[3][0] - 1.0).abs() < 1e-6);
+        for [left, right] in frames {
+            assert!((left * left + right * right - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn flanger_delay_stays_within_the_depth() {
+        // 2ms either side of the 3ms base at 48kHz is 48..240 samples
+        let mut flanger = Flanger::new(10.0, 2.0, 0.0, 0.5, 48000.0);
+        let delays: Vec<f32> = (0..4800).map(|_| flanger.next_delay()).collect();
+        let (min, max) = delays.iter().fold((f32::MAX, f32::MIN), |(lo, hi), d| (lo.min(*d), hi.max(*d)));
+        assert!((48.0 - 1e-3..49.0).contains(&min), "{}", min);
+        assert!((239.0..=240.0 + 1e-3).contains(&max), "{}", max);
+    }
+
+    #[test]
+    fn flanger_mix_zero_is_a_bypass() {
+        let mut flanger = Flanger::new(0.3, 2.0, 0.5, 0.0, 48000.0);
+        for n in 0..1000 {
+            let input = (n as f32 * 0.1).sin();
+            assert_eq!(flanger.process(input), input);
+        }
+    }
+
+    #[test]
+    fn note_values_at_120_bpm() {
+        let ms = |note: &str| note_delay_ms(note, 120.0).unwrap();
+        assert!((ms("1/4") - 500.0).abs() < 1e-3);
+        assert!((ms("1/8") - 250.0).abs() < 1e-3);
+        assert!((ms("1/8.") - 375.0).abs() < 1e-3);
+        assert!((ms("1/4t") - 1000.0 / 3.0).abs() < 1e-3);
+        assert!((ms("1") - 2000.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn bad_note_values_and_tempos_are_errors() {
+        assert!(note_delay_ms("1/0", 120.0).is_err());
+        assert!(note_delay_ms("eighth", 120.0).is_err());
+        assert!(note_delay_ms("1/8", 0.0).is_err());
+        assert!(note_delay_ms("1/8", f32::NAN).is_err());
+    }
+
+    #[test]
+    fn identical_channels_mix_back_to_the_mono_signal() {
+        let mono: Vec<f32> = (0..100).map(|n| (n as f32 * 0.1).sin()).collect();
+        let stereo: Vec<f32> = mono.iter().flat_map(|v| [*v, *v]).collect();
+        assert_eq!(mix_to_mono(&stereo, 0.0), mono);
+        for (half, v) in mix_to_mono(&stereo, -6.0).iter().zip(mono.iter()) {
+            assert!((half - v / 2.0).abs() < 0.003, "{} {}", half, v);
+        }
+        // Opposite channels cancel
+        assert!(mix_to_mono(&[0.5, -0.5, 1.0, -1.0], 0.0).iter().all(|v| *v == 0.0));
+    }
+
+    #[test]
+    fn mid_side_round_trips() {
+        for frame in [[0.5, -0.25], [1.0, 1.0], [-0.3, 0.7], [0.0, 0.0]] {
+            let back = MidSide::decode(MidSide::encode(frame));
+            assert!((back[0] - frame[0]).abs() < 1e-7 && (back[1] - frame[1]).abs() < 1e-7, "{:?}", back);
+            assert_eq!(MidSide::new(1.0).process(frame), back);
+        }
+    }
+
+    #[test]
+    fn zero_width_makes_both_channels_equal() {
+        let widener = MidSide::new(0.0);
+        for frame in [[0.5, -0.25], [1.0, 0.0], [-0.3, 0.7]] {
+            let [l, r] = widener.process(frame);
+            assert_eq!(l, r);
+            assert!((l - (frame[0] + frame[1]) / 2.0).abs() < 1e-7);
+        }
+    }
+
+    #[test]
+    fn decorrelated_sides_keep_a_flat_magnitude() {
+        const LEN: usize = 8192;
+        let mut decorrelator = Decorrelator::new(1.0, 44100.0);
+        let response: Vec<[f32; 2]> = (0..LEN)
+            .map(|n| decorrelator.process(if n == 0 { [1.0, 1.0] } else { [0.0, 0.0] }))
+            .collect();
+        for side in 0..2 {
+            let mut re: Vec<f32> = response.iter().map(|frame| frame[side]).collect();
+            let mut im = vec![0_f32; LEN];
+            crate::fft::fft(&mut re, &mut im);
+            // The impulse's spectrum is 1 everywhere, an allpass chain leaves it there
+            for k in 0..LEN / 2 {
+                let magnitude = (re[k] * re[k] + im[k] * im[k]).sqrt();
+                assert!((magnitude - 1.0).abs() < 0.01, "side {} bin {}: {}", side, k, magnitude);
+            }
+        }
+        // Same magnitudes, different phases
+        assert!(response.iter().any(|frame| frame[0] != frame[1]));
+    }
+
+    #[test]
+    fn decorrelated_noise_is_less_correlated() {
+        let mut rng = common::rng::XorShift::new(3);
+        let mut decorrelator = Decorrelator::new(1.0, 44100.0);
+        let out: Vec<[f32; 2]> = (0..44100)
+            .map(|_| {
+                let x = rng.next_f32();
+                decorrelator.process([x, x])
+            })
+            .collect();
+        let dot = |a: usize, b: usize| out.iter().map(|frame| frame[a] * frame[b]).sum::<f32>();
+        let correlation = dot(0, 1) / (dot(0, 0) * dot(1, 1)).sqrt();
+        // The input is identical on both sides, a correlation of 1
+        assert!(correlation.abs() < 0.5, "{}", correlation);
+        // The bypass leaves it alone
+        let mut bypass = Decorrelator::new(0.0, 44100.0);
+        assert_eq!(bypass.process([0.3, 0.3]), [0.3, 0.3]);
+    }
+}
@@ -0,0 +1,134 @@
+// Granular synthesis, short Hann windowed grains read from a loaded sample and scattered over the output
+// Grains start at random times (`density` per second on average) from around `position` in the sample
+
+use common::rng::XorShift;
+
+pub struct GrainParams {
+    // Length of each grain in the output
+    pub grain_ms: f32,
+    // Average grains started per second, density * grain length is how many overlap on average
+    pub density: f32,
+    // Playback speed of each grain, 2.0 is an octave up
+    pub pitch: f32,
+    // Where in the sample the grains are read from, 0 is the start and 1 the end
+    pub position: f32,
+    // Random offset of each grain's read position, as a fraction of the sample (0 reads them all from `position`)
+    pub spread: f32,
+}
+
+pub struct Granulator {
+    params: GrainParams,
+    rng: XorShift,
+}
+
+impl Granulator {
+    pub fn new(params: GrainParams, seed: u32) -> Self {
+        Self {
+            params,
+            rng: XorShift::new(seed),
+        }
+    }
+
+    // Grain length in output samples
+    pub fn grain_len(&self, sample_rate: f32) -> usize {
+        ((self.params.grain_ms.max(1.0) / 1000.0) * sample_rate).round() as usize
+    }
+
+    // (output start, sample read position) of every grain starting within `len` output samples
+    // The gaps are exponentially distributed (Poisson arrivals), so the average matches `density` without a pulse
+    pub fn schedule(&mut self, source_len: usize, len: usize, sample_rate: f32) -> Vec<(usize, f32)> {
+        let mean_gap = sample_rate / self.params.density.max(0.01);
+        let center = self.params.position.clamp(0.0, 1.0) * source_len as f32;
+        let mut grains = Vec::new();
+        let mut time = -mean_gap * self.rng.unit().ln();
+        while (time as usize) < len {
+            let offset = (2.0 * self.rng.unit() - 1.0) * self.params.spread.max(0.0) * source_len as f32;
+            grains.push((time as usize, (center + offset).clamp(0.0, source_len as f32)));
+            time += -mean_gap * self.rng.unit().ln();
+        }
+        grains
+    }
+
+    // `len` output samples of grains from `source`, reads past either end of the sample are silent
+    pub fn render(&mut self, source: &[f32], len: usize, sample_rate: f32) -> Vec<f32> {
+        let grain_len = self.grain_len(sample_rate);
+        let pitch = self.params.pitch.max(0.01);
+        let mut out = vec![0_f32; len];
+        if source.is_empty() || grain_len < 2 {
+            return out;
+        }
+        for (start, read) in self.schedule(source.len(), len, sample_rate) {
+            for n in 0..grain_len.min(len - start) {
+                let window = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / (grain_len - 1) as f32).cos();
+                let pos = read + n as f32 * pitch;
+                let i = pos.floor() as usize;
+                if i + 1 >= source.len() {
+                    break;
+                }
+                let frac = pos - i as f32;
+                out[start + n] += window * (source[i] + (source[i + 1] - source[i]) * frac);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> GrainParams {
+        GrainParams {
+            grain_ms: 20.0,
+            density: 50.0,
+            pitch: 1.0,
+            position: 0.5,
+            spread: 0.2,
+        }
+    }
+
+    fn source() -> Vec<f32> {
+        (0..8000).map(|n| (n as f32 * 0.05).sin()).collect()
+    }
+
+    #[test]
+    fn reusing_the_seed_repeats_the_grains() {
+        let first = Granulator::new(params(), 1234).render(&source(), 8000, 8000.0);
+        assert_eq!(Granulator::new(params(), 1234).render(&source(), 8000, 8000.0), first);
+        assert_ne!(Granulator::new(params(), 1235).render(&source(), 8000, 8000.0), first);
+    }
+
+    fn with_density(density: f32) -> GrainParams {
+        GrainParams {
+            grain_ms: 50.0,
+            density,
+            ..params()
+        }
+    }
+
+    #[test]
+    fn density_sets_the_average_overlap() {
+        for density in [20.0, 50.0] {
+            let mut granulator = Granulator::new(with_density(density), 7);
+            let grain_len = granulator.grain_len(1000.0);
+            // 100 seconds at 1kHz, plenty of grains for the average to settle
+            let grains = granulator.schedule(8000, 100_000, 1000.0);
+            let overlap = (grains.len() * grain_len) as f32 / 100_000.0;
+            let expected = density * 50.0 / 1000.0;
+            assert!((overlap - expected).abs() < expected * 0.1, "density {}: {} overlapping", density, overlap);
+        }
+    }
+
+    #[test]
+    fn output_is_bounded_by_the_overlapping_windows() {
+        let source = vec![1.0; 8000];
+        let grains = Granulator::new(with_density(50.0), 7).schedule(source.len(), 8000, 8000.0);
+        let out = Granulator::new(with_density(50.0), 7).render(&source, 8000, 8000.0);
+        let grain_len = Granulator::new(with_density(50.0), 7).grain_len(8000.0);
+        for (n, value) in out.iter().enumerate() {
+            let sounding = grains.iter().filter(|(start, _)| (*start..*start + grain_len).contains(&n)).count();
+            assert!(value.is_finite() && *value >= 0.0 && *value <= sounding as f32 + 1e-4, "{}: {} from {} grains", n, value, sounding);
+        }
+        assert!(out.iter().any(|v| *v > 0.5));
+    }
+}
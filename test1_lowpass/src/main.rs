@@ -1,47 +1,33 @@
 use std::fs::File;
 use std::io::Write;
 
+use common::fade::{fade_out_gain, Faded, PLAY_MS};
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::pan::pan;
+use common::preview::{play_preview, preview_arrays, PreviewFn};
+use common::rng::{resolve_seed, seed_from_time};
+use common::safety::{no_limit_from_args, ClipGuard, DEFAULT_CEILING_DBFS};
+use common::sample_format::{force_sample_format, sample_format_from_args};
+use common::setup::setup_default_device_default_config;
+use common::wav::{check_format, samples_to_raw_pcm, samples_to_wav, upmix, wav_to_raw_audio, BitDepth};
+use common::waveform::WaveformName;
 use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
+    traits::{DeviceTrait, StreamTrait},
     Device, FromSample, Sample, SizedSample, StreamConfig, SupportedStreamConfig,
 };
-use wav::raw_audio_to_wav;
+use breakpoints::Breakpoints;
+use fft::brickwall_lowpass;
+use fx::{AutoPan, Decorrelator, Delay, Flanger, MidSide, PingPongDelay};
+use granular::{GrainParams, Granulator};
+use partial_envelope::PartialEnvelope;
 
-mod wav;
-
-fn setup_default_device_default_config(quiet: bool) -> (Device, SupportedStreamConfig) {
-    if !quiet {
-        eprintln!("SETUP");
-    }
-    let host = cpal::default_host();
-    let dev = host
-        .default_output_device()
-        .expect("Did not find output audio device!");
-    if !quiet {
-        eprintln!(
-            "- Output device: {}",
-            dev.name().expect("Device lacks a name..?")
-        );
-    }
-
-    let conf = dev
-        .default_output_config()
-        .expect("Did not find default output config for device!");
-    if !quiet {
-        eprintln!("- Default output config: {:?}", conf);
-    }
-
-    let supported = dev
-        .supported_output_configs()
-        .expect("Could not list supported configs");
-    if !quiet {
-        eprintln!("- Supported output configs:");
-        for (n, c) in supported.enumerate() {
-            eprintln!("  {}. {:?}", n, c);
-        }
-    }
-    (dev, conf)
-}
+mod breakpoints;
+mod fft;
+mod fx;
+mod granular;
+mod meter;
+mod partial_envelope;
+mod thd;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum WaveformKind {
@@ -52,37 +38,319 @@ enum WaveformKind {
     Triangle,
 }
 
+impl std::fmt::Display for WaveformKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WaveformKind::Silence => write!(f, "silence"),
+            WaveformKind::Sine => write!(f, "sine"),
+            WaveformKind::Square => write!(f, "square"),
+            WaveformKind::Sawtooth => write!(f, "sawtooth"),
+            WaveformKind::Triangle => write!(f, "triangle"),
+        }
+    }
+}
+
+// The shared names in any case ("SAW", "sawtooth", ...)
+impl std::str::FromStr for WaveformKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse::<WaveformName>()? {
+            WaveformName::Silence => Ok(WaveformKind::Silence),
+            WaveformName::Sine => Ok(WaveformKind::Sine),
+            WaveformName::Square => Ok(WaveformKind::Square),
+            WaveformName::Sawtooth => Ok(WaveformKind::Sawtooth),
+            WaveformName::Triangle => Ok(WaveformKind::Triangle),
+            other => Err(format!("{} isn't one of Test 1: Lowpass's waveforms, expected silence, sine, square, saw/sawtooth or triangle", other)),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct RunArgs {
-    quiet: bool,
-    waveforms: Vec<(WaveformKind, f32)>,
+    log_level: LogLevel,
+    waveforms: Vec<(WaveformKind, f32, f32)>,
     generate_arrays: bool,
     cutoff: f32,
+    // Set the lowpass by its time constant instead (ms to reach 63% of a step), replaces `cutoff`
+    filter_tau_ms: Option<f32>,
+    // Directory to also write each waveform as its own wav stem, they sum back to the mono master
+    stems: Option<String>,
+    // Brickwall lowpass (Hz) on the generated arrays, every FFT bin above it is zeroed
+    fft_lowpass: Option<f32>,
+    // Wav file added to the generated arrays (before normalization)
+    mix_file: Option<String>,
+    mix_gain: f32,
+    // Breakpoint file ("time_ms,value" lines) the generated arrays are multiplied by, linearly interpolated
+    envelope_file: Option<String>,
+    // Granular render of this wav file to granular.wav (`granular_ms` long) instead of the generated arrays
+    // Hann windowed `grain_ms` grains, `grain_density` per second, read at `grain_pitch` speed from around `grain_position` (0..1)
+    // `grain_spread` scatters the read positions (fraction of the file), `grain_seed` makes the scatter repeatable
+    // None takes the seed from the clock, main prints the one used so it can be passed back
+    granular: Option<String>,
+    granular_ms: f32,
+    grain_ms: f32,
+    grain_density: f32,
+    grain_pitch: f32,
+    grain_position: f32,
+    grain_spread: f32,
+    grain_seed: Option<u32>,
+    // Attack/release of each partial over the generated arrays, by index into `waveforms`, missing or None holds a steady level
+    partial_envelopes: Vec<Option<PartialEnvelope>>,
+    // Echo on the generated arrays, `ping_pong` writes a stereo wav with the echoes bouncing between channels
+    delay: bool,
+    ping_pong: bool,
+    delay_ms: f32,
+    feedback: f32,
+    delay_mix: f32,
+    // Note value ("1/8", dotted "1/8.", triplet "1/8t") at `bpm` that replaces `delay_ms` when set
+    delay_note: Option<String>,
+    bpm: f32,
+    // Flanger on the generated arrays, a short delay swept by an LFO (rate in Hz, +-depth ms)
+    // mix crossfades dry (0) to delayed (1), 0.5 gives the deepest notches
+    flanger: bool,
+    flanger_rate: f32,
+    flanger_depth_ms: f32,
+    flanger_feedback: f32,
+    flanger_mix: f32,
+    // Stereo wav with the sound swept between the channels by an LFO (rate in Hz, depth 0..1)
+    autopan: bool,
+    autopan_rate: f32,
+    autopan_depth: f32,
+    // Stereo wav with each side through its own allpass chain, wide without an echo (mix 0..1, 1 fully decorrelated)
+    decorrelate: bool,
+    decorrelate_mix: f32,
+    // Pan each partial across a stereo wav, lowest frequency left to highest right, 0 keeps them all centered (mono)
+    stereo_spread: f32,
+    // Mid/side width of a stereo render, 1 as it is, 0 mono, above 1 wider
+    width: f32,
+    // Warn when summing the stereo render to mono is more than `mono_sum_threshold_db` quieter (phase cancellation)
+    mono_sum_check: bool,
+    mono_sum_threshold_db: f32,
+    // Average a stereo render down to a mono wav (after the mono sum check), turned by `mix_to_mono_db` (0, -3 or -6)
+    mix_to_mono: bool,
+    mix_to_mono_db: f32,
+    // Keep playing until Enter is pressed instead of for a fixed duration
+    hold: bool,
+    // Print the combined period of the mix then exit
+    print_period: bool,
+    // Test tone (wave, Hz) put through the lowpass, print its THD then exit
+    analyze_thd: Option<(WaveformKind, f32)>,
+    // Sample format of samples.wav
+    bit_depth: BitDepth,
+    // Also write the samples as headerless s16le (no wav header) to this file
+    raw_file: Option<String>,
+    // Length of the generated arrays instead of the automatic one (a period, or enough for the effects), not both
+    // duration_ms rounds to the nearest sample, duration_samples is exact (FFT windows, checksums)
+    duration_ms: Option<f32>,
+    duration_samples: Option<usize>,
+    // Ramp to silence over the end of the playback so stopping doesn't click (not with `hold`)
+    fade_out_ms: f32,
+    // Plays this much of the generated arrays through the device before writing them, 0 for no preview
+    preview_ms: f32,
+    // Skip the audio device entirely and only generate arrays at `sample_rate`
+    no_device: bool,
+    sample_rate: u32,
+    // Channel count of samples.wav and the raw file (1 or 2), a mono render is copied to each channel
+    channels: u16,
+    // Every sample is clamped to this before it's played, `no_limit` (only from --no-limit on the command line) bypasses it
+    limit_ceiling_dbfs: f32,
+    no_limit: bool,
+    // Output sample format instead of the device default, only from --sample-format on the command line
+    sample_format: Option<cpal::SampleFormat>,
 }
 
 impl RunArgs {
     fn new(
-        quiet: bool,
-        waveforms: Vec<(WaveformKind, f32)>,
+        log_level: LogLevel,
+        waveforms: Vec<(WaveformKind, f32, f32)>,
         generate_arrays: bool,
         cutoff: f32,
     ) -> Self {
         Self {
-            quiet,
+            log_level,
             waveforms,
             generate_arrays,
             cutoff,
+            ..Default::default()
+        }
+    }
+
+    // Human readable summary for the startup output
+    fn describe(&self) -> String {
+        let mix: Vec<String> = self
+            .waveforms
+            .iter()
+            .map(|(k, f, g)| format!("{} @ {} Hz (gain {})", k, f, g))
+            .collect();
+        let mut out = match self.filter_tau_ms {
+            Some(tau) => format!("Mix: {}; Lowpass time constant: {} ms", mix.join(", "), tau),
+            None => format!("Mix: {}; Lowpass cutoff: {} Hz", mix.join(", "), self.cutoff),
+        };
+        if let Some(dir) = self.stems.as_ref() {
+            out += &format!("; Stems: {}", dir);
+        }
+        if let Some(cutoff) = self.fft_lowpass {
+            out += &format!("; FFT brickwall: {} Hz", cutoff);
+        }
+        if let Some(path) = self.mix_file.as_ref() {
+            out += &format!("; Mixing in {} (gain {})", path, self.mix_gain);
+        }
+        if let Some(path) = self.envelope_file.as_ref() {
+            out += &format!("; Envelope: {}", path);
+        }
+        if let Some(path) = self.granular.as_ref() {
+            out += &format!(
+                "; Granular: {} ({} ms grains, {}/s, pitch {}, position {}, spread {}, seed {:?})",
+                path,
+                self.grain_ms,
+                self.grain_density,
+                self.grain_pitch,
+                self.grain_position,
+                self.grain_spread,
+                self.grain_seed
+            );
+        }
+        let enveloped = self
+            .partial_envelopes
+            .iter()
+            .flatten()
+            .filter(|e| e.attack_s > 0.0 || e.release_s > 0.0)
+            .count();
+        if enveloped > 0 {
+            out += &format!("; Partial envelopes: {} of {}", enveloped, self.waveforms.len());
+        }
+        if self.delay || self.ping_pong {
+            out += &format!(
+                "; {}Delay: {} ms, feedback {}, mix {}",
+                if self.ping_pong { "Ping-pong " } else { "" },
+                self.delay_ms,
+                self.feedback,
+                self.delay_mix
+            );
+            if let Some(note) = self.delay_note.as_ref() {
+                out += &format!(" ({} @ {} BPM)", note, self.bpm);
+            }
+        }
+        if self.flanger {
+            out += &format!(
+                "; Flanger: {} Hz, depth {} ms, feedback {}, mix {}",
+                self.flanger_rate, self.flanger_depth_ms, self.flanger_feedback, self.flanger_mix
+            );
+        }
+        if self.autopan {
+            out += &format!("; Auto-pan: {} Hz, depth {}", self.autopan_rate, self.autopan_depth);
+        }
+        if self.decorrelate {
+            out += &format!("; Decorrelate: mix {}", self.decorrelate_mix);
+        }
+        if self.stereo_spread > 0.0 {
+            out += &format!("; Stereo spread: {}", self.stereo_spread);
+        }
+        if self.width != 1.0 {
+            out += &format!("; Width: {}", self.width);
+        }
+        if self.mono_sum_check {
+            out += &format!("; Mono sum check ({} dB)", self.mono_sum_threshold_db);
+        }
+        if self.mix_to_mono {
+            out += &format!("; Mix to mono ({} dB)", self.mix_to_mono_db);
+        }
+        if let Some((kind, freq)) = self.analyze_thd.as_ref() {
+            out += &format!("; THD of {} @ {} Hz", kind, freq);
+        }
+        if self.bit_depth != BitDepth::Int16 {
+            out += &format!("; Bit depth: {}", self.bit_depth);
+        }
+        if let Some(path) = self.raw_file.as_ref() {
+            out += &format!("; Raw s16le: {}", path);
+        }
+        if let Some(ms) = self.duration_ms {
+            out += &format!("; Duration: {} ms", ms);
+        }
+        if let Some(samples) = self.duration_samples {
+            out += &format!("; Duration: {} samples", samples);
+        }
+        if self.no_device {
+            out += &format!("; Generating arrays without a device @ {} Hz", self.sample_rate);
+        } else if self.generate_arrays {
+            out += "; Generating arrays";
+        }
+        if self.channels != 1 {
+            out += &format!("; Files: {} channels", self.channels);
+        }
+        if self.no_limit {
+            out += "; Clip guard OFF";
+        } else if self.limit_ceiling_dbfs != DEFAULT_CEILING_DBFS {
+            out += &format!("; Clip guard ceiling: {} dBFS", self.limit_ceiling_dbfs);
         }
+        if let Some(format) = self.sample_format {
+            out += &format!("; Sample format: {} (forced)", format);
+        }
+        out
     }
 }
 
 impl Default for RunArgs {
     fn default() -> Self {
         Self {
-            quiet: false,
-            waveforms: vec![(WaveformKind::Silence, 0_f32)],
+            log_level: LogLevel::Normal,
+            waveforms: vec![(WaveformKind::Silence, 0_f32, 1_f32)],
             generate_arrays: false,
             cutoff: 22050.0,
+            filter_tau_ms: None,
+            fft_lowpass: None,
+            stems: None,
+            mix_file: None,
+            mix_gain: 0.5,
+            envelope_file: None,
+            granular: None,
+            granular_ms: 2000.0,
+            grain_ms: 50.0,
+            grain_density: 20.0,
+            grain_pitch: 1.0,
+            grain_position: 0.3,
+            grain_spread: 0.02,
+            grain_seed: None,
+            partial_envelopes: vec![],
+            delay: false,
+            ping_pong: false,
+            delay_ms: 300.0,
+            feedback: 0.5,
+            delay_mix: 0.5,
+            delay_note: None,
+            bpm: 120.0,
+            flanger: false,
+            flanger_rate: 0.3,
+            flanger_depth_ms: 2.0,
+            flanger_feedback: 0.5,
+            flanger_mix: 0.5,
+            autopan: false,
+            autopan_rate: 0.5,
+            autopan_depth: 0.8,
+            decorrelate: false,
+            decorrelate_mix: 1.0,
+            stereo_spread: 0.0,
+            width: 1.0,
+            mono_sum_check: false,
+            mix_to_mono: false,
+            mix_to_mono_db: 0.0,
+            mono_sum_threshold_db: 3.0,
+            hold: false,
+            print_period: false,
+            analyze_thd: None,
+            bit_depth: BitDepth::Int16,
+            raw_file: None,
+            duration_ms: None,
+            duration_samples: None,
+            fade_out_ms: 20.0,
+            preview_ms: 0.0,
+            no_device: false,
+            sample_rate: 44100,
+            channels: 1,
+            limit_ceiling_dbfs: DEFAULT_CEILING_DBFS,
+            no_limit: false,
+            sample_format: None,
         }
     }
 }
@@ -128,24 +396,20 @@ fn calculate_amplitude(
     if FAST_AMPLITUDE {
         // Makes some assumptions about max amplitude that I'm not sure I've proven to myself but seems to work
         // for the samples that we are using
+        // Each voice peaks at its gain so the worst case is the sum of the gains
         args.waveforms
             .iter()
-            .filter_map(|(k, f)| {
-                if k != &WaveformKind::Silence && f > &0.0_f32 {
-                    Some((sample_rate / *f).ceil() as usize) // use ceil to ensure the combined waveform period is AT LEAST the entire length of the waveform, without ceil it could be to short from integer truncation
-                } else {
-                    None
-                }
-            })
-            .count() as f32
+            .filter(|(k, f, _)| k != &WaveformKind::Silence && f > &0.0_f32)
+            .map(|(_, _, g)| g.abs())
+            .sum()
     } else {
         // Samples a complete period and takes the max.. extremely naive
         let mut max = 0_f32;
         for sample_num in 0..combined_period {
             // Calculate current sample value,
             let mut acc = 0_f32;
-            for (i, (_, f)) in args.waveforms.iter().enumerate() {
-                acc += next_value[i](sample_num as f32, sample_rate, *f);
+            for (i, (_, f, g)) in args.waveforms.iter().enumerate() {
+                acc += g * next_value[i](sample_num as f32, sample_rate, *f);
             }
             if acc > max {
                 max = acc;
@@ -160,22 +424,26 @@ fn calculate_alpha(sample_rate: f32, cutoff: f32) -> f32 {
     1.0 / (1.0 + std::f32::consts::PI / nc)
 }
 
-const CAP_ARRAY_GENERATION_SIZE: bool = true;
-fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args: RunArgs) {
-    // Initialize constants
-    let sample_rate = conf.sample_rate.0 as f32;
-    let channels = conf.channels as usize;
-    if !args.quiet {
-        eprintln!("RUN");
-        for sample in args.waveforms.iter() {
-            eprintln!("- {:?} @ {}", sample.0, sample.1)
-        }
-        if args.waveforms.is_empty() {
-            eprintln!("- You didn't add any samples to play...");
-        }
+// Alpha for a lowpass that gets 63% (1 - 1/e) of the way through a step in `tau_ms`
+// After n samples a step has reached 1 - (1 - alpha)^n, so (1 - alpha)^(tau samples) = 1/e
+fn alpha_from_time_constant(tau_ms: f32, sample_rate: f32) -> f32 {
+    let tau_samples = tau_ms / 1000.0 * sample_rate;
+    if tau_samples <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-1.0 / tau_samples).exp()
     }
+}
 
-    // Initialize sample generator
+// The lowpass alpha from the time constant if there is one, otherwise from the cutoff
+fn filter_alpha(sample_rate: f32, args: &RunArgs) -> f32 {
+    match args.filter_tau_ms {
+        Some(tau_ms) => alpha_from_time_constant(tau_ms, sample_rate),
+        None => calculate_alpha(sample_rate, args.cutoff),
+    }
+}
+
+fn waveform_fns(args: &RunArgs) -> Vec<fn(f32, f32, f32) -> f32> {
     let mut next_value: Vec<fn(f32, f32, f32) -> f32> = Vec::with_capacity(args.waveforms.len());
     for sample in args.waveforms.iter() {
         next_value.push(match sample.0 {
@@ -206,16 +474,15 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
             },
         });
     }
+    next_value
+}
 
-    // Find the max amplitude of the new waveform
-    if !args.quiet {
-        eprintln!("FIND AMPLITUDE:");
-    }
-    // 1 - Figure out the length of the new waveform:
-    let mut combined_period = args
-        .waveforms
+// Samples until the mix repeats, the LCM of every voice's period (ceil'd to whole samples)
+// None when every voice is silent
+fn combined_period(waveforms: &[(WaveformKind, f32, f32)], sample_rate: f32) -> Option<usize> {
+    waveforms
         .iter()
-        .filter_map(|(k, f)| {
+        .filter_map(|(k, f, _)| {
             if k != &WaveformKind::Silence && f > &0.0_f32 {
                 Some((sample_rate / *f).ceil() as usize) // use ceil to ensure the combined waveform period is AT LEAST the entire length of the waveform, without ceil it could be to short from integer truncation
             } else {
@@ -223,26 +490,80 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
             }
         })
         .reduce(lcm)
-        .unwrap_or(1);
-    if !args.quiet {
-        eprintln!("- Period: {}", combined_period);
+}
+
+// Prints the combined period in samples and ms
+fn print_period(sample_rate: u32, args: &RunArgs) {
+    eprintln!("PERIOD");
+    match combined_period(&args.waveforms, sample_rate as f32) {
+        Some(period) => eprintln!(
+            "- {} samples, {:.3}ms @ {}Hz",
+            period,
+            period as f32 / sample_rate as f32 * 1000.0,
+            sample_rate
+        ),
+        None => eprintln!("- Every voice is silent, there is no period"),
+    }
+}
+
+// Puts one second of the test tone through the lowpass and prints the THD before and after
+fn print_thd(sample_rate: u32, args: &RunArgs) {
+    let Some((kind, freq)) = args.analyze_thd else {
+        return;
+    };
+    let tone_args = RunArgs {
+        waveforms: vec![(kind, freq, 1.0)],
+        ..args.clone()
+    };
+    let next_value = waveform_fns(&tone_args)[0];
+    let sample_rate_f = sample_rate as f32;
+    let alpha = filter_alpha(sample_rate_f, args);
+    let mut prev = 0.0;
+    let (dry, filtered): (Vec<f32>, Vec<f32>) = (0..sample_rate)
+        .map(|sample_num| {
+            let value = next_value(sample_num as f32, sample_rate_f, freq);
+            prev = alpha * value + (1.0 - alpha) * prev;
+            (value, prev)
+        })
+        .unzip();
+    eprintln!("THD");
+    match args.filter_tau_ms {
+        Some(tau) => eprintln!("- Test tone: {} @ {}Hz, lowpass time constant {}ms", kind, freq, tau),
+        None => eprintln!("- Test tone: {} @ {}Hz, lowpass cutoff {}Hz", kind, freq, args.cutoff),
     }
-    if combined_period as f32 >= sample_rate {
-        if !args.quiet {
-            eprintln!("- WARNING: Period is > 1 second worth of samples. Generation may be slow.");
+    for (label, samples) in [("Dry", &dry), ("Filtered", &filtered)] {
+        match thd::thd(samples, sample_rate_f, freq) {
+            Some(thd) => eprintln!("- {}: {:.4}%", label, thd),
+            None => eprintln!("- {}: N/A (tone too low or silent)", label),
         }
+    }
+}
+
+const CAP_ARRAY_GENERATION_SIZE: bool = true;
+// Returns the (capped) period of the mix and its amplitude
+fn find_amplitude(
+    sample_rate: u32,
+    next_value: &Vec<fn(f32, f32, f32) -> f32>,
+    args: &RunArgs,
+) -> (usize, f32) {
+    let sample_rate_f = sample_rate as f32;
+    // Find the max amplitude of the new waveform
+    log(args.log_level, "FIND AMPLITUDE:");
+    // 1 - Figure out the length of the new waveform:
+    let mut combined_period = combined_period(&args.waveforms, sample_rate_f).unwrap_or(1);
+    log(args.log_level, &format!("- Period: {}", combined_period));
+    if combined_period as f32 >= sample_rate_f {
+        log(args.log_level, "- WARNING: Period is > 1 second worth of samples. Generation may be slow.");
         if CAP_ARRAY_GENERATION_SIZE {
-            if !args.quiet {
-                eprintln!("- CAP ARRAY SIZE SET: Bounding the maximum sample count to 1 second worth of samples.");
-            }
-            combined_period = conf.sample_rate.0 as usize;
+            log(args.log_level, "- CAP ARRAY SIZE SET: Bounding the maximum sample count to 1 second worth of samples.");
+            combined_period = sample_rate as usize;
         }
     }
 
     // 2 - Calculate one period worth of samples and find the maximum amplitude
-    let amplitude = calculate_amplitude(combined_period, &next_value, sample_rate, &args);
+    let amplitude = calculate_amplitude(combined_period, next_value, sample_rate_f, args);
     // 3 - Debug print the max amplitude
-    if !args.quiet {
+    if args.log_level >= LogLevel::Normal {
         eprintln!("- Amplitude: {}", amplitude);
         if amplitude > 1.0 {
             eprintln!("- Amplitude is above threshold. Mix will be normalized.");
@@ -250,68 +571,452 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
             eprintln!("- Amplitude is below threshold. Mix does not need normalization.");
         }
     }
-    let alpha = calculate_alpha(sample_rate, args.cutoff);
-    // Generate one second worth of samples, write to a file then exit
-    if args.generate_arrays {
-        // Get the lowest freq (we are only taking up to that many samples for the array generation)
-        // On failure we will only have 1 sample, you shouldn't pass this an empty vec...
-        let mut vals: Vec<f32> = Vec::with_capacity(conf.sample_rate.0 as usize);
-        let mut prev = 0.0;
-        for sample_num in 0..combined_period {
-            // Calculate current sample value
-            let mut acc = 0_f32;
-            for (i, (_, f)) in args.waveforms.iter().enumerate() {
-                acc += next_value[i](sample_num as f32, sample_rate, *f);
+    (combined_period, amplitude)
+}
+
+// Loads a wav file (to mix in or granulate), converted to mono at our sample rate
+fn load_wav_file(path: &str, sample_rate: u32, log_level: LogLevel) -> Vec<f32> {
+    let bytes = std::fs::read(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+    let (samples, file_rate) = wav_to_raw_audio(&bytes)
+        .unwrap_or_else(|e| panic!("Failed to parse the wav file {}: {}", path, e));
+    if file_rate == sample_rate {
+        return samples;
+    }
+    if log_level >= LogLevel::Normal {
+        eprintln!(
+            "- WARNING: {} is {}Hz, resampling to {}Hz with nearest neighbour (expect aliasing)",
+            path, file_rate, sample_rate
+        );
+    }
+    let ratio = file_rate as f64 / sample_rate as f64;
+    let len = (samples.len() as f64 / ratio).floor() as usize;
+    (0..len)
+        .map(|i| samples[((i as f64 * ratio).round() as usize).min(samples.len() - 1)])
+        .collect()
+}
+
+// Renders `granular_ms` of grains from the `granular` wav file to granular.wav
+fn write_granular(path: &str, sample_rate: u32, args: &RunArgs) {
+    let source = load_wav_file(path, sample_rate, args.log_level);
+    let params = GrainParams {
+        grain_ms: args.grain_ms,
+        density: args.grain_density,
+        pitch: args.grain_pitch,
+        position: args.grain_position,
+        spread: args.grain_spread,
+    };
+    let mut granulator = Granulator::new(params, args.grain_seed.unwrap_or_else(seed_from_time));
+    let len = (args.granular_ms.max(0.0) / 1000.0 * sample_rate as f32).round() as usize;
+    let mut vals = granulator.render(&source, len, sample_rate as f32);
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("GRANULAR");
+        eprintln!(
+            "- {} samples from {} ({} samples), ~{:.1} grains overlapping",
+            len,
+            path,
+            source.len(),
+            args.grain_density * args.grain_ms / 1000.0
+        );
+    }
+    // Overlapping grains add up, pull the whole render down together if that clips
+    let peak = vals.iter().fold(0_f32, |m, v| m.max(v.abs()));
+    if peak > 1.0 {
+        log(args.log_level, &format!("- Grain peak {}, normalizing", peak));
+        for val in vals.iter_mut() {
+            *val /= peak;
+        }
+    }
+    let bytes = samples_to_wav(&upmix(&vals, args.channels), args.channels, sample_rate, args.bit_depth);
+    std::fs::write("granular.wav", bytes).expect("Failed to write granular.wav!");
+    eprintln!("GRANULAR WAVE FILE WRITE SUCCESS...");
+}
+
+// Compares the mono sum of the stereo render with its channels (printed even when Silent, it was asked for)
+fn check_mono_sum(interleaved: &[f32], threshold_db: f32) {
+    eprintln!("MONO SUM:");
+    match meter::mono_sum_drop_db(interleaved) {
+        Some(drop) if drop > threshold_db => eprintln!(
+            "- WARNING: Mono sum is {:.1}dB quieter than the channels (threshold {}dB), they are cancelling out",
+            drop, threshold_db
+        ),
+        Some(drop) => eprintln!("- Mono sum drop: {:.1}dB", drop),
+        None => eprintln!("- Both channels are silent"),
+    }
+}
+
+// Interleaved samples as headerless s16le
+fn write_raw_file(path: &str, samples: &[f32], channels: u16, sample_rate: u32) {
+    let mut rawfile = File::create(path).expect("Failed to create file!");
+    rawfile
+        .write_all(&samples_to_raw_pcm(samples))
+        .expect("Failed to write the raw samples!");
+    eprintln!("RAW FILE WRITE SUCCESS... (s16le, {}Hz, {} channel(s))", sample_rate, channels);
+}
+
+// Generate one second worth of samples and write to files
+// Doesn't need an audio device, only the sample rate
+// The generated arrays before they're written out
+struct Rendered {
+    // Mono master after the mono effects, what samples.txt and the preview get
+    mono: Vec<f32>,
+    // Interleaved frames for samples.wav and the raw file
+    out: Vec<f32>,
+    channels: u16,
+    // One buffer per waveform (then the mix file) when `stems` is set, normalized like the master
+    stems: Vec<Vec<f32>>,
+}
+
+fn generate_arrays(sample_rate: u32, args: &RunArgs, preview: Option<PreviewFn>) {
+    let Rendered { mono, out, channels, mut stems } = render_arrays(sample_rate, args);
+    if let Some(dir) = args.stems.as_ref() {
+        let delay_samples = fx::delay_samples(args.delay_ms, sample_rate as f32);
+        write_stems(dir, &mut stems, sample_rate, delay_samples, args);
+    }
+    preview_arrays(&mono, args.preview_ms, preview, args.log_level);
+    let mut file = File::create("samples.txt").expect("Failed to create file!");
+    for val in mono.iter() {
+        write!(file, "{} ", val).expect("Failed to write file!");
+    }
+    file.flush().expect("Failed to flush the file buffer");
+    eprintln!("FILE WRITE SUCCESS...");
+
+    if let Some(path) = args.raw_file.as_ref() {
+        write_raw_file(path, &out, channels, sample_rate);
+    }
+    let bytes = samples_to_wav(&out, channels, sample_rate, args.bit_depth);
+    let mut wavefile = File::create("samples.wav").expect("Failed to create file!");
+    wavefile
+        .write(&bytes)
+        .expect("Failed to write the samples to wave!");
+    eprintln!("WAVE FILE WRITE SUCCESS...");
+}
+
+// Number of samples to generate, `default` unless a duration was given
+fn render_len(sample_rate: u32, args: &RunArgs, default: usize) -> Result<usize, String> {
+    match (args.duration_ms, args.duration_samples) {
+        (Some(_), Some(_)) => Err("duration_ms and duration_samples can't both be set".to_string()),
+        (Some(ms), None) if ms < 0.0 => Err(format!("duration_ms can't be negative, got {}", ms)),
+        (Some(ms), None) => Ok((ms / 1000.0 * sample_rate as f32).round() as usize),
+        (None, Some(samples)) => Ok(samples),
+        (None, None) => Ok(default),
+    }
+}
+
+// Everything generate_arrays writes, without touching any file (the mix file is still read)
+fn render_arrays(sample_rate: u32, args: &RunArgs) -> Rendered {
+    let next_value = waveform_fns(args);
+    let (combined_period, mut amplitude) = find_amplitude(sample_rate, &next_value, args);
+    let alpha = filter_alpha(sample_rate as f32, args);
+    // Only mixed over the overlap with the generated samples
+    let mix = match args.mix_file.as_ref() {
+        Some(path) => {
+            let mix = load_wav_file(path, sample_rate, args.log_level);
+            let peak = mix
+                .iter()
+                .take(combined_period)
+                .fold(0_f32, |m, v| m.max(v.abs()));
+            amplitude += args.mix_gain.abs() * peak;
+            if args.log_level >= LogLevel::Normal {
+                eprintln!("- Mixing {} ({} samples, gain {})", path, mix.len(), args.mix_gain);
+                eprintln!("- Amplitude with mix file: {}", amplitude);
             }
-            // Normalize sample if necessary
-            if amplitude > 1.0 {
-                acc /= amplitude;
+            mix
+        }
+        None => vec![],
+    };
+    // Get the lowest freq (we are only taking up to that many samples for the array generation)
+    // On failure we will only have 1 sample, you shouldn't pass this an empty vec...
+    // Echoes need longer than one period to be heard, take at least a second
+    let mut len = if args.delay || args.ping_pong {
+        combined_period.max(sample_rate as usize)
+    } else {
+        combined_period
+    };
+    // One whole sweep of the panner/flanger
+    if args.autopan {
+        len = len.max((sample_rate as f32 / args.autopan_rate.max(0.01)).ceil() as usize);
+    }
+    if args.flanger {
+        len = len.max((sample_rate as f32 / args.flanger_rate.max(0.01)).ceil() as usize);
+    }
+    // Every partial's attack and release
+    for envelope in args.partial_envelopes.iter().flatten() {
+        len = len.max(envelope.min_len(sample_rate as f32));
+    }
+    let len = render_len(sample_rate, args, len).unwrap_or_else(|e| panic!("Invalid duration: {}", e));
+    let spread = (args.stereo_spread > 0.0).then(|| spread_gains(args));
+    let mut vals: Vec<f32> = Vec::with_capacity(sample_rate as usize);
+    // Left and right when the partials are spread, mixed the same way as `vals` (the mix file sits in the center)
+    let mut left: Vec<f32> = Vec::new();
+    let mut right: Vec<f32> = Vec::new();
+    // One buffer per waveform (then the mix file) for `stems`, with the same normalization as the master
+    let mut stems: Vec<Vec<f32>> = match args.stems {
+        Some(_) => vec![Vec::with_capacity(len); args.waveforms.len() + usize::from(!mix.is_empty())],
+        None => vec![],
+    };
+    let gain = if amplitude > 1.0 { 1.0 / amplitude } else { 1.0 };
+    let mut prev = 0.0;
+    for sample_num in 0..len {
+        // Calculate current sample value
+        let mut acc = 0_f32;
+        let mut frame = [0_f32; 2];
+        for (i, (_, f, g)) in args.waveforms.iter().enumerate() {
+            let mut value = g * next_value[i](sample_num as f32, sample_rate as f32, *f);
+            if let Some(Some(envelope)) = args.partial_envelopes.get(i) {
+                value *= envelope.gain(sample_num, len, sample_rate as f32);
+            }
+            acc += value;
+            if let Some(stem) = stems.get_mut(i) {
+                stem.push(value * gain);
+            }
+            if let Some(spread) = spread.as_ref() {
+                frame[0] += spread[i][0] * value;
+                frame[1] += spread[i][1] * value;
             }
-            // Filter
-            prev = alpha * acc + (1.0 - alpha) * prev;
-            // Push
-            vals.push(acc);
         }
-        let mut file = File::create("samples.txt").expect("Failed to create file!");
-        for val in vals.iter() {
-            write!(file, "{} ", val).expect("Failed to write file!");
+        if let Some(m) = mix.get(sample_num) {
+            acc += args.mix_gain * m;
+            frame[0] += args.mix_gain * m;
+            frame[1] += args.mix_gain * m;
         }
-        file.flush().expect("Failed to flush the file buffer");
-        eprintln!("FILE WRITE SUCCESS...");
-
-        let vals_u16: Vec<i16> = vals
+        if let Some(stem) = stems.get_mut(args.waveforms.len()) {
+            stem.push(args.mix_gain * mix.get(sample_num).unwrap_or(&0.0) * gain);
+        }
+        // Normalize sample if necessary
+        if amplitude > 1.0 {
+            acc /= amplitude;
+            frame[0] /= amplitude;
+            frame[1] /= amplitude;
+        }
+        // Filter
+        prev = alpha * acc + (1.0 - alpha) * prev;
+        // Push
+        vals.push(acc);
+        if spread.is_some() {
+            left.push(frame[0]);
+            right.push(frame[1]);
+        }
+    }
+    if let Some(cutoff) = args.fft_lowpass {
+        log(args.log_level, &format!("- FFT brickwall lowpass at {}Hz", cutoff));
+        vals = brickwall_lowpass(&vals, sample_rate as f32, cutoff);
+        if spread.is_some() {
+            left = brickwall_lowpass(&left, sample_rate as f32, cutoff);
+            right = brickwall_lowpass(&right, sample_rate as f32, cutoff);
+        }
+    }
+    let delay_samples = fx::delay_samples(args.delay_ms, sample_rate as f32);
+    apply_mono_fx(&mut vals, sample_rate, delay_samples, args);
+    if spread.is_some() {
+        // Each side gets its own effect chain
+        apply_mono_fx(&mut left, sample_rate, delay_samples, args);
+        apply_mono_fx(&mut right, sample_rate, delay_samples, args);
+    }
+    let (out, channels) = if args.ping_pong || args.autopan || args.decorrelate || spread.is_some() {
+        // Only the left feeds the delay so the echoes bounce, the dry signal stays in the center
+        let mut ping_pong = args
+            .ping_pong
+            .then(|| PingPongDelay::new(delay_samples, args.feedback, args.delay_mix));
+        let mut autopan = args
+            .autopan
+            .then(|| AutoPan::new(args.autopan_rate, args.autopan_depth, sample_rate as f32));
+        let mut decorrelator = args
+            .decorrelate
+            .then(|| Decorrelator::new(args.decorrelate_mix, sample_rate as f32));
+        let widener = MidSide::new(args.width);
+        let mut interleaved: Vec<f32> = vals
             .iter()
-            .map(|f| ((*f * 32768_f32).round() as i64).clamp(-32768, 32767) as i16)
+            .enumerate()
+            .flat_map(|(n, v)| {
+                let dry = match spread {
+                    Some(_) => [left[n], right[n]],
+                    None => [*v, *v],
+                };
+                let dry = match decorrelator.as_mut() {
+                    Some(decorrelator) => decorrelator.process(dry),
+                    None => dry,
+                };
+                let frame = match ping_pong.as_mut() {
+                    Some(ping_pong) => {
+                        let [l, r] = ping_pong.process([dry[0], 0.0]);
+                        [l, r + dry[1]]
+                    }
+                    None => dry,
+                };
+                let frame = match autopan.as_mut() {
+                    Some(autopan) => autopan.process(frame),
+                    None => frame,
+                };
+                widener.process(frame)
+            })
             .collect();
-        let bytes = raw_audio_to_wav(vals_u16, conf.sample_rate.0);
-        let mut wavefile = File::create("samples.wav").expect("Failed to create file!");
-        wavefile
-            .write(&bytes)
-            .expect("Failed to write the samples to wave!");
-        eprintln!("WAVE FILE WRITE SUCCESS...");
+        // Partials panned towards one side and widening can both push a side past full scale
+        // Checked last so the widened frames are what's measured, both channels are pulled down together
+        let peak = interleaved.iter().fold(0_f32, |m, v| m.max(v.abs()));
+        if peak > 1.0 {
+            log(args.log_level, &format!("- Stereo peak {}, normalizing the stereo channels", peak));
+            for val in interleaved.iter_mut() {
+                *val /= peak;
+            }
+        }
+        if args.mono_sum_check {
+            check_mono_sum(&interleaved, args.mono_sum_threshold_db);
+        }
+        if args.mix_to_mono {
+            log(args.log_level, &format!("- Mixing the stereo render to mono ({} dB)", args.mix_to_mono_db));
+            (fx::mix_to_mono(&interleaved, args.mix_to_mono_db), 1)
+        } else {
+            if args.channels != 2 {
+                log(args.log_level, "- WARNING: The stereo render is written as 2 channels, use mix_to_mono for 1");
+            }
+            (interleaved, 2)
+        }
+    } else {
+        (upmix(&vals, args.channels), args.channels)
+    };
+    Rendered {
+        mono: vals,
+        out,
+        channels,
+        stems,
+    }
+}
+
+// Writes every waveform (and the mix file) as its own mono wav in `dir`, through the same chain as the mono master
+// Every step is linear so the stems add up to samples.txt, the stereo effects (spread, ping-pong, auto-pan, decorrelate) aren't in them
+fn write_stems(dir: &str, stems: &mut [Vec<f32>], sample_rate: u32, delay_samples: usize, args: &RunArgs) {
+    std::fs::create_dir_all(dir).unwrap_or_else(|e| panic!("Failed to create the stems directory {}: {}", dir, e));
+    for (i, stem) in stems.iter_mut().enumerate() {
+        if let Some(cutoff) = args.fft_lowpass {
+            *stem = brickwall_lowpass(stem, sample_rate as f32, cutoff);
+        }
+        apply_mono_fx(stem, sample_rate, delay_samples, args);
+        let name = match args.waveforms.get(i) {
+            Some((kind, freq, _)) => format!("{:02}_{}_{}hz.wav", i, kind, freq),
+            None => format!("{:02}_mix_file.wav", i),
+        };
+        let path = std::path::Path::new(dir).join(name);
+        std::fs::write(&path, samples_to_wav(stem, 1, sample_rate, args.bit_depth))
+            .unwrap_or_else(|e| panic!("Failed to write the stem {}: {}", path.display(), e));
+    }
+    eprintln!("STEMS WRITE SUCCESS... ({} in {})", stems.len(), dir);
+}
+
+// Flanger, echo and breakpoint envelope on one channel of the generated arrays
+fn apply_mono_fx(vals: &mut [f32], sample_rate: u32, delay_samples: usize, args: &RunArgs) {
+    if args.flanger {
+        let mut flanger = Flanger::new(
+            args.flanger_rate,
+            args.flanger_depth_ms,
+            args.flanger_feedback,
+            args.flanger_mix,
+            sample_rate as f32,
+        );
+        for val in vals.iter_mut() {
+            *val = flanger.process(*val);
+        }
+    }
+    if args.delay {
+        let mut delay = Delay::new(delay_samples, args.feedback, args.delay_mix);
+        for val in vals.iter_mut() {
+            *val = delay.process(*val);
+        }
+    }
+    if let Some(path) = args.envelope_file.as_ref() {
+        let envelope =
+            Breakpoints::load(path, sample_rate as f32).unwrap_or_else(|e| panic!("Failed to load the envelope: {}", e));
+        for (n, val) in vals.iter_mut().enumerate() {
+            *val *= envelope.gain(n);
+        }
+    }
+}
+
+// Per partial (left, right) gains for `stereo_spread`, positions even across -spread..spread by frequency rank
+fn spread_gains(args: &RunArgs) -> Vec<[f32; 2]> {
+    let count = args.waveforms.len();
+    let mut order: Vec<usize> = (0..count).collect();
+    order.sort_by(|a, b| args.waveforms[*a].1.total_cmp(&args.waveforms[*b].1));
+    let mut gains = vec![[1.0, 1.0]; count];
+    for (rank, i) in order.into_iter().enumerate() {
+        let position = if count > 1 {
+            args.stereo_spread * (2.0 * rank as f32 / (count - 1) as f32 - 1.0)
+        } else {
+            0.0
+        };
+        gains[i] = pan([1.0, 1.0], position);
+    }
+    gains
+}
+
+fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args: RunArgs) {
+    // Initialize constants
+    let sample_rate = conf.sample_rate.0 as f32;
+    let channels = conf.channels as usize;
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("RUN");
+        for sample in args.waveforms.iter() {
+            eprintln!("- {} @ {}, Gain: {}", sample.0, sample.1, sample.2)
+        }
+        if args.waveforms.is_empty() {
+            eprintln!("- You didn't add any samples to play...");
+        }
+    }
+
+    // Generate one second worth of samples, write to a file then exit
+    if args.generate_arrays {
+        generate_arrays(
+            conf.sample_rate.0,
+            &args,
+            Some(&|samples: &[f32]| {
+                play_preview::<T>(dev, &conf, samples, args.preview_ms, ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit))
+            }),
+        );
         return;
     }
 
+    // Initialize sample generator
+    let next_value = waveform_fns(&args);
+    let (_, amplitude) = find_amplitude(conf.sample_rate.0, &next_value, &args);
+    let alpha = filter_alpha(sample_rate, &args);
+
     let volume = 0.5;
+    let safety = ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit);
     let mut sample_clock = 0f32;
     let mut prev = 0.0;
+    // Samples played so far, for the fade out
+    let mut elapsed = 0_usize;
+    let total = conf.sample_rate.0 as usize * PLAY_MS as usize / 1000;
+    let fade = if args.hold {
+        0
+    } else {
+        (args.fade_out_ms.max(0.0) / 1000.0 * sample_rate).round() as usize
+    };
+    let faded = Faded::new();
+    let callback_faded = faded.clone();
+    verbose(args.log_level, &format!("- Stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(args.log_level, "Output", conf.sample_rate.0);
     let stream = dev
         .build_output_stream(
             &conf,
             move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                stats.record(data.len() / channels);
                 for frame in data.chunks_mut(channels) {
                     // COPIED: Calculate current sample value
                     let mut acc = 0_f32;
                     // Sum the samples
-                    for (i, (_, f)) in args.waveforms.iter().enumerate() {
-                        acc += next_value[i](sample_clock, sample_rate, *f);
+                    for (i, (_, f, g)) in args.waveforms.iter().enumerate() {
+                        acc += g * next_value[i](sample_clock, sample_rate, *f);
                     }
                     if amplitude > 1.0 {
                         acc /= amplitude;
                     }
                     // Filter
                     prev = alpha * acc + (1.0 - alpha) * prev;
-                    let value: T = (prev * volume).to_sample::<T>();
+                    let value: T = safety.process(prev * volume * fade_out_gain(elapsed, total, fade)).to_sample::<T>();
+                    elapsed += 1;
+                    if elapsed >= total {
+                        callback_faded.set();
+                    }
                     sample_clock = (sample_clock + 1.0) % sample_rate;
                     for sample in frame.iter_mut() {
                         *sample = value;
@@ -325,31 +1030,380 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
 
     stream.play().expect("Failed to play the stream!");
 
-    std::thread::sleep(std::time::Duration::from_millis(1000));
+    if args.hold {
+        log(args.log_level, "- Playing, press Enter to stop...");
+        let mut input = String::new();
+        std::io::stdin()
+            .read_line(&mut input)
+            .expect("Failed to read from stdin");
+    } else {
+        faded.wait(PLAY_MS);
+    }
+    // Some backends can't pause, dropping the stream stops it either way
+    if let Err(e) = stream.pause() {
+        verbose(args.log_level, &format!("- Could not pause the stream ({}), dropping it", e));
+    }
+    drop(stream);
 }
 
 fn main() {
-    let args = RunArgs::new(
-        false,
+    let wave = |name: &str| name.parse::<WaveformKind>().unwrap_or_else(|e| panic!("{}", e));
+    let mut args = RunArgs::new(
+        LogLevel::from_args(std::env::args().skip(1)),
         vec![
-            //(WaveformKind::Square, 110.0),
-            (WaveformKind::Sine, 440.0),
-            //(WaveformKind::Triangle, 554.37),
-            //(WaveformKind::Triangle, 659.25),
-            (WaveformKind::Sine, 4400.0),
+            //(wave("square"), 110.0, 1.0),
+            (wave("sine"), 440.0, 1.0),
+            //(wave("triangle"), 554.37, 1.0),
+            //(wave("triangle"), 659.25, 1.0),
+            (wave("sine"), 4400.0, 1.0),
         ],
         false,
         441.0,
     );
-    if !args.quiet {
+    args.limit_ceiling_dbfs = DEFAULT_CEILING_DBFS;
+    // Only from the command line, it can't be left switched off by an edit here
+    args.no_limit = no_limit_from_args(std::env::args().skip(1));
+    args.sample_format = sample_format_from_args(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{}", e));
+    args.filter_tau_ms = None;
+    args.fft_lowpass = None;
+    args.stems = None;
+    if args.fft_lowpass.is_some_and(|cutoff| cutoff.is_nan() || cutoff <= 0.0) {
+        panic!("fft_lowpass should be above 0 Hz, got {:?}", args.fft_lowpass);
+    }
+    args.mix_file = None;
+    args.mix_gain = 0.5;
+    args.envelope_file = None;
+    args.granular = None;
+    args.granular_ms = 2000.0;
+    args.grain_ms = 50.0;
+    args.grain_density = 20.0;
+    args.grain_pitch = 1.0;
+    args.grain_position = 0.3;
+    args.grain_spread = 0.02;
+    args.grain_seed = None;
+    args.grain_seed = Some(resolve_seed(args.grain_seed, args.granular.is_some()));
+    // No attack or release holds a steady level, PartialEnvelope::new(1.0, 0.5) blooms in over a second
+    args.partial_envelopes = vec![Some(PartialEnvelope::new(0.0, 0.0)); args.waveforms.len()];
+    args.delay = false;
+    args.ping_pong = false;
+    args.delay_ms = 300.0;
+    args.feedback = 0.5;
+    // 0 is only the dry signal, 1 only the echoes
+    args.delay_mix = 0.5;
+    args.delay_note = None;
+    args.bpm = 120.0;
+    args.flanger = false;
+    args.flanger_rate = 0.3;
+    args.flanger_depth_ms = 2.0;
+    args.flanger_feedback = 0.5;
+    args.flanger_mix = 0.5;
+    args.autopan = false;
+    args.autopan_rate = 0.5;
+    args.autopan_depth = 0.8;
+    args.decorrelate = false;
+    args.decorrelate_mix = 1.0;
+    args.stereo_spread = 0.0;
+    args.width = 1.0;
+    if args.width < 0.0 {
+        panic!("width should be 0 or above, got {}", args.width);
+    }
+    args.mono_sum_check = false;
+    args.mix_to_mono = false;
+    args.mix_to_mono_db = 0.0;
+    args.hold = false;
+    args.print_period = false;
+    args.analyze_thd = None;
+    args.bit_depth = BitDepth::parse("16").unwrap_or_else(|e| panic!("{}", e));
+    args.raw_file = None;
+    args.duration_ms = None;
+    args.duration_samples = None;
+    // Checked up front so both durations being set fails before the device is opened
+    if let Err(e) = render_len(args.sample_rate, &args, 0) {
+        panic!("Invalid duration: {}", e);
+    }
+    args.fade_out_ms = 20.0;
+    args.preview_ms = 0.0;
+    args.no_device = false;
+    args.sample_rate = 44100;
+    args.channels = 1;
+    if let Err(e) = check_format(args.sample_rate, args.channels) {
+        panic!("Invalid file format: {}", e);
+    }
+    if let Some(note) = args.delay_note.as_ref() {
+        args.delay_ms =
+            fx::note_delay_ms(note, args.bpm).unwrap_or_else(|e| panic!("Failed to parse the delay note: {}", e));
+    }
+    if !(0.0..=1.0).contains(&args.stereo_spread) {
+        panic!("stereo_spread should be in 0..=1, got {}", args.stereo_spread);
+    }
+    if args.log_level >= LogLevel::Normal {
         eprintln!("ARGUMENTS:");
-        eprintln!("- {:?}", args);
+        eprintln!("- {}", args.describe());
+    }
+    if args.no_limit {
+        log(args.log_level, "- WARNING: Clip guard off (--no-limit)");
+    }
+    if args.no_device {
+        if args.print_period {
+            print_period(args.sample_rate, &args);
+            return;
+        }
+        if args.analyze_thd.is_some() {
+            print_thd(args.sample_rate, &args);
+            return;
+        }
+        if let Some(path) = args.granular.as_ref() {
+            write_granular(path, args.sample_rate, &args);
+            return;
+        }
+        if args.log_level >= LogLevel::Normal {
+            eprintln!("NO DEVICE");
+            eprintln!("- Generating arrays at {}Hz", args.sample_rate);
+        }
+        generate_arrays(args.sample_rate, &args, None);
+        return;
     }
     let mut args2 = args.clone();
-    args2.quiet = true;
+    args2.log_level = LogLevel::Silent;
     args2.generate_arrays = true;
-    let (dev, conf) = setup_default_device_default_config(args.quiet);
+    log(args.log_level, "SETUP");
+    let (dev, conf, _) = setup_default_device_default_config(args.log_level)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let conf = force_sample_format(&dev, conf, args.sample_format)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(format) = args.sample_format {
+        log(args.log_level, &format!("- Sample format forced to {}", format));
+    }
+    if args.print_period {
+        print_period(conf.sample_rate().0, &args);
+        return;
+    }
+    if args.analyze_thd.is_some() {
+        print_thd(conf.sample_rate().0, &args);
+        return;
+    }
+    if let Some(path) = args.granular.as_ref() {
+        write_granular(path, conf.sample_rate().0, &args);
+        return;
+    }
     let conf2 = conf.clone();
     dispatch_run(&dev, conf, args);
     dispatch_run(&dev, conf2, args2);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::wav::to_i16;
+
+    const RATE: u32 = 44100;
+
+    fn args(waveforms: Vec<(WaveformKind, f32, f32)>) -> RunArgs {
+        RunArgs::new(LogLevel::Silent, waveforms, true, 20000.0)
+    }
+
+    #[test]
+    fn zero_gain_voice_adds_nothing() {
+        let alone = render_arrays(RATE, &args(vec![(WaveformKind::Sine, 441.0, 0.5)]));
+        let muted = render_arrays(
+            RATE,
+            &args(vec![(WaveformKind::Sine, 441.0, 0.5), (WaveformKind::Sine, 882.0, 0.0)]),
+        );
+        assert_eq!(alone.mono, muted.mono);
+    }
+
+    #[test]
+    fn amplitude_estimate_scales_with_the_gains() {
+        let full = args(vec![(WaveformKind::Sine, 441.0, 1.0), (WaveformKind::Sine, 882.0, 1.0)]);
+        let half = args(vec![(WaveformKind::Sine, 441.0, 0.5), (WaveformKind::Sine, 882.0, 0.5)]);
+        let amplitude = |args: &RunArgs| calculate_amplitude(1, &waveform_fns(args), RATE as f32, args);
+        assert_eq!(amplitude(&full), 2.0);
+        assert_eq!(amplitude(&half), 1.0);
+    }
+
+    #[test]
+    fn mix_file_at_zero_gain_changes_nothing() {
+        let path = std::env::temp_dir().join("test1_lowpass_mix_zero_gain.wav");
+        let noise: Vec<f32> = (0..RATE).map(|n| ((n * 7919) % 200) as f32 / 100.0 - 1.0).collect();
+        std::fs::write(&path, samples_to_wav(&noise, 1, RATE, BitDepth::Int16)).unwrap();
+        let plain = args(vec![(WaveformKind::Sine, 441.0, 0.5)]);
+        let mut mixed = plain.clone();
+        mixed.mix_file = Some(path.to_string_lossy().into_owned());
+        mixed.mix_gain = 0.0;
+        let plain = render_arrays(RATE, &plain);
+        let mixed = render_arrays(RATE, &mixed);
+        assert_eq!(plain.mono, mixed.mono);
+        assert_eq!(plain.out, mixed.out);
+    }
+
+    #[test]
+    fn waveform_display_is_lowercase() {
+        assert_eq!(WaveformKind::Silence.to_string(), "silence");
+        assert_eq!(WaveformKind::Sine.to_string(), "sine");
+        assert_eq!(WaveformKind::Square.to_string(), "square");
+        assert_eq!(WaveformKind::Sawtooth.to_string(), "sawtooth");
+        assert_eq!(WaveformKind::Triangle.to_string(), "triangle");
+    }
+
+    #[test]
+    fn combined_period_is_the_lcm_of_the_ceiled_periods() {
+        // 441Hz is exactly 100 samples, 300Hz is 147, 1000Hz ceils 44.1 up to 45
+        let waveforms = vec![
+            (WaveformKind::Sine, 441.0, 1.0),
+            (WaveformKind::Sine, 300.0, 1.0),
+            (WaveformKind::Silence, 1.0, 1.0),
+        ];
+        assert_eq!(combined_period(&waveforms, 44100.0), Some(14700));
+        assert_eq!(combined_period(&[(WaveformKind::Sine, 1000.0, 1.0)], 44100.0), Some(45));
+        assert_eq!(combined_period(&[(WaveformKind::Silence, 440.0, 1.0)], 44100.0), None);
+    }
+
+    #[test]
+    fn tau_step_response_reaches_63_percent_after_tau() {
+        // 10ms at 48kHz is 480 samples
+        let alpha = alpha_from_time_constant(10.0, 48000.0);
+        let mut prev = 0_f32;
+        for _ in 0..480 {
+            prev = alpha * 1.0 + (1.0 - alpha) * prev;
+        }
+        assert!((prev - (1.0 - (-1_f32).exp())).abs() < 1e-3, "{}", prev);
+        assert_eq!(alpha_from_time_constant(0.0, 48000.0), 1.0);
+    }
+
+    #[test]
+    fn duration_samples_sets_the_exact_data_length() {
+        let mut plain = args(vec![(WaveformKind::Sine, 441.0, 0.5)]);
+        plain.duration_samples = Some(1001);
+        let mut ping_pong = plain.clone();
+        ping_pong.ping_pong = true;
+        for args in [plain, ping_pong] {
+            let Rendered { out, channels, .. } = render_arrays(RATE, &args);
+            for (bit_depth, bytes_per_sample) in [(BitDepth::Int16, 2), (BitDepth::Int24, 3), (BitDepth::Float32, 4)] {
+                let bytes = samples_to_wav(&out, channels, RATE, bit_depth);
+                let at = bytes.windows(4).position(|w| w == b"data").unwrap();
+                let data_len = u32::from_le_bytes(bytes[at + 4..at + 8].try_into().unwrap()) as usize;
+                assert_eq!(data_len, 1001 * channels as usize * bytes_per_sample, "{} {}", channels, bit_depth);
+            }
+        }
+    }
+
+    #[test]
+    fn both_durations_are_an_error() {
+        let mut args = args(vec![(WaveformKind::Sine, 441.0, 0.5)]);
+        args.duration_ms = Some(10.01);
+        assert_eq!(render_len(RATE, &args, 100), Ok(441));
+        args.duration_samples = Some(441);
+        assert!(render_len(RATE, &args, 100).is_err());
+    }
+
+    #[test]
+    fn full_spread_puts_the_outer_partials_on_opposite_sides() {
+        // Out of frequency order, the pan follows the frequency not the list
+        let mut args = args(vec![
+            (WaveformKind::Sine, 882.0, 0.5),
+            (WaveformKind::Sine, 441.0, 0.5),
+            (WaveformKind::Sine, 1323.0, 0.5),
+        ]);
+        args.stereo_spread = 1.0;
+        let gains = spread_gains(&args);
+        assert!((gains[1][0] - 1.0).abs() < 1e-6 && gains[1][1].abs() < 1e-6, "{:?}", gains[1]);
+        assert!(gains[2][0].abs() < 1e-6 && (gains[2][1] - 1.0).abs() < 1e-6, "{:?}", gains[2]);
+        assert_eq!(gains[0][0], gains[0][1]);
+        let rendered = render_arrays(RATE, &args);
+        assert_eq!(rendered.channels, 2);
+        assert!(rendered.out.chunks(2).any(|frame| frame[0] != frame[1]));
+    }
+
+    #[test]
+    fn zero_spread_keeps_the_channels_identical() {
+        let mut args = args(vec![(WaveformKind::Sine, 441.0, 0.5), (WaveformKind::Sine, 1323.0, 0.5)]);
+        args.stereo_spread = 0.0;
+        assert!(spread_gains(&args).iter().all(|gains| gains[0] == gains[1]));
+        let rendered = render_arrays(RATE, &args);
+        assert_eq!(rendered.channels, 1);
+        assert_eq!(rendered.out, rendered.mono);
+    }
+
+    #[test]
+    fn stems_sum_to_the_master() {
+        // Loud enough to be normalized, with an echo so the stems go through the effects too
+        let mut args = args(vec![
+            (WaveformKind::Sine, 441.0, 0.8),
+            (WaveformKind::Sine, 882.0, 0.6),
+            (WaveformKind::Sine, 1323.0, 0.4),
+        ]);
+        args.delay = true;
+        // Absolute, another test changes the working directory
+        let dir = std::env::temp_dir().join("test1_lowpass-stems");
+        let _ = std::fs::remove_dir_all(&dir);
+        args.stems = Some(dir.to_str().unwrap().to_string());
+        let Rendered { mono, mut stems, .. } = render_arrays(RATE, &args);
+        assert_eq!(stems.len(), 3);
+        let delay_samples = fx::delay_samples(args.delay_ms, RATE as f32);
+        write_stems(args.stems.as_ref().unwrap(), &mut stems, RATE, delay_samples, &args);
+        let mut files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).collect();
+        files.sort();
+        assert_eq!(files[0].file_name().unwrap(), "00_sine_441hz.wav");
+        // 16 bit mono after the 44 byte header
+        let read = |path: &std::path::PathBuf| -> Vec<i32> {
+            std::fs::read(path).unwrap()[44..]
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]) as i32)
+                .collect()
+        };
+        let stems: Vec<Vec<i32>> = files.iter().map(read).collect();
+        assert_eq!(stems[0].len(), mono.len());
+        for (n, master) in mono.iter().enumerate() {
+            let sum: i32 = stems.iter().map(|stem| stem[n]).sum();
+            // Each stem rounds by up to half a step
+            assert!((sum - to_i16(*master) as i32).abs() <= 2, "sample {}: {} != {}", n, sum, master);
+        }
+    }
+
+    #[test]
+    fn widening_is_normalized_after_the_mid_side() {
+        let mut args = args(vec![(WaveformKind::Sine, 441.0, 1.0), (WaveformKind::Sine, 1323.0, 1.0)]);
+        args.stereo_spread = 1.0;
+        args.width = 3.0;
+        let rendered = render_arrays(RATE, &args);
+        let peak = rendered.out.iter().fold(0_f32, |m, v| m.max(v.abs()));
+        assert!(peak <= 1.0 + 1e-6 && peak > 0.99, "{}", peak);
+    }
+
+    // Magnitude of `frequency` in a Hann windowed stretch of `samples`
+    fn windowed_magnitude(samples: &[f32], frequency: f32) -> f32 {
+        let len = samples.len() as f32;
+        let (re, im) = samples.iter().enumerate().fold((0_f32, 0_f32), |(re, im), (n, v)| {
+            let window = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / len).cos();
+            let angle = 2.0 * std::f32::consts::PI * frequency * n as f32 / RATE as f32;
+            (re + window * v * angle.cos(), im - window * v * angle.sin())
+        });
+        (re * re + im * im).sqrt() / len
+    }
+
+    #[test]
+    fn slow_attack_partial_blooms_after_a_second() {
+        let mut args = args(vec![(WaveformKind::Sine, 441.0, 0.5), (WaveformKind::Sine, 882.0, 0.5)]);
+        args.partial_envelopes = vec![None, Some(PartialEnvelope::new(1.0, 0.0))];
+        args.duration_samples = Some(2 * RATE as usize);
+        let vals = render_arrays(RATE, &args).mono;
+        let window = RATE as usize / 10;
+        let start = &vals[..window];
+        let after = &vals[RATE as usize + window..RATE as usize + 2 * window];
+        // The steady partial is the reference, the slow one is ~5% of it over the first 100ms and matches it after 1s
+        let steady = windowed_magnitude(after, 441.0);
+        assert!((windowed_magnitude(start, 441.0) - steady).abs() < steady * 0.01);
+        assert!(windowed_magnitude(start, 882.0) < steady * 0.1, "{}", windowed_magnitude(start, 882.0) / steady);
+        assert!((windowed_magnitude(after, 882.0) - steady).abs() < steady * 0.01);
+    }
+
+    #[test]
+    fn waveform_kind_parses_the_shared_names() {
+        assert_eq!("SAW".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("sawtooth".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("Sine".parse::<WaveformKind>(), Ok(WaveformKind::Sine));
+        let err = "bogus".parse::<WaveformKind>().unwrap_err();
+        assert!(err.contains("saw/sawtooth"), "{}", err);
+        assert!("onoff".parse::<WaveformKind>().unwrap_err().contains("Test 1: Lowpass"));
+    }
+}
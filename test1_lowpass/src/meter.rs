@@ -0,0 +1,56 @@
+// Level checks on the rendered output
+
+fn rms(samples: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = samples.fold((0_f32, 0_usize), |(sum, count), s| (sum + s * s, count + 1));
+    if count == 0 {
+        0.0
+    } else {
+        (sum / count as f32).sqrt()
+    }
+}
+
+// How much quieter (dB) the mono sum (L + R) / 2 of interleaved stereo is than the average channel RMS
+// 0 for identical channels, ~3 for unrelated ones, infinite when they cancel out completely
+// None when both channels are silent
+pub fn mono_sum_drop_db(interleaved: &[f32]) -> Option<f32> {
+    let frames = interleaved.chunks_exact(2);
+    let left = rms(frames.clone().map(|f| f[0]));
+    let right = rms(frames.clone().map(|f| f[1]));
+    let mono = rms(frames.map(|f| (f[0] + f[1]) / 2.0));
+    let average = (left + right) / 2.0;
+    if average == 0.0 {
+        return None;
+    }
+    Some(20.0 * (average / mono).log10())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo(right_of: impl Fn(f32, usize) -> f32) -> Vec<f32> {
+        (0..4800)
+            .flat_map(|n| {
+                let left = (2.0 * std::f32::consts::PI * 440.0 * n as f32 / 48000.0).sin();
+                [left, right_of(left, n)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_channels_do_not_drop() {
+        assert!(mono_sum_drop_db(&stereo(|left, _| left)).unwrap().abs() < 1e-4);
+    }
+
+    #[test]
+    fn inverted_channels_cancel() {
+        assert!(mono_sum_drop_db(&stereo(|left, _| -left)).unwrap() > 60.0);
+    }
+
+    #[test]
+    fn unrelated_channels_drop_about_3db() {
+        let drop = mono_sum_drop_db(&stereo(|_, n| (2.0 * std::f32::consts::PI * 1000.0 * n as f32 / 48000.0).sin())).unwrap();
+        assert!((drop - 3.01).abs() < 0.1, "{}", drop);
+        assert_eq!(mono_sum_drop_db(&[0.0; 100]), None);
+    }
+}
@@ -0,0 +1,67 @@
+// Fade in/out of a single partial over the generated arrays, for pads that evolve as the partials bloom one by one
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PartialEnvelope {
+    // Seconds from silence to full level at the start of the arrays
+    pub attack_s: f32,
+    // Seconds from full level to silence at the end of the arrays
+    pub release_s: f32,
+}
+
+impl PartialEnvelope {
+    pub fn new(attack_s: f32, release_s: f32) -> Self {
+        Self {
+            attack_s: attack_s.max(0.0),
+            release_s: release_s.max(0.0),
+        }
+    }
+
+    // Samples needed for the attack and release to play out in full
+    pub fn min_len(&self, sample_rate: f32) -> usize {
+        ((self.attack_s + self.release_s) * sample_rate).ceil() as usize
+    }
+
+    // Gain 0..1 of sample `n` out of `len`, straight ramps, an overlapping attack and release take the lower of the two
+    pub fn gain(&self, n: usize, len: usize, sample_rate: f32) -> f32 {
+        let ramp = |samples: usize, time_s: f32| {
+            let ramp_len = time_s * sample_rate;
+            if ramp_len <= 0.0 {
+                1.0
+            } else {
+                (samples as f32 / ramp_len).min(1.0)
+            }
+        };
+        ramp(n, self.attack_s).min(ramp(len.saturating_sub(n + 1), self.release_s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attack_ramps_up_then_holds() {
+        let envelope = PartialEnvelope::new(1.0, 0.0);
+        assert_eq!(envelope.gain(0, 2000, 1000.0), 0.0);
+        assert_eq!(envelope.gain(500, 2000, 1000.0), 0.5);
+        assert_eq!(envelope.gain(1000, 2000, 1000.0), 1.0);
+        assert_eq!(envelope.gain(1999, 2000, 1000.0), 1.0);
+    }
+
+    #[test]
+    fn release_reaches_silence_on_the_last_sample() {
+        let envelope = PartialEnvelope::new(0.0, 0.5);
+        assert_eq!(envelope.gain(0, 2000, 1000.0), 1.0);
+        assert_eq!(envelope.gain(1749, 2000, 1000.0), 0.5);
+        assert_eq!(envelope.gain(1999, 2000, 1000.0), 0.0);
+    }
+
+    #[test]
+    fn overlapping_ramps_take_the_lower_and_min_len_fits_both() {
+        let envelope = PartialEnvelope::new(1.0, 1.0);
+        assert_eq!(envelope.min_len(1000.0), 2000);
+        // Too short for either to finish, the middle only gets halfway
+        assert_eq!(envelope.gain(500, 1001, 1000.0), 0.5);
+        assert_eq!(PartialEnvelope::new(-1.0, -1.0), PartialEnvelope::new(0.0, 0.0));
+    }
+}
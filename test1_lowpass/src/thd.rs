@@ -0,0 +1,87 @@
+// Total harmonic distortion of a test tone, measured with an FFT
+
+use crate::fft::fft;
+
+// Bins either side of each harmonic that are summed (the Hann window spreads a tone over ~3 bins)
+const BIN_SPREAD: usize = 3;
+
+// Power of each bin up to nyquist, the samples are Hann windowed and cut to a power of two
+fn power_spectrum(samples: &[f32]) -> Vec<f32> {
+    let n = 1 << samples.len().ilog2();
+    let mut re: Vec<f32> = samples[..n]
+        .iter()
+        .enumerate()
+        .map(|(i, s)| s * (0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / n as f32).cos()))
+        .collect();
+    let mut im = vec![0_f32; n];
+    fft(&mut re, &mut im);
+    re.iter()
+        .zip(im.iter())
+        .take(n / 2)
+        .map(|(r, i)| r * r + i * i)
+        .collect()
+}
+
+// THD in percent: sqrt(energy of 2f, 3f, ... up to nyquist / energy of f) * 100
+// None when there are too few samples or no energy at the fundamental
+pub fn thd(samples: &[f32], sample_rate: f32, fundamental: f32) -> Option<f32> {
+    if samples.len() < 2 || fundamental <= 0.0 {
+        return None;
+    }
+    let spectrum = power_spectrum(samples);
+    let bin_hz = sample_rate / (spectrum.len() * 2) as f32;
+    let energy_at = |hz: f32| {
+        let bin = (hz / bin_hz).round() as usize;
+        let range = bin.saturating_sub(BIN_SPREAD)..(bin + BIN_SPREAD + 1).min(spectrum.len());
+        spectrum[range].iter().sum::<f32>()
+    };
+    let fundamental_energy = energy_at(fundamental);
+    if fundamental_energy <= 0.0 || fundamental / bin_hz < (BIN_SPREAD * 2) as f32 {
+        return None;
+    }
+    let harmonic_energy: f32 = (2..)
+        .map(|k| k as f32 * fundamental)
+        .take_while(|hz| hz / bin_hz + (BIN_SPREAD as f32) < spectrum.len() as f32)
+        .map(energy_at)
+        .sum();
+    Some((harmonic_energy / fundamental_energy).sqrt() * 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    fn tone(harmonics: &[(f32, f32)]) -> Vec<f32> {
+        (0..8192)
+            .map(|n| {
+                harmonics
+                    .iter()
+                    .map(|(hz, level)| level * (2.0 * std::f32::consts::PI * hz * n as f32 / RATE).sin())
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn injected_second_harmonic_is_measured() {
+        let thd = thd(&tone(&[(1000.0, 1.0), (2000.0, 0.1)]), RATE, 1000.0).unwrap();
+        assert!((thd - 10.0).abs() < 0.1, "{}", thd);
+    }
+
+    #[test]
+    fn clean_sine_is_near_zero_and_square_is_high() {
+        assert!(thd(&tone(&[(1000.0, 1.0)]), RATE, 1000.0).unwrap() < 0.01);
+        // Odd harmonics at 1/k, sqrt(pi^2/8 - 1) is about 48% for the full series
+        let square: Vec<(f32, f32)> = (0..12).map(|k| ((2 * k + 1) as f32 * 1000.0, 1.0 / (2 * k + 1) as f32)).collect();
+        let thd = thd(&tone(&square), RATE, 1000.0).unwrap();
+        assert!((40.0..50.0).contains(&thd), "{}", thd);
+    }
+
+    #[test]
+    fn too_short_or_silent_is_none() {
+        assert_eq!(thd(&[0.5], RATE, 1000.0), None);
+        assert_eq!(thd(&[0.0; 1024], RATE, 1000.0), None);
+    }
+}
@@ -0,0 +1,89 @@
+// Per voice "analog" pitch drift, every voice wanders slightly out of tune on its own
+
+use common::rng::XorShift;
+
+// Roughly how many times a second the walk can cross its whole range, low so it's heard as drift not vibrato
+const DRIFT_SPEED: f32 = 0.5;
+
+// Random walk in cents bounced back off +-max_cents
+pub struct Drift {
+    rng: XorShift,
+    cents: f32,
+    max_cents: f32,
+    step: f32,
+}
+
+impl Drift {
+    pub fn new(max_cents: f32, sample_rate: f32, seed: u32) -> Self {
+        let max_cents = max_cents.abs();
+        Self {
+            rng: XorShift::new(seed),
+            cents: 0.0,
+            max_cents,
+            // A random walk covers ~step * sqrt(n) in n steps
+            step: 2.0 * max_cents * DRIFT_SPEED / sample_rate.sqrt(),
+        }
+    }
+
+    // Steps the walk and returns the frequency ratio, exactly 1 when there is no drift
+    pub fn next_ratio(&mut self) -> f32 {
+        if self.max_cents == 0.0 {
+            return 1.0;
+        }
+        self.cents += self.step * self.rng.next_f32();
+        if self.cents.abs() > self.max_cents {
+            self.cents = (2.0 * self.max_cents - self.cents.abs()) * self.cents.signum();
+        }
+        2f32.powf(self.cents / 1200.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    #[test]
+    fn zero_drift_is_exactly_in_tune() {
+        let mut drift = Drift::new(0.0, RATE, 7);
+        assert!((0..RATE as usize).all(|_| drift.next_ratio() == 1.0));
+    }
+
+    #[test]
+    fn drift_wanders_within_its_bound() {
+        let bound = 2f32.powf(3.0 / 1200.0);
+        for seed in 1..5 {
+            let mut drift = Drift::new(3.0, RATE, seed);
+            let ratios: Vec<f32> = (0..10 * RATE as usize).map(|_| drift.next_ratio()).collect();
+            assert!(ratios.iter().all(|r| *r <= bound * 1.000001 && *r >= 1.0 / bound / 1.000001));
+            // Moves by a good part of its range, not stuck at the start
+            let widest = ratios.iter().map(|r| (1200.0 * r.log2()).abs()).fold(0_f32, f32::max);
+            assert!(widest > 1.0, "seed {}: {} cents", seed, widest);
+        }
+    }
+
+    #[test]
+    fn same_seed_drifts_the_same_way() {
+        let mut a = Drift::new(3.0, RATE, 42);
+        let mut b = Drift::new(3.0, RATE, 42);
+        assert!((0..1000).all(|_| a.next_ratio() == b.next_ratio()));
+    }
+
+    // Like run: the printed seed hands every new voice its own drift seed
+    fn voice_ratios(seed: u32) -> Vec<f32> {
+        let mut seeds = XorShift::new(seed);
+        (0..4)
+            .flat_map(|_| {
+                let mut drift = Drift::new(3.0, RATE, seeds.next_u32());
+                (0..1000).map(move |_| drift.next_ratio())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reusing_the_printed_seed_repeats_every_voice() {
+        assert_eq!(voice_ratios(1234), voice_ratios(1234));
+        assert_ne!(voice_ratios(1234), voice_ratios(1235));
+    }
+}
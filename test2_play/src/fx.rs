@@ -0,0 +1,278 @@
+// Master bus processing
+
+// A value that exponentially approaches its target every sample instead of jumping
+// Use for anything changed at runtime so the change doesn't click (zipper noise)
+pub struct SmoothedValue {
+    current: f32,
+    target: f32,
+    coeff: f32,
+}
+
+impl SmoothedValue {
+    pub fn new(value: f32, time_ms: f32, sample_rate: f32) -> Self {
+        Self {
+            current: value,
+            target: value,
+            coeff: smoothing_coeff(time_ms, sample_rate),
+        }
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target;
+    }
+
+    pub fn next(&mut self) -> f32 {
+        self.current = self.target + self.coeff * (self.current - self.target);
+        self.current
+    }
+}
+
+// Peak limiter without lookahead, gain reduction is smoothed so the level doesn't jump around
+// The smoothed gain lags a sudden peak by about the attack time, the output is clamped to the ceiling so it can't overshoot meanwhile
+pub struct Limiter {
+    ceiling: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+    gain: f32,
+}
+
+// Coefficient for a one-pole smoother that gets ~63% of the way to its target in `time_ms`
+fn smoothing_coeff(time_ms: f32, sample_rate: f32) -> f32 {
+    let samples = time_ms / 1000.0 * sample_rate;
+    if samples <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / samples).exp()
+    }
+}
+
+impl Limiter {
+    pub fn new(ceiling: f32, attack_ms: f32, release_ms: f32, sample_rate: f32) -> Self {
+        Self {
+            ceiling,
+            attack_coeff: smoothing_coeff(attack_ms, sample_rate),
+            release_coeff: smoothing_coeff(release_ms, sample_rate),
+            gain: 1.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        // The gain we would need right now to keep this sample under the ceiling
+        let peak = input.abs();
+        let target = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+        // Clamp down quickly (attack), let go slowly (release)
+        let coeff = if target < self.gain {
+            self.attack_coeff
+        } else {
+            self.release_coeff
+        };
+        self.gain = target + coeff * (self.gain - target);
+        // Squares off whatever the gain hasn't caught up with yet, only during the attack
+        (input * self.gain).clamp(-self.ceiling, self.ceiling)
+    }
+}
+
+// Feed-forward compressor, turns the level down by (1 - 1/ratio) of how far it's over the threshold
+// The level is followed with separate attack/release so the gain moves smoothly
+pub struct Compressor {
+    threshold_db: f32,
+    ratio: f32,
+    makeup_db: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+    // Followed input level, linear
+    envelope: f32,
+}
+
+impl Compressor {
+    pub fn new(threshold_db: f32, ratio: f32, attack_ms: f32, release_ms: f32, makeup_db: f32, sample_rate: f32) -> Self {
+        Self {
+            threshold_db,
+            ratio: ratio.max(1.0),
+            makeup_db,
+            attack_coeff: smoothing_coeff(attack_ms, sample_rate),
+            release_coeff: smoothing_coeff(release_ms, sample_rate),
+            envelope: 0.0,
+        }
+    }
+
+    // Gain change in dB for a level in dB, 0 below the threshold
+    fn gain_db(&self, level_db: f32) -> f32 {
+        let over_db = level_db - self.threshold_db;
+        if over_db > 0.0 {
+            -over_db * (1.0 - 1.0 / self.ratio)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let level = input.abs();
+        let coeff = if level > self.envelope {
+            self.attack_coeff
+        } else {
+            self.release_coeff
+        };
+        self.envelope = level + coeff * (self.envelope - level);
+        let level_db = 20.0 * self.envelope.max(1e-9).log10();
+        input * 10_f32.powf((self.gain_db(level_db) + self.makeup_db) / 20.0)
+    }
+}
+
+// Second order (12dB/octave) lowpass, coefficients from the RBJ audio EQ cookbook
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+    cutoff: f32,
+}
+
+impl Biquad {
+    pub fn lowpass(cutoff: f32, q: f32, sample_rate: f32) -> Self {
+        let mut biquad = Self {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+            cutoff: 0.0,
+        };
+        biquad.set_lowpass(cutoff, q, sample_rate);
+        biquad
+    }
+
+    // Keeps the filter state so it can be swept while playing, does nothing if the cutoff didn't move
+    pub fn set_lowpass(&mut self, cutoff: f32, q: f32, sample_rate: f32) {
+        let cutoff = cutoff.clamp(10.0, sample_rate * 0.49);
+        if cutoff == self.cutoff {
+            return;
+        }
+        self.cutoff = cutoff;
+        let w0 = 2.0 * std::f32::consts::PI * cutoff / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        self.b0 = (1.0 - cos) / 2.0 / a0;
+        self.b1 = (1.0 - cos) / a0;
+        self.b2 = self.b0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = input;
+        self.y2 = self.y1;
+        self.y1 = output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    // Samples in `ms` at RATE
+    fn samples(ms: f32) -> usize {
+        (ms / 1000.0 * RATE) as usize
+    }
+
+    #[test]
+    fn limiter_holds_a_sustained_overload_at_the_ceiling_then_recovers() {
+        let mut limiter = Limiter::new(0.9, 1.0, 50.0, RATE);
+        // Nothing gets over the ceiling within the attack time either, it's clamped while the gain catches up
+        let mut out = 0.0;
+        for _ in 0..samples(1.0) {
+            out = limiter.process(2.0);
+            assert!(out.abs() <= 0.9, "{}", out);
+        }
+        assert_eq!(out, 0.9);
+        // One-pole attack: after one attack time ~63% of the gain reduction (1 -> 0.45) is done
+        let reduction = (1.0 - limiter.gain) / (1.0 - 0.45);
+        assert!((reduction - 0.632).abs() < 0.01, "{}", reduction);
+        // Five time constants gets the gain within 1% of the ceiling, the clamp has nothing left to do
+        for _ in samples(1.0)..samples(5.0) {
+            out = limiter.process(2.0);
+        }
+        assert!((2.0 * limiter.gain - 0.9).abs() < 0.01 * 0.9, "{}", limiter.gain);
+        for _ in 0..samples(100.0) {
+            assert!(limiter.process(2.0) <= 0.9 * 1.01);
+        }
+        // Under the ceiling again, the gain lets go over the release
+        for _ in 0..samples(500.0) {
+            out = limiter.process(0.5);
+        }
+        assert!((out - 0.5).abs() < 0.001, "{}", out);
+    }
+
+    #[test]
+    fn smoothed_step_approaches_monotonically_and_settles() {
+        let mut value = SmoothedValue::new(0.0, 20.0, RATE);
+        value.set_target(1.0);
+        let mut previous = 0.0;
+        for _ in 0..samples(20.0) {
+            let next = value.next();
+            assert!(next > previous && next < 1.0);
+            previous = next;
+        }
+        // One time constant is ~63% of the way, five is within 1%
+        assert!((previous - 0.632).abs() < 0.01, "{}", previous);
+        for _ in samples(20.0)..samples(100.0) {
+            previous = value.next();
+        }
+        assert!((1.0 - previous) < 0.01, "{}", previous);
+    }
+
+    #[test]
+    fn compressor_leaves_quiet_signals_alone() {
+        let mut compressor = Compressor::new(-10.0, 4.0, 5.0, 50.0, 0.0, RATE);
+        for _ in 0..samples(100.0) {
+            assert_eq!(compressor.process(0.1), 0.1);
+        }
+    }
+
+    #[test]
+    fn compressor_reduces_the_overshoot_by_the_ratio() {
+        // 0dBFS is 20dB over, at 4:1 only 5dB of that is left, so 15dB less
+        let mut compressor = Compressor::new(-20.0, 4.0, 5.0, 50.0, 0.0, RATE);
+        let mut out = 0.0;
+        for _ in 0..samples(100.0) {
+            out = compressor.process(1.0);
+        }
+        assert!((20.0 * out.log10() + 15.0).abs() < 0.05, "{}", 20.0 * out.log10());
+    }
+
+    #[test]
+    fn compressor_envelope_attacks_and_releases_in_their_times() {
+        let mut compressor = Compressor::new(-20.0, 4.0, 5.0, 50.0, 0.0, RATE);
+        for _ in 0..samples(5.0) {
+            compressor.process(1.0);
+        }
+        // One time constant is ~63% of the way
+        assert!((compressor.envelope - 0.632).abs() < 0.01, "{}", compressor.envelope);
+        for _ in samples(5.0)..samples(100.0) {
+            compressor.process(1.0);
+        }
+        for _ in 0..samples(50.0) {
+            compressor.process(0.0);
+        }
+        assert!((compressor.envelope - 0.368).abs() < 0.01, "{}", compressor.envelope);
+    }
+}
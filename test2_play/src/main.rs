@@ -1,52 +1,55 @@
 use std::collections::HashMap;
 use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
 use std::io::stdout;
-use std::io::{stdin, Write};
+use std::io::{stdin, IsTerminal, Write};
 
+use common::fade::{fade_out_gain, Faded};
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::rng::{resolve_seed, seed_from_time, XorShift};
+use common::safety::{no_limit_from_args, ClipGuard, DEFAULT_CEILING_DBFS};
+use common::sample_format::{force_sample_format, sample_format_from_args};
+use common::setup::setup_default_device_default_config;
+use common::waveform::WaveformName;
 use cpal::Stream;
 use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    Device, FromSample, Sample, SizedSample, StreamConfig, SupportedStreamConfig,
+    traits::{DeviceTrait, StreamTrait},
+    BufferSize, Device, FromSample, Sample, SizedSample, StreamConfig, SupportedBufferSize,
+    SupportedStreamConfig,
 };
+use drift::Drift;
+use fx::{Biquad, Compressor, Limiter, SmoothedValue};
+use meter::{spawn_peak_printer, PeakMeter};
+use mono::{HeldNotes, MonoPriority};
 use midir::{Ignore, MidiInput, MidiInputConnection};
+use tuning::{load_scala, Tuning, A4_HZ};
 
-fn setup_default_device_default_config(quiet: bool) -> (Device, SupportedStreamConfig) {
-    if !quiet {
-        eprintln!("SETUP OUTPUT:");
-    }
-    let host = cpal::default_host();
-    let dev = host
-        .default_output_device()
-        .expect("Did not find output audio device!");
-    if !quiet {
-        eprintln!(
-            "- Output device: {}",
-            dev.name().expect("Device lacks a name..?")
-        );
-    }
+mod drift;
+mod fx;
+mod meter;
+mod mono;
+mod tuning;
 
-    let conf = dev
-        .default_output_config()
-        .expect("Did not find default output config for device!");
-    if !quiet {
-        eprintln!("- Default output config: {:?}", conf);
+// Shift a note by some semitones, None if it falls off the end of the midi range
+fn transpose_note(note: u8, semitones: i32) -> Option<u8> {
+    let shifted = note as i32 + semitones;
+    if (0..=127).contains(&shifted) {
+        Some(shifted as u8)
+    } else {
+        None
     }
+}
 
-    let supported = dev
-        .supported_output_configs()
-        .expect("Could not list supported configs");
-    if !quiet {
-        eprintln!("- Supported output configs:");
-        for (n, c) in supported.enumerate() {
-            eprintln!("  {}. {:?}", n, c);
-        }
-    }
-    (dev, conf)
+// The transposed note, None when it or its octave shift (12 notes per octave) is outside the midi range
+// The octave itself goes on the frequency in note_frequency, the note number doesn't move by it
+fn shift_note(note: u8, transpose_semitones: i32, octave_shift: i32) -> Option<u8> {
+    let note = transpose_note(note, transpose_semitones)?;
+    transpose_note(note, 12 * octave_shift).map(|_| note)
 }
 
-fn midi_to_frequency(note: u8) -> f32 {
-    const A4: f32 = 440.0;
-    A4 * 2f32.powf((note as f32 - 69.0) / 12.0)
+// Whole octaves double the tuned frequency, so they stay octaves in a Scala scale that isn't 12 notes per octave
+fn note_frequency(tuning: &Tuning, note: u8, octave_shift: i32) -> f32 {
+    tuning.frequency(note) * 2f32.powi(octave_shift)
 }
 
 fn midi_velocity_to_loudness(velocity: u8) -> f32 {
@@ -58,31 +61,186 @@ fn midi_velocity_to_loudness(velocity: u8) -> f32 {
 enum MidiEvent {
     KeyOff(u8),
     KeyOn(u8, u8),
+    ControlChange(u8, u8),
+    ProgramChange(u8),
+}
+
+// The event in a raw midi message, Err with what to log for anything that isn't handled
+fn parse_midi_message(message: &[u8]) -> Result<MidiEvent, String> {
+    // Program change is the only message we handle with a single data byte
+    let expected_len = match message.first().map(|status| status & 0xF0) {
+        Some(0b11000000) => 2,
+        _ => 3,
+    };
+    if message.len() != expected_len {
+        return Err(format!("* Unknown midi message pack. Length not {}! {:?}", expected_len, message));
+    }
+    match message[0] & 0xF0 {
+        0b10000000 => Ok(MidiEvent::KeyOff(message[1])),
+        0b10010000 => Ok(MidiEvent::KeyOn(message[1], message[2])),
+        0b10110000 => Ok(MidiEvent::ControlChange(message[1], message[2])),
+        0b11000000 => Ok(MidiEvent::ProgramChange(message[1])),
+        x => Err(format!("Unknown message type: {}", x)),
+    }
+}
+
+// A sounding note, each voice keeps its own phase so there's no shared clock to reset
+struct Voice {
+    loudness: f32,
+    frequency: f32,
+    // Position in the current period, 0..1
+    phase: f32,
+    // Whole periods played (wrapping), the sub oscillator's phase comes from it so it stays locked to the note
+    cycles: u32,
+    // Waveform selected when the note started, program changes don't affect sounding notes
+    wave: VoiceWaveFn,
+    drift: Drift,
+    // 0..1, ramps up when the note starts and back down once it's released so neither end clicks
+    level: f32,
+    // How far `level` moves each sample, negative once released
+    ramp_step: f32,
+}
+
+// How long a voice takes to ramp in and out
+const VOICE_RAMP_MS: f32 = 5.0;
+
+impl Voice {
+    fn new(frequency: f32, velocity: u8, wave: VoiceWaveFn, drift: Drift, sample_rate: f32) -> Self {
+        Self {
+            loudness: midi_velocity_to_loudness(velocity),
+            frequency,
+            phase: 0.0,
+            cycles: 0,
+            wave,
+            drift,
+            level: 0.0,
+            ramp_step: 1.0 / (VOICE_RAMP_MS / 1000.0 * sample_rate).max(1.0),
+        }
+    }
+
+    // Starts the ramp down, the voice keeps sounding until `is_done`
+    fn release(&mut self) {
+        self.ramp_step = -self.ramp_step.abs();
+    }
+
+    fn is_done(&self) -> bool {
+        self.ramp_step < 0.0 && self.level <= 0.0
+    }
+
+    // Next sample at the voice's loudness and ramp level, plus the sub oscillator `sub_octave` below at `sub_level`
+    fn next_sample(&mut self, retune: f32, sample_rate: f32, sub_wave: VoiceWaveFn, sub_octave: u32, sub_level: f32) -> f32 {
+        let dt = self.frequency * retune / sample_rate;
+        let mut value = (self.wave)(self.phase, dt);
+        if sub_level > 0.0 {
+            let sub_dt = dt / (1 << sub_octave) as f32;
+            value += sub_level * sub_wave(self.sub_phase(sub_octave), sub_dt);
+        }
+        self.level = (self.level + self.ramp_step).clamp(0.0, 1.0);
+        self.advance(retune, sample_rate);
+        self.loudness * self.level * value
+    }
+
+    // `retune` scales the note's frequency (live A4 reference), 1 plays it as tuned
+    fn advance(&mut self, retune: f32, sample_rate: f32) {
+        self.phase += self.frequency * retune * self.drift.next_ratio() / sample_rate;
+        self.cycles = self.cycles.wrapping_add(self.phase.floor() as u32);
+        self.phase -= self.phase.floor();
+    }
+
+    // Phase 0..1 of an oscillator `octaves` below the note
+    fn sub_phase(&self, octaves: u32) -> f32 {
+        let periods = 1_u32 << octaves;
+        ((self.cycles % periods) as f32 + self.phase) / periods as f32
+    }
+}
+
+// The mono voice for a new note and the one it replaces (to be released), `new_voice` starts one from its attack
+// Legato: a voice still sounding from the last key keeps its phase and level at the new pitch, nothing is replaced
+fn next_mono_voice(
+    previous: Option<Voice>,
+    legato: bool,
+    frequency: f32,
+    new_voice: impl FnOnce() -> Voice,
+) -> (Voice, Option<Voice>) {
+    match previous {
+        Some(mut voice) if legato => {
+            voice.frequency = frequency;
+            (voice, None)
+        }
+        other => (new_voice(), other),
+    }
 }
 
-fn setup_midi_device(quiet: bool) -> (Receiver<MidiEvent>, MidiInputConnection<()>) {
-    if !quiet {
-        eprintln!("SETUP MIDI:")
+// Midi CC numbers
+const CC_MOD_WHEEL: u8 = 1;
+const CC_CHANNEL_VOLUME: u8 = 7;
+const CC_BRIGHTNESS: u8 = 74;
+
+// What the mix of voices at `loudness` is divided by, never below 1 so quiet notes aren't pushed up
+// The plain sum can't clip, the power sum (uncorrelated voices add in power) keeps chords louder but may peak over
+fn normalization_divisor(loudness: impl Iterator<Item = f32>, sqrt_headroom: bool) -> f32 {
+    let divisor = if sqrt_headroom {
+        loudness.map(|l| l * l).sum::<f32>().sqrt()
+    } else {
+        loudness.sum()
+    };
+    divisor.max(1.0)
+}
+
+// CC value 0..=127 to a cutoff between min_hz and max_hz, logarithmic so each step is the same musical interval
+fn cc_to_cutoff(value: u8, min_hz: f32, max_hz: f32) -> f32 {
+    min_hz * (max_hz / min_hz).powf(value.min(127) as f32 / 127.0)
+}
+
+// Pick a port without prompting, by index or by a (case insensitive) substring of its name
+// Ok(None) means there is more than one port and nothing was asked for, so the user needs to choose
+fn select_midi_port(names: &[String], port: Option<&str>) -> Result<Option<usize>, String> {
+    if names.is_empty() {
+        return Err("no input port found".to_string());
+    }
+    match port {
+        Some(port) => {
+            if let Ok(i) = port.trim().parse::<usize>() {
+                if i < names.len() {
+                    return Ok(Some(i));
+                }
+            }
+            let wanted = port.to_lowercase();
+            names
+                .iter()
+                .position(|n| n.to_lowercase().contains(&wanted))
+                .map(Some)
+                .ok_or_else(|| format!("midi port '{}' not found, available ports: {:?}", port, names))
+        }
+        None if names.len() == 1 => Ok(Some(0)),
+        None => Ok(None),
     }
+}
+
+fn setup_midi_device(log_level: LogLevel, port: Option<&str>) -> (Receiver<MidiEvent>, MidiInputConnection<()>) {
+    log(log_level, "SETUP MIDI:");
 
     let mut midi_in = MidiInput::new("midir reading input").expect("could not read midi input!");
     midi_in.ignore(Ignore::None);
 
     // Get an input port (read from console if multiple are available)
     let in_ports = midi_in.ports();
-    let in_port = match in_ports.len() {
-        0 => panic!("no input port found"),
-        1 => {
-            println!(
-                "- Choosing the only available input port: {}",
-                midi_in.port_name(&in_ports[0]).unwrap()
-            );
-            &in_ports[0]
+    let names: Vec<String> = in_ports
+        .iter()
+        .map(|p| midi_in.port_name(p).unwrap())
+        .collect();
+    let in_port = match select_midi_port(&names, port).unwrap_or_else(|e| panic!("{}", e)) {
+        Some(i) => {
+            println!("- Choosing input port: {}", names[i]);
+            &in_ports[i]
         }
-        _ => {
+        None => {
+            if !stdin().is_terminal() {
+                panic!("multiple midi input ports and stdin is not a terminal, pick one with RunArgs::midi_port");
+            }
             println!("\n- Available input ports:");
-            for (i, p) in in_ports.iter().enumerate() {
-                println!("- {}: {}", i, midi_in.port_name(p).unwrap());
+            for (i, name) in names.iter().enumerate() {
+                println!("- {}: {}", i, name);
             }
             print!("- Please select input port: ");
             stdout().flush().expect("could not flush stdout");
@@ -94,9 +252,7 @@ fn setup_midi_device(quiet: bool) -> (Receiver<MidiEvent>, MidiInputConnection<(
         }
     };
 
-    if !quiet {
-        eprintln!("\n- Opening connection");
-    }
+    log(log_level, "\n- Opening connection");
     let in_port_name = midi_in.port_name(in_port).expect("could not open midi port!");
 
     let (send, recv) = mpsc::channel();
@@ -107,38 +263,20 @@ fn setup_midi_device(quiet: bool) -> (Receiver<MidiEvent>, MidiInputConnection<(
         "midir-read-input",
         move |_, message, _| {
             //println!("{}: {:?} (len = {})", stamp, message, message.len());
-            if message.len() == 3 {
-                if (message[0] & 0x0F) != 0 {
-                    if !quiet {
-                        eprintln!("Only support midi channel 0, received: {}", (message[0] & 0x0F));
-                    }
-                }
-                match message[0] & 0xF0 {
-                    0b10000000 => {
-                        //eprintln!("Note off: {} @ velocity {}", message[1], message[2]);
-                        send.send(MidiEvent::KeyOff(message[1])).expect("channel closed!");
-                    },
-                    0b10010000 => {
-                        //eprintln!("Note on: {} @ velocity {}", message[1], message[2]);
-                        send.send(MidiEvent::KeyOn(message[1], message[2])).expect("channel closed!");
-                    },
-                    x => {
-                        if !quiet {
-                            eprintln!("Unknown message type: {}", x);
-                        }
+            match parse_midi_message(message) {
+                Ok(event) => {
+                    if (message[0] & 0x0F) != 0 {
+                        log(log_level, &format!("Only support midi channel 0, received: {}", (message[0] & 0x0F)));
                     }
+                    send.send(event).expect("channel closed!");
                 }
-
-            } else {
-                if !quiet {
-                    eprintln!("* Unknown midi message pack. Length not 3! {:?}", message);
-                }
+                Err(e) => log(log_level, &e),
             }
         },
         (),
     ).expect("could not create connection!");
 
-    if !quiet {
+    if log_level >= LogLevel::Normal {
         eprintln!(
             "- Connection open, reading input from '{}' (press ctrl+c to exit) ...",
             in_port_name
@@ -155,135 +293,599 @@ enum WaveformKind {
     Triangle,
 }
 
+impl std::fmt::Display for WaveformKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WaveformKind::Sine => write!(f, "sine"),
+            WaveformKind::Square => write!(f, "square"),
+            WaveformKind::Sawtooth => write!(f, "sawtooth"),
+            WaveformKind::Triangle => write!(f, "triangle"),
+        }
+    }
+}
+
+// The shared names in any case ("SAW", "sawtooth", ...)
+impl std::str::FromStr for WaveformKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse::<WaveformName>()? {
+            WaveformName::Sine => Ok(WaveformKind::Sine),
+            WaveformName::Square => Ok(WaveformKind::Square),
+            WaveformName::Sawtooth => Ok(WaveformKind::Sawtooth),
+            WaveformName::Triangle => Ok(WaveformKind::Triangle),
+            other => Err(format!("{} isn't one of Test 2: Play's waveforms, expected sine, square, saw/sawtooth or triangle", other)),
+        }
+    }
+}
+
+// Sample generator, takes the voice phase (0..1) and how far it moves per sample (frequency / sample_rate)
+type VoiceWaveFn = fn(f32, f32) -> f32;
+
+// PolyBLEP correction, rounds off a jump of +2 at phase 0 over one sample either side
+// Subtracting the aliased part of the step is far cheaper than summing band-limited harmonics
+fn poly_blep(phase: f32, dt: f32) -> f32 {
+    if phase < dt {
+        let t = phase / dt;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - dt {
+        let t = (phase - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+// `antialiased` swaps square/sawtooth for PolyBLEP versions so high notes don't alias
+fn waveform_fn(waveform: WaveformKind, antialiased: bool) -> VoiceWaveFn {
+    match (waveform, antialiased) {
+        (WaveformKind::Sine, _) => |phase, _| (2.0 * std::f32::consts::PI * phase).sin(),
+        (WaveformKind::Square, false) => |phase, _| if phase < 0.5 { 1.0 } else { -1.0 },
+        (WaveformKind::Square, true) => |phase, dt| {
+            let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+            naive + poly_blep(phase, dt) - poly_blep((phase + 0.5) % 1.0, dt)
+        },
+        (WaveformKind::Sawtooth, false) => |phase, _| 1_f32 - 2_f32 * phase,
+        (WaveformKind::Sawtooth, true) => |phase, dt| 1_f32 - 2_f32 * phase + poly_blep(phase, dt),
+        (WaveformKind::Triangle, _) => |phase, _| {
+            if phase < 0.5 {
+                4_f32 * (phase - 0.25_f32)
+            } else {
+                1_f32 - 4_f32 * (phase - 0.5_f32)
+            }
+        },
+    }
+}
+
+// Midi programs 0..=3 pick a waveform, anything else is ignored
+fn program_to_waveform(program: u8) -> Option<WaveformKind> {
+    match program {
+        0 => Some(WaveformKind::Sine),
+        1 => Some(WaveformKind::Square),
+        2 => Some(WaveformKind::Sawtooth),
+        3 => Some(WaveformKind::Triangle),
+        _ => None,
+    }
+}
+
 #[derive(Clone, Debug)]
 struct RunArgs {
-    quiet: bool,
+    log_level: LogLevel,
     waveform: WaveformKind,
+    // Midi input port index or part of its name, None asks when there is more than one
+    midi_port: Option<String>,
+    // Shifts every incoming note, notes pushed outside 0..=127 are dropped
+    transpose_semitones: i32,
+    // Whole octaves (frequency * 2^n) on top of the transpose, dropped the same way outside the midi range
+    octave_shift: i32,
+    // Scala (.scl) scale the notes are tuned to instead of 12 tone equal temperament
+    // Degree 0 is `scala_reference_note` at `scala_reference_freq` (default middle C at its equal tempered pitch)
+    scala_file: Option<String>,
+    scala_reference_note: u8,
+    scala_reference_freq: f32,
+    // Loaded from `scala_file` in main
+    tuning: Tuning,
+    // Glide time for master volume changes (CC7)
+    volume_smoothing_ms: f32,
+    // Glide time for the mix normalization when notes start/stop, so the other voices don't jump in level
+    normalization_smoothing_ms: f32,
+    // Divide by the power sum sqrt(sum of loudness^2), 1/sqrt(n) per voice for equal notes, false for the plain sum
+    sqrt_headroom: bool,
+    // Band-limited (PolyBLEP) square and sawtooth
+    antialiased: bool,
+    // Master lowpass swept by the mod wheel (CC1) or brightness knob (CC74), starts fully open
+    cc_filter: bool,
+    filter_min_hz: f32,
+    filter_max_hz: f32,
+    filter_q: f32,
+    filter_smoothing_ms: f32,
+    // Midi CC that sweeps the A4 reference between `retune_min_hz` and `retune_max_hz`, sounding notes glide along
+    // The whole tuning (Scala too) moves by a4 / 440, it stays at 440 until the CC is first moved
+    retune_cc: Option<u8>,
+    retune_min_hz: f32,
+    retune_max_hz: f32,
+    retune_smoothing_ms: f32,
+    // Each voice slowly wanders up to this many cents out of tune, 0 keeps every voice exact
+    drift_cents: f32,
+    // Seed for the drift, the same seed and notes drift the same way. None picks one from the clock (printed at startup)
+    seed: Option<u32>,
+    // Play one note at a time, `mono_priority` is which of the held keys sounds ("last", "high" or "low")
+    mono: bool,
+    mono_priority: MonoPriority,
+    // Mono only: a key pressed while another is held only changes the pitch, the voice carries on without restarting
+    legato: bool,
+    // Sub oscillator `sub_octave` (1 or 2) octaves below every voice at `sub_level` times its level, 0 is off
+    sub_octave: u32,
+    sub_level: f32,
+    sub_waveform: WaveformKind,
+    // Frames per device buffer, smaller responds to keys faster but may crackle. None lets cpal pick
+    buffer_frames: Option<u32>,
+    // Master bus compressor, evens out the level of big chords against single notes
+    compress: bool,
+    compressor_threshold_db: f32,
+    compressor_ratio: f32,
+    compressor_attack_ms: f32,
+    compressor_release_ms: f32,
+    compressor_makeup_db: f32,
+    // Master bus limiter, replaces the divide by amplitude normalization when enabled
+    gain_compensate: bool,
+    limiter_ceiling: f32,
+    limiter_attack_ms: f32,
+    limiter_release_ms: f32,
+    // Fade out and exit after this many seconds instead of waiting for Ctrl-C (which still works), 0 listens forever
+    listen_seconds: f32,
+    // Ramp to silence over the end of `listen_seconds` so stopping doesn't click
+    fade_out_ms: f32,
+    // Print the peak output level (dBFS) every `peak_hold_ms` while playing, the hold resets each time
+    peak_hold: bool,
+    peak_hold_ms: u64,
+    // Every sample is clamped to this before it's played, `no_limit` (only from --no-limit on the command line) bypasses it
+    limit_ceiling_dbfs: f32,
+    no_limit: bool,
+    // Output sample format instead of the device default, only from --sample-format on the command line
+    sample_format: Option<cpal::SampleFormat>,
 }
 
 impl RunArgs {
-    fn new(quiet: bool, waveform: WaveformKind) -> Self {
+    fn new(log_level: LogLevel, waveform: WaveformKind) -> Self {
         Self {
-            quiet,
+            log_level,
             waveform,
+            ..Default::default()
+        }
+    }
+
+    // Human readable summary for the startup output
+    fn describe(&self) -> String {
+        let mut out = format!("Waveform: {}", self.waveform);
+        out += &format!("; Midi port: {}", self.midi_port.as_deref().unwrap_or("ask"));
+        if self.antialiased {
+            out += "; Antialiased";
+        }
+        if self.cc_filter {
+            out += &format!(
+                "; CC1/CC74 lowpass: {}..{} Hz, Q {}",
+                self.filter_min_hz, self.filter_max_hz, self.filter_q
+            );
         }
+        if let Some(cc) = self.retune_cc {
+            out += &format!("; CC{} A4: {}..{} Hz", cc, self.retune_min_hz, self.retune_max_hz);
+        }
+        if self.drift_cents != 0.0 {
+            out += &format!("; Drift: +-{} cents", self.drift_cents);
+        }
+        if self.mono {
+            out += &format!("; Mono: {} note priority", self.mono_priority);
+            if self.legato {
+                out += "; Legato";
+            }
+        }
+        if self.sub_level > 0.0 {
+            out += &format!(
+                "; Sub: {} {} octave(s) down at {}",
+                self.sub_waveform, self.sub_octave, self.sub_level
+            );
+        }
+        if self.transpose_semitones != 0 {
+            out += &format!("; Transpose: {} semitones", self.transpose_semitones);
+        }
+        if self.octave_shift != 0 {
+            out += &format!("; Octave shift: {:+}", self.octave_shift);
+        }
+        if let Some(path) = self.scala_file.as_ref() {
+            out += &format!(
+                "; Scala tuning: {} (note {} = {} Hz)",
+                path, self.scala_reference_note, self.scala_reference_freq
+            );
+        }
+        if let Some(frames) = self.buffer_frames {
+            out += &format!("; Buffer: {} frames", frames);
+        }
+        if self.compress {
+            out += &format!(
+                "; Compressor: {} dB threshold, {}:1, attack {} ms, release {} ms, makeup {} dB",
+                self.compressor_threshold_db,
+                self.compressor_ratio,
+                self.compressor_attack_ms,
+                self.compressor_release_ms,
+                self.compressor_makeup_db
+            );
+        }
+        if !self.sqrt_headroom {
+            out += "; Headroom: plain sum";
+        }
+        if self.gain_compensate {
+            out += &format!(
+                "; Limiter: ceiling {}, attack {} ms, release {} ms",
+                self.limiter_ceiling, self.limiter_attack_ms, self.limiter_release_ms
+            );
+        }
+        if self.listen_seconds > 0.0 {
+            out += &format!("; Listen: {} s, {} ms fade", self.listen_seconds, self.fade_out_ms);
+        }
+        if self.peak_hold {
+            out += &format!("; Peak hold: every {} ms", self.peak_hold_ms);
+        }
+        if self.no_limit {
+            out += "; Clip guard OFF";
+        } else if self.limit_ceiling_dbfs != DEFAULT_CEILING_DBFS {
+            out += &format!("; Clip guard ceiling: {} dBFS", self.limit_ceiling_dbfs);
+        }
+        if let Some(format) = self.sample_format {
+            out += &format!("; Sample format: {} (forced)", format);
+        }
+        out
     }
 }
 
 impl Default for RunArgs {
     fn default() -> Self {
         Self {
-            quiet: false,
+            log_level: LogLevel::Normal,
             waveform: WaveformKind::Sine,
+            midi_port: None,
+            transpose_semitones: 0,
+            octave_shift: 0,
+            scala_file: None,
+            scala_reference_note: 60,
+            scala_reference_freq: 261.6256,
+            tuning: Tuning::Equal,
+            volume_smoothing_ms: 20.0,
+            normalization_smoothing_ms: 10.0,
+            sqrt_headroom: true,
+            antialiased: false,
+            cc_filter: false,
+            filter_min_hz: 100.0,
+            filter_max_hz: 12000.0,
+            filter_q: 0.707,
+            filter_smoothing_ms: 20.0,
+            retune_cc: None,
+            retune_min_hz: 415.0,
+            retune_max_hz: 466.0,
+            retune_smoothing_ms: 50.0,
+            drift_cents: 0.0,
+            seed: None,
+            mono: false,
+            mono_priority: MonoPriority::Last,
+            legato: false,
+            sub_octave: 1,
+            sub_level: 0.0,
+            sub_waveform: WaveformKind::Sine,
+            buffer_frames: None,
+            compress: false,
+            compressor_threshold_db: -18.0,
+            compressor_ratio: 4.0,
+            compressor_attack_ms: 10.0,
+            compressor_release_ms: 100.0,
+            compressor_makeup_db: 0.0,
+            gain_compensate: false,
+            limiter_ceiling: 1.0,
+            limiter_attack_ms: 1.0,
+            limiter_release_ms: 100.0,
+            listen_seconds: 0.0,
+            fade_out_ms: 20.0,
+            peak_hold: false,
+            peak_hold_ms: 500,
+            limit_ceiling_dbfs: DEFAULT_CEILING_DBFS,
+            no_limit: false,
+            sample_format: None,
         }
     }
 }
 
-fn dispatch_run(dev: &Device, conf: SupportedStreamConfig, args: RunArgs, recv: Receiver<MidiEvent>) -> Stream  {
+// Fixed buffer of `frames` if the device supports it, Err (with why) when it should fall back to the default
+fn select_buffer_size(frames: Option<u32>, supported: &SupportedBufferSize) -> Result<BufferSize, String> {
+    let Some(frames) = frames else {
+        return Ok(BufferSize::Default);
+    };
+    match supported {
+        SupportedBufferSize::Range { min, max } if (*min..=*max).contains(&frames) => Ok(BufferSize::Fixed(frames)),
+        SupportedBufferSize::Range { min, max } => Err(format!(
+            "{} frames is outside the supported {}..={}",
+            frames, min, max
+        )),
+        SupportedBufferSize::Unknown => Err("the device doesn't report its supported buffer sizes".to_string()),
+    }
+}
+
+// Ends the playback after `listen_seconds`, the last `fade` samples ramp down and `faded` is set once they've played
+struct ListenTimeout {
+    total: usize,
+    fade: usize,
+    elapsed: usize,
+    faded: Faded,
+}
+
+impl ListenTimeout {
+    // None without a timeout (0 or less), it plays until Ctrl-C
+    fn new(listen_seconds: f32, fade_out_ms: f32, sample_rate: f32, faded: Faded) -> Option<Self> {
+        (listen_seconds > 0.0).then(|| Self {
+            total: (listen_seconds * sample_rate).round() as usize,
+            fade: (fade_out_ms.max(0.0) / 1000.0 * sample_rate).round() as usize,
+            elapsed: 0,
+            faded,
+        })
+    }
+
+    // Gain of the next sample, main exits once the last one has been asked for
+    fn next_gain(&mut self) -> f32 {
+        let gain = fade_out_gain(self.elapsed, self.total, self.fade);
+        self.elapsed += 1;
+        if self.elapsed >= self.total {
+            self.faded.set();
+        }
+        gain
+    }
+}
+
+// `faded` is set once the fade at the end of `listen_seconds` is down to silence
+fn dispatch_run(dev: &Device, conf: SupportedStreamConfig, args: RunArgs, recv: Receiver<MidiEvent>, faded: Faded) -> Stream {
     use cpal::SampleFormat::*;
+    let mut stream_conf = conf.config();
+    stream_conf.buffer_size = select_buffer_size(args.buffer_frames, conf.buffer_size()).unwrap_or_else(|e| {
+        log(args.log_level, &format!("- WARNING: Not using a fixed buffer, {}. Using the default", e));
+        BufferSize::Default
+    });
     match conf.sample_format() {
-        I8 => run::<i8>(dev, conf.into(), args, recv),
-        I16 => run::<i16>(dev, conf.into(), args, recv),
-        I32 => run::<i32>(dev, conf.into(), args, recv),
-        I64 => run::<i64>(dev, conf.into(), args, recv),
-        U8 => run::<u8>(dev, conf.into(), args, recv),
-        U16 => run::<u16>(dev, conf.into(), args, recv),
-        U32 => run::<u32>(dev, conf.into(), args, recv),
-        U64 => run::<u64>(dev, conf.into(), args, recv),
-        F32 => run::<f32>(dev, conf.into(), args, recv),
-        F64 => run::<f64>(dev, conf.into(), args, recv),
+        I8 => run::<i8>(dev, stream_conf, args, recv, faded),
+        I16 => run::<i16>(dev, stream_conf, args, recv, faded),
+        I32 => run::<i32>(dev, stream_conf, args, recv, faded),
+        I64 => run::<i64>(dev, stream_conf, args, recv, faded),
+        U8 => run::<u8>(dev, stream_conf, args, recv, faded),
+        U16 => run::<u16>(dev, stream_conf, args, recv, faded),
+        U32 => run::<u32>(dev, stream_conf, args, recv, faded),
+        U64 => run::<u64>(dev, stream_conf, args, recv, faded),
+        F32 => run::<f32>(dev, stream_conf, args, recv, faded),
+        F64 => run::<f64>(dev, stream_conf, args, recv, faded),
         f => panic!("Unknown sample format: {}", f),
     }
 }
 
-fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args: RunArgs, recv: Receiver<MidiEvent>) -> Stream {
+fn run<T: SizedSample + FromSample<f32>>(
+    dev: &Device,
+    conf: StreamConfig,
+    args: RunArgs,
+    recv: Receiver<MidiEvent>,
+    faded: Faded,
+) -> Stream {
     // Initialize constants
     let sample_rate = conf.sample_rate.0 as f32;
     let channels = conf.channels as usize;
-    if !args.quiet {
+    if args.log_level >= LogLevel::Normal {
         eprintln!("RUN");
-        eprintln!("- Sound: {:?}", args.waveform);
+        if let BufferSize::Fixed(frames) = conf.buffer_size {
+            eprintln!("- Buffer: {} frames ({:.1}ms)", frames, frames as f32 / sample_rate * 1000.0);
+        }
+        eprintln!("- Sound: {}", args.waveform);
+        if args.transpose_semitones != 0 {
+            eprintln!("- Transpose: {} semitones", args.transpose_semitones);
+        }
+        if args.octave_shift != 0 {
+            eprintln!("- Octave shift: {:+}", args.octave_shift);
+        }
+        if args.drift_cents != 0.0 {
+            eprintln!("- Drift: +-{} cents", args.drift_cents);
+        }
+        if args.mono {
+            eprintln!("- Mono, {} note priority{}", args.mono_priority, if args.legato { ", legato" } else { "" });
+        }
+        if args.sub_level > 0.0 {
+            eprintln!("- Sub: {} -{} octave(s), level {}", args.sub_waveform, args.sub_octave, args.sub_level);
+        }
+        if args.sqrt_headroom && !args.gain_compensate {
+            eprintln!("- Headroom: 1/sqrt(n), smoothed over {}ms", args.normalization_smoothing_ms);
+        }
+        if args.gain_compensate {
+            eprintln!(
+                "- Limiter: ceiling {}, attack {}ms, release {}ms",
+                args.limiter_ceiling, args.limiter_attack_ms, args.limiter_release_ms
+            );
+        }
     }
 
-    // Initialize sample generator
-    let next_sample_fn: fn(f32, f32, f32) -> f32 = match args.waveform {
-            WaveformKind::Sine => |sample_clock, sample_rate, frequency| {
-                let period = sample_rate / frequency;
-                let normalized_location = (sample_clock % period) / period;
-                (2.0 * std::f32::consts::PI * normalized_location).sin()
-            },
-            WaveformKind::Square => |sample_clock, sample_rate, frequency| {
-                let period = sample_rate / frequency;
-                if (sample_clock % period) < (period / 2.0) {
-                    1.0
-                } else {
-                    -1.0
-                }
-            },
-            WaveformKind::Sawtooth => |sample_clock, sample_rate, frequency| {
-                let period = sample_rate / frequency;
-                1_f32 - (2_f32 * (sample_clock % period) / period)
-            },
-            WaveformKind::Triangle => |sample_clock, sample_rate, frequency| {
-                let period = sample_rate / frequency;
-                let normalized_location = (sample_clock % period) / period;
-                if normalized_location < 0.5 {
-                    4_f32 * (normalized_location - 0.25_f32)
-                } else {
-                    1_f32 - 4_f32 * (normalized_location - 0.5_f32)
-                }
-            },
-        };
+    // Waveform for new notes, program change switches it
+    let antialiased = args.antialiased;
+    let mut next_sample_fn = waveform_fn(args.waveform, antialiased);
+    // Sub oscillator, it adds to each voice's level so the normalization makes room for it
+    let sub_wave = waveform_fn(args.sub_waveform, antialiased);
+    let (sub_octave, sub_level) = (args.sub_octave, args.sub_level.max(0.0));
 
-    let volume = 0.3333;
-    let mut sample_clock = 0f32;
-    let mut playing = HashMap::new();
-    let mut amplitude = 0.0_f32;
+    // Master volume, CC7 (channel volume) scales it and the change glides to avoid zipper noise
+    let max_volume = 0.3333;
+    let mut volume = SmoothedValue::new(max_volume, args.volume_smoothing_ms, sample_rate);
+    let mut playing: HashMap<u8, Voice> = HashMap::new();
+    // Released (or replaced) voices still ramping down, dropped once they're silent
+    let mut releasing: Vec<Voice> = Vec::with_capacity(128);
+    // Mono mode keeps every held key here and only the one with priority in `playing`
+    let mut held = args.mono.then(|| HeldNotes::new(args.mono_priority));
+    let legato = args.legato;
+    // What the mix is divided by, follows the summed loudness (never below 1) but glides to it
+    let mut normalization = SmoothedValue::new(1.0, args.normalization_smoothing_ms, sample_rate);
+    let (transpose_semitones, octave_shift) = (args.transpose_semitones, args.octave_shift);
+    let tuning = args.tuning.clone();
+    // Hands every new voice its own drift seed
+    let drift_cents = args.drift_cents;
+    let mut seeds = XorShift::new(args.seed.unwrap_or_else(seed_from_time));
+    let gain_compensate = args.gain_compensate;
+    let sqrt_headroom = args.sqrt_headroom;
+    // CC swept master lowpass, the cutoff glides so turning the knob doesn't zipper
+    let cc_filter = args.cc_filter;
+    let (filter_min_hz, filter_max_hz, filter_q) = (args.filter_min_hz, args.filter_max_hz, args.filter_q);
+    let mut cutoff = SmoothedValue::new(filter_max_hz, args.filter_smoothing_ms, sample_rate);
+    let mut filter = Biquad::lowpass(filter_max_hz, filter_q, sample_rate);
+    // Live A4 reference as a ratio to 440Hz, glides so held notes bend to the new pitch
+    let retune_cc = args.retune_cc;
+    let (retune_min_hz, retune_max_hz) = (args.retune_min_hz, args.retune_max_hz);
+    let mut retune = SmoothedValue::new(1.0, args.retune_smoothing_ms, sample_rate);
+    let safety = ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit);
+    let compress = args.compress;
+    let mut compressor = Compressor::new(
+        args.compressor_threshold_db,
+        args.compressor_ratio,
+        args.compressor_attack_ms,
+        args.compressor_release_ms,
+        args.compressor_makeup_db,
+        sample_rate,
+    );
+    let mut limiter = Limiter::new(
+        args.limiter_ceiling,
+        args.limiter_attack_ms,
+        args.limiter_release_ms,
+        sample_rate,
+    );
+    let mut listen = ListenTimeout::new(args.listen_seconds, args.fade_out_ms, sample_rate, faded);
+    // Shared with the printing thread, the callback only does one atomic max per buffer
+    let meter = args.peak_hold.then(|| Arc::new(PeakMeter::new()));
+    if let Some(meter) = meter.as_ref() {
+        spawn_peak_printer(meter.clone(), args.peak_hold_ms);
+    }
+    verbose(args.log_level, &format!("- Stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(args.log_level, "Output", conf.sample_rate.0);
     let stream = dev
         .build_output_stream(
             &conf,
             move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                stats.record(data.len() / channels);
+                let mut peak = 0_f32;
                 for frame in data.chunks_mut(channels) {
                     // Check if playing sounds changed
                     let mut changed = false;
                     while let Ok(event) = recv.try_recv() {
                         match event {
                             MidiEvent::KeyOff(note) => {
-                                playing.remove(&note);
+                                if let Some(note) = shift_note(note, transpose_semitones, octave_shift) {
+                                    match held.as_mut() {
+                                        Some(held) => held.release(note),
+                                        None => {
+                                            if let Some(mut voice) = playing.remove(&note) {
+                                                voice.release();
+                                                releasing.push(voice);
+                                            }
+                                        }
+                                    }
+                                }
+                                changed = true;
                             },
                             MidiEvent::KeyOn(note, velocity) => {
-                                playing.insert(note,  midi_velocity_to_loudness(velocity));
+                                if let Some(note) = shift_note(note, transpose_semitones, octave_shift) {
+                                    match held.as_mut() {
+                                        Some(held) => held.press(note, velocity),
+                                        None => {
+                                            let drift = Drift::new(drift_cents, sample_rate, seeds.next_u32());
+                                            let voice = Voice::new(note_frequency(&tuning, note, octave_shift), velocity, next_sample_fn, drift, sample_rate);
+                                            if let Some(mut previous) = playing.insert(note, voice) {
+                                                previous.release();
+                                                releasing.push(previous);
+                                            }
+                                        }
+                                    }
+                                }
+                                changed = true;
+                            },
+                            MidiEvent::ControlChange(CC_CHANNEL_VOLUME, value) => {
+                                volume.set_target(max_volume * midi_velocity_to_loudness(value));
+                            },
+                            MidiEvent::ControlChange(cc, value) if Some(cc) == retune_cc => {
+                                // Same logarithmic map as the cutoff, each step is the same number of cents
+                                retune.set_target(cc_to_cutoff(value, retune_min_hz, retune_max_hz) / A4_HZ);
+                            },
+                            MidiEvent::ControlChange(CC_MOD_WHEEL | CC_BRIGHTNESS, value) => {
+                                cutoff.set_target(cc_to_cutoff(value, filter_min_hz, filter_max_hz));
+                            },
+                            MidiEvent::ControlChange(_, _) => {},
+                            MidiEvent::ProgramChange(program) => {
+                                if let Some(waveform) = program_to_waveform(program) {
+                                    next_sample_fn = waveform_fn(waveform, antialiased);
+                                }
                             },
                         }
-                        changed = true;
                     }
-                    // Update amplitude of changed signal, hack clock to 0 if all notes released
-                    if changed {
-                        if playing.is_empty() {
-                            sample_clock = 0f32;
-                        }
-                        amplitude = 0_f32;
-                        for (_, v) in playing.iter() {
-                            amplitude += v;
+                    // Mono: (re)start the voice when the note with priority changed
+                    if let (true, Some(held)) = (changed, held.as_ref()) {
+                        let sounding = held.sounding();
+                        if sounding.map(|(note, _)| note) != playing.keys().next().copied() {
+                            let mut previous = playing.drain().next().map(|(_, voice)| voice);
+                            if let Some((note, velocity)) = sounding {
+                                let frequency = note_frequency(&tuning, note, octave_shift);
+                                let (voice, replaced) = next_mono_voice(previous.take(), legato, frequency, || {
+                                    let drift = Drift::new(drift_cents, sample_rate, seeds.next_u32());
+                                    Voice::new(frequency, velocity, next_sample_fn, drift, sample_rate)
+                                });
+                                previous = replaced;
+                                playing.insert(note, voice);
+                            }
+                            // Whatever wasn't carried over fades out
+                            if let Some(mut voice) = previous {
+                                voice.release();
+                                releasing.push(voice);
+                            }
                         }
                     }
+                    // Update amplitude of changed signal
+                    if changed {
+                        normalization.set_target(normalization_divisor(
+                            playing.values().map(|voice| voice.loudness * (1.0 + sub_level)),
+                            sqrt_headroom,
+                        ));
+                    }
+                    let divisor = normalization.next();
+                    let retune_ratio = retune.next();
                     // MIX:
                     let mut acc = 0_f32;
-                    if playing.len() > 0 {
+                    if !playing.is_empty() || !releasing.is_empty() {
                         // Sum the samples
-                        for (n, v) in playing.iter() {
-                            acc += v * next_sample_fn(sample_clock, sample_rate, midi_to_frequency(*n));
+                        for voice in playing.values_mut().chain(releasing.iter_mut()) {
+                            acc += voice.next_sample(retune_ratio, sample_rate, sub_wave, sub_octave, sub_level);
                         }
-                        if amplitude > 1.0 {
-                            acc /= amplitude;
+                        releasing.retain(|voice| !voice.is_done());
+                        if !gain_compensate {
+                            acc /= divisor;
                         }
                     }
-                    let value: T = (acc * volume).to_sample::<T>();
-                    sample_clock = sample_clock + 1.0;
+                    if cc_filter {
+                        filter.set_lowpass(cutoff.next(), filter_q, sample_rate);
+                        acc = filter.process(acc);
+                    }
+                    if compress {
+                        acc = compressor.process(acc);
+                    }
+                    if gain_compensate {
+                        acc = limiter.process(acc);
+                    }
+                    let mut out = acc * volume.next();
+                    if let Some(listen) = listen.as_mut() {
+                        out *= listen.next_gain();
+                    }
+                    peak = peak.max(out.abs());
+                    // The meter above still shows anything over the ceiling
+                    let value: T = safety.process(out).to_sample::<T>();
                     for sample in frame.iter_mut() {
                         *sample = value;
                     }
                 }
+                if let Some(meter) = meter.as_ref() {
+                    meter.update(peak);
+                }
             },
             |err| eprintln!("An error occurred during output stream: {}", err),
             None,
@@ -295,22 +897,434 @@ fn run<T: SizedSample + FromSample<f32>>(dev: &Device, conf: StreamConfig, args:
 }
 
 fn main() {
-    let args = RunArgs::new(
-        false,
-        WaveformKind::Sine,
-    );
-    if !args.quiet {
+    let wave = |name: &str| name.parse::<WaveformKind>().unwrap_or_else(|e| panic!("{}", e));
+    let mut args = RunArgs::new(LogLevel::from_args(std::env::args().skip(1)), wave("sine"));
+    args.limit_ceiling_dbfs = DEFAULT_CEILING_DBFS;
+    // Only from the command line, it can't be left switched off by an edit here
+    args.no_limit = no_limit_from_args(std::env::args().skip(1));
+    args.sample_format = sample_format_from_args(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{}", e));
+    args.midi_port = None;
+    args.transpose_semitones = 0;
+    args.octave_shift = 0;
+    args.scala_file = None;
+    args.scala_reference_note = 60;
+    args.scala_reference_freq = 261.6256;
+    if let Some(path) = args.scala_file.as_ref() {
+        args.tuning = load_scala(path, args.scala_reference_note, args.scala_reference_freq)
+            .unwrap_or_else(|e| panic!("Failed to load the scale: {}", e));
+    }
+    args.antialiased = false;
+    args.drift_cents = 0.0;
+    args.seed = None;
+    // Only the drift is random
+    args.seed = Some(resolve_seed(args.seed, args.drift_cents != 0.0));
+    args.mono = false;
+    args.mono_priority = MonoPriority::parse("last").unwrap_or_else(|e| panic!("{}", e));
+    args.legato = false;
+    args.sub_octave = 1;
+    args.sub_level = 0.0;
+    args.sub_waveform = wave("sine");
+    if args.sub_level > 0.0 && !(1..=2).contains(&args.sub_octave) {
+        panic!("sub_octave should be 1 or 2, got {}", args.sub_octave);
+    }
+    args.cc_filter = false;
+    args.retune_cc = None;
+    args.retune_min_hz = 415.0;
+    args.retune_max_hz = 466.0;
+    if args.retune_min_hz <= 0.0 || args.retune_max_hz <= 0.0 {
+        panic!("retune_min_hz and retune_max_hz should be above 0 Hz");
+    }
+    args.buffer_frames = None;
+    args.sqrt_headroom = true;
+    args.compress = false;
+    args.gain_compensate = false;
+    args.peak_hold = false;
+    args.peak_hold_ms = 500;
+    args.listen_seconds = 0.0;
+    args.fade_out_ms = 20.0;
+    if args.log_level >= LogLevel::Normal {
         eprintln!("ARGUMENTS:");
-        eprintln!("- {:?}", args);
+        eprintln!("- {}", args.describe());
+    }
+    if args.no_limit {
+        log(args.log_level, "- WARNING: Clip guard off (--no-limit)");
+    }
+    log(args.log_level, "SETUP OUTPUT:");
+    let (dev, conf, _) = setup_default_device_default_config(args.log_level)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let conf = force_sample_format(&dev, conf, args.sample_format)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(format) = args.sample_format {
+        log(args.log_level, &format!("- Sample format forced to {}", format));
     }
-    let (dev, conf) = setup_default_device_default_config(args.quiet);
-    let (recv, _midi_handle) = setup_midi_device(args.quiet);
-    let _stream = dispatch_run(&dev, conf, args, recv);
+    let (recv, _midi_handle) = setup_midi_device(args.log_level, args.midi_port.as_deref());
+    let listen_seconds = args.listen_seconds;
+    let log_level = args.log_level;
+    let faded = Faded::new();
+    let _stream = dispatch_run(&dev, conf, args, recv, faded.clone());
 
     // wait for ctrl c example code
     let (tx, rx) = mpsc::channel();
     
+    let listen_done = tx.clone();
     ctrlc::set_handler(move || tx.send(()).expect("Could not send signal on channel."))
         .expect("Error setting Ctrl-C handler");
-    rx.recv().expect("Could not receive from channel.");
+    if listen_seconds > 0.0 {
+        // Ctrl-C still stops it early, otherwise it's over once the callback has faded to silence
+        std::thread::spawn(move || {
+            faded.wait((listen_seconds * 1000.0) as u64);
+            log(log_level, &format!("- Listened for {}s, exiting", listen_seconds));
+            let _ = listen_done.send(());
+        });
+        let _ = rx.recv();
+    } else {
+        rx.recv().expect("Could not receive from channel.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports() -> Vec<String> {
+        vec!["Midi Through Port-0".to_string(), "USB Keystation 49".to_string()]
+    }
+
+    #[test]
+    fn midi_port_by_index_or_name() {
+        assert_eq!(select_midi_port(&ports(), Some("1")), Ok(Some(1)));
+        assert_eq!(select_midi_port(&ports(), Some("keystation")), Ok(Some(1)));
+        assert_eq!(select_midi_port(&ports(), Some("through")), Ok(Some(0)));
+    }
+
+    #[test]
+    fn midi_port_asks_only_when_ambiguous() {
+        assert_eq!(select_midi_port(&ports(), None), Ok(None));
+        assert_eq!(select_midi_port(&ports()[1..], None), Ok(Some(0)));
+    }
+
+    #[test]
+    fn missing_midi_port_is_an_error() {
+        assert!(select_midi_port(&ports(), Some("launchpad")).unwrap_err().contains("launchpad"));
+        assert!(select_midi_port(&[], None).is_err());
+    }
+
+    #[test]
+    fn transpose_an_octave_up() {
+        let note = transpose_note(60, 12).unwrap();
+        assert_eq!(Tuning::Equal.frequency(note), Tuning::Equal.frequency(72));
+        assert!((Tuning::Equal.frequency(note) - 2.0 * Tuning::Equal.frequency(60)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn transpose_drops_notes_outside_the_midi_range() {
+        assert_eq!(transpose_note(120, 12), None);
+        assert_eq!(transpose_note(5, -12), None);
+        assert_eq!(transpose_note(127, 0), Some(127));
+    }
+
+    #[test]
+    fn octave_shift_doubles_every_note() {
+        // 19 notes per octave, 12 notes up is not an octave there
+        let tunings = [
+            Tuning::Equal,
+            Tuning::Scala {
+                degrees: (1..=19).map(|n| n as f64 * 1200.0 / 19.0).collect(),
+                reference_note: 60,
+                reference_freq: 261.63,
+            },
+        ];
+        for tuning in tunings.iter() {
+            for note in 0..=115 {
+                let shifted = shift_note(note, 0, 1).unwrap();
+                let ratio = note_frequency(tuning, shifted, 1) / note_frequency(tuning, note, 0);
+                assert!((ratio - 2.0).abs() < 1e-4, "note {}: {}", note, ratio);
+            }
+        }
+        // Composes with the transpose, and notes pushed above 127 are dropped
+        assert_eq!(shift_note(60, 2, 1), Some(62));
+        assert_eq!(shift_note(116, 0, 1), None);
+        assert_eq!(shift_note(110, 6, 1), None);
+    }
+
+    #[test]
+    fn waveform_display_is_lowercase() {
+        assert_eq!(WaveformKind::Sine.to_string(), "sine");
+        assert_eq!(WaveformKind::Square.to_string(), "square");
+        assert_eq!(WaveformKind::Sawtooth.to_string(), "sawtooth");
+        assert_eq!(WaveformKind::Triangle.to_string(), "triangle");
+    }
+
+    #[test]
+    fn re_pressing_a_released_note_does_not_click() {
+        const RATE: f32 = 48000.0;
+        let sine = waveform_fn(WaveformKind::Sine, false);
+        let voice = || Voice::new(440.0, 127, sine, Drift::new(0.0, RATE, 1), RATE);
+        let mut playing = vec![voice()];
+        let mut releasing: Vec<Voice> = vec![];
+        let mut out = vec![];
+        let mut play = |playing: &mut Vec<Voice>, releasing: &mut Vec<Voice>, samples: usize| {
+            for _ in 0..samples {
+                out.push(
+                    playing
+                        .iter_mut()
+                        .chain(releasing.iter_mut())
+                        .map(|voice| voice.next_sample(1.0, RATE, sine, 1, 0.0))
+                        .sum::<f32>(),
+                );
+                releasing.retain(|voice| !voice.is_done());
+            }
+        };
+        // Released mid-cycle, well away from a zero crossing, and pressed again straight away
+        play(&mut playing, &mut releasing, 1000);
+        let mut released = playing.remove(0);
+        released.release();
+        releasing.push(released);
+        playing.push(voice());
+        play(&mut playing, &mut releasing, 1000);
+        assert!(releasing.is_empty());
+        // The held sine's own biggest step, plus what the ramps of both voices can add
+        let ramp_step = 1.0 / (VOICE_RAMP_MS / 1000.0 * RATE);
+        let one_step = 2.0 * std::f32::consts::PI * 440.0 / RATE + 2.0 * ramp_step;
+        let biggest = out.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0_f32, f32::max);
+        assert!(biggest <= one_step * 1.01, "{} > {}", biggest, one_step);
+    }
+
+    #[test]
+    fn program_change_one_makes_new_notes_square() {
+        let program = match parse_midi_message(&[0xC0, 1]) {
+            Ok(MidiEvent::ProgramChange(program)) => program,
+            other => panic!("expected a program change, got {:?}", other),
+        };
+        let waveform = program_to_waveform(program).unwrap();
+        assert_eq!(waveform, WaveformKind::Square);
+        // What a note started after it plays
+        let wave = waveform_fn(waveform, false);
+        assert_eq!(wave(0.25, 0.01), 1.0);
+        assert_eq!(wave(0.75, 0.01), -1.0);
+    }
+
+    #[test]
+    fn midi_messages_need_their_length() {
+        assert_eq!(parse_midi_message(&[0x90, 60, 100]), Ok(MidiEvent::KeyOn(60, 100)));
+        assert!(parse_midi_message(&[0x90, 60]).is_err());
+        assert!(parse_midi_message(&[0xC0, 1, 0]).is_err());
+    }
+
+    // Hann windowed DFT magnitude at one frequency, the aliases don't land on bins
+    fn magnitude(samples: &[f32], hz: f32, rate: f32) -> f32 {
+        let len = samples.len() as f32;
+        let (re, im) = samples.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, x)| {
+            let window = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * n as f32 / len).cos();
+            let angle = 2.0 * std::f32::consts::PI * hz * n as f32 / rate;
+            (re + x * window * angle.cos(), im - x * window * angle.sin())
+        });
+        (re * re + im * im).sqrt() / len
+    }
+
+    #[test]
+    fn antialiased_saw_near_the_top_of_the_keyboard_aliases_less() {
+        const RATE: f32 = 44100.0;
+        let freq = Tuning::Equal.frequency(120);
+        let aliased_energy = |antialiased: bool| {
+            let wave = waveform_fn(WaveformKind::Sawtooth, antialiased);
+            let mut voice = Voice::new(freq, 127, wave, Drift::new(0.0, RATE, 1), RATE);
+            // Past the start ramp
+            let samples: Vec<f32> = (0..8820)
+                .map(|_| voice.next_sample(1.0, RATE, wave, 1, 0.0))
+                .skip(441)
+                .collect();
+            // Harmonics above Nyquist fold back between the real ones
+            (3..12)
+                .map(|k| {
+                    let folded = (k as f32 * freq) % RATE;
+                    let folded = if folded > RATE / 2.0 { RATE - folded } else { folded };
+                    magnitude(&samples, folded, RATE).powi(2)
+                })
+                .sum::<f32>()
+        };
+        let naive = aliased_energy(false);
+        let band_limited = aliased_energy(true);
+        assert!(band_limited * 4.0 < naive, "{} vs {}", band_limited, naive);
+    }
+
+    #[test]
+    fn buffer_frames_inside_the_supported_range_are_fixed() {
+        let range = SupportedBufferSize::Range { min: 64, max: 4096 };
+        assert_eq!(select_buffer_size(Some(256), &range), Ok(BufferSize::Fixed(256)));
+        assert_eq!(select_buffer_size(Some(64), &range), Ok(BufferSize::Fixed(64)));
+        assert_eq!(select_buffer_size(Some(4096), &range), Ok(BufferSize::Fixed(4096)));
+        assert_eq!(select_buffer_size(None, &range), Ok(BufferSize::Default));
+    }
+
+    #[test]
+    fn buffer_frames_outside_the_range_or_unknown_are_errors() {
+        let range = SupportedBufferSize::Range { min: 64, max: 4096 };
+        assert!(select_buffer_size(Some(32), &range).unwrap_err().contains("64"));
+        assert!(select_buffer_size(Some(8192), &range).is_err());
+        assert!(select_buffer_size(Some(256), &SupportedBufferSize::Unknown).is_err());
+        assert_eq!(select_buffer_size(None, &SupportedBufferSize::Unknown), Ok(BufferSize::Default));
+    }
+
+    #[test]
+    fn adding_a_voice_glides_the_normalization() {
+        const RATE: f32 = 48000.0;
+        let args = RunArgs::new(LogLevel::Silent, WaveformKind::Sine);
+        let mut normalization = SmoothedValue::new(
+            normalization_divisor([1.0].into_iter(), args.sqrt_headroom),
+            args.normalization_smoothing_ms,
+            RATE,
+        );
+        let before = normalization.next();
+        // What the callback does when a second note starts
+        let target = normalization_divisor([1.0, 1.0].into_iter(), args.sqrt_headroom);
+        assert!(target > before);
+        normalization.set_target(target);
+        let glide: Vec<f32> = (0..(0.05 * RATE) as usize).map(|_| normalization.next()).collect();
+        // No jump: the first sample has barely moved and every step after is small
+        assert!(glide[0] - before < (target - before) * 0.01, "{}", glide[0]);
+        assert!(glide.windows(2).all(|w| w[1] >= w[0] && w[1] - w[0] < (target - before) * 0.01));
+        // Five times the smoothing time gets there
+        assert!((target - glide.last().unwrap()) < (target - before) * 0.01);
+    }
+
+    #[test]
+    fn mod_wheel_sweeps_the_cutoff_between_its_limits() {
+        assert_eq!(parse_midi_message(&[0xB0, CC_MOD_WHEEL, 0]), Ok(MidiEvent::ControlChange(CC_MOD_WHEEL, 0)));
+        assert!((cc_to_cutoff(0, 100.0, 12000.0) - 100.0).abs() < 1e-3);
+        assert!((cc_to_cutoff(127, 100.0, 12000.0) - 12000.0).abs() < 1e-1);
+        // Logarithmic, halfway up is the geometric middle
+        let middle = (100.0_f32 * 12000.0).sqrt();
+        assert!((cc_to_cutoff(63, 100.0, 12000.0)..cc_to_cutoff(64, 100.0, 12000.0)).contains(&middle));
+    }
+
+    #[test]
+    fn sqrt_headroom_is_3db_per_doubling() {
+        assert!(RunArgs::new(LogLevel::Silent, WaveformKind::Sine).sqrt_headroom);
+        // Per voice gain of `n` equal full velocity voices
+        let gain = |n: usize, sqrt_headroom: bool| 1.0 / normalization_divisor(std::iter::repeat_n(1.0, n), sqrt_headroom);
+        assert_eq!(gain(1, true), 1.0);
+        for n in [1, 2, 4, 8] {
+            let drop_db = 20.0 * (gain(2 * n, true) / gain(n, true)).log10();
+            assert!((drop_db + 3.0103).abs() < 0.01, "{} voices: {}", n, drop_db);
+            // The plain sum is -6dB
+            let plain_db = 20.0 * (gain(2 * n, false) / gain(n, false)).log10();
+            assert!((plain_db + 6.0206).abs() < 0.01, "{} voices: {}", n, plain_db);
+        }
+        // A single quiet voice isn't pushed up
+        assert_eq!(normalization_divisor([0.25].into_iter(), true), 1.0);
+    }
+
+    #[test]
+    fn listen_timeout_fades_then_signals_at_the_configured_time() {
+        // Half a second at 1kHz with a 100ms fade
+        let faded = Faded::new();
+        let mut listen = ListenTimeout::new(0.5, 100.0, 1000.0, faded.clone()).unwrap();
+        let gains: Vec<f32> = (0..499).map(|_| listen.next_gain()).collect();
+        assert!(!faded.is_set());
+        assert!(gains[..400].iter().all(|g| *g == 1.0));
+        assert!(gains[401..].windows(2).all(|w| w[1] < w[0]));
+        // The 500th sample is the last one, the bottom step of the ramp
+        assert!((listen.next_gain() - 0.01).abs() < 1e-6);
+        assert!(faded.is_set());
+    }
+
+    #[test]
+    fn no_listen_timeout_plays_until_ctrl_c() {
+        assert!(ListenTimeout::new(0.0, 20.0, 48000.0, Faded::new()).is_none());
+        assert!(ListenTimeout::new(-1.0, 20.0, 48000.0, Faded::new()).is_none());
+    }
+
+    #[test]
+    fn sub_octave_adds_energy_an_octave_below() {
+        const RATE: f32 = 44100.0;
+        let sine = waveform_fn(WaveformKind::Sine, false);
+        let render = |sub_octave: u32, sub_level: f32| {
+            let mut voice = Voice::new(440.0, 127, sine, Drift::new(0.0, RATE, 1), RATE);
+            (0..8820)
+                .map(|_| voice.next_sample(1.0, RATE, sine, sub_octave, sub_level))
+                .skip(441)
+                .collect::<Vec<f32>>()
+        };
+        let with_sub = render(1, 0.5);
+        let note = magnitude(&with_sub, 440.0, RATE);
+        // Half the level of the note, the Hann window doesn't care about the frequency
+        assert!((magnitude(&with_sub, 220.0, RATE) / note - 0.5).abs() < 0.02);
+        assert!(magnitude(&render(2, 0.5), 110.0, RATE) > 0.4 * note);
+        let without = render(1, 0.0);
+        assert!(magnitude(&without, 220.0, RATE) < 0.01 * note);
+        // Level 0 is exactly the plain voice
+        let plain: Vec<f32> = {
+            let mut voice = Voice::new(440.0, 127, sine, Drift::new(0.0, RATE, 1), RATE);
+            (0..8820).map(|_| voice.next_sample(1.0, RATE, sine, 2, 0.0)).skip(441).collect()
+        };
+        assert_eq!(without, plain);
+    }
+
+    #[test]
+    fn legato_changes_the_pitch_without_retriggering() {
+        const RATE: f32 = 48000.0;
+        let sine = waveform_fn(WaveformKind::Sine, false);
+        let voice = |frequency| Voice::new(frequency, 127, sine, Drift::new(0.0, RATE, 1), RATE);
+        let mut held = voice(440.0);
+        for _ in 0..RATE as usize / 10 {
+            held.next_sample(1.0, RATE, sine, 1, 0.0);
+        }
+        assert_eq!(held.level, 1.0);
+        let phase = held.phase;
+        let (next, replaced) = next_mono_voice(Some(held), true, 660.0, || voice(660.0));
+        assert!(replaced.is_none());
+        assert_eq!((next.frequency, next.level, next.phase), (660.0, 1.0, phase));
+        // From silence there's nothing to carry over, the new note starts its attack
+        let (next, replaced) = next_mono_voice(None, true, 660.0, || voice(660.0));
+        assert!(replaced.is_none());
+        assert_eq!((next.frequency, next.level), (660.0, 0.0));
+    }
+
+    #[test]
+    fn without_legato_the_new_note_retriggers() {
+        const RATE: f32 = 48000.0;
+        let sine = waveform_fn(WaveformKind::Sine, false);
+        let voice = |frequency| Voice::new(frequency, 127, sine, Drift::new(0.0, RATE, 1), RATE);
+        let mut held = voice(440.0);
+        for _ in 0..RATE as usize / 10 {
+            held.next_sample(1.0, RATE, sine, 1, 0.0);
+        }
+        let (next, replaced) = next_mono_voice(Some(held), false, 660.0, || voice(660.0));
+        assert_eq!((next.frequency, next.level), (660.0, 0.0));
+        assert_eq!(replaced.map(|voice| voice.frequency), Some(440.0));
+    }
+
+    #[test]
+    fn retune_cc_sweeps_a4_and_the_sounding_notes() {
+        const RATE: f32 = 48000.0;
+        let sine = waveform_fn(WaveformKind::Sine, false);
+        assert_eq!(cc_to_cutoff(0, 415.0, 466.0), 415.0);
+        assert!((cc_to_cutoff(127, 415.0, 466.0) - 466.0).abs() < 1e-3);
+        for (cc, a4) in [(0, 415.0), (127, 466.0)] {
+            // Same as run: the CC sets a glide target, held notes follow it once it has settled
+            let mut retune = SmoothedValue::new(1.0, 50.0, RATE);
+            retune.set_target(cc_to_cutoff(cc, 415.0, 466.0) / A4_HZ);
+            assert!((retune.next() - 1.0).abs() < 0.01, "jumped instead of gliding");
+            let ratio = (0..RATE as usize).map(|_| retune.next()).last().unwrap();
+            for note in [69, 81] {
+                let mut voice = Voice::new(note_frequency(&Tuning::Equal, note, 0), 127, sine, Drift::new(0.0, RATE, 1), RATE);
+                for _ in 0..RATE as usize {
+                    voice.advance(ratio, RATE);
+                }
+                let expected = a4 * 2f32.powf((note as f32 - 69.0) / 12.0);
+                assert!((voice.cycles as f32 - expected).abs() <= 1.0, "CC {} note {}: {} Hz not {}", cc, note, voice.cycles, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn waveform_kind_parses_the_shared_names() {
+        assert_eq!("SAW".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("sawtooth".parse::<WaveformKind>(), Ok(WaveformKind::Sawtooth));
+        assert_eq!("Sine".parse::<WaveformKind>(), Ok(WaveformKind::Sine));
+        let err = "bogus".parse::<WaveformKind>().unwrap_err();
+        assert!(err.contains("saw/sawtooth"), "{}", err);
+        assert!("silence".parse::<WaveformKind>().unwrap_err().contains("Test 2: Play"));
+    }
 }
@@ -0,0 +1,84 @@
+// Peak level of the live output, held between prints by a background thread
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Highest absolute sample since the last `take`, stored as f32 bits so the callback never locks
+// Non negative floats order the same as their bits, so fetch_max on the bits is a max on the values
+pub struct PeakMeter {
+    bits: AtomicU32,
+}
+
+impl PeakMeter {
+    pub fn new() -> Self {
+        Self {
+            bits: AtomicU32::new(0),
+        }
+    }
+
+    // Raises the held peak to |value| if it's higher, NaN is ignored
+    pub fn update(&self, value: f32) {
+        let value = value.abs();
+        if value.is_nan() {
+            return;
+        }
+        self.bits.fetch_max(value.to_bits(), Ordering::Relaxed);
+    }
+
+    // Held peak, resetting the hold to 0
+    pub fn take(&self) -> f32 {
+        f32::from_bits(self.bits.swap(0, Ordering::Relaxed))
+    }
+}
+
+// Linear peak to dBFS, silence is -inf
+pub fn to_dbfs(peak: f32) -> f32 {
+    20.0 * peak.log10()
+}
+
+// Prints the peak every `interval_ms` and starts a new hold, for as long as the program runs
+pub fn spawn_peak_printer(meter: Arc<PeakMeter>, interval_ms: u64) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_millis(interval_ms.max(1)));
+        eprintln!("- Peak: {:.1} dBFS", to_dbfs(meter.take()));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_the_highest_absolute_value_until_taken() {
+        let meter = PeakMeter::new();
+        for value in [0.1, -0.7, 0.3, f32::NAN, -0.2] {
+            meter.update(value);
+        }
+        assert_eq!(meter.take(), 0.7);
+        // The hold starts over
+        assert_eq!(meter.take(), 0.0);
+        meter.update(0.25);
+        assert_eq!(meter.take(), 0.25);
+    }
+
+    #[test]
+    fn updates_from_other_threads_all_count() {
+        let meter = Arc::new(PeakMeter::new());
+        let threads: Vec<_> = (1..=4)
+            .map(|n| {
+                let meter = meter.clone();
+                std::thread::spawn(move || (0..1000).for_each(|i| meter.update(-(n * i) as f32 / 4000.0)))
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert_eq!(meter.take(), 999.0 / 1000.0);
+    }
+
+    #[test]
+    fn peak_in_dbfs() {
+        assert_eq!(to_dbfs(1.0), 0.0);
+        assert!((to_dbfs(0.5) + 6.0206).abs() < 1e-3);
+        assert_eq!(to_dbfs(0.0), f32::NEG_INFINITY);
+    }
+}
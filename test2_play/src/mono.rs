@@ -0,0 +1,119 @@
+// Monophonic mode, one voice at a time picked from the keys that are held down
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MonoPriority {
+    // The most recently pressed key, releasing it goes back to the one before
+    Last,
+    // The highest held key
+    High,
+    // The lowest held key
+    Low,
+}
+
+impl MonoPriority {
+    // "last", "high" or "low"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "last" => Ok(MonoPriority::Last),
+            "high" => Ok(MonoPriority::High),
+            "low" => Ok(MonoPriority::Low),
+            other => Err(format!("unknown mono priority '{}', expected last, high or low", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for MonoPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MonoPriority::Last => write!(f, "last"),
+            MonoPriority::High => write!(f, "high"),
+            MonoPriority::Low => write!(f, "low"),
+        }
+    }
+}
+
+// Keys held down (note, velocity) in the order they were pressed
+pub struct HeldNotes {
+    priority: MonoPriority,
+    notes: Vec<(u8, u8)>,
+}
+
+impl HeldNotes {
+    pub fn new(priority: MonoPriority) -> Self {
+        Self {
+            priority,
+            // Every midi note, so the callback never has to grow it
+            notes: Vec::with_capacity(128),
+        }
+    }
+
+    // A key pressed again moves to the top with its new velocity
+    pub fn press(&mut self, note: u8, velocity: u8) {
+        self.release(note);
+        self.notes.push((note, velocity));
+    }
+
+    pub fn release(&mut self, note: u8) {
+        self.notes.retain(|(held, _)| *held != note);
+    }
+
+    // The (note, velocity) that should sound, None when nothing is held
+    pub fn sounding(&self) -> Option<(u8, u8)> {
+        match self.priority {
+            MonoPriority::Last => self.notes.last().copied(),
+            MonoPriority::High => self.notes.iter().max_by_key(|(note, _)| *note).copied(),
+            MonoPriority::Low => self.notes.iter().min_by_key(|(note, _)| *note).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const C4: u8 = 60;
+    const E4: u8 = 64;
+    const G4: u8 = 67;
+
+    #[test]
+    fn high_priority_sounds_the_highest_held_key() {
+        let mut held = HeldNotes::new(MonoPriority::High);
+        held.press(C4, 100);
+        held.press(G4, 90);
+        assert_eq!(held.sounding(), Some((G4, 90)));
+        // A lower key doesn't take over
+        held.press(E4, 80);
+        assert_eq!(held.sounding(), Some((G4, 90)));
+        held.release(G4);
+        assert_eq!(held.sounding(), Some((E4, 80)));
+        held.release(E4);
+        assert_eq!(held.sounding(), Some((C4, 100)));
+        held.release(C4);
+        assert_eq!(held.sounding(), None);
+    }
+
+    #[test]
+    fn last_priority_follows_the_most_recent_key() {
+        let mut held = HeldNotes::new(MonoPriority::Last);
+        for note in [G4, C4, E4] {
+            held.press(note, 100);
+            assert_eq!(held.sounding(), Some((note, 100)));
+        }
+        // Pressing a held key again brings it back to the top
+        held.press(G4, 50);
+        assert_eq!(held.sounding(), Some((G4, 50)));
+        held.release(G4);
+        assert_eq!(held.sounding(), Some((E4, 100)));
+    }
+
+    #[test]
+    fn low_priority_and_parsing() {
+        let mut held = HeldNotes::new(MonoPriority::Low);
+        held.press(G4, 100);
+        held.press(C4, 100);
+        held.press(E4, 100);
+        assert_eq!(held.sounding(), Some((C4, 100)));
+        assert_eq!(MonoPriority::parse(" High"), Ok(MonoPriority::High));
+        assert!(MonoPriority::parse("first").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+// Maps midi notes to frequencies, 12 tone equal temperament or a Scala (.scl) scale for microtonal tunings
+//
+// ! example.scl
+// !
+// 5 note equal temperament
+//  5
+// !
+//  240.0
+//  480.0
+//  720.0
+//  960.0
+//  2/1
+
+// Concert pitch, what A4 (note 69) plays in equal temperament
+pub const A4_HZ: f32 = 440.0;
+
+#[derive(Clone, Debug)]
+pub enum Tuning {
+    // A4 (note 69) = 440Hz, 12 semitones to the octave
+    Equal,
+    // `reference_note` plays `reference_freq` and is degree 0 of the scale, the notes above and below walk the degrees
+    Scala {
+        // Cents of degrees 1..=n above degree 0, the last one is the period the scale repeats at (usually 1200)
+        degrees: Vec<f64>,
+        reference_note: u8,
+        reference_freq: f32,
+    },
+}
+
+impl Tuning {
+    pub fn frequency(&self, note: u8) -> f32 {
+        match self {
+            Tuning::Equal => A4_HZ * 2f32.powf((note as f32 - 69.0) / 12.0),
+            Tuning::Scala {
+                degrees,
+                reference_note,
+                reference_freq,
+            } => {
+                let steps = note as i32 - *reference_note as i32;
+                let count = degrees.len() as i32;
+                let period = degrees[degrees.len() - 1];
+                let degree = steps.rem_euclid(count);
+                let cents = steps.div_euclid(count) as f64 * period
+                    + if degree == 0 { 0.0 } else { degrees[degree as usize - 1] };
+                (*reference_freq as f64 * 2f64.powf(cents / 1200.0)) as f32
+            }
+        }
+    }
+}
+
+// One pitch line, anything with a '.' is cents, otherwise a ratio "3/2" or a whole number "2" (= 2/1)
+// Only the first word counts, Scala allows a label after it
+fn parse_pitch(line: &str) -> Result<f64, String> {
+    let value = line.split_whitespace().next().unwrap_or("");
+    if value.contains('.') {
+        return value
+            .parse::<f64>()
+            .map_err(|_| format!("'{}' is not a cents value", value));
+    }
+    let (numerator, denominator) = value.split_once('/').unwrap_or((value, "1"));
+    let parse = |v: &str| {
+        v.parse::<u64>()
+            .map_err(|_| format!("'{}' is not a ratio", value))
+    };
+    let (numerator, denominator) = (parse(numerator)?, parse(denominator)?);
+    if numerator == 0 || denominator == 0 {
+        return Err(format!("'{}' is not a positive ratio", value));
+    }
+    Ok(1200.0 * (numerator as f64 / denominator as f64).log2())
+}
+
+// Cents of every scale degree from the text of a .scl file
+// Lines starting with '!' are comments, then a description line (may be empty), the note count and that many pitches
+pub fn parse_scl(text: &str) -> Result<Vec<f64>, String> {
+    let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+    lines.next().ok_or("missing the description line")?;
+    let mut lines = lines.map(str::trim).filter(|line| !line.is_empty());
+    let count_line = lines.next().ok_or("missing the note count")?;
+    let count: usize = count_line
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .parse()
+        .map_err(|_| format!("note count '{}' is not a number", count_line))?;
+    if count == 0 {
+        return Err("the scale has no notes".to_string());
+    }
+    let degrees = lines
+        .take(count)
+        .enumerate()
+        .map(|(i, line)| parse_pitch(line).map_err(|e| format!("degree {}: {}", i + 1, e)))
+        .collect::<Result<Vec<f64>, String>>()?;
+    if degrees.len() < count {
+        return Err(format!("expected {} notes, found {}", count, degrees.len()));
+    }
+    if degrees[count - 1] <= 0.0 {
+        return Err(format!("the period (last degree) should be above 0 cents, got {}", degrees[count - 1]));
+    }
+    Ok(degrees)
+}
+
+pub fn load_scala(path: &str, reference_note: u8, reference_freq: f32) -> Result<Tuning, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let degrees = parse_scl(&text).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(Tuning::Scala {
+        degrees,
+        reference_note,
+        reference_freq,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn twelve_equal_scl_matches_equal_temperament() {
+        // Cents lines then the period as a ratio, with comments in between
+        let mut text = "! 12edo.scl\n!\n12 tone equal temperament\n 12\n!\n".to_string();
+        for n in 1..12 {
+            text += &format!(" {:.1}\n", n as f64 * 100.0);
+        }
+        text += " 2/1\n";
+        let degrees = parse_scl(&text).unwrap();
+        assert_eq!(degrees.len(), 12);
+        let tuning = Tuning::Scala {
+            degrees,
+            reference_note: 69,
+            reference_freq: A4_HZ,
+        };
+        for note in 0..=127 {
+            let (scala, equal) = (tuning.frequency(note), Tuning::Equal.frequency(note));
+            assert!((scala / equal - 1.0).abs() < 1e-5, "note {}: {} != {}", note, scala, equal);
+        }
+    }
+
+    #[test]
+    fn pitches_are_cents_or_ratios() {
+        assert!((parse_pitch("701.955 fifth").unwrap() - 701.955).abs() < 1e-9);
+        assert!((parse_pitch("3/2").unwrap() - 701.955).abs() < 1e-3);
+        assert!((parse_pitch("2").unwrap() - 1200.0).abs() < 1e-9);
+        assert!(parse_pitch("0/2").is_err());
+        assert!(parse_scl("just a description\n").is_err());
+        assert!(parse_scl("desc\n3\n100.0\n200.0\n").unwrap_err().contains("expected 3"));
+    }
+}
@@ -0,0 +1,409 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::safety::{no_limit_from_args, ClipGuard, DEFAULT_CEILING_DBFS};
+use common::sample_format::{force_sample_format, sample_format_from_args};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, FromSample, Sample, SizedSample, Stream, StreamConfig, StreamInstant, SupportedStreamConfig,
+};
+
+
+fn setup_default_devices_default_configs(
+    log_level: LogLevel,
+) -> (Device, SupportedStreamConfig, Device, SupportedStreamConfig) {
+    log(log_level, "SETUP");
+    let host = cpal::default_host();
+    let out_dev = host
+        .default_output_device()
+        .expect("Did not find output audio device!");
+    let in_dev = host
+        .default_input_device()
+        .expect("Did not find input audio device!");
+    if log_level >= LogLevel::Normal {
+        eprintln!(
+            "- Output device: {}",
+            out_dev.name().expect("Device lacks a name..?")
+        );
+        eprintln!(
+            "- Input device: {}",
+            in_dev.name().expect("Device lacks a name..?")
+        );
+    }
+
+    let out_conf = out_dev
+        .default_output_config()
+        .expect("Did not find default output config for device!");
+    let in_conf = in_dev
+        .default_input_config()
+        .expect("Did not find default input config for device!");
+    if log_level >= LogLevel::Normal {
+        eprintln!("- Default output config: {:?}", out_conf);
+        eprintln!("- Default input config: {:?}", in_conf);
+    }
+    (out_dev, out_conf, in_dev, in_conf)
+}
+
+#[derive(Clone, Debug)]
+struct RunArgs {
+    log_level: LogLevel,
+    // When the impulse is played after the streams start
+    impulse_delay_ms: u64,
+    // Length of the impulse, a short burst correlates more reliably than a single sample
+    impulse_samples: usize,
+    // How long to record for, needs to cover the delay and the round trip
+    record_ms: u64,
+    // Normalized correlation needed to count the impulse as found
+    detection_threshold: f32,
+    // Every sample is clamped to this before it's played, `no_limit` (only from --no-limit on the command line) bypasses it
+    limit_ceiling_dbfs: f32,
+    no_limit: bool,
+    // Output sample format instead of the device default, only from --sample-format on the command line
+    sample_format: Option<cpal::SampleFormat>,
+}
+
+impl RunArgs {
+    fn new(log_level: LogLevel, impulse_delay_ms: u64, record_ms: u64) -> Self {
+        Self {
+            log_level,
+            impulse_delay_ms,
+            record_ms,
+            ..Default::default()
+        }
+    }
+
+    // Human readable summary for the startup output
+    fn describe(&self) -> String {
+        let mut out = format!(
+            "Impulse: {} samples after {} ms; Recording: {} ms; Detection threshold: {}",
+            self.impulse_samples, self.impulse_delay_ms, self.record_ms, self.detection_threshold
+        );
+        if self.no_limit {
+            out += "; Clip guard OFF";
+        } else if self.limit_ceiling_dbfs != DEFAULT_CEILING_DBFS {
+            out += &format!("; Clip guard ceiling: {} dBFS", self.limit_ceiling_dbfs);
+        }
+        if let Some(format) = self.sample_format {
+            out += &format!("; Sample format: {} (forced)", format);
+        }
+        out
+    }
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        Self {
+            log_level: LogLevel::Normal,
+            impulse_delay_ms: 500,
+            impulse_samples: 64,
+            record_ms: 2000,
+            detection_threshold: 0.5,
+            limit_ceiling_dbfs: DEFAULT_CEILING_DBFS,
+            no_limit: false,
+            sample_format: None,
+        }
+    }
+}
+
+// Hann windowed burst of alternating samples, loud and short but not a full scale square edge
+fn make_impulse(len: usize) -> Vec<f32> {
+    (0..len)
+        .map(|i| {
+            let window = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / len as f32).cos();
+            let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+            sign * window
+        })
+        .collect()
+}
+
+// Slides the reference over the signal and returns the lag with the highest normalized correlation
+// Normalized so the result is in -1..=1 and doesn't depend on how loud the recording is
+fn cross_correlate(signal: &[f32], reference: &[f32]) -> Option<(usize, f32)> {
+    if reference.is_empty() || signal.len() < reference.len() {
+        return None;
+    }
+    let ref_energy: f32 = reference.iter().map(|r| r * r).sum();
+    if ref_energy == 0.0 {
+        return None;
+    }
+    let mut best: Option<(usize, f32)> = None;
+    for lag in 0..=(signal.len() - reference.len()) {
+        let window = &signal[lag..lag + reference.len()];
+        let window_energy: f32 = window.iter().map(|s| s * s).sum();
+        if window_energy == 0.0 {
+            continue;
+        }
+        let dot: f32 = window.iter().zip(reference).map(|(s, r)| s * r).sum();
+        let corr = dot / (ref_energy * window_energy).sqrt();
+        if best.is_none_or(|(_, b)| corr.abs() > b.abs()) {
+            best = Some((lag, corr));
+        }
+    }
+    best
+}
+
+// Stream time of a stream's first callback, set once by the callback and read after the streams are dropped
+type FirstCallback = Arc<OnceLock<StreamInstant>>;
+
+// `later - earlier` in seconds, negative when `later` is actually the earlier one
+fn offset_secs(later: &StreamInstant, earlier: &StreamInstant) -> f64 {
+    match later.duration_since(earlier) {
+        Some(d) => d.as_secs_f64(),
+        None => -earlier.duration_since(later).map_or(0.0, |d| d.as_secs_f64()),
+    }
+}
+
+// Where the impulse would be in the recording with no latency at all
+// The recording starts with the input's first callback and the impulse is `start` samples after the output's
+// first callback, the two streams don't start together so the gap between them is taken out
+fn expected_lag(start: usize, output_after_input_secs: f64, sample_rate: f32) -> i64 {
+    start as i64 + (output_after_input_secs * sample_rate as f64).round() as i64
+}
+
+fn dispatch_output(
+    dev: &Device,
+    conf: SupportedStreamConfig,
+    impulse: Vec<f32>,
+    start: usize,
+    first_callback: FirstCallback,
+    log_level: LogLevel,
+    safety: ClipGuard,
+) -> Stream {
+    use cpal::SampleFormat::*;
+    match conf.sample_format() {
+        I8 => run_output::<i8>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        I16 => run_output::<i16>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        I32 => run_output::<i32>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        I64 => run_output::<i64>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        U8 => run_output::<u8>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        U16 => run_output::<u16>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        U32 => run_output::<u32>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        U64 => run_output::<u64>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        F32 => run_output::<f32>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        F64 => run_output::<f64>(dev, conf.into(), impulse, start, first_callback, log_level, safety),
+        f => panic!("Unknown sample format: {}", f),
+    }
+}
+
+fn dispatch_input(
+    dev: &Device,
+    conf: SupportedStreamConfig,
+    recording: Arc<Mutex<Vec<f32>>>,
+    first_callback: FirstCallback,
+    log_level: LogLevel,
+) -> Stream {
+    use cpal::SampleFormat::*;
+    match conf.sample_format() {
+        I8 => run_input::<i8>(dev, conf.into(), recording, first_callback, log_level),
+        I16 => run_input::<i16>(dev, conf.into(), recording, first_callback, log_level),
+        I32 => run_input::<i32>(dev, conf.into(), recording, first_callback, log_level),
+        I64 => run_input::<i64>(dev, conf.into(), recording, first_callback, log_level),
+        U8 => run_input::<u8>(dev, conf.into(), recording, first_callback, log_level),
+        U16 => run_input::<u16>(dev, conf.into(), recording, first_callback, log_level),
+        U32 => run_input::<u32>(dev, conf.into(), recording, first_callback, log_level),
+        U64 => run_input::<u64>(dev, conf.into(), recording, first_callback, log_level),
+        F32 => run_input::<f32>(dev, conf.into(), recording, first_callback, log_level),
+        F64 => run_input::<f64>(dev, conf.into(), recording, first_callback, log_level),
+        f => panic!("Unknown sample format: {}", f),
+    }
+}
+
+// Plays silence with the impulse starting `start` samples in
+fn run_output<T: SizedSample + FromSample<f32>>(
+    dev: &Device,
+    conf: StreamConfig,
+    impulse: Vec<f32>,
+    start: usize,
+    first_callback: FirstCallback,
+    log_level: LogLevel,
+    safety: ClipGuard,
+) -> Stream {
+    let channels = conf.channels as usize;
+    let volume = 0.5;
+    let mut sample_clock = 0_usize;
+    verbose(log_level, &format!("- Output stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(log_level, "Output", conf.sample_rate.0);
+    let stream = dev
+        .build_output_stream(
+            &conf,
+            move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+                stats.record(data.len() / channels);
+                first_callback.get_or_init(|| info.timestamp().callback);
+                for frame in data.chunks_mut(channels) {
+                    let acc = if sample_clock >= start && sample_clock - start < impulse.len() {
+                        impulse[sample_clock - start]
+                    } else {
+                        0_f32
+                    };
+                    let value: T = safety.process(acc * volume).to_sample::<T>();
+                    sample_clock += 1;
+                    for sample in frame.iter_mut() {
+                        *sample = value;
+                    }
+                }
+            },
+            |err| eprintln!("An error occurred during output stream: {}", err),
+            None,
+        )
+        .expect("Failed to create output stream!");
+    stream.play().expect("Failed to play the output stream!");
+    stream
+}
+
+// Records the first channel of the input until the recording's capacity is used up, it's allocated up front
+// so the callback never reallocates, and main only locks it once the stream is dropped
+fn run_input<T: SizedSample>(
+    dev: &Device,
+    conf: StreamConfig,
+    recording: Arc<Mutex<Vec<f32>>>,
+    first_callback: FirstCallback,
+    log_level: LogLevel,
+) -> Stream
+where
+    f32: FromSample<T>,
+{
+    let channels = conf.channels as usize;
+    verbose(log_level, &format!("- Input stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(log_level, "Input", conf.sample_rate.0);
+    let stream = dev
+        .build_input_stream(
+            &conf,
+            move |data: &[T], info: &cpal::InputCallbackInfo| {
+                stats.record(data.len() / channels);
+                first_callback.get_or_init(|| info.timestamp().callback);
+                let mut recording = recording.lock().expect("Recording lock poisoned");
+                let room = recording.capacity() - recording.len();
+                for frame in data.chunks(channels).take(room) {
+                    recording.push(frame[0].to_sample::<f32>());
+                }
+            },
+            |err| eprintln!("An error occurred during input stream: {}", err),
+            None,
+        )
+        .expect("Failed to create input stream!");
+    stream.play().expect("Failed to play the input stream!");
+    stream
+}
+
+fn main() {
+    let mut args = RunArgs::new(LogLevel::from_args(std::env::args().skip(1)), 500, 2000);
+    args.limit_ceiling_dbfs = DEFAULT_CEILING_DBFS;
+    // Only from the command line, it can't be left switched off by an edit here
+    args.no_limit = no_limit_from_args(std::env::args().skip(1));
+    args.sample_format = sample_format_from_args(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{}", e));
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("ARGUMENTS:");
+        eprintln!("- {}", args.describe());
+    }
+    if args.no_limit {
+        log(args.log_level, "- WARNING: Clip guard off (--no-limit)");
+    }
+    let (out_dev, out_conf, in_dev, in_conf) = setup_default_devices_default_configs(args.log_level);
+    let out_conf = force_sample_format(&out_dev, out_conf, args.sample_format)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(format) = args.sample_format {
+        log(args.log_level, &format!("- Sample format forced to {}", format));
+    }
+    if out_conf.sample_rate() != in_conf.sample_rate() {
+        panic!(
+            "Input and output sample rates differ ({}Hz vs {}Hz), can't compare the buffers",
+            in_conf.sample_rate().0,
+            out_conf.sample_rate().0
+        );
+    }
+    let sample_rate = out_conf.sample_rate().0 as f32;
+
+    let impulse = make_impulse(args.impulse_samples);
+    let start = (args.impulse_delay_ms as f32 / 1000.0 * sample_rate) as usize;
+    // Half a second more than the recording time, the callbacks can run a little over
+    let capacity = ((args.record_ms + 500) as f32 / 1000.0 * sample_rate) as usize;
+    let recording = Arc::new(Mutex::new(Vec::with_capacity(capacity)));
+    let output_started: FirstCallback = Arc::new(OnceLock::new());
+    let input_started: FirstCallback = Arc::new(OnceLock::new());
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("RUN");
+        eprintln!("- Playing impulse at sample {} and recording for {}ms", start, args.record_ms);
+    }
+
+    // Start recording first so the impulse can't be missed
+    let input = dispatch_input(&in_dev, in_conf, recording.clone(), input_started.clone(), args.log_level);
+    let output = dispatch_output(
+        &out_dev,
+        out_conf,
+        impulse.clone(),
+        start,
+        output_started.clone(),
+        args.log_level,
+        ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit),
+    );
+    std::thread::sleep(std::time::Duration::from_millis(args.record_ms));
+    drop(output);
+    drop(input);
+
+    let recording = recording.lock().expect("Recording lock poisoned");
+    eprintln!("RESULT");
+    eprintln!("- Recorded {} samples", recording.len());
+    let skew = match (output_started.get(), input_started.get()) {
+        (Some(output), Some(input)) => offset_secs(output, input),
+        _ => {
+            eprintln!("- WARNING: A stream never called back, not correcting for when the streams started");
+            0.0
+        }
+    };
+    verbose(args.log_level, &format!("- Output started {:.2}ms after the input", skew * 1000.0));
+    let expected = expected_lag(start, skew, sample_rate);
+    match cross_correlate(&recording, &impulse) {
+        Some((lag, corr)) if corr.abs() >= args.detection_threshold => {
+            let latency = lag as i64 - expected;
+            eprintln!("- Correlation: {:.3}{}", corr, if corr < 0.0 { " (inverted polarity)" } else { "" });
+            eprintln!(
+                "- Latency: {} samples, {:.2}ms",
+                latency,
+                latency as f32 / sample_rate * 1000.0
+            );
+        }
+        Some((_, corr)) => {
+            eprintln!(
+                "- Impulse not detected (best correlation {:.3}). Is the output looped back into the input?",
+                corr
+            );
+        }
+        None => {
+            eprintln!("- Impulse not detected, the recording was silent or too short. Is the output looped back into the input?");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cross_correlation_finds_a_known_offset() {
+        let impulse = make_impulse(64);
+        let mut signal = vec![0_f32; 4800];
+        // Quieter and inverted like a real loopback might be, plus a little of something else
+        for (i, v) in impulse.iter().enumerate() {
+            signal[1234 + i] = -0.3 * v;
+        }
+        for (n, v) in signal.iter_mut().enumerate() {
+            *v += 0.01 * (n as f32 * 0.37).sin();
+        }
+        let (lag, corr) = cross_correlate(&signal, &impulse).unwrap();
+        assert_eq!(lag, 1234);
+        assert!(corr < -0.9);
+    }
+
+    #[test]
+    fn silent_recording_finds_nothing() {
+        assert_eq!(cross_correlate(&[0_f32; 1000], &make_impulse(64)), None);
+        assert_eq!(cross_correlate(&[0_f32; 10], &make_impulse(64)), None);
+    }
+
+    #[test]
+    fn stream_start_skew_is_not_latency() {
+        // Output started 10ms after the input, at 48kHz the impulse lands 480 samples later than `start`
+        assert_eq!(expected_lag(24000, 0.010, 48000.0), 24480);
+        assert_eq!(expected_lag(24000, -0.010, 48000.0), 23520);
+    }
+}
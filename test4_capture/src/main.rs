@@ -0,0 +1,275 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::{mpsc, Arc, Mutex};
+
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::wav::{samples_to_wav, BitDepth};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, FromSample, SizedSample, Stream, StreamConfig, SupportedStreamConfig,
+};
+use meter::{channel_levels, correlation, to_db};
+use ring::RingBuffer;
+
+mod meter;
+mod ring;
+
+fn setup_default_input_device_default_config(log_level: LogLevel) -> (Device, SupportedStreamConfig) {
+    log(log_level, "SETUP");
+    let host = cpal::default_host();
+    let dev = host
+        .default_input_device()
+        .expect("Did not find input audio device!");
+    if log_level >= LogLevel::Normal {
+        eprintln!(
+            "- Input device: {}",
+            dev.name().expect("Device lacks a name..?")
+        );
+    }
+
+    let conf = dev
+        .default_input_config()
+        .expect("Did not find default input config for device!");
+    log(log_level, &format!("- Default input config: {:?}", conf));
+    (dev, conf)
+}
+
+#[derive(Clone, Debug)]
+struct RunArgs {
+    log_level: LogLevel,
+    record_ms: u64,
+    // Wav file the capture is written to (first two channels), None to not write one
+    output_file: Option<String>,
+    // Report per channel peak/RMS and the stereo correlation of the capture
+    meter: bool,
+    // Keep only the last this many seconds and record until Ctrl-C instead of for `record_ms`, memory stays fixed
+    replay_buffer_secs: Option<f32>,
+}
+
+impl RunArgs {
+    fn new(log_level: LogLevel, record_ms: u64) -> Self {
+        Self {
+            log_level,
+            record_ms,
+            ..Default::default()
+        }
+    }
+
+    // Human readable summary for the startup output
+    fn describe(&self) -> String {
+        let mut out = match self.replay_buffer_secs {
+            Some(secs) => format!("Replay buffer: last {} s (until Ctrl-C)", secs),
+            None => format!("Recording: {} ms", self.record_ms),
+        };
+        out += &format!("; Output: {}", self.output_file.as_deref().unwrap_or("none"));
+        if self.meter {
+            out += "; Meter";
+        }
+        out
+    }
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        Self {
+            log_level: LogLevel::Normal,
+            record_ms: 5000,
+            output_file: Some("capture.wav".to_string()),
+            meter: false,
+            replay_buffer_secs: None,
+        }
+    }
+}
+
+// Where the input callback puts the samples, one buffer per channel
+enum Recording {
+    // Everything, grows for as long as it records
+    All(Vec<Vec<f32>>),
+    // Only the newest samples, allocated up front so the callback never allocates
+    Replay(Vec<RingBuffer<f32>>),
+}
+
+impl Recording {
+    // `replay_frames` keeps only that many frames per channel
+    fn new(channels: usize, replay_frames: Option<usize>) -> Self {
+        match replay_frames {
+            Some(frames) => Recording::Replay((0..channels).map(|_| RingBuffer::new(frames)).collect()),
+            None => Recording::All(vec![Vec::new(); channels]),
+        }
+    }
+
+    fn push_frame(&mut self, frame: impl Iterator<Item = f32>) {
+        match self {
+            Recording::All(channels) => channels.iter_mut().zip(frame).for_each(|(channel, s)| channel.push(s)),
+            Recording::Replay(channels) => channels.iter_mut().zip(frame).for_each(|(channel, s)| channel.push(s)),
+        }
+    }
+
+    // Every channel oldest first, the recording is left empty
+    fn take(&mut self) -> Vec<Vec<f32>> {
+        match self {
+            Recording::All(channels) => channels.iter_mut().map(std::mem::take).collect(),
+            Recording::Replay(channels) => channels.iter_mut().map(|channel| channel.drain()).collect(),
+        }
+    }
+}
+
+fn dispatch_input(dev: &Device, conf: SupportedStreamConfig, recording: Arc<Mutex<Recording>>, log_level: LogLevel) -> Stream {
+    use cpal::SampleFormat::*;
+    match conf.sample_format() {
+        I8 => run_input::<i8>(dev, conf.into(), recording, log_level),
+        I16 => run_input::<i16>(dev, conf.into(), recording, log_level),
+        I32 => run_input::<i32>(dev, conf.into(), recording, log_level),
+        I64 => run_input::<i64>(dev, conf.into(), recording, log_level),
+        U8 => run_input::<u8>(dev, conf.into(), recording, log_level),
+        U16 => run_input::<u16>(dev, conf.into(), recording, log_level),
+        U32 => run_input::<u32>(dev, conf.into(), recording, log_level),
+        U64 => run_input::<u64>(dev, conf.into(), recording, log_level),
+        F32 => run_input::<f32>(dev, conf.into(), recording, log_level),
+        F64 => run_input::<f64>(dev, conf.into(), recording, log_level),
+        f => panic!("Unknown sample format: {}", f),
+    }
+}
+
+// Records every channel of the input into `recording`, made for `conf.channels` channels
+fn run_input<T: SizedSample>(dev: &Device, conf: StreamConfig, recording: Arc<Mutex<Recording>>, log_level: LogLevel) -> Stream
+where
+    f32: FromSample<T>,
+{
+    let channels = conf.channels as usize;
+    verbose(log_level, &format!("- Input stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(log_level, "Input", conf.sample_rate.0);
+    let stream = dev
+        .build_input_stream(
+            &conf,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                stats.record(data.len() / channels);
+                let mut recording = recording.lock().expect("Recording lock poisoned");
+                for frame in data.chunks(channels) {
+                    recording.push_frame(frame.iter().map(|sample| sample.to_sample::<f32>()));
+                }
+            },
+            |err| eprintln!("An error occurred during input stream: {}", err),
+            None,
+        )
+        .expect("Failed to create input stream!");
+    stream.play().expect("Failed to play the input stream!");
+    stream
+}
+
+// One channel per Vec to frame after frame, the layout wav files use
+fn interleave(recording: &[Vec<f32>]) -> Vec<f32> {
+    let frames = recording.iter().map(|c| c.len()).min().unwrap_or(0);
+    (0..frames).flat_map(|n| recording.iter().map(move |c| c[n])).collect()
+}
+
+// Every channel the device delivered goes in the file
+fn write_capture(path: &str, recording: &[Vec<f32>], sample_rate: u32) {
+    if recording.is_empty() {
+        eprintln!("- Nothing was recorded, not writing {}", path);
+        return;
+    }
+    let bytes = samples_to_wav(&interleave(recording), recording.len() as u16, sample_rate, BitDepth::Int16);
+    let mut file = File::create(path).expect("Failed to create file!");
+    file.write_all(&bytes).expect("Failed to write the capture to wave!");
+    eprintln!("- Wrote {}", path);
+}
+
+fn report_meter(recording: &[Vec<f32>]) {
+    eprintln!("METER");
+    for (n, channel) in recording.iter().enumerate() {
+        let (peak, rms) = channel_levels(channel);
+        eprintln!(
+            "- Channel {}: peak {:.1} dBFS, RMS {:.1} dBFS",
+            n,
+            to_db(peak),
+            to_db(rms)
+        );
+    }
+    match recording {
+        [left, right, ..] => match correlation(left, right) {
+            Some(corr) => {
+                let verdict = if corr > 0.5 {
+                    "mono compatible"
+                } else if corr < -0.5 {
+                    "out of phase, will cancel when summed to mono"
+                } else {
+                    "wide/unrelated"
+                };
+                eprintln!("- Correlation: {:.3} ({})", corr, verdict);
+            }
+            None => eprintln!("- Correlation: N/A (a channel is silent)"),
+        },
+        _ => eprintln!("- Correlation: N/A (mono)"),
+    }
+}
+
+fn main() {
+    let mut args = RunArgs::new(LogLevel::from_args(std::env::args().skip(1)), 5000);
+    args.output_file = Some("capture.wav".to_string());
+    args.meter = true;
+    args.replay_buffer_secs = None;
+    if let Some(secs) = args.replay_buffer_secs {
+        if !(secs.is_finite() && secs > 0.0) {
+            panic!("replay_buffer_secs should be above 0, got {}", secs);
+        }
+    }
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("ARGUMENTS:");
+        eprintln!("- {}", args.describe());
+    }
+    let (dev, conf) = setup_default_input_device_default_config(args.log_level);
+    let sample_rate = conf.sample_rate().0;
+
+    let replay_frames = args
+        .replay_buffer_secs
+        .map(|secs| (secs * sample_rate as f32).round() as usize);
+    let recording = Arc::new(Mutex::new(Recording::new(conf.channels() as usize, replay_frames)));
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("RUN");
+        match args.replay_buffer_secs {
+            Some(secs) => eprintln!("- Keeping the last {}s, press Ctrl-C to save it", secs),
+            None => eprintln!("- Recording for {}ms", args.record_ms),
+        }
+    }
+    let input = dispatch_input(&dev, conf, recording.clone(), args.log_level);
+    if replay_frames.is_some() {
+        let (tx, rx) = mpsc::channel();
+        ctrlc::set_handler(move || tx.send(()).expect("Could not send signal on channel."))
+            .expect("Error setting Ctrl-C handler");
+        rx.recv().expect("Could not receive from channel.");
+    } else {
+        std::thread::sleep(std::time::Duration::from_millis(args.record_ms));
+    }
+    drop(input);
+
+    let recording = recording.lock().expect("Recording lock poisoned").take();
+    log(args.log_level, "RESULT");
+    log(
+        args.log_level,
+        &format!(
+            "- Recorded {} channels, {} frames",
+            recording.len(),
+            recording.first().map_or(0, |c| c.len())
+        ),
+    );
+    if let Some(path) = args.output_file.as_ref() {
+        write_capture(path, &recording, sample_rate);
+    }
+    if args.meter {
+        report_meter(&recording);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_channel_is_interleaved() {
+        let recording = vec![vec![0.1, 0.2], vec![0.3, 0.4], vec![0.5, 0.6]];
+        assert_eq!(interleave(&recording), vec![0.1, 0.3, 0.5, 0.2, 0.4, 0.6]);
+        assert_eq!(interleave(&recording[..1]), vec![0.1, 0.2]);
+        assert!(interleave(&[]).is_empty());
+    }
+}
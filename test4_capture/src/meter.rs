@@ -0,0 +1,76 @@
+// Level and stereo measurements over a captured buffer
+
+// (peak, rms) of one channel, both linear (1.0 = full scale)
+pub fn channel_levels(samples: &[f32]) -> (f32, f32) {
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+    let peak = samples.iter().fold(0_f32, |m, s| m.max(s.abs()));
+    let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+    (peak, rms)
+}
+
+// Linear level to dBFS, silence is -inf
+pub fn to_db(level: f32) -> f32 {
+    20.0 * level.log10()
+}
+
+// Pearson correlation between two channels
+// ~1 is mono compatible (same signal), ~-1 is out of phase (cancels when summed), ~0 is unrelated
+// None when either channel is silent or constant
+pub fn correlation(left: &[f32], right: &[f32]) -> Option<f32> {
+    let len = left.len().min(right.len());
+    if len == 0 {
+        return None;
+    }
+    let (left, right) = (&left[..len], &right[..len]);
+    let mean_left = left.iter().sum::<f32>() / len as f32;
+    let mean_right = right.iter().sum::<f32>() / len as f32;
+    let mut cov = 0_f32;
+    let mut var_left = 0_f32;
+    let mut var_right = 0_f32;
+    for (l, r) in left.iter().zip(right) {
+        let (l, r) = (l - mean_left, r - mean_right);
+        cov += l * r;
+        var_left += l * l;
+        var_right += r * r;
+    }
+    if var_left == 0.0 || var_right == 0.0 {
+        return None;
+    }
+    Some(cov / (var_left * var_right).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::rng::XorShift;
+
+    fn noise(seed: u32) -> Vec<f32> {
+        let mut rng = XorShift::new(seed);
+        (0..48000).map(|_| rng.next_f32()).collect()
+    }
+
+    #[test]
+    fn identical_channels_correlate() {
+        let left = noise(1);
+        assert!((correlation(&left, &left).unwrap() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn inverted_channels_anti_correlate() {
+        let left = noise(1);
+        let right: Vec<f32> = left.iter().map(|v| -v).collect();
+        assert!((correlation(&left, &right).unwrap() + 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn independent_noise_is_uncorrelated() {
+        assert!(correlation(&noise(1), &noise(2)).unwrap().abs() < 0.05);
+    }
+
+    #[test]
+    fn silent_channel_has_no_correlation() {
+        assert_eq!(correlation(&noise(1), &[0.0; 48000]), None);
+    }
+}
@@ -0,0 +1,73 @@
+// Fixed size buffer that keeps the newest samples, for the replay buffer
+// Never allocates after creation, pushing into a full buffer overwrites the oldest sample instead of growing
+pub struct RingBuffer<T> {
+    buffer: Vec<T>,
+    // Next slot to write, once full it's also the oldest sample
+    write: usize,
+    len: usize,
+}
+
+impl<T: Copy + Default> RingBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffer: vec![T::default(); capacity.max(1)],
+            write: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.buffer[self.write] = value;
+        self.write = (self.write + 1) % self.buffer.len();
+        self.len = (self.len + 1).min(self.buffer.len());
+    }
+
+    // Everything kept, oldest first, and leaves the buffer empty
+    pub fn drain(&mut self) -> Vec<T> {
+        let capacity = self.buffer.len();
+        let oldest = (self.write + capacity - self.len) % capacity;
+        let out = (0..self.len).map(|n| self.buffer[(oldest + n) % capacity]).collect();
+        self.len = 0;
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drains_everything_before_it_wraps() {
+        let mut ring = RingBuffer::new(4);
+        assert!(ring.drain().is_empty());
+        ring.push(1.0);
+        ring.push(2.0);
+        assert_eq!(ring.drain(), vec![1.0, 2.0]);
+        assert!(ring.drain().is_empty());
+    }
+
+    #[test]
+    fn wraparound_keeps_the_newest_in_order() {
+        let mut ring = RingBuffer::new(4);
+        for n in 0..10 {
+            ring.push(n as f32);
+        }
+        assert_eq!(ring.drain(), vec![6.0, 7.0, 8.0, 9.0]);
+        // Drained from part way through the slots, the next pushes still come out in order
+        for n in 10..13 {
+            ring.push(n as f32);
+        }
+        assert_eq!(ring.drain(), vec![10.0, 11.0, 12.0]);
+    }
+
+    #[test]
+    fn pushing_never_grows_the_buffer() {
+        let mut ring = RingBuffer::new(3);
+        let slots = ring.buffer.as_ptr();
+        for n in 0..1000 {
+            ring.push(n);
+        }
+        assert_eq!((ring.buffer.as_ptr(), ring.buffer.len()), (slots, 3));
+        assert_eq!(ring.drain(), vec![997, 998, 999]);
+    }
+}
@@ -0,0 +1,267 @@
+use common::logging::{log, verbose, CallbackStats, LogLevel};
+use common::safety::{no_limit_from_args, ClipGuard, DEFAULT_CEILING_DBFS};
+use common::sample_format::{force_sample_format, sample_format_from_args};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, FromSample, Sample, SizedSample, Stream, StreamConfig, SupportedStreamConfig,
+};
+use ring::{ring_buffer, Consumer, Producer};
+
+mod ring;
+
+fn setup_default_devices_default_configs(
+    log_level: LogLevel,
+) -> (Device, SupportedStreamConfig, Device, SupportedStreamConfig) {
+    log(log_level, "SETUP");
+    let host = cpal::default_host();
+    let out_dev = host
+        .default_output_device()
+        .expect("Did not find output audio device!");
+    let in_dev = host
+        .default_input_device()
+        .expect("Did not find input audio device!");
+    if log_level >= LogLevel::Normal {
+        eprintln!(
+            "- Output device: {}",
+            out_dev.name().expect("Device lacks a name..?")
+        );
+        eprintln!(
+            "- Input device: {}",
+            in_dev.name().expect("Device lacks a name..?")
+        );
+    }
+
+    let out_conf = out_dev
+        .default_output_config()
+        .expect("Did not find default output config for device!");
+    let in_conf = in_dev
+        .default_input_config()
+        .expect("Did not find default input config for device!");
+    if log_level >= LogLevel::Normal {
+        eprintln!("- Default output config: {:?}", out_conf);
+        eprintln!("- Default input config: {:?}", in_conf);
+    }
+    (out_dev, out_conf, in_dev, in_conf)
+}
+
+#[derive(Clone, Debug)]
+struct RunArgs {
+    log_level: LogLevel,
+    // Lowpass cutoff applied between input and output
+    cutoff: f32,
+    run_ms: u64,
+    // Size of the buffer between the input and output, the most latency it can add
+    buffer_ms: u64,
+    // Every sample is clamped to this before it's played, `no_limit` (only from --no-limit on the command line) bypasses it
+    limit_ceiling_dbfs: f32,
+    no_limit: bool,
+    // Output sample format instead of the device default, only from --sample-format on the command line
+    sample_format: Option<cpal::SampleFormat>,
+}
+
+impl RunArgs {
+    fn new(log_level: LogLevel, cutoff: f32, run_ms: u64) -> Self {
+        Self {
+            log_level,
+            cutoff,
+            run_ms,
+            ..Default::default()
+        }
+    }
+
+    // Human readable summary for the startup output
+    fn describe(&self) -> String {
+        let mut out = format!(
+            "Lowpass cutoff: {} Hz; Running: {} ms; Buffer: {} ms",
+            self.cutoff, self.run_ms, self.buffer_ms
+        );
+        if self.no_limit {
+            out += "; Clip guard OFF";
+        } else if self.limit_ceiling_dbfs != DEFAULT_CEILING_DBFS {
+            out += &format!("; Clip guard ceiling: {} dBFS", self.limit_ceiling_dbfs);
+        }
+        if let Some(format) = self.sample_format {
+            out += &format!("; Sample format: {} (forced)", format);
+        }
+        out
+    }
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        Self {
+            log_level: LogLevel::Normal,
+            cutoff: 1000.0,
+            run_ms: 10000,
+            buffer_ms: 100,
+            limit_ceiling_dbfs: DEFAULT_CEILING_DBFS,
+            no_limit: false,
+            sample_format: None,
+        }
+    }
+}
+
+// Same first order lowpass as test1_lowpass
+fn calculate_alpha(sample_rate: f32, cutoff: f32) -> f32 {
+    let nc = cutoff / (sample_rate / 2.0);
+    1.0 / (1.0 + std::f32::consts::PI / nc)
+}
+
+fn dispatch_input(dev: &Device, conf: SupportedStreamConfig, ring: Producer, log_level: LogLevel) -> Stream {
+    use cpal::SampleFormat::*;
+    match conf.sample_format() {
+        I8 => run_input::<i8>(dev, conf.into(), ring, log_level),
+        I16 => run_input::<i16>(dev, conf.into(), ring, log_level),
+        I32 => run_input::<i32>(dev, conf.into(), ring, log_level),
+        I64 => run_input::<i64>(dev, conf.into(), ring, log_level),
+        U8 => run_input::<u8>(dev, conf.into(), ring, log_level),
+        U16 => run_input::<u16>(dev, conf.into(), ring, log_level),
+        U32 => run_input::<u32>(dev, conf.into(), ring, log_level),
+        U64 => run_input::<u64>(dev, conf.into(), ring, log_level),
+        F32 => run_input::<f32>(dev, conf.into(), ring, log_level),
+        F64 => run_input::<f64>(dev, conf.into(), ring, log_level),
+        f => panic!("Unknown sample format: {}", f),
+    }
+}
+
+fn dispatch_output(
+    dev: &Device,
+    conf: SupportedStreamConfig,
+    ring: Consumer,
+    alpha: f32,
+    log_level: LogLevel,
+    safety: ClipGuard,
+) -> Stream {
+    use cpal::SampleFormat::*;
+    match conf.sample_format() {
+        I8 => run_output::<i8>(dev, conf.into(), ring, alpha, log_level, safety),
+        I16 => run_output::<i16>(dev, conf.into(), ring, alpha, log_level, safety),
+        I32 => run_output::<i32>(dev, conf.into(), ring, alpha, log_level, safety),
+        I64 => run_output::<i64>(dev, conf.into(), ring, alpha, log_level, safety),
+        U8 => run_output::<u8>(dev, conf.into(), ring, alpha, log_level, safety),
+        U16 => run_output::<u16>(dev, conf.into(), ring, alpha, log_level, safety),
+        U32 => run_output::<u32>(dev, conf.into(), ring, alpha, log_level, safety),
+        U64 => run_output::<u64>(dev, conf.into(), ring, alpha, log_level, safety),
+        F32 => run_output::<f32>(dev, conf.into(), ring, alpha, log_level, safety),
+        F64 => run_output::<f64>(dev, conf.into(), ring, alpha, log_level, safety),
+        f => panic!("Unknown sample format: {}", f),
+    }
+}
+
+// Pushes the interleaved input into the ring, anything that doesn't fit is dropped
+fn run_input<T: SizedSample>(dev: &Device, conf: StreamConfig, mut ring: Producer, log_level: LogLevel) -> Stream
+where
+    f32: FromSample<T>,
+{
+    let channels = conf.channels as usize;
+    verbose(log_level, &format!("- Input stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(log_level, "Input", conf.sample_rate.0);
+    let stream = dev
+        .build_input_stream(
+            &conf,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                stats.record(data.len() / channels);
+                for sample in data.iter() {
+                    ring.push(sample.to_sample::<f32>());
+                }
+            },
+            |err| eprintln!("An error occurred during input stream: {}", err),
+            None,
+        )
+        .expect("Failed to create input stream!");
+    stream.play().expect("Failed to play the input stream!");
+    stream
+}
+
+// Pops interleaved samples out of the ring and lowpasses each channel, silence when the ring runs dry
+fn run_output<T: SizedSample + FromSample<f32>>(
+    dev: &Device,
+    conf: StreamConfig,
+    mut ring: Consumer,
+    alpha: f32,
+    log_level: LogLevel,
+    safety: ClipGuard,
+) -> Stream {
+    let channels = conf.channels as usize;
+    let mut prev = vec![0_f32; channels];
+    verbose(log_level, &format!("- Output stream config: {:?}", conf));
+    let mut stats = CallbackStats::new(log_level, "Output", conf.sample_rate.0);
+    let stream = dev
+        .build_output_stream(
+            &conf,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                stats.record(data.len() / channels);
+                for frame in data.chunks_mut(channels) {
+                    for (sample, prev) in frame.iter_mut().zip(prev.iter_mut()) {
+                        let input = ring.pop().unwrap_or(0.0);
+                        // Filter
+                        *prev = alpha * input + (1.0 - alpha) * *prev;
+                        *sample = safety.process(*prev).to_sample::<T>();
+                    }
+                }
+            },
+            |err| eprintln!("An error occurred during output stream: {}", err),
+            None,
+        )
+        .expect("Failed to create output stream!");
+    stream.play().expect("Failed to play the output stream!");
+    stream
+}
+
+fn main() {
+    let mut args = RunArgs::new(LogLevel::from_args(std::env::args().skip(1)), 1000.0, 10000);
+    args.limit_ceiling_dbfs = DEFAULT_CEILING_DBFS;
+    // Only from the command line, it can't be left switched off by an edit here
+    args.no_limit = no_limit_from_args(std::env::args().skip(1));
+    args.sample_format = sample_format_from_args(std::env::args().skip(1)).unwrap_or_else(|e| panic!("{}", e));
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("ARGUMENTS:");
+        eprintln!("- {}", args.describe());
+    }
+    if args.no_limit {
+        log(args.log_level, "- WARNING: Clip guard off (--no-limit)");
+    }
+    let (out_dev, out_conf, in_dev, in_conf) = setup_default_devices_default_configs(args.log_level);
+    let out_conf = force_sample_format(&out_dev, out_conf, args.sample_format)
+        .unwrap_or_else(|e| panic!("{}", e));
+    if let Some(format) = args.sample_format {
+        log(args.log_level, &format!("- Sample format forced to {}", format));
+    }
+    // No resampling or channel mapping, the devices have to agree
+    if out_conf.sample_rate() != in_conf.sample_rate() {
+        panic!(
+            "Input and output sample rates differ ({}Hz vs {}Hz), passthrough needs them to match",
+            in_conf.sample_rate().0,
+            out_conf.sample_rate().0
+        );
+    }
+    if out_conf.channels() != in_conf.channels() {
+        panic!(
+            "Input and output channel counts differ ({} vs {}), passthrough needs them to match",
+            in_conf.channels(),
+            out_conf.channels()
+        );
+    }
+    let sample_rate = out_conf.sample_rate().0 as f32;
+    let alpha = calculate_alpha(sample_rate, args.cutoff);
+    // Whole frames only, both callbacks move whole frames so the channels can't get out of step
+    let capacity = (args.buffer_ms as f32 / 1000.0 * sample_rate) as usize * out_conf.channels() as usize;
+    let (producer, consumer) = ring_buffer(capacity);
+    if args.log_level >= LogLevel::Normal {
+        eprintln!("RUN");
+        eprintln!("- Passing input to output for {}ms (headphones, or it will feed back!)", args.run_ms);
+    }
+
+    let input = dispatch_input(&in_dev, in_conf, producer, args.log_level);
+    let output = dispatch_output(
+        &out_dev,
+        out_conf,
+        consumer,
+        alpha,
+        args.log_level,
+        ClipGuard::new(args.limit_ceiling_dbfs, args.no_limit),
+    );
+    std::thread::sleep(std::time::Duration::from_millis(args.run_ms));
+    drop(input);
+    drop(output);
+}
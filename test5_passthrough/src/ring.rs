@@ -0,0 +1,135 @@
+// Fixed size FIFO between the input and output callbacks
+// Never allocates after creation, pushing into a full buffer drops the sample instead of growing
+// Lock free single producer/single consumer: the input callback owns the Producer, the output
+// callback the Consumer, so neither can block the other
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct Shared {
+    // f32 bits, one more slot than the capacity so full and empty can be told apart
+    slots: Vec<AtomicU32>,
+    // Next slot to read, only moved by the Consumer
+    read: AtomicUsize,
+    // Next slot to write, only moved by the Producer
+    write: AtomicUsize,
+}
+
+impl Shared {
+    fn next(&self, index: usize) -> usize {
+        (index + 1) % self.slots.len()
+    }
+}
+
+pub struct Producer {
+    shared: Arc<Shared>,
+}
+
+pub struct Consumer {
+    shared: Arc<Shared>,
+}
+
+// The two ends of a ring holding up to `capacity` samples
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let shared = Arc::new(Shared {
+        slots: (0..capacity.max(1) + 1).map(|_| AtomicU32::new(0)).collect(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: Arc::clone(&shared),
+        },
+        Consumer { shared },
+    )
+}
+
+impl Producer {
+    // false when full (the sample is dropped)
+    pub fn push(&mut self, value: f32) -> bool {
+        let write = self.shared.write.load(Ordering::Relaxed);
+        let next = self.shared.next(write);
+        if next == self.shared.read.load(Ordering::Acquire) {
+            return false;
+        }
+        self.shared.slots[write].store(value.to_bits(), Ordering::Relaxed);
+        // Release so the consumer sees the sample once it sees the new write index
+        self.shared.write.store(next, Ordering::Release);
+        true
+    }
+}
+
+impl Consumer {
+    // Oldest sample, None when empty
+    pub fn pop(&mut self) -> Option<f32> {
+        let read = self.shared.read.load(Ordering::Relaxed);
+        if read == self.shared.write.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = f32::from_bits(self.shared.slots[read].load(Ordering::Relaxed));
+        // Release so the producer only reuses the slot after it has been read
+        self.shared.read.store(self.shared.next(read), Ordering::Release);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around_in_order() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        for round in 0..10 {
+            // Three in, three out, so the indices cross the end of the slots every other round
+            for n in 0..3 {
+                assert!(producer.push((round * 3 + n) as f32));
+            }
+            for n in 0..3 {
+                assert_eq!(consumer.pop(), Some((round * 3 + n) as f32));
+            }
+            assert_eq!(consumer.pop(), None);
+        }
+    }
+
+    #[test]
+    fn full_drops_and_empty_is_none() {
+        let (mut producer, mut consumer) = ring_buffer(3);
+        assert_eq!(consumer.pop(), None);
+        assert!(producer.push(1.0));
+        assert!(producer.push(2.0));
+        assert!(producer.push(3.0));
+        assert!(!producer.push(4.0));
+        assert_eq!(consumer.pop(), Some(1.0));
+        // A freed slot takes the next sample, past the end of the slots
+        assert!(producer.push(5.0));
+        assert_eq!(consumer.pop(), Some(2.0));
+        assert_eq!(consumer.pop(), Some(3.0));
+        assert_eq!(consumer.pop(), Some(5.0));
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn separate_threads_keep_the_order() {
+        let (mut producer, mut consumer) = ring_buffer(16);
+        let count = 100_000;
+        let writer = std::thread::spawn(move || {
+            for n in 0..count {
+                while !producer.push(n as f32) {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < count {
+            match consumer.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected as f32);
+                    expected += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(consumer.pop(), None);
+    }
+}